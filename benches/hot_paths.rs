@@ -0,0 +1,140 @@
+//! Benchmarks for the hot paths of the runtime and the services
+
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Body, Node};
+use maelstrom::services::broadcast;
+
+/// Amount of messages the broadcast node has seen before being measured
+const SEEN: usize = 10_000;
+
+/// A broadcast message as sent by a Maelstrom client
+const BROADCAST_LINE: &str = r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":1,"message":1000}}"#;
+
+/// Build a broadcast node that has already seen `SEEN` messages
+fn seeded_broadcast_node() -> broadcast::BroadcastNode {
+    let init = msg::Init {
+        node_id:  "n1".into(),
+        node_ids: vec!["n1".into(), "n2".into(), "n3".into()],
+    };
+    let mut node = broadcast::BroadcastNode::from_init(&init,
+        &Config::default()).unwrap();
+
+    for message in 0..SEEN {
+        node.step(broadcast_msg(message), &mut std::io::sink()).unwrap();
+    }
+    node
+}
+
+fn broadcast_msg(message: usize) -> Message<broadcast::Payload> {
+    Message {
+        src: "c1".into(),
+        dst: "n1".into(),
+        body: Body {
+            id: Some(message),
+            reply_id: None,
+            deadline: None,
+            trace: None,
+            payload: broadcast::Payload::Broadcast { message },
+        },
+    }
+}
+
+fn parse_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message");
+    group.throughput(Throughput::Bytes(BROADCAST_LINE.len() as u64));
+
+    group.bench_function("parse", |b| b.iter(|| {
+        msg::parse_line::<broadcast::Payload>(black_box(BROADCAST_LINE))
+            .unwrap()
+    }));
+
+    group.bench_function("parse_borrowed", |b| b.iter(|| {
+        msg::parse_line_ref::<broadcast::Payload>(black_box(BROADCAST_LINE))
+            .unwrap()
+    }));
+
+    let parsed = msg::parse_line::<broadcast::Payload>(BROADCAST_LINE)
+        .unwrap();
+    let mut out = Vec::with_capacity(256);
+    group.bench_function("serialize", |b| b.iter(|| {
+        out.clear();
+        black_box(&parsed).send(&mut out).unwrap();
+    }));
+
+    group.finish();
+}
+
+fn broadcast_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broadcast_step");
+    let mut node = seeded_broadcast_node();
+
+    group.bench_function("broadcast", |b| b.iter(|| {
+        node.step(broadcast_msg(black_box(SEEN)), &mut std::io::sink())
+            .unwrap()
+    }));
+
+    // The seen set grew during the previous benchmark, start afresh
+    let mut node = seeded_broadcast_node();
+    group.bench_function("read", |b| b.iter(|| {
+        let read = Message {
+            src: "c1".into(),
+            dst: "n1".into(),
+            body: Body {
+                id: Some(1),
+                reply_id: None,
+                deadline: None,
+                trace: None,
+                payload: broadcast::Payload::Read {
+                    seen:     None,
+                    messages: Vec::new(),
+                    upto:     None,
+                    epoch:    None,
+                },
+            },
+        };
+        node.step(read, &mut std::io::sink()).unwrap()
+    }));
+
+    group.finish();
+}
+
+fn bulk_encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bulk_encoding");
+    let msg = Message {
+        src: "n1".into(),
+        dst: "n2".into(),
+        body: Body {
+            id: Some(1),
+            reply_id: None,
+            deadline: None,
+            trace: None,
+            payload: broadcast::Payload::ReadOk {
+                messages: (0..SEEN).collect(),
+                upto:     None,
+                epoch:    None,
+            },
+        },
+    };
+
+    let mut out = Vec::with_capacity(128 * 1024);
+    group.throughput(Throughput::Elements(SEEN as u64));
+    group.bench_function("read_ok", |b| b.iter(|| {
+        out.clear();
+        black_box(&msg).send(&mut out).unwrap();
+    }));
+
+    // Gossip dominates what broadcast nodes parse; compare with and without
+    // the `simd-json` feature
+    let line = serde_json::to_string(&msg).unwrap();
+    group.bench_function("parse_read_ok", |b| b.iter(|| {
+        msg::parse_line::<broadcast::Payload>(black_box(&line))
+            .unwrap()
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, parse_serialize, broadcast_step, bulk_encoding);
+criterion_main!(benches);
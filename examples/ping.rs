@@ -0,0 +1,40 @@
+use std::io::Write;
+use maelstrom::message::{self as msg, Message};
+use maelstrom::config::Config;
+
+maelstrom::payload! {
+    /// Payloads handled by the ping server
+    pub enum Payload {
+        Ping,
+        PingOk { count: u64 },
+    }
+}
+
+/// A node answering pings with the amount of pings it answered so far
+struct PingNode {
+    count: u64,
+}
+
+impl msg::Node<Payload> for PingNode {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self { count: 0 })
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let mut input = input;
+        let id = input.body.id;
+
+        match input.body.payload {
+            Payload::PingOk { .. } => Ok(()),
+            Payload::Ping => {
+                self.count += 1;
+                input.body.payload = Payload::PingOk { count: self.count };
+                input.into_reply(id).send(output)
+            },
+        }
+    }
+}
+
+maelstrom::service_main!(bin "ping", Payload, PingNode);
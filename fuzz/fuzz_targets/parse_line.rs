@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use maelstrom::message::parse_line;
+use maelstrom::services::{echo, uuid, broadcast};
+
+fuzz_target!(|data: &[u8]| {
+    // Lines are always read as UTF-8 by the main loop
+    let Ok(line) = core::str::from_utf8(data) else { return; };
+
+    // Only errors are allowed here, a panic is a bug
+    let _ = parse_line::<echo::Payload>(line);
+    let _ = parse_line::<uuid::Payload>(line);
+    let _ = parse_line::<broadcast::Payload>(line);
+});
@@ -0,0 +1,59 @@
+use std::hash::Hash;
+use serde::{Serialize, Deserialize};
+use crate::merkle::hash;
+
+/// Bloom filter; a compact set that may report items it does not contain,
+/// but never misses the items it does
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bloom {
+    /// The bit array
+    bits: Vec<u64>,
+
+    /// Amount of bits set per item
+    hashes: u32,
+
+    /// Mixed into the hashes, so that differently salted filters have
+    /// different false positives
+    salt: u64,
+}
+
+impl Bloom {
+    /// Build an empty filter sized for `items` items at the false positive
+    /// rate `fp_rate`, with its hashes salted by `salt`
+    pub fn new(items: usize, fp_rate: f64, salt: u64) -> Self {
+        let items = items.max(1) as f64;
+        let ln2 = core::f64::consts::LN_2;
+        let bits = (-items * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.);
+        let hashes = (bits / items * ln2).round().clamp(1., 16.);
+
+        Self {
+            bits:   vec![0; (bits as usize).div_ceil(64)],
+            hashes: hashes as u32,
+            salt,
+        }
+    }
+
+    /// Indices of the bits of `item`, by double hashing
+    fn indices(&self, item: &impl Hash) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 64;
+        let h1 = hash(&(self.salt, item));
+        let h2 = hash(&(h1, item)) | 1;
+        (0..self.hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Add `item` to the filter
+    pub fn insert(&mut self, item: &impl Hash) {
+        if self.bits.is_empty() { return; }
+        let indices: Vec<_> = self.indices(item).collect();
+        for idx in indices {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    /// Returns `true` if `item` may be in the filter
+    pub fn contains(&self, item: &impl Hash) -> bool {
+        !self.bits.is_empty() && self.indices(item)
+            .all(|idx| self.bits[idx / 64] & (1 << (idx % 64)) != 0)
+    }
+}
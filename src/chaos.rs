@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use crate::rng::Rng;
+
+/// Environment variable holding the chaos configuration, e.g.
+/// `drop=0.05,dup=0.01,delay=0.1,delay_ms=200,seed=7`
+pub const CHAOS_ENV: &str = "MAELSTROM_CHAOS";
+
+/// What faults the node injects into its own outgoing messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    /// Probability of a message getting dropped
+    pub drop: f64,
+
+    /// Probability of a message getting sent twice
+    pub dup: f64,
+
+    /// Probability of a message getting delayed
+    pub delay: f64,
+
+    /// Upper bound of the delay of a delayed message
+    pub max_delay: Duration,
+
+    /// Seed of the fault decisions. Picked from the time if not given
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            drop:      0.,
+            dup:       0.,
+            delay:     0.,
+            max_delay: Duration::from_millis(100),
+            seed:      None,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Parse the configuration out of `MAELSTROM_CHAOS`, if it's set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        std::env::var(CHAOS_ENV).ok().map(|spec| Self::parse(&spec))
+            .transpose()
+    }
+
+    /// Parse the configuration out of a `key=value,...` list
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        for pair in spec.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("chaos: expected key=value, \
+                    got `{pair}`"))?;
+            match key.trim() {
+                "drop"     => config.drop = value.parse()?,
+                "dup"      => config.dup = value.parse()?,
+                "delay"    => config.delay = value.parse()?,
+                "delay_ms" => config.max_delay =
+                    Duration::from_millis(value.parse()?),
+                "seed"     => config.seed = Some(value.parse()?),
+                _ => anyhow::bail!("chaos: unknown key `{key}`"),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Writer injecting the faults of a `ChaosConfig` into the messages that
+/// pass through it. Without a configuration, everything is passed through
+/// untouched
+pub struct Chaos<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    config: Option<ChaosConfig>,
+
+    rng: Rng,
+
+    /// Partially written line
+    buf: Vec<u8>,
+
+    /// Delayed lines keyed by the time they are due and a sequence number
+    /// keeping the keys unique
+    delayed: BTreeMap<(Instant, u64), Vec<u8>>,
+
+    /// Sequence number of the next delayed line
+    seq: u64,
+}
+
+impl<W: Write> Chaos<W> {
+    pub fn new(out: W, config: Option<ChaosConfig>) -> Self {
+        let rng = config.as_ref()
+            .and_then(|config| config.seed)
+            .map(Rng::new)
+            .unwrap_or_else(Rng::from_time);
+
+        Self {
+            out,
+            config,
+            rng,
+            buf: Vec::new(),
+            delayed: BTreeMap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Amount of messages currently delayed
+    pub fn delayed(&self) -> usize {
+        self.delayed.len()
+    }
+
+    /// When the next delayed message is due
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.delayed.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Write out the delayed messages that are due. They're left for the
+    /// next flush, along with whatever else is written by then
+    pub fn release_due(&mut self) -> std::io::Result<()> {
+        let now = Instant::now();
+        while let Some(entry) = self.delayed.first_entry() {
+            if entry.key().0 > now { break; }
+            self.out.write_all(&entry.remove())?;
+        }
+        Ok(())
+    }
+
+    /// Write out all the delayed messages right away
+    pub fn release_all(&mut self) -> std::io::Result<()> {
+        while let Some((_, line)) = self.delayed.pop_first() {
+            self.out.write_all(&line)?;
+        }
+        self.out.flush()
+    }
+
+    /// Decide the fate of the complete `line`
+    fn inject(&mut self, line: Vec<u8>) -> std::io::Result<()> {
+        let config = self.config.as_ref().expect("chaos is configured");
+
+        if self.rng.chance(config.drop) { return Ok(()); }
+        let copies = if self.rng.chance(config.dup) { 2 } else { 1 };
+
+        for _ in 0..copies {
+            if self.rng.chance(config.delay) {
+                let max = config.max_delay.as_micros() as u64;
+                let at = Instant::now() +
+                    Duration::from_micros(self.rng.below(max + 1));
+                self.delayed.insert((at, self.seq), line.clone());
+                self.seq += 1;
+            } else {
+                self.out.write_all(&line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Chaos<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.config.is_none() {
+            return self.out.write(data);
+        }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.inject(line)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
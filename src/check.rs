@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use crate::history::{History, Op, OpType};
+
+/// Properties a history can be checked for, per workload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checker {
+    /// Every acknowledged broadcast shows up in the final reads of all nodes
+    /// and no read returns a value that was never broadcast
+    Broadcast,
+
+    /// All generated IDs are unique
+    UniqueIds,
+
+    /// Every counter read lies between the acknowledged and the attempted
+    /// additions
+    Counter,
+}
+
+impl Checker {
+    /// Get the checker of the workload with the Maelstrom name `name`
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "broadcast"  => Self::Broadcast,
+            "unique-ids" => Self::UniqueIds,
+            "g-counter"  => Self::Counter,
+            _ => anyhow::bail!("no checker for workload `{name}`"),
+        })
+    }
+
+    /// Check the history `ops`
+    pub fn check(&self, ops: &[Op]) -> Verdict {
+        let ops = Pairs::new(ops);
+        match self {
+            Self::Broadcast => check_broadcast(&ops),
+            Self::UniqueIds => check_unique_ids(&ops),
+            Self::Counter   => check_counter(&ops),
+        }
+    }
+}
+
+/// Outcome of a check
+#[derive(Debug, Default)]
+pub struct Verdict {
+    /// Amount of completed operations that were checked
+    pub checked: usize,
+
+    /// Everything that violates the checked properties
+    pub problems: Vec<String>,
+}
+
+impl Verdict {
+    /// Returns `true` if the history has none of the looked for problems
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl core::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{problem}")?;
+        }
+        write!(f, "{} operations checked: {}", self.checked,
+            if self.is_valid() { "valid" } else { "INVALID" })
+    }
+}
+
+/// Invocations of a history paired with their completions
+struct Pairs<'a> {
+    /// All invocations in the order they were recorded, with their
+    /// completions if any
+    ops: Vec<(&'a Op, Option<&'a Op>)>,
+}
+
+impl<'a> Pairs<'a> {
+    fn new(history: &'a [Op]) -> Self {
+        let mut ops = Vec::new();
+        let mut pending: HashMap<(&str, Option<usize>), usize> =
+            HashMap::new();
+
+        for op in history {
+            let key = (op.process.as_str(), op.msg_id);
+            if op.kind == OpType::Invoke {
+                pending.insert(key, ops.len());
+                ops.push((op, None));
+            } else if let Some(idx) = pending.remove(&key) {
+                ops[idx].1 = Some(op);
+            }
+        }
+
+        Self { ops }
+    }
+
+    /// Iterate over the successfully completed operations with function `f`
+    fn ok(&self, f: &'a str) -> impl Iterator<Item = (&'a Op, &'a Op)> + '_ {
+        self.ops.iter()
+            .filter(move |(invoke, _)| invoke.f == f)
+            .filter_map(|(invoke, done)| match done {
+                Some(done) if done.kind == OpType::Ok => Some((*invoke, *done)),
+                _ => None,
+            })
+    }
+
+    /// Iterate over the invocations with function `f` that didn't fail
+    fn attempted(&self, f: &'a str) -> impl Iterator<Item = &'a Op> + '_ {
+        self.ops.iter()
+            .filter(move |(invoke, done)| invoke.f == f &&
+                done.is_none_or(|done| done.kind != OpType::Fail))
+            .map(|(invoke, _)| *invoke)
+    }
+}
+
+/// Get the messages returned by the broadcast read `done`
+fn read_messages(done: &Op) -> impl Iterator<Item = u64> + '_ {
+    done.value["messages"].as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|msg| msg.as_u64())
+}
+
+fn check_broadcast(ops: &Pairs) -> Verdict {
+    let mut verdict = Verdict::default();
+    let message = |op: &Op| op.value["message"].as_u64();
+
+    let attempted: HashSet<u64> = ops.attempted("broadcast")
+        .filter_map(message)
+        .collect();
+    let acked: Vec<(u64, u128)> = ops.ok("broadcast")
+        .filter_map(|(invoke, done)| Some((message(invoke)?, done.time)))
+        .collect();
+    verdict.checked += acked.len();
+
+    // The last read of every node
+    let mut final_reads: HashMap<&str, (&Op, &Op)> = HashMap::new();
+    for (invoke, done) in ops.ok("read") {
+        verdict.checked += 1;
+        for value in read_messages(done).filter(|v| !attempted.contains(v)) {
+            verdict.problems.push(format!("{} read {value}, which was never \
+                broadcast", done.node));
+        }
+
+        let last = final_reads.entry(&done.node).or_insert((invoke, done));
+        if last.1.time < done.time { *last = (invoke, done); }
+    }
+
+    // Everything acknowledged before the final read was issued must be there
+    for (node, (invoke, done)) in final_reads {
+        let read: HashSet<u64> = read_messages(done).collect();
+        let mut lost: Vec<u64> = acked.iter()
+            .filter(|(value, acked)| *acked < invoke.time &&
+                !read.contains(value))
+            .map(|(value, _)| *value)
+            .collect();
+
+        if !lost.is_empty() {
+            lost.sort_unstable();
+            verdict.problems.push(format!("{node} lost {} acknowledged \
+                messages: {lost:?}", lost.len()));
+        }
+    }
+
+    verdict
+}
+
+fn check_unique_ids(ops: &Pairs) -> Verdict {
+    let mut verdict = Verdict::default();
+    let mut seen: HashMap<String, &Op> = HashMap::new();
+
+    for (_, done) in ops.ok("generate") {
+        verdict.checked += 1;
+        let id = done.value["id"].to_string();
+        if let Some(first) = seen.insert(id.clone(), done) {
+            verdict.problems.push(format!("ID {id} was generated by both {} \
+                and {}", first.node, done.node));
+        }
+    }
+
+    verdict
+}
+
+fn check_counter(ops: &Pairs) -> Verdict {
+    let mut verdict = Verdict::default();
+    let delta = |op: &Op| op.value["delta"].as_i64().unwrap_or(0);
+
+    let acked: Vec<(u128, i64)> = ops.ok("add")
+        .map(|(invoke, done)| (done.time, delta(invoke)))
+        .collect();
+    let attempted: Vec<(u128, i64)> = ops.attempted("add")
+        .map(|invoke| (invoke.time, delta(invoke)))
+        .collect();
+    verdict.checked += acked.len();
+
+    for (invoke, done) in ops.ok("read") {
+        verdict.checked += 1;
+        let Some(value) = done.value["value"].as_i64() else {
+            verdict.problems.push(format!("{} returned a read without a \
+                value", done.node));
+            continue;
+        };
+
+        // Additions acknowledged before the read started must be visible and
+        // additions started after it completed can't be
+        let lower: i64 = acked.iter()
+            .filter(|(time, _)| *time < invoke.time)
+            .map(|(_, delta)| delta)
+            .sum();
+        let upper: i64 = attempted.iter()
+            .filter(|(time, _)| *time < done.time)
+            .map(|(_, delta)| delta)
+            .sum();
+
+        if value < lower || value > upper {
+            verdict.problems.push(format!("{} read {value}, outside of the \
+                possible range [{lower}, {upper}]", done.node));
+        }
+    }
+
+    verdict
+}
+
+pub fn main(args: &[String]) -> anyhow::Result<()> {
+    let (checker, path) = match args {
+        [flag, workload, path] if flag == "--workload" =>
+            (Checker::from_name(workload)?, path),
+        _ => anyhow::bail!("usage: check --workload <workload> <history>"),
+    };
+
+    let verdict = checker.check(&History::read(path)?);
+    println!("{verdict}");
+    anyhow::ensure!(verdict.is_valid(), "the history is invalid");
+    Ok(())
+}
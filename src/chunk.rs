@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::message::{Message, Body};
+
+/// Longest message put back together out of chunks. Chunks claiming to be
+/// of a longer one are rejected, rather than waited for
+pub const MAX_MESSAGE: usize = 64 << 20;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Payload of a chunk of a message; `data` is the piece `index` of the
+/// `count` the serialized message was split into
+enum Chunked {
+    Chunk { chunk_id: u64, index: usize, count: usize, data: String },
+}
+
+/// Just enough of a message to tell who it's between
+#[derive(Deserialize)]
+struct Peek {
+    src: String,
+    dest: String,
+}
+
+/// Bytes `c` takes up in a JSON string, escaped
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\u{8}' | '\u{c}' | '\n' | '\r' | '\t' => 2,
+        '\0'..='\u{1f}' => 6,
+        _ => c.len_utf8(),
+    }
+}
+
+/// Split `line` into pieces taking up at most `size` bytes each once escaped
+/// into a JSON string, without splitting any character
+fn split(line: &str, size: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let (mut start, mut len) = (0, 0);
+    for (idx, c) in line.char_indices() {
+        // A character longer than the whole piece still has to go somewhere
+        if len + escaped_len(c) > size && idx > start {
+            pieces.push(&line[start..idx]);
+            (start, len) = (idx, 0);
+        }
+        len += escaped_len(c);
+    }
+    if start < line.len() {
+        pieces.push(&line[start..]);
+    }
+    pieces
+}
+
+/// Writer splitting the messages to the `nodes` longer than `size` bytes into
+/// chunks of at most `size` bytes each, envelope included, which the
+/// receiving `Chunks` put back together. A size too small to fit the
+/// envelope in is overshot. Messages to clients are never chunked, they
+/// couldn't put them back together. Without a size, everything is passed
+/// through untouched
+pub struct Chunker<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    size: Option<usize>,
+
+    nodes: HashSet<String>,
+
+    /// ID of the next chunked message
+    next_id: u64,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Chunker<W> {
+    pub fn new(out: W, size: Option<usize>, nodes: &[String]) -> Self {
+        Self {
+            out,
+            size,
+            nodes: nodes.iter().cloned().collect(),
+            next_id: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Write the complete `line`, in chunks if it's too long
+    fn chunk_line(&mut self, size: usize, line: &[u8])
+            -> std::io::Result<()> {
+        if line.len() <= size { return self.out.write_all(line); }
+        let peek = serde_json::from_slice::<Peek>(line).ok()
+            .filter(|peek| self.nodes.contains(&peek.dest));
+        let (Some(peek), Ok(text)) = (peek, std::str::from_utf8(line)) else {
+            return self.out.write_all(line);
+        };
+
+        self.next_id += 1;
+        let chunk = |index, count, data: &str| Message {
+            src:  peek.src.clone(),
+            dst:  peek.dest.clone(),
+            body: Body { id: None, reply_id: None, deadline: None,
+                trace: None,
+                payload: Chunked::Chunk { chunk_id: self.next_id, index,
+                    count, data: data.into() },
+            },
+        };
+
+        // The envelope of a chunk, along with its newline, at its longest;
+        // there are never more chunks than bytes
+        let envelope = serde_json::to_vec(&chunk(line.len(), line.len(), ""))?
+            .len() + 1;
+        let pieces = split(text.trim_end_matches('\n'),
+            size.saturating_sub(envelope));
+        let count = pieces.len();
+        for (index, data) in pieces.into_iter().enumerate() {
+            serde_json::to_writer(&mut self.out, &chunk(index, count, data))?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Chunker<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let Some(size) = self.size else { return self.out.write(data); };
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.chunk_line(size, &line)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Chunks of a message received so far
+#[derive(Debug)]
+struct Partial {
+    /// When the first of them was received
+    started: Instant,
+
+    /// Amount of pieces the message was split into
+    count: usize,
+
+    /// The pieces received so far, by their index, and their total length
+    pieces: BTreeMap<usize, String>,
+    len: usize,
+}
+
+/// Chunks received from the other nodes, put back together into the
+/// messages they were split from. Those of a message not received whole
+/// within `timeout` are given up on; it was likely lost to a partition
+#[derive(Debug)]
+pub struct Chunks {
+    /// Most chunks a message may be split into, by the chunks being at
+    /// most `size` bytes
+    most: usize,
+
+    timeout: Duration,
+
+    /// Messages partially received, by their sender and chunk ID
+    partial: HashMap<(String, u64), Partial>,
+}
+
+impl Chunks {
+    pub fn new(size: Option<usize>, timeout: Duration) -> Self {
+        Self {
+            most: size.map_or(MAX_MESSAGE, |size| MAX_MESSAGE.div_ceil(size)),
+            timeout,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Take the received `line` in. Returns the line itself if it's not a
+    /// chunk, the whole message once its last chunk was received and `None`
+    /// until then. Chunks that can't be of a message are rejected
+    pub fn reassemble(&mut self, line: String)
+            -> anyhow::Result<Option<String>> {
+        // Only look closer at what may be a chunk
+        if !line.contains("\"chunk\"") { return Ok(Some(line)); }
+        let Ok(msg) = serde_json::from_str::<Message<Chunked>>(&line) else {
+            return Ok(Some(line));
+        };
+        let Chunked::Chunk { chunk_id, index, count, data } = msg.body.payload;
+        anyhow::ensure!(index < count,
+            "chunk {index} of {count} of message {chunk_id} from {}", msg.src);
+        anyhow::ensure!(count <= self.most, "message {chunk_id} from {} \
+            split into {count} chunks, more than {}", msg.src, self.most);
+
+        let now = Instant::now();
+        let timeout = self.timeout;
+        self.partial.retain(|_, partial| now - partial.started < timeout);
+        let key = (msg.src, chunk_id);
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| {
+            Partial { started: now, count, pieces: BTreeMap::new(), len: 0 }
+        });
+        anyhow::ensure!(partial.count == count,
+            "chunks of message {chunk_id} from {} disagree on their count",
+            key.0);
+
+        // Chunks may be duplicated on the way
+        let len = data.len();
+        if let Some(earlier) = partial.pieces.insert(index, data) {
+            partial.len -= earlier.len();
+        }
+        partial.len += len;
+        if partial.len > MAX_MESSAGE {
+            self.partial.remove(&key);
+            anyhow::bail!("message {chunk_id} from {} longer than {} bytes",
+                key.0, MAX_MESSAGE);
+        }
+        if partial.pieces.len() < count { return Ok(None); }
+
+        let partial = self.partial.remove(&key).expect("partial was there");
+        Ok(Some(partial.pieces.into_values().collect()))
+    }
+
+    /// Amount of messages partially received
+    pub fn len(&self) -> usize {
+        self.partial.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partial.is_empty()
+    }
+}
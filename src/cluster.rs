@@ -0,0 +1,282 @@
+use std::io::{Read, Write, BufRead, BufReader};
+use std::process::{Command, Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use crate::message::{Message, Body};
+
+/// A message of any service, with the payload left uninterpreted
+pub type RawMessage = Message<Value>;
+
+/// ID the cluster itself uses when talking to the nodes
+pub const ADMIN: &str = "admin";
+
+/// Command running `service` out of this very binary
+pub fn self_command(service: &str) -> anyhow::Result<Vec<String>> {
+    Ok(vec![
+        std::env::current_exe()?.to_string_lossy().into_owned(),
+        service.into(),
+    ])
+}
+
+/// Something that happened on one of the inputs of the cluster
+enum Event {
+    /// A line was read, tagged with the index of the node that printed it.
+    /// Lines from attached inputs carry no index
+    Line(Option<usize>, String),
+
+    /// The node at this index and generation closed its output
+    Exited(usize, usize),
+}
+
+/// Forward every line of `input` through `tx`, tagged with `idx`.
+/// `generation` is reported once the input is exhausted
+fn forward_lines(input: impl Read + Send + 'static, idx: Option<usize>,
+        generation: usize, tx: Sender<Event>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(input).lines() {
+            let Ok(line) = line else { break; };
+            if tx.send(Event::Line(idx, line)).is_err() { return; }
+        }
+        if let Some(idx) = idx {
+            let _ = tx.send(Event::Exited(idx, generation));
+        }
+    })
+}
+
+/// A single running node process
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+
+    /// Unique number of this process, telling apart restarts of a node
+    generation: usize,
+}
+
+/// Cluster of node processes running locally, talking over their stdio
+pub struct Cluster {
+    /// IDs of the nodes in the cluster, index-aligned with the processes
+    pub node_ids: Vec<String>,
+
+    /// Command spawning a node
+    cmd: Vec<String>,
+
+    /// The node processes. Killed nodes have no process
+    procs: Vec<Option<Process>>,
+
+    /// Generation of the next spawned process
+    next_generation: usize,
+
+    /// Lines printed by the nodes and read from the attached inputs
+    events: Receiver<Event>,
+
+    /// Sender of `events`, for attaching more inputs
+    tx: Sender<Event>,
+}
+
+impl Cluster {
+    /// Spawn `nodes` copies of `cmd` named `n0`, `n1`...
+    pub fn spawn(cmd: &[String], nodes: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(!cmd.is_empty(), "empty node command");
+        let (tx, events) = mpsc::channel();
+
+        let mut cluster = Self {
+            node_ids: (0..nodes).map(|idx| format!("n{idx}")).collect(),
+            cmd: cmd.to_vec(),
+            procs: (0..nodes).map(|_| None).collect(),
+            next_generation: 0,
+            events,
+            tx,
+        };
+        for idx in 0..nodes {
+            cluster.spawn_node(idx)?;
+        }
+
+        Ok(cluster)
+    }
+
+    /// Start a fresh process for the node at `idx`
+    fn spawn_node(&mut self, idx: usize) -> anyhow::Result<()> {
+        let mut child = Command::new(&self.cmd[0])
+            .args(&self.cmd[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        // Forward everything the node prints to the receiver
+        let stdout = child.stdout.take().expect("stdout is piped");
+        forward_lines(stdout, Some(idx), generation, self.tx.clone());
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        self.procs[idx] = Some(Process { child, stdin, generation });
+        Ok(())
+    }
+
+    /// Receive the lines of `input` alongside the lines printed by the
+    /// nodes. The returned handle finishes once `input` is exhausted
+    pub fn attach_input(&self, input: impl Read + Send + 'static)
+            -> JoinHandle<()> {
+        forward_lines(input, None, 0, self.tx.clone())
+    }
+
+    /// Send `init` to the node at `idx`
+    fn send_init(&mut self, idx: usize) -> anyhow::Result<()> {
+        let node_id = self.node_ids[idx].clone();
+        self.send(&Message {
+            src: ADMIN.into(),
+            dst: node_id.clone(),
+            body: Body {
+                id: Some(idx),
+                reply_id: None,
+                deadline: None,
+                trace: None,
+                payload: serde_json::json!({
+                    "type":     "init",
+                    "node_id":  node_id,
+                    "node_ids": self.node_ids,
+                }),
+            },
+        })
+    }
+
+    /// Send `init` to every node and wait until all of them reply
+    pub fn init(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        for idx in 0..self.node_ids.len() {
+            self.send_init(idx)?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.node_ids.len();
+        while pending > 0 {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.recv_timeout(left)? {
+                Some(msg) if msg.body.payload["type"] == "init_ok" =>
+                    pending -= 1,
+                Some(msg) => anyhow::bail!("unexpected message during init: \
+                    {}", serde_json::to_string(&msg)?),
+                None => anyhow::bail!("{pending} nodes did not reply to init"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Kill the process of the node at `idx`. Messages sent to it are lost
+    /// until it's restarted
+    pub fn kill(&mut self, idx: usize) {
+        if let Some(mut proc) = self.procs[idx].take() {
+            let _ = proc.child.kill();
+            let _ = proc.child.wait();
+        }
+    }
+
+    /// Replace the process of the node at `idx` with a fresh one and send it
+    /// `init`. Its reply is swallowed by `route`
+    pub fn restart(&mut self, idx: usize) -> anyhow::Result<()> {
+        self.kill(idx);
+        self.spawn_node(idx)?;
+        self.send_init(idx)
+    }
+
+    /// Returns `true` if `id` belongs to a node of this cluster
+    pub fn is_node(&self, id: &str) -> bool {
+        self.node_idx(id).is_some()
+    }
+
+    /// Get the index of the node `id`
+    pub fn node_idx(&self, id: &str) -> Option<usize> {
+        self.node_ids.iter().position(|node| node == id)
+    }
+
+    /// Send `msg` to the node it is addressed to
+    pub fn send(&mut self, msg: &RawMessage) -> anyhow::Result<()> {
+        let idx = self.node_idx(&msg.dst)
+            .ok_or_else(|| anyhow::anyhow!("no such node: {}", msg.dst))?;
+        self.send_raw(idx, &serde_json::to_string(msg)?);
+        Ok(())
+    }
+
+    /// Write the already serialized `line` to the node at `idx`. Just like on
+    /// a real network, lines sent to dead nodes are lost
+    pub fn send_raw(&mut self, idx: usize, line: &str) {
+        let Some(proc) = &mut self.procs[idx] else { return; };
+
+        // A failed write means the node is dying, which is reported once its
+        // output closes
+        let _ = proc.stdin.write_all(line.as_bytes())
+            .and_then(|_| proc.stdin.write_all(b"\n"));
+    }
+
+    /// Wait at most `timeout` for a line printed by any of the nodes or read
+    /// from an attached input. Returns the index of the node and the line
+    pub fn recv_raw(&mut self, timeout: Duration)
+            -> anyhow::Result<Option<(Option<usize>, String)>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(left) {
+                Ok(Event::Line(idx, line)) => return Ok(Some((idx, line))),
+                Ok(Event::Exited(idx, generation)) => {
+                    // Killed and restarted processes are expected to exit
+                    let current = self.procs[idx].as_ref()
+                        .map(|proc| proc.generation);
+                    if current != Some(generation) { continue; }
+
+                    eprintln!("{} exited", self.node_ids[idx]);
+                    self.kill(idx);
+                    anyhow::ensure!(self.procs.iter().any(Option::is_some),
+                        "all nodes exited");
+                },
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) =>
+                    unreachable!("the cluster holds a sender"),
+            }
+        }
+    }
+
+    /// Wait at most `timeout` for a message sent by any of the nodes or read
+    /// from an attached input. Lines that are not valid messages are reported
+    /// and skipped
+    pub fn recv_timeout(&mut self, timeout: Duration)
+            -> anyhow::Result<Option<RawMessage>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let Some((idx, line)) = self.recv_raw(left)? else {
+                return Ok(None);
+            };
+            match serde_json::from_str(&line) {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(e) => eprintln!("{}: invalid message {line:?}: {e}",
+                    idx.map_or("input", |idx| &self.node_ids[idx])),
+            }
+        }
+    }
+
+    /// Deliver `msg` if it is addressed to a node in the cluster. Messages
+    /// for anyone else (clients) are handed back to the caller, except for
+    /// replies to the cluster itself which are dropped
+    pub fn route(&mut self, msg: RawMessage)
+            -> anyhow::Result<Option<RawMessage>> {
+        if self.is_node(&msg.dst) {
+            self.send(&msg)?;
+            Ok(None)
+        } else if msg.dst == ADMIN {
+            Ok(None)
+        } else {
+            Ok(Some(msg))
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for idx in 0..self.procs.len() {
+            self.kill(idx);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::rc::Rc;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::Message;
+
+/// Name nodes announce the MessagePack encoding by
+const MSGPACK: &str = "msgpack";
+
+/// Encoding of the messages between the nodes. Clients are always sent JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+
+    /// Base64-wrapped MessagePack payloads, for the nodes that announced they
+    /// decode them as well
+    MsgPack,
+}
+
+impl Codec {
+    /// Parse the codec out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "json" => Self::Json,
+            MSGPACK if cfg!(feature = "msgpack") => Self::MsgPack,
+            MSGPACK => anyhow::bail!("this binary was built without the \
+                `msgpack` feature"),
+            _ => anyhow::bail!("unknown codec `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json    => "json",
+            Self::MsgPack => MSGPACK,
+        }
+    }
+
+    /// Names of the encodings the codec decodes, besides JSON
+    pub fn announced(&self) -> Vec<String> {
+        match self {
+            Self::Json => Vec::new(),
+            Self::MsgPack => vec![MSGPACK.into()],
+        }
+    }
+
+    /// Returns `true` if a node announcing `codecs` decodes what we'd send it
+    pub fn understood_by(&self, codecs: &[String]) -> bool {
+        *self == Self::MsgPack && codecs.iter().any(|codec| codec == MSGPACK)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Payload of an encoded message. The IDs of the message stay outside, where
+/// Maelstrom and the outbox can see them
+enum Encoded {
+    Packed { data: String },
+}
+
+/// Nodes that announced they decode MessagePack. The runtime hears the
+/// announcements and the `Packer` packs the messages sent to them
+pub type Peers = Rc<RefCell<HashSet<String>>>;
+
+/// Encode the payload of `msg` as base64-wrapped MessagePack
+#[cfg(feature = "msgpack")]
+pub fn pack(msg: Message<Value>) -> anyhow::Result<String> {
+    use base64::Engine;
+    use crate::message::Body;
+
+    let payload = rmp_serde::to_vec_named(&msg.body.payload)?;
+    let data = base64::engine::general_purpose::STANDARD.encode(payload);
+    let body = Body {
+        id:       msg.body.id,
+        reply_id: msg.body.reply_id,
+        deadline: msg.body.deadline,
+        trace:    msg.body.trace,
+        payload:  Encoded::Packed { data },
+    };
+    Ok(serde_json::to_string(&Message { src: msg.src, dst: msg.dst, body })?)
+}
+
+/// Decode the packed message `msg` back into JSON
+#[cfg(feature = "msgpack")]
+fn unpack_message(msg: Message<Encoded>) -> anyhow::Result<String> {
+    use base64::Engine;
+    use crate::message::Body;
+
+    let Encoded::Packed { data } = msg.body.payload;
+    let payload = base64::engine::general_purpose::STANDARD.decode(data)?;
+    let body = Body {
+        id:       msg.body.id,
+        reply_id: msg.body.reply_id,
+        deadline: msg.body.deadline,
+        trace:    msg.body.trace,
+        payload:  rmp_serde::from_slice::<Value>(&payload)?,
+    };
+    Ok(serde_json::to_string(&Message { src: msg.src, dst: msg.dst, body })?)
+}
+
+/// Without the `msgpack` feature there's nothing to pack the messages with
+#[cfg(not(feature = "msgpack"))]
+pub fn pack(_msg: Message<Value>) -> anyhow::Result<String> {
+    anyhow::bail!("this binary was built without the `msgpack` feature")
+}
+
+#[cfg(not(feature = "msgpack"))]
+fn unpack_message(_msg: Message<Encoded>) -> anyhow::Result<String> {
+    anyhow::bail!("received a packed message, but this binary was built \
+        without the `msgpack` feature")
+}
+
+/// Decode `line` back into JSON if it's a packed message
+pub fn unpack(line: &str) -> anyhow::Result<Option<String>> {
+    // Only look closer at what may be packed
+    if !line.contains("\"packed\"") { return Ok(None); }
+    match serde_json::from_str::<Message<Encoded>>(line) {
+        Ok(msg) => unpack_message(msg).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Writer packing the messages sent to the `Peers`, passing the rest through
+/// untouched
+pub struct Packer<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    peers: Peers,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Packer<W> {
+    pub fn new(out: W, peers: Peers) -> Self {
+        Self { out, peers, buf: Vec::new() }
+    }
+
+    /// Write the complete `line`, packed if it's sent to one of the peers
+    fn pack_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let msg = serde_json::from_slice::<Message<Value>>(line).ok()
+            .filter(|msg| self.peers.borrow().contains(&msg.dst));
+        let Some(msg) = msg else {
+            return self.out.write_all(line);
+        };
+
+        let packed = pack(msg).map_err(std::io::Error::other)?;
+        self.out.write_all(packed.as_bytes())?;
+        self.out.write_all(b"\n")
+    }
+}
+
+impl<W: Write> Write for Packer<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.peers.borrow().is_empty() && self.buf.is_empty() {
+            return self.out.write(data);
+        }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.pack_line(&line)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,373 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::topology::Strategy;
+use crate::codec::Codec;
+use crate::services::broadcast::{GossipFilter, ReadOrder};
+use crate::services::vclock_kv::SiblingMerge;
+use crate::services::uuid::IdSource;
+
+/// Verbosity of the messages the nodes log to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    /// Parse the level out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "error" => Self::Error,
+            "warn"  => Self::Warn,
+            "info"  => Self::Info,
+            "debug" => Self::Debug,
+            _ => anyhow::bail!("unknown log level `{name}`"),
+        })
+    }
+}
+
+/// Options of the config, as `(flag, environment variable)`
+const OPTIONS: &[(&str, &str)] = &[
+    ("gossip-interval-ms", "MAELSTROM_GOSSIP_INTERVAL_MS"),
+    ("gossip-fanout",      "MAELSTROM_GOSSIP_FANOUT"),
+    ("topology",           "MAELSTROM_TOPOLOGY"),
+    ("gossip-filter",      "MAELSTROM_GOSSIP_FILTER"),
+    ("read-order",         "MAELSTROM_READ_ORDER"),
+    ("primaries",          "MAELSTROM_PRIMARIES"),
+    ("sibling-merge",      "MAELSTROM_SIBLING_MERGE"),
+    ("batch-window-ms",    "MAELSTROM_BATCH_WINDOW_MS"),
+    ("retry-timeout-ms",   "MAELSTROM_RETRY_TIMEOUT_MS"),
+    ("echo-delay-ms",      "MAELSTROM_ECHO_DELAY_MS"),
+    ("echo-jitter-ms",     "MAELSTROM_ECHO_JITTER_MS"),
+    ("id-source",          "MAELSTROM_ID_SOURCE"),
+    ("id-audit",           "MAELSTROM_ID_AUDIT"),
+    ("rate-limit",         "MAELSTROM_RATE_LIMIT"),
+    ("rate-burst",         "MAELSTROM_RATE_BURST"),
+    ("dedupe-window-ms",   "MAELSTROM_DEDUPE_WINDOW_MS"),
+    ("stream-retry-ms",    "MAELSTROM_STREAM_RETRY_MS"),
+    ("stream-window",      "MAELSTROM_STREAM_WINDOW"),
+    ("workers",            "MAELSTROM_WORKERS"),
+    ("max-queue",          "MAELSTROM_MAX_QUEUE"),
+    ("codec",              "MAELSTROM_CODEC"),
+    ("chunk-size",         "MAELSTROM_CHUNK_SIZE"),
+    ("chunk-timeout-ms",   "MAELSTROM_CHUNK_TIMEOUT_MS"),
+    ("storage-dir",        "MAELSTROM_STORAGE_DIR"),
+    ("restore",            "MAELSTROM_RESTORE"),
+    ("secret",             "MAELSTROM_SECRET"),
+    ("log",                "MAELSTROM_LOG"),
+];
+
+/// Tunables of the services, handed to every node on `from_init`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// How often the replicating services gossip with their peers
+    pub gossip_interval: Duration,
+
+    /// Most neighbors gossiped with per round, the neediest first. Without
+    /// it, every neighbor is gossiped with every round
+    pub gossip_fanout: Option<usize>,
+
+    /// Overlay gossiped over, in place of the topology Maelstrom hands out
+    /// unless it's the given one
+    pub topology: Strategy,
+
+    /// What the gossip reads of the broadcast tell about the messages their
+    /// sender has already seen, so that the replies skip them
+    pub gossip_filter: GossipFilter,
+
+    /// Order of the messages the broadcast read replies return
+    pub read_order: ReadOrder,
+
+    /// Nodes the KV applies the writes on. The rest are read-only replicas,
+    /// serving reads and forwarding the writes to the primaries. Every node
+    /// is a primary without it
+    pub primaries: Vec<String>,
+
+    /// How the reads of the vector clock KV resolve concurrent siblings
+    pub sibling_merge: SiblingMerge,
+
+    /// How long requests are collected into a batch before it's acted on
+    pub batch_window: Duration,
+
+    /// How long to wait for a reply before retrying or giving up
+    pub retry_timeout: Duration,
+
+    /// How long the echo service holds its replies back, plus up to
+    /// `echo_jitter` more at random. Makes for a known-slow service to test
+    /// latency measurements and deadlines against
+    pub echo_delay: Duration,
+    pub echo_jitter: Duration,
+
+    /// Where the IDs the unique ID service generates come from
+    pub id_source: IdSource,
+
+    /// Whether the unique ID service audits for collisions. The nodes gossip
+    /// the IDs they generated and check them against their own, logging
+    /// every ID generated twice. Meant for debugging the generators; every
+    /// ID is remembered
+    pub id_audit: bool,
+
+    /// Requests per second a node sends to each other node, up to
+    /// `rate_burst` at once; those over it are deferred. Keeps aggressive
+    /// gossip and retries within the messages-per-op budget. Unlimited
+    /// without it
+    pub rate_limit: Option<u32>,
+    pub rate_burst: u32,
+
+    /// How long a request to another node suppresses repeats of itself, so
+    /// that gossip triggered twice in a row is sent once. Keep it well below
+    /// the gossip interval and retry timeout. Nothing is suppressed if zero
+    pub dedupe_window: Duration,
+
+    /// How long a message to another node goes unacknowledged before it's
+    /// sent again. With it, the messages between the nodes are numbered and
+    /// each is handed to the receiving service exactly once, lost and
+    /// duplicated ones alike. Delivered as sent, at most once, without it
+    pub stream_retry: Option<Duration>,
+
+    /// Most messages to another node that go unacknowledged at once, along
+    /// with `stream_retry`. The rest are held back and coalesced into
+    /// batches, so that a slow or cut off node doesn't pile up retries.
+    /// Unlimited without it
+    pub stream_window: Option<usize>,
+
+    /// Threads the services run on the worker pool handle messages on
+    pub workers: usize,
+
+    /// Most messages waiting to be handled, or being handled on the worker
+    /// pool, before client requests are refused as temporarily unavailable
+    /// rather than left to time out. Never refused without it
+    pub max_queue: Option<usize>,
+
+    /// How the messages to the other nodes are encoded, for those that
+    /// announced they decode it
+    pub codec: Codec,
+
+    /// Bytes past which the messages to the other nodes are split into
+    /// chunks, put back together by the receiver. Keeps snapshots and large
+    /// anti-entropy payloads within line length limits
+    pub chunk_size: Option<usize>,
+
+    /// How long the chunks of a message are waited for. Past it, the chunks
+    /// received so far are given up on; the message was likely lost to a
+    /// partition
+    pub chunk_timeout: Duration,
+
+    /// Directory the services spill their data to, and state dumps are
+    /// written to. Without it, everything is kept in memory
+    pub storage_dir: Option<PathBuf>,
+
+    /// Whether the services pick their data up from `storage_dir` as an
+    /// earlier run left it, rather than starting out empty. Set along with
+    /// `storage_dir` by `--restore <dir>`, to restart a crashed node. The
+    /// chain KV doesn't save its place in the chain, so it starts over
+    pub restore: bool,
+
+    /// Secret shared by the nodes, which the messages between them are
+    /// signed with. Needs the `hmac` feature. Unsigned without it
+    pub secret: Option<Vec<u8>>,
+
+    /// Most verbose messages logged
+    pub log_level: LogLevel,
+
+    /// Name of the service run, as set by `message::run` rather than by an
+    /// option
+    pub service: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gossip_interval: Duration::from_millis(100),
+            gossip_fanout:   None,
+            topology:        Strategy::Given,
+            gossip_filter:   GossipFilter::None,
+            read_order:      ReadOrder::Seen,
+            primaries:       Vec::new(),
+            sibling_merge:   SiblingMerge::None,
+            batch_window:    Duration::ZERO,
+            retry_timeout:   Duration::from_millis(500),
+            echo_delay:      Duration::ZERO,
+            echo_jitter:     Duration::ZERO,
+            id_source:       IdSource::Random,
+            id_audit:        false,
+            rate_limit:      None,
+            rate_burst:      10,
+            dedupe_window:   Duration::ZERO,
+            stream_retry:    None,
+            stream_window:   None,
+            workers:         4,
+            max_queue:       None,
+            codec:           Codec::Json,
+            chunk_size:      None,
+            chunk_timeout:   Duration::from_secs(10),
+            storage_dir:     None,
+            restore:         false,
+            secret:          None,
+            log_level:       LogLevel::Warn,
+            service:         None,
+        }
+    }
+}
+
+impl Config {
+    /// Build the config out of the defaults, overridden by the `MAELSTROM_*`
+    /// environment variables, overridden by the flags in `args`
+    pub fn load(args: &[String]) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for (flag, var) in OPTIONS {
+            if let Ok(value) = std::env::var(var) {
+                config.set(flag, &value)
+                    .map_err(|e| anyhow::anyhow!("{var}: {e}"))?;
+            }
+        }
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    /// Override the config with the `--flag value` pairs in `args`
+    pub fn apply_args(&mut self, args: &[String]) -> anyhow::Result<()> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flag = arg.strip_prefix("--")
+                .ok_or_else(|| anyhow::anyhow!("unexpected argument `{arg}`"))?;
+            let value = args.next()
+                .ok_or_else(|| anyhow::anyhow!("`{arg}` needs a value"))?;
+            self.set(flag, value)
+                .map_err(|e| anyhow::anyhow!("{arg}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Set the option `flag` to `value`
+    fn set(&mut self, flag: &str, value: &str) -> anyhow::Result<()> {
+        let millis = || -> anyhow::Result<Duration> {
+            Ok(Duration::from_millis(value.parse()?))
+        };
+        let positive = || -> anyhow::Result<Duration> {
+            let millis = millis()?;
+            anyhow::ensure!(!millis.is_zero(), "must be positive");
+            Ok(millis)
+        };
+
+        match flag {
+            "gossip-interval-ms" => self.gossip_interval = positive()?,
+            "gossip-fanout" => {
+                let fanout = value.parse()?;
+                anyhow::ensure!(fanout > 0, "must be positive");
+                self.gossip_fanout = Some(fanout);
+            },
+            "topology" => self.topology = Strategy::from_name(value)?,
+            "gossip-filter" =>
+                self.gossip_filter = GossipFilter::from_name(value)?,
+            "read-order" => self.read_order = ReadOrder::from_name(value)?,
+            "primaries" => self.primaries = value.split(',')
+                .filter(|node| !node.is_empty())
+                .map(String::from)
+                .collect(),
+            "sibling-merge" =>
+                self.sibling_merge = SiblingMerge::from_name(value)?,
+            "batch-window-ms"    => self.batch_window = millis()?,
+            "retry-timeout-ms"   => self.retry_timeout = positive()?,
+            "echo-delay-ms"      => self.echo_delay = millis()?,
+            "echo-jitter-ms"     => self.echo_jitter = millis()?,
+            "id-source" => self.id_source = IdSource::from_name(value)?,
+            "id-audit" => self.id_audit = match value {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => anyhow::bail!("expected true or false"),
+            },
+            "rate-limit" => {
+                let rate = value.parse()?;
+                anyhow::ensure!(rate > 0, "must be positive");
+                self.rate_limit = Some(rate);
+            },
+            "rate-burst" => {
+                let burst = value.parse()?;
+                anyhow::ensure!(burst > 0, "must be positive");
+                self.rate_burst = burst;
+            },
+            "dedupe-window-ms"   => self.dedupe_window = millis()?,
+            "stream-retry-ms"    => self.stream_retry = Some(positive()?),
+            "stream-window" => {
+                let window = value.parse()?;
+                anyhow::ensure!(window > 0, "must be positive");
+                self.stream_window = Some(window);
+            },
+            "workers" => {
+                let workers = value.parse()?;
+                anyhow::ensure!(workers > 0, "must be positive");
+                self.workers = workers;
+            },
+            "max-queue" => {
+                let max = value.parse()?;
+                anyhow::ensure!(max > 0, "must be positive");
+                self.max_queue = Some(max);
+            },
+            "codec" => self.codec = Codec::from_name(value)?,
+            "chunk-size" => {
+                let size = value.parse()?;
+                anyhow::ensure!(size > 0, "must be positive");
+                self.chunk_size = Some(size);
+            },
+            "chunk-timeout-ms"   => self.chunk_timeout = positive()?,
+            "storage-dir"        => self.storage_dir = Some(value.into()),
+            "restore" => {
+                self.storage_dir = Some(value.into());
+                self.restore = true;
+            },
+            "secret" => {
+                anyhow::ensure!(cfg!(feature = "hmac"),
+                    "needs a binary built with the `hmac` feature");
+                anyhow::ensure!(!value.is_empty(), "must not be empty");
+                self.secret = Some(value.as_bytes().to_vec());
+            },
+            "log" => self.log_level = LogLevel::from_name(value)?,
+            _ => anyhow::bail!("unknown option `--{flag}`"),
+        }
+        Ok(())
+    }
+
+    /// The effective config, by the flags of its options
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "gossip-interval-ms": self.gossip_interval.as_millis() as u64,
+            "gossip-fanout":      self.gossip_fanout,
+            "topology":           self.topology.name(),
+            "gossip-filter":      self.gossip_filter.name(),
+            "read-order":         self.read_order.name(),
+            "primaries":          self.primaries,
+            "sibling-merge":      self.sibling_merge.name(),
+            "batch-window-ms":    self.batch_window.as_millis() as u64,
+            "retry-timeout-ms":   self.retry_timeout.as_millis() as u64,
+            "echo-delay-ms":      self.echo_delay.as_millis() as u64,
+            "echo-jitter-ms":     self.echo_jitter.as_millis() as u64,
+            "id-source":          self.id_source.name(),
+            "id-audit":           self.id_audit,
+            "rate-limit":         self.rate_limit,
+            "rate-burst":         self.rate_burst,
+            "dedupe-window-ms":   self.dedupe_window.as_millis() as u64,
+            "stream-retry-ms":    self.stream_retry
+                .map(|retry| retry.as_millis() as u64),
+            "stream-window":      self.stream_window,
+            "workers":            self.workers,
+            "max-queue":          self.max_queue,
+            "codec":              self.codec.name(),
+            "chunk-size":         self.chunk_size,
+            "chunk-timeout-ms":   self.chunk_timeout.as_millis() as u64,
+            "storage-dir":        self.storage_dir,
+            "restore": self.storage_dir.as_ref().filter(|_| self.restore),
+            "secret":  self.secret.as_ref().map(|_| "<redacted>"),
+            "log": format!("{:?}", self.log_level).to_lowercase(),
+        })
+    }
+
+    /// Log `msg` to stderr if `level` is verbose enough
+    pub fn log(&self, level: LogLevel, msg: impl core::fmt::Display) {
+        if level <= self.log_level {
+            eprintln!("[{level:?}] {msg}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use crate::metrics::Metrics;
+
+/// Hash of the request `msg` as far as its receiver can tell it apart from
+/// another: its body, but for the message ID. JSON objects keep their keys
+/// sorted, so equal bodies serialize the same
+fn fingerprint(msg: &mut Value) -> Option<u64> {
+    let body = msg.get_mut("body")?.as_object_mut()?;
+    if body.contains_key("in_reply_to") { return None; }
+    body.remove("msg_id");
+
+    let mut hasher = DefaultHasher::new();
+    msg["body"].to_string().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Writer suppressing requests to the `nodes` that repeat one sent to the
+/// same node within `window`, such as gossip sent both when a message
+/// arrives and on the next tick. Replies and messages to clients are never
+/// suppressed. The window should stay well below the gossip interval and the
+/// retry timeout, or the repeats meant as retries are suppressed along with
+/// the rest. Without a window, everything is passed through untouched
+pub struct Dedupe<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    window: Duration,
+
+    nodes: HashSet<String>,
+
+    /// When each request was sent, by its destination and fingerprint
+    recent: HashMap<(String, u64), Instant>,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+
+    metrics: Arc<Metrics>,
+}
+
+impl<W: Write> Dedupe<W> {
+    pub fn new(out: W, window: Duration, nodes: &[String],
+            metrics: Arc<Metrics>) -> Self {
+        Self {
+            out,
+            window,
+            nodes: nodes.iter().cloned().collect(),
+            recent: HashMap::new(),
+            buf: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner(&self) -> &W {
+        &self.out
+    }
+
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Amount of requests remembered as recently sent
+    pub fn len(&self) -> usize {
+        self.recent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recent.is_empty()
+    }
+
+    /// Write the complete `line`, unless it was just sent
+    fn suppress(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let Ok(mut msg) = serde_json::from_slice::<Value>(line) else {
+            return self.out.write_all(line);
+        };
+        let Some(dest) = msg["dest"].as_str()
+                .filter(|dest| self.nodes.contains(*dest))
+                .map(String::from) else {
+            return self.out.write_all(line);
+        };
+        let Some(fingerprint) = fingerprint(&mut msg) else {
+            return self.out.write_all(line);
+        };
+
+        let now = Instant::now();
+        let window = self.window;
+        self.recent.retain(|_, sent| now - *sent < window);
+
+        // The window runs from when the request was actually sent, so that
+        // a steady stream of repeats still gets one through every window
+        match self.recent.entry((dest, fingerprint)) {
+            Entry::Occupied(_) => {
+                Metrics::inc(&self.metrics.suppressed);
+                Ok(())
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                self.out.write_all(line)
+            },
+        }
+    }
+}
+
+impl<W: Write> Write for Dedupe<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.window.is_zero() { return self.out.write(data); }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.suppress(&line)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::time::Duration;
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::message::{Message, Body, Node, Init};
+use crate::config::Config;
+use crate::metrics::Link;
+
+/// A `Node` with its payload type erased; messages are handed to it with
+/// their payloads as raw JSON. Nodes of different services can be kept
+/// side by side as `Box<dyn ErasedNode>`, whichever crate they come from
+pub trait ErasedNode {
+    /// Parse the payload of `input` and step the node through it
+    fn step(&mut self, input: Message<Value>, output: &mut dyn Write)
+        -> anyhow::Result<()>;
+
+    fn tick_interval(&self) -> Option<Duration>;
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()>;
+
+    fn timer(&mut self, name: &str, output: &mut dyn Write)
+        -> anyhow::Result<()>;
+
+    /// Why the node can't handle `input` right now, if it can't. Payloads
+    /// the node doesn't understand are left for `step` to report
+    fn unavailable(&self, input: &Message<Value>) -> Option<String>;
+
+    /// Whether `input` is handled ahead of whatever else is waiting
+    fn urgent(&self, input: &Message<Value>) -> bool;
+
+    fn status(&self) -> Value;
+
+    fn dump(&self) -> Value;
+
+    fn membership(&mut self, nodes: &[String]) -> bool;
+
+    fn links(&self) -> BTreeMap<String, Link>;
+
+    fn export(&mut self) -> anyhow::Result<Option<Value>>;
+
+    fn import(&mut self, from: &str, state: Value) -> anyhow::Result<bool>;
+
+    fn shutdown(&mut self, output: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+/// Builds an erased node of some service out of the `init` message
+pub type Constructor = fn(&Init, &Config)
+    -> anyhow::Result<Box<dyn ErasedNode>>;
+
+/// Adapter erasing the payload type `P` of the node `N`
+pub struct Erased<P, N> {
+    node: N,
+    _payload: PhantomData<fn(P)>,
+}
+
+impl<P, N: Node<P>> Erased<P, N> {
+    pub fn new(node: N) -> Self {
+        Self { node, _payload: PhantomData }
+    }
+
+    /// The erased node
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+}
+
+/// Build the node `N` out of `init` and erase it. Coerces to a `Constructor`
+pub fn from_init<P, N>(init: &Init, config: &Config)
+        -> anyhow::Result<Box<dyn ErasedNode>>
+where
+    P: DeserializeOwned + 'static,
+    N: Node<P> + 'static,
+{
+    Ok(Box::new(Erased::<P, N>::new(N::from_init(init, config)?)))
+}
+
+/// Parse the payload of the raw message `msg` as a `P`
+fn typed<P: DeserializeOwned>(msg: Message<Value>)
+        -> anyhow::Result<Message<P>> {
+    let payload = serde_json::from_value(msg.body.payload)
+        .with_context(|| format!("unexpected payload from {}", msg.src))?;
+    Ok(Message {
+        src: msg.src,
+        dst: msg.dst,
+        body: Body {
+            id:       msg.body.id,
+            reply_id: msg.body.reply_id,
+            deadline: msg.body.deadline,
+            trace:    msg.body.trace,
+            payload,
+        },
+    })
+}
+
+impl<P: DeserializeOwned, N: Node<P>> ErasedNode for Erased<P, N> {
+    fn step(&mut self, input: Message<Value>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        self.node.step(typed(input)?, output)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        self.node.tick_interval()
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.node.tick(output)
+    }
+
+    fn timer(&mut self, name: &str, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        self.node.timer(name, output)
+    }
+
+    fn unavailable(&self, input: &Message<Value>) -> Option<String> {
+        let input = typed(input.clone()).ok()?;
+        self.node.unavailable(&input)
+    }
+
+    fn urgent(&self, input: &Message<Value>) -> bool {
+        typed(input.clone()).is_ok_and(|input| self.node.urgent(&input))
+    }
+
+    fn status(&self) -> Value {
+        self.node.status()
+    }
+
+    fn dump(&self) -> Value {
+        self.node.dump()
+    }
+
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        self.node.membership(nodes)
+    }
+
+    fn links(&self) -> BTreeMap<String, Link> {
+        self.node.links()
+    }
+
+    fn export(&mut self) -> anyhow::Result<Option<Value>> {
+        self.node.export()
+    }
+
+    fn import(&mut self, from: &str, state: Value) -> anyhow::Result<bool> {
+        self.node.import(from, state)
+    }
+
+    fn shutdown(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.node.shutdown(output)
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Write, BufRead, BufReader};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::cluster::RawMessage;
+
+/// Environment variable holding the path of the history file
+pub const HISTORY_ENV: &str = "MAELSTROM_HISTORY";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Kind of an operation in the history, as in Jepsen histories
+pub enum OpType {
+    /// A client issued a request
+    Invoke,
+
+    /// The request completed successfully
+    Ok,
+
+    /// The request was replied to with an error
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A single entry of the operation history
+pub struct Op {
+    #[serde(rename = "type")]
+    pub kind: OpType,
+
+    /// The client that issued the operation
+    pub process: String,
+
+    /// Function of the operation; the `type` of the request
+    pub f: String,
+
+    /// The `msg_id` of the request, pairing invocations with completions
+    pub msg_id: Option<usize>,
+
+    /// Arguments of the request or the results of the reply
+    pub value: Value,
+
+    /// The node that handled the operation
+    pub node: String,
+
+    /// Wall-clock time of the operation in nanoseconds since the UNIX epoch
+    pub time: u128,
+}
+
+impl Op {
+    /// Build the invocation out of the client request `msg`
+    pub fn invoke(msg: &RawMessage) -> Self {
+        Self::new(OpType::Invoke, &msg.src, &msg.dst, msg.body.id,
+            &msg.body.payload)
+    }
+
+    /// Build the completion out of the reply `msg` to a client request
+    pub fn complete(msg: &RawMessage) -> Self {
+        let kind = if msg.body.payload["type"] == "error" {
+            OpType::Fail
+        } else {
+            OpType::Ok
+        };
+        Self::new(kind, &msg.dst, &msg.src, msg.body.reply_id,
+            &msg.body.payload)
+    }
+
+    fn new(kind: OpType, process: &str, node: &str, msg_id: Option<usize>,
+            payload: &Value) -> Self {
+        // Everything except the type goes into the value
+        let mut value = payload.clone();
+        let f = value.as_object_mut()
+            .and_then(|obj| obj.remove("type"))
+            .and_then(|f| f.as_str().map(str::to_string))
+            .unwrap_or_default();
+
+        // Completions share the function of their invocation
+        let f = match f.strip_suffix("_ok") {
+            Some(f) if kind == OpType::Ok => f.to_string(),
+            _ => f,
+        };
+
+        Self {
+            kind,
+            process: process.into(),
+            f,
+            msg_id,
+            value,
+            node: node.into(),
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Operation history appended to a JSONL file. Multiple nodes may share the
+/// same file
+pub struct History {
+    file: File,
+}
+
+impl History {
+    /// Open the history file named by `MAELSTROM_HISTORY`, if any
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        std::env::var_os(HISTORY_ENV).map(Self::open).transpose()
+    }
+
+    /// Open the history file at `path` for appending
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+        })
+    }
+
+    /// Append `op` to the history
+    pub fn record(&mut self, op: &Op) -> anyhow::Result<()> {
+        // Write the whole line at once so the entries of different nodes
+        // don't interleave
+        let mut line = serde_json::to_vec(op)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Read back all operations recorded at `path`
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Vec<Op>> {
+        BufReader::new(File::open(path)?).lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+}
+
+/// Writer recording the client operations that pass through the node. With
+/// no history, everything is passed through untouched
+pub struct Recorder<'a> {
+    /// Where the messages are actually written
+    out: &'a mut dyn Write,
+
+    /// The history the operations are recorded to
+    history: Option<History>,
+
+    /// IDs of the nodes in the cluster. Everyone else is a client
+    node_ids: &'a [String],
+
+    /// Partially written line
+    buf: Vec<u8>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(out: &'a mut dyn Write, history: Option<History>,
+            node_ids: &'a [String]) -> Self {
+        Self { out, history, node_ids, buf: Vec::new() }
+    }
+
+    /// Record the received `line` if it is a client request
+    pub fn record_request(&mut self, line: &str) -> anyhow::Result<()> {
+        let Some(history) = &mut self.history else { return Ok(()); };
+
+        let msg: RawMessage = serde_json::from_str(line)?;
+        if self.node_ids.contains(&msg.src) { return Ok(()); }
+        history.record(&Op::invoke(&msg))
+    }
+
+    /// Record the sent `line` if it is a reply to a client
+    fn record_reply(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let Some(history) = &mut self.history else { return Ok(()); };
+
+        let Ok(msg) = serde_json::from_slice::<RawMessage>(line) else {
+            return Ok(());
+        };
+        if msg.body.reply_id.is_none() || self.node_ids.contains(&msg.dst) {
+            return Ok(());
+        }
+        history.record(&Op::complete(&msg)).map_err(std::io::Error::other)
+    }
+}
+
+impl Write for Recorder<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.history.is_none() {
+            return self.out.write(data);
+        }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.record_reply(&line)?;
+            self.out.write_all(&line)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
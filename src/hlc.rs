@@ -0,0 +1,83 @@
+use serde::{Serialize, Deserialize};
+
+/// Bits of a timestamp taken up by the logical counter
+const LOGICAL_BITS: u32 = 16;
+
+/// A 64bit hybrid logical timestamp; 48 bits of wall-clock milliseconds
+/// followed by 16 bits of logical counter. Timestamps compare in causal order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+    Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// Build a timestamp out of its physical and logical parts
+    pub fn new(physical_ms: u64, logical: u16) -> Self {
+        Self((physical_ms << LOGICAL_BITS) | logical as u64)
+    }
+
+    /// Wall-clock milliseconds part of the timestamp
+    pub fn physical_ms(&self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    /// Logical counter part of the timestamp
+    pub fn logical(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+/// Milliseconds since the UNIX epoch according to the wall clock
+pub fn wall_clock_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Hybrid logical clock. It follows the wall clock when it can and counts
+/// logically when the wall clock stalls, goes backwards or lags behind the
+/// timestamps received from other nodes
+#[derive(Debug, Clone, Default)]
+pub struct Hlc {
+    /// The last timestamp handed out or seen
+    last: Timestamp,
+}
+
+impl Hlc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last timestamp handed out or seen
+    pub fn last(&self) -> Timestamp {
+        self.last
+    }
+
+    /// Get a timestamp for a local event, such as sending a message
+    pub fn now(&mut self) -> Timestamp {
+        self.now_at(wall_clock_ms())
+    }
+
+    /// Same as `now`, with the wall clock reading `wall_ms`
+    pub fn now_at(&mut self, wall_ms: u64) -> Timestamp {
+        // The logical counter overflows into the physical part, which keeps
+        // the timestamps strictly increasing
+        self.last = Timestamp((self.last.0 + 1)
+            .max(Timestamp::new(wall_ms, 0).0));
+        self.last
+    }
+
+    /// Merge the `remote` timestamp of a received message into the clock and
+    /// get a timestamp for the receive event
+    pub fn update(&mut self, remote: Timestamp) -> Timestamp {
+        self.update_at(remote, wall_clock_ms())
+    }
+
+    /// Same as `update`, with the wall clock reading `wall_ms`
+    pub fn update_at(&mut self, remote: Timestamp, wall_ms: u64) -> Timestamp {
+        self.last = Timestamp((self.last.max(remote).0 + 1)
+            .max(Timestamp::new(wall_ms, 0).0));
+        self.last
+    }
+}
@@ -0,0 +1,74 @@
+use std::hash::Hash;
+use serde::{Serialize, Deserialize};
+use crate::merkle::hash;
+
+/// HyperLogLog sketch; estimates the amount of distinct items added to it in
+/// constant space. Sketches merge losslessly, the merge of two sketches is the
+/// sketch of the union of their items
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hll {
+    /// For every bucket of the hashes, the largest position of the first set
+    /// bit seen
+    registers: Vec<u8>,
+}
+
+impl Hll {
+    /// Build an empty sketch of `2^precision` registers. The standard error
+    /// of the estimates is about `1.04 / sqrt(2^precision)`
+    pub fn new(precision: u32) -> Self {
+        let precision = precision.clamp(4, 16);
+        Self { registers: vec![0; 1 << precision] }
+    }
+
+    /// Bits of the hashes picking the register
+    fn precision(&self) -> u32 {
+        self.registers.len().trailing_zeros()
+    }
+
+    /// Add `item` to the sketch. Returns `true` if the sketch changed
+    pub fn insert(&mut self, item: &impl Hash) -> bool {
+        let precision = self.precision();
+        let hash = hash(item);
+        let idx = (hash >> (64 - precision)) as usize;
+        let rank = ((hash << precision).leading_zeros() + 1)
+            .min(64 - precision + 1) as u8;
+
+        if rank <= self.registers[idx] { return false; }
+        self.registers[idx] = rank;
+        true
+    }
+
+    /// Merge `other` into the sketch. Returns `true` if the sketch changed
+    pub fn merge(&mut self, other: &Hll) -> anyhow::Result<bool> {
+        if other.registers.len() != self.registers.len() {
+            anyhow::bail!("merging sketches of {} and {} registers",
+                self.registers.len(), other.registers.len());
+        }
+
+        let mut changed = false;
+        for (ours, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            if theirs > ours {
+                *ours = *theirs;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Estimate the amount of distinct items added
+    pub fn count(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum: f64 = self.registers.iter()
+            .map(|&rank| 2f64.powi(-(rank as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // Small cardinalities are better estimated by the empty registers
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
@@ -1,2 +1,36 @@
 pub mod services;
+pub mod topology;
 pub mod message;
+pub mod payload;
+pub mod erased;
+pub mod pool;
+pub mod rng;
+pub mod cluster;
+pub mod loadgen;
+pub mod router;
+pub mod nemesis;
+pub mod history;
+pub mod check;
+pub mod chaos;
+pub mod throttle;
+pub mod dedupe;
+pub mod stream;
+pub mod slab;
+pub mod timer;
+pub mod outbox;
+pub mod codec;
+pub mod chunk;
+pub mod sign;
+pub mod hlc;
+pub mod ulid;
+pub mod vclock;
+pub mod state_machine;
+pub mod vr;
+pub mod session;
+pub mod config;
+pub mod merkle;
+pub mod bloom;
+pub mod hll;
+pub mod storage;
+pub mod replies;
+pub mod metrics;
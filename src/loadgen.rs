@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::cluster::{self, Cluster, RawMessage};
+use crate::message::Body;
+use crate::rng::Rng;
+
+/// Amount of distinct clients the operations are spread over
+const CLIENTS: usize = 10;
+
+/// Workloads the load generator knows how to issue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    LwwKv,
+}
+
+impl Workload {
+    /// Parse the workload out of its Maelstrom name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "echo"       => Self::Echo,
+            "unique-ids" => Self::UniqueIds,
+            "broadcast"  => Self::Broadcast,
+            "lww-kv"     => Self::LwwKv,
+            _ => anyhow::bail!("unknown workload `{name}`"),
+        })
+    }
+
+    /// Name of the service in this binary that serves this workload
+    pub fn service(&self) -> &'static str {
+        match self {
+            Self::Echo      => "echo",
+            Self::UniqueIds => "unique-ids",
+            Self::Broadcast => "broadcast",
+            Self::LwwKv     => "lww-kv",
+        }
+    }
+
+    /// Build the payload of the `seq`th operation
+    fn request(&self, rng: &mut Rng, seq: usize) -> Value {
+        match self {
+            Self::Echo => json!({
+                "type": "echo",
+                "echo": format!("Please echo {}", rng.below(128)),
+            }),
+            Self::UniqueIds => json!({ "type": "generate" }),
+            // Mostly writes with an occasional read
+            Self::Broadcast if rng.below(4) == 0 => json!({ "type": "read" }),
+            Self::Broadcast => json!({ "type": "broadcast", "message": seq }),
+            // Reads and writes over a handful of keys
+            Self::LwwKv if rng.below(10) == 0 => json!({
+                "type":  "scan",
+                "from":  rng.below(5),
+                "limit": 3,
+            }),
+            Self::LwwKv if rng.below(2) == 0 => json!({
+                "type": "read",
+                "key":  rng.below(5),
+            }),
+            Self::LwwKv => json!({
+                "type":  "write",
+                "key":   rng.below(5),
+                "value": seq,
+            }),
+        }
+    }
+}
+
+/// Options of a load generator run
+#[derive(Debug)]
+pub struct Options {
+    /// The workload to issue
+    pub workload: Workload,
+
+    /// Amount of nodes to spawn
+    pub nodes: usize,
+
+    /// Operations issued per second
+    pub rate: f64,
+
+    /// Total amount of operations to issue
+    pub ops: usize,
+
+    /// How long to wait for a reply before considering it lost
+    pub timeout: Duration,
+
+    /// Seed of the operation generator
+    pub seed: Option<u64>,
+
+    /// Command spawning a node. Defaults to this binary running the service
+    /// serving `workload`
+    pub cmd: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            workload: Workload::Echo,
+            nodes:    1,
+            rate:     100.,
+            ops:      1000,
+            timeout:  Duration::from_secs(1),
+            seed:     None,
+            cmd:      Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Parse the options out of the command line arguments. Everything after
+    /// `--` is the command spawning a node
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut opts = Self::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                opts.cmd = args.cloned().collect();
+                break;
+            }
+
+            let value = args.next()
+                .ok_or_else(|| anyhow::anyhow!("`{arg}` needs a value"))?;
+            match arg.as_str() {
+                "--workload"   => opts.workload = Workload::from_name(value)?,
+                "--nodes"      => opts.nodes = value.parse()?,
+                "--rate"       => opts.rate = value.parse()?,
+                "--ops"        => opts.ops = value.parse()?,
+                "--timeout-ms" => opts.timeout =
+                    Duration::from_millis(value.parse()?),
+                "--seed"       => opts.seed = Some(value.parse()?),
+                _ => anyhow::bail!("unknown option `{arg}`"),
+            }
+        }
+
+        anyhow::ensure!(opts.nodes > 0, "at least one node is required");
+        anyhow::ensure!(opts.rate > 0., "the rate must be positive");
+
+        if opts.cmd.is_empty() {
+            opts.cmd = cluster::self_command(opts.workload.service())?;
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Results of a load generator run
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Latencies of the successful operations
+    pub latencies: Vec<Duration>,
+
+    /// Amount of operations that were replied to with an error
+    pub errors: usize,
+
+    /// Amount of operations that were never replied to
+    pub lost: usize,
+
+    /// How long the whole run took
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Get the `pct`th percentile latency
+    pub fn percentile(&self, pct: usize) -> Duration {
+        let mut sorted = self.latencies.clone();
+        sorted.sort_unstable();
+        sorted.get(sorted.len().saturating_sub(1) * pct / 100)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl core::fmt::Display for Report {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let ok = self.latencies.len();
+        let total = ok + self.errors + self.lost;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.;
+
+        writeln!(f, "{total} ops in {:.2}s ({:.1} ops/s): {ok} ok, {} errors, \
+            {} lost", self.elapsed.as_secs_f64(),
+            total as f64 / self.elapsed.as_secs_f64(), self.errors, self.lost)?;
+        write!(f, "latency (ms): p50 {:.3}, p90 {:.3}, p99 {:.3}, max {:.3}",
+            ms(self.percentile(50)), ms(self.percentile(90)),
+            ms(self.percentile(99)), ms(self.percentile(100)))
+    }
+}
+
+/// Build a message from the client `src` to the node `dst`
+fn request(src: String, dst: String, id: usize, payload: Value) -> RawMessage {
+    RawMessage {
+        src,
+        dst,
+        body: Body { id: Some(id), reply_id: None, deadline: None,
+            trace: None, payload },
+    }
+}
+
+/// Make every node a neighbor of every other node
+fn setup_topology(cluster: &mut Cluster, timeout: Duration)
+        -> anyhow::Result<()> {
+    let topology: HashMap<&String, Vec<&String>> = cluster.node_ids.iter()
+        .map(|node| (node, cluster.node_ids.iter()
+            .filter(|other| *other != node)
+            .collect()))
+        .collect();
+    let payload = json!({ "type": "topology", "topology": topology });
+
+    for (id, node) in cluster.node_ids.clone().into_iter().enumerate() {
+        cluster.send(&request("c0".into(), node, id, payload.clone()))?;
+    }
+
+    let mut pending = cluster.node_ids.len();
+    while pending > 0 {
+        let msg = cluster.recv_timeout(timeout)?
+            .ok_or_else(|| anyhow::anyhow!("topology was not acknowledged"))?;
+        if let Some(reply) = cluster.route(msg)? {
+            if reply.body.payload["type"] == "topology_ok" { pending -= 1; }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the load described by `opts` against a freshly spawned cluster
+pub fn run(opts: &Options) -> anyhow::Result<Report> {
+    let mut rng = opts.seed.map(Rng::new).unwrap_or_else(Rng::from_time);
+    let mut cluster = Cluster::spawn(&opts.cmd, opts.nodes)?;
+    cluster.init(opts.timeout)?;
+
+    if opts.workload == Workload::Broadcast {
+        setup_topology(&mut cluster, opts.timeout)?;
+    }
+
+    let mut report = Report::default();
+    let interval = Duration::from_secs_f64(1. / opts.rate);
+    let start = Instant::now();
+    let mut next_op = start;
+    let mut sent = 0;
+
+    // Operations waiting for a reply, keyed by (client, msg_id), along with
+    // their payload for as long as they may be redirected
+    let mut inflight: HashMap<(String, usize), (Instant, Option<Value>)> =
+        HashMap::new();
+
+    loop {
+        let now = Instant::now();
+
+        // Issue the next operation if it's time to
+        if sent < opts.ops && now >= next_op {
+            let client = format!("c{}", 1 + sent % CLIENTS);
+            let node = cluster.node_ids[rng.below(opts.nodes as u64) as usize]
+                .clone();
+            let payload = opts.workload.request(&mut rng, sent);
+
+            cluster.send(&request(client.clone(), node, sent,
+                payload.clone()))?;
+            inflight.insert((client, sent), (now, Some(payload)));
+            sent += 1;
+            next_op += interval;
+            continue;
+        }
+
+        // Wait for replies until the next operation or until the last ones
+        // time out
+        let wait_until = if sent < opts.ops {
+            next_op
+        } else if inflight.is_empty() {
+            break;
+        } else {
+            next_op + opts.timeout
+        };
+        if sent == opts.ops && now >= wait_until { break; }
+
+        let Some(msg) = cluster.recv_timeout(wait_until - now)? else {
+            continue;
+        };
+        let Some(reply) = cluster.route(msg)? else { continue; };
+        let Some(reply_id) = reply.body.reply_id else { continue; };
+        let key = (reply.dst, reply_id);
+        let Some((issued, payload)) = inflight.remove(&key) else {
+            continue;
+        };
+
+        // Go to the leader an error pointed at, once
+        let error = reply.body.payload["type"] == "error";
+        let leader = reply.body.payload["leader"].as_str()
+            .filter(|leader| error &&
+                cluster.node_ids.iter().any(|id| id == leader));
+        if let (Some(leader), Some(payload)) = (leader, payload) {
+            cluster.send(&request(key.0.clone(), leader.into(), key.1,
+                payload))?;
+            inflight.insert(key, (issued, None));
+            continue;
+        }
+
+        if reply.body.payload["type"] == "error" {
+            report.errors += 1;
+        } else {
+            report.latencies.push(issued.elapsed());
+        }
+    }
+
+    report.lost = inflight.len();
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+pub fn main(args: &[String]) -> anyhow::Result<()> {
+    let opts = Options::from_args(args)?;
+    let report = run(&opts)?;
+    println!("{}: {} nodes", opts.workload.service(), opts.nodes);
+    println!("{report}");
+    Ok(())
+}
@@ -1,7 +1,28 @@
 use maelstrom::*;
+use maelstrom::config::Config;
 
-fn main() {
-    // services::echo::main().unwrap();
-    // services::uuid::main().unwrap();
-    services::broadcast::main().unwrap();
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // Services take the flags of their config after their name
+    let config = || Config::load(args.get(1..).unwrap_or_default());
+
+    match args.first().map(String::as_str) {
+        Some("echo")              => services::echo::main(&config()?),
+        Some("unique-ids")        => services::uuid::main(&config()?),
+        Some("broadcast") | None  => services::broadcast::main(&config()?),
+        Some("shared-broadcast")  =>
+            services::shared_broadcast::main(&config()?),
+        Some("sequencer")         => services::sequencer::main(&config()?),
+        Some("lww-kv")            => services::lww_kv::main(&config()?),
+        Some("vclock-kv")         => services::vclock_kv::main(&config()?),
+        Some("chain-kv")          => services::chain_kv::main(&config()?),
+        Some("lin-kv")            => services::lin_kv::main(&config()?),
+        Some("lock")              => services::lock::main(&config()?),
+        Some("distinct-count")    => services::distinct::main(&config()?),
+        Some("loadgen")           => loadgen::main(&args[1..]),
+        Some("router")            => router::main(&args[1..]),
+        Some("check")             => check::main(&args[1..]),
+        Some(other) => anyhow::bail!("unknown service `{other}`"),
+    }
 }
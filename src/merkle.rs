@@ -0,0 +1,63 @@
+use std::hash::{Hash, Hasher, DefaultHasher};
+
+/// Children of every inner node of the tree
+pub const FANOUT: usize = 16;
+
+/// Amount of leaf buckets; the tree has two levels of `FANOUT` below the root
+pub const BUCKETS: usize = FANOUT * FANOUT;
+
+/// Hash `item`. The hash is the same on every node running this binary
+pub fn hash(item: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Leaf bucket of the item under `key`
+pub fn bucket(key: &impl Hash) -> usize {
+    (hash(key) % BUCKETS as u64) as usize
+}
+
+/// Merkle tree over a set of items spread into buckets by their keys. Two
+/// replicas compare the trees top-down to find the buckets they differ in
+/// without exchanging their contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merkle {
+    /// Hash of every leaf bucket; the XOR of the hashes of its items
+    leaves: Vec<u64>,
+}
+
+impl Default for Merkle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Merkle {
+    /// Build an empty tree
+    pub fn new() -> Self {
+        Self { leaves: vec![0; BUCKETS] }
+    }
+
+    /// Add the item hashed to `item` to `bucket`, or remove it if it's there
+    pub fn toggle(&mut self, bucket: usize, item: u64) {
+        self.leaves[bucket] ^= item;
+    }
+
+    /// Hashes of the `FANOUT` subtrees right under the root
+    pub fn top(&self) -> Vec<u64> {
+        self.leaves.chunks(FANOUT).map(|leaves| hash(&leaves)).collect()
+    }
+
+    /// Hashes of the leaf buckets under the `subtree`th subtree of the root
+    pub fn leaves(&self, subtree: usize) -> &[u64] {
+        &self.leaves[subtree * FANOUT..(subtree + 1) * FANOUT]
+    }
+}
+
+/// Indices at which the hashes `ours` and `theirs` differ
+pub fn diff<'a>(ours: &'a [u64], theirs: &'a [u64])
+        -> impl Iterator<Item = usize> + 'a {
+    (0..ours.len().max(theirs.len()))
+        .filter(|&idx| ours.get(idx) != theirs.get(idx))
+}
@@ -1,7 +1,29 @@
-use std::io::{Write, BufRead};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Write, BufRead, BufReader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use signal_hook::consts::{SIGTERM, SIGUSR1};
+use signal_hook::iterator::Signals;
 use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use serde_json::Value;
+use crate::history::{History, Recorder};
+use crate::chaos::{Chaos, ChaosConfig};
+use crate::throttle::Throttle;
+use crate::timer::Timers;
+use crate::dedupe::Dedupe;
+use crate::stream::{Streamer, Streams};
+use crate::outbox::Outbox;
+use crate::codec::{self, Packer, Peers};
+use crate::chunk::{Chunker, Chunks};
+use crate::sign::{self, Signer, Verifier};
+use crate::metrics::{self, Metrics, Counted, Link};
+use crate::config::{Config, LogLevel};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 /// Message passed around the network. This message is generic over all services
 pub struct Message<Payload> {
     /// A string identifying the node this message came from
@@ -16,55 +38,282 @@ pub struct Message<Payload> {
 }
 
 impl<Payload> Message<Payload> {
+    /// Build a new message from `src` to `dst` with the ID `id`, which isn't
+    /// a reply to anything
+    pub fn new(src: impl Into<String>, dst: impl Into<String>, id: usize,
+            payload: Payload) -> Self {
+        Self {
+            src: src.into(),
+            dst: dst.into(),
+            body: Body { id: Some(id), reply_id: None, deadline: None,
+                trace: None, payload },
+        }
+    }
+
+    /// Bound the message by `deadline`; used to pass the deadline of a
+    /// request on to the requests made on its behalf
+    pub fn with_deadline(mut self, deadline: Option<u64>) -> Self {
+        self.body.deadline = deadline;
+        self
+    }
+
     /// Build a reply out of this message, replying to `id`
     pub fn into_reply(mut self, id: Option<usize>) -> Self {
         // Switch the source and destinations
-        let src = self.src;
-        self.src = self.dst;
-        self.dst = src;
+        core::mem::swap(&mut self.src, &mut self.dst);
 
         // Set the correct IDs
         self.body.id = self.body.id.map(|sid| sid + 1);
         self.body.reply_id = id;
 
+        // Replies are never dropped, the requester decides if they're late
+        self.body.deadline = None;
+
         self
     }
 
-    /// Send the message through `out`
+    /// Send the message through `out`, as a single line written at once
     pub fn send(&self, out: &mut dyn Write) -> anyhow::Result<()>
         where Payload: Serialize,
     {
-        serde_json::to_writer(&mut *out, self)?;
-        out.write_all("\n".as_bytes())?;
-        Ok(())
+        // Taken out of the thread, so that a writer sending messages of its
+        // own doesn't find it borrowed
+        let mut buf = SEND_BUF.take();
+        buf.clear();
+        trace::ARMED.set(self.body.reply_id.is_none());
+        let serialized = serde_json::to_writer(&mut buf, self);
+        trace::ARMED.set(false);
+        serialized?;
+        buf.push(b'\n');
+        let written = out.write_all(&buf);
+        SEND_BUF.set(buf);
+        Ok(written?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Message borrowing its source and destination from the line it was parsed
+/// out of, for the runtime to look messages over without allocating. Strings
+/// with escapes in them can't be borrowed, and are copied all the same.
+/// Messages kept around are turned `into_owned` first
+pub struct MessageRef<'a, Payload> {
+    #[serde(borrow)]
+    pub src: Cow<'a, str>,
+
+    #[serde(borrow, rename = "dest")]
+    pub dst: Cow<'a, str>,
+
+    pub body: Body<Payload>,
+}
+
+impl<Payload> MessageRef<'_, Payload> {
+    /// Copy what's borrowed into a `Message` of its own
+    pub fn into_owned(self) -> Message<Payload> {
+        Message {
+            src:  self.src.into_owned(),
+            dst:  self.dst.into_owned(),
+            body: self.body,
+        }
+    }
+
+    /// Copy what's borrowed, so that the message outlives the line
+    pub fn into_static(self) -> MessageRef<'static, Payload> {
+        MessageRef {
+            src:  Cow::Owned(self.src.into_owned()),
+            dst:  Cow::Owned(self.dst.into_owned()),
+            body: self.body,
+        }
+    }
+}
+
+thread_local! {
+    /// Buffer the messages are serialized into before they're sent, kept
+    /// around so that sending doesn't allocate
+    static SEND_BUF: std::cell::Cell<Vec<u8>> = const {
+        std::cell::Cell::new(Vec::new())
+    };
+}
+
+/// Run `f` with the requests it sends stamped with `trace`, unless they're
+/// traced already. Replies carry the trace of what they reply to, if any
+pub fn traced<T>(trace: Option<String>, f: impl FnOnce() -> T) -> T {
+    let outer = trace::CURRENT.replace(trace);
+    let ret = f();
+    trace::CURRENT.set(outer);
+    ret
+}
+
+/// Set the timer `name` of the node to fire after `after`, moving it if it's
+/// set already. Once it fires, the node is handed it through `Node::timer`.
+/// Timers are run by the runtime of `main_loop`; the worker pool has none
+pub fn set_timer(name: impl Into<String>, after: Duration) {
+    timers::REQUESTS.with_borrow_mut(|requests| {
+        requests.push((name.into(), Some(Instant::now() + after)));
+    });
+}
+
+/// Cancel the timer `name` of the node, if it's set
+pub fn cancel_timer(name: impl Into<String>) {
+    timers::REQUESTS.with_borrow_mut(|requests| {
+        requests.push((name.into(), None));
+    });
+}
+
+/// Timers the node set and cancelled, taken in by the runtime as it goes
+mod timers {
+    use std::cell::RefCell;
+    use std::time::Instant;
+
+    thread_local! {
+        /// Timers by their names, along with when they're due; cancelled
+        /// ones aren't due at all
+        pub static REQUESTS: RefCell<Vec<(String, Option<Instant>)>> =
+            const { RefCell::new(Vec::new()) };
+    }
+}
+
+/// The trace of the message being handled, stamped onto the requests sent
+/// meanwhile
+mod trace {
+    use std::cell::{Cell, RefCell};
+    use serde::Serializer;
+
+    thread_local! {
+        pub static CURRENT: RefCell<Option<String>> = const {
+            RefCell::new(None)
+        };
+
+        /// A request is being sent, to be stamped with the current trace
+        pub static ARMED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Returns `true` if there's no trace to serialize in place of `trace`
+    pub fn untraced(trace: &Option<String>) -> bool {
+        trace.is_none()
+            && !(ARMED.get() && CURRENT.with_borrow(Option::is_some))
+    }
+
+    pub fn stamp<S: Serializer>(trace: &Option<String>, ser: S)
+            -> Result<S::Ok, S::Error> {
+        match trace {
+            Some(trace) => ser.serialize_str(trace),
+            None => CURRENT.with_borrow(|current| match current {
+                Some(trace) => ser.serialize_str(trace),
+                None => ser.serialize_none(),
+            }),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 /// Internal body of the message; ID metadata and the internal payload
 pub struct Body<Payload> {
-    #[serde(rename = "msg_id")]
+    #[serde(rename = "msg_id", default,
+        skip_serializing_if = "Option::is_none")]
     /// A unique integer identifier
     pub id: Option<usize>,
 
-    #[serde(rename = "in_reply_to")]
+    #[serde(rename = "in_reply_to", default,
+        skip_serializing_if = "Option::is_none")]
     /// For req/response, the msg_id of the request
     pub reply_id: Option<usize>,
 
+    #[serde(rename = "deadline_ms", default,
+        skip_serializing_if = "Option::is_none")]
+    /// Milliseconds since the Unix epoch after which the sender no longer
+    /// waits for a reply
+    pub deadline: Option<u64>,
+
+    #[serde(rename = "trace_id", default, serialize_with = "trace::stamp",
+        skip_serializing_if = "trace::untraced")]
+    /// Identifies the client request that led to the message, across the
+    /// nodes. Messages sent while handling a traced message are stamped with
+    /// its trace
+    pub trace: Option<String>,
+
     #[serde(flatten, rename = "type")]
     /// A string identifying the type of message this is
     pub payload: Payload,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl<Payload> Body<Payload> {
+    /// Returns `true` if the deadline of the message has passed
+    pub fn expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| now_ms() >= deadline)
+    }
+}
+
+/// Milliseconds since the Unix epoch, as used by deadlines
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Error codes defined by the Maelstrom protocol
+pub mod error_code {
+    /// The request timed out; it may or may not have been applied
+    pub const TIMEOUT: usize = 0;
+
+    /// The request type is not supported by the node
+    pub const NOT_SUPPORTED: usize = 10;
+
+    /// The request can't be handled right now, but may be retried
+    pub const TEMPORARILY_UNAVAILABLE: usize = 11;
+
+    /// The request was malformed
+    pub const MALFORMED_REQUEST: usize = 12;
+
+    /// Some indefinite error; the request may or may not have been applied
+    pub const CRASH: usize = 13;
+
+    /// Some definite error; the request was not applied
+    pub const ABORT: usize = 14;
+
+    /// The requested key does not exist
+    pub const KEY_DOES_NOT_EXIST: usize = 20;
+
+    /// The key to be created already exists
+    pub const KEY_ALREADY_EXISTS: usize = 21;
+
+    /// A precondition of the request, such as the `from` of a CAS, failed
+    pub const PRECONDITION_FAILED: usize = 22;
+
+    /// The transaction conflicted with another one and was aborted
+    pub const TXN_CONFLICT: usize = 30;
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 /// Init payload. Used on node initialization
-enum InitPayload {
+pub(crate) enum InitPayload {
     Init(Init),
-    InitOk,
+    InitOk(Capabilities),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// What a node tells about itself in its `init_ok` and to the other nodes of
+/// the cluster, so that extensions are only used with those supporting them
+pub struct Capabilities {
+    /// Name of the service the node runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+
+    /// Encodings the node decodes, besides JSON
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<String>,
+
+    /// Protocol extensions of the service the node supports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+
+    /// Whether the node was restored after a crash, having lost track of
+    /// the streams of messages it received
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub restored: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 /// Initialization metadata
 pub struct Init {
     /// ID of the node which is receiving this message
@@ -74,11 +323,82 @@ pub struct Init {
     pub node_ids: Vec<String>,
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Requests handled by the runtime itself on behalf of every service
+enum RuntimePayload {
+    DebugStatus,
+    DebugStatusOk(Status),
+
+    /// `node` joined the cluster or left it
+    NodeJoin { node: String },
+    NodeJoinOk,
+    NodeLeave { node: String },
+    NodeLeaveOk,
+
+    /// Announcement of the capabilities of the sender
+    Hello(Capabilities),
+
+    /// The sender received everything of our `stream` up to `upto`
+    StreamAck { stream: u64, upto: u64 },
+
+    /// Export the state of the service. It's answered with the `parts` of
+    /// the state serialized, each carrying its `part` of the `data`
+    ExportState,
+    ExportStateOk { part: usize, parts: usize, data: String },
+
+    /// Take on the state the node `from` exports, on top of ours
+    ImportState { from: String },
+    ImportStateOk,
+
+    Error { code: usize, text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Introspection of a running node, replied to `debug_status`
+pub struct Status {
+    pub node_id: String,
+
+    /// Milliseconds since the node started
+    pub uptime_ms: u64,
+
+    /// Messages received and sent, and times ticked so far
+    pub received: u64,
+    pub sent: u64,
+    pub ticks: u64,
+
+    /// Outgoing messages held back by the chaos layer
+    pub delayed: usize,
+
+    /// Outgoing requests held back by the rate limit
+    #[serde(default)]
+    pub deferred: usize,
+
+    /// Messages to the other nodes not acknowledged yet, and held back
+    /// until their windows have room
+    #[serde(default)]
+    pub unacked: usize,
+    #[serde(default)]
+    pub pending: usize,
+
+    /// Summary of the state of the service, as given by `Node::status`
+    pub service: Value,
+
+    /// Capabilities the other nodes announced
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peers: BTreeMap<String, Capabilities>,
+
+    /// Links to the neighbors, as given by `Node::links`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, Link>,
+}
+
 /// Trait generic over `Payload` that makes it possible to build
 /// distributed systems.
 pub trait Node<Payload> {
-    /// Given the `init` struct, creates a new `Node` in the cluster
-    fn from_init(init: &Init) -> anyhow::Result<Self>
+    /// Given the `init` struct and the `config`, creates a new `Node` in the
+    /// cluster
+    fn from_init(init: &Init, config: &Config) -> anyhow::Result<Self>
         where Self: Sized;
 
     /// Single steps through the main event loop of the node.
@@ -86,49 +406,1286 @@ pub trait Node<Payload> {
     /// appropriate responses through `output`
     fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
         -> anyhow::Result<()>;
+
+    /// How often `tick` should be called, if at all
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called every `tick_interval` to let the node do periodic work, such
+    /// as retrying requests, even when no messages arrive
+    fn tick(&mut self, _output: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the timer `name`, set with `set_timer`, fires
+    fn timer(&mut self, _name: &str, _output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Why the node can't handle `input` right now, if it can't. Such
+    /// requests are answered with `TEMPORARILY_UNAVAILABLE` by the runtime,
+    /// so that clients retry them later
+    fn unavailable(&self, _input: &Message<Payload>) -> Option<String> {
+        None
+    }
+
+    /// Whether `input` is handled ahead of whatever else is waiting, such as
+    /// the messages changing how the node is set up. None are by default
+    fn urgent(&self, _input: &Message<Payload>) -> bool {
+        false
+    }
+
+    /// Summary of the state of the node, reported to `debug_status`
+    fn status(&self) -> Value {
+        Value::Null
+    }
+
+    /// Everything there is to the state of the node, dumped on `SIGUSR1`.
+    /// Defaults to the `status` summary
+    fn dump(&self) -> Value {
+        self.status()
+    }
+
+    /// Called with all the `nodes` of the cluster after a node joined or
+    /// left it. Returns `false` if the node can't change its cluster at
+    /// runtime, which is the default
+    fn membership(&mut self, _nodes: &[String]) -> bool {
+        false
+    }
+
+    /// Counters of the links to the neighbors the node gossips with, by
+    /// neighbor. Reported to `debug_status` and kept with the metrics
+    fn links(&self) -> BTreeMap<String, Link> {
+        BTreeMap::new()
+    }
+
+    /// Protocol extensions the node supports, announced to the others
+    fn extensions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Called when `node` announced its `capabilities`
+    fn hello(&mut self, _node: &str, _capabilities: &Capabilities) {}
+
+    /// Everything there is to the state of the service, for `export_state`
+    /// to hand to another node; `None` if the node can't export it, which
+    /// is the default. The services replicated through view-stamped
+    /// replication keep it that way, as their replicas take on state by
+    /// the log alone, and so does the sequencer, which keeps its state in
+    /// the lin-kv service
+    fn export(&mut self) -> anyhow::Result<Option<Value>> {
+        Ok(None)
+    }
+
+    /// Take on the `state` the node `from` exported, on top of what the
+    /// node has. Returns `false` if the node can't import state, which is
+    /// the default
+    fn import(&mut self, _from: &str, _state: Value) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// Called once the node is done, its input closed or the process asked
+    /// to terminate, to flush what it holds back and persist its state
+    fn shutdown(&mut self, _output: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Parse a single line received from the network into a message.
+/// Malformed input results in an error, never a panic
+#[cfg(not(feature = "simd-json"))]
+pub fn parse_line<P: DeserializeOwned>(line: &str)
+        -> anyhow::Result<Message<P>> {
+    Ok(serde_json::from_str(line)?)
+}
+
+/// Parse a single line received from the network into a message, with the
+/// SIMD parser of simd-json. It parses in place, so it works on a copy of the
+/// line. Malformed input results in an error, never a panic
+#[cfg(feature = "simd-json")]
+pub fn parse_line<P: DeserializeOwned>(line: &str)
+        -> anyhow::Result<Message<P>> {
+    let mut bytes = line.as_bytes().to_vec();
+    Ok(simd_json::serde::from_slice(&mut bytes)?)
+}
+
+/// Parse a single line received from the network into a message borrowing
+/// from it. The SIMD parser only parses in place, so this always goes
+/// through serde_json. Malformed input results in an error, never a panic
+pub fn parse_line_ref<'a, P: Deserialize<'a>>(line: &'a str)
+        -> anyhow::Result<MessageRef<'a, P>> {
+    Ok(serde_json::from_str(line)?)
+}
+
+/// Acknowledge the init message `init`, telling our `capabilities`
+pub(crate) fn send_init_ok(init: &MessageRef<'_, InitPayload>,
+        capabilities: &Capabilities, output: &mut dyn Write)
+        -> anyhow::Result<()> {
+    Message {
+        src: init.dst.to_string(),
+        dst: init.src.to_string(),
+        body: Body {
+            id: Some(0),
+            reply_id: init.body.id,
+            deadline: None,
+            trace: None,
+            payload: InitPayload::InitOk(capabilities.clone()),
+        },
+    }.send(output)
 }
 
-/// Implementation of the main loop generic over a service `Node<Payload>` impl
-pub fn main_loop<P, N>() -> anyhow::Result<()>
+/// Implementation of the main loop generic over a service `Node<Payload>` impl.
+/// `SIGTERM` shuts the node down the same as the end of its input, `SIGUSR1`
+/// dumps its state without interrupting it
+pub fn main_loop<P, N>(config: &Config) -> anyhow::Result<()>
 where
     P: DeserializeOwned + core::fmt::Debug,
     N: Node<P>,
 {
-    // Lock the IO
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
+    // Lock the output, the input is read on its own thread
+    let stdin = BufReader::new(std::io::stdin());
     let mut stdout = std::io::stdout().lock();
 
-    // Get the init message
-    let init_msg: Message<InitPayload> = serde_json::from_str(
-        &stdin.next().expect("no init msg received")?)?;
+    serve::<P, N>(stdin, &mut stdout, config, &[SIGTERM, SIGUSR1])
+}
+
+/// Run the node `N` of the service `name` over stdin and stdout. Panics of
+/// the node are reported along with the name of the service and errors are
+/// given it as context
+pub fn run<P, N>(name: &'static str, config: &Config) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    run_service(name, config, main_loop::<P, N>)
+}
+
+/// Run the `main_loop` of the service `name`, as described by `run`
+pub(crate) fn run_service(name: &'static str, config: &Config,
+        main_loop: impl FnOnce(&Config) -> anyhow::Result<()>)
+        -> anyhow::Result<()> {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        eprintln!("the `{name}` service panicked");
+        hook(info)
+    }));
+
+    let config = Config { service: Some(name.into()), ..config.clone() };
+    main_loop(&config)
+        .with_context(|| format!("the `{name}` service failed"))
+}
+
+/// Generate the entry point of a service, running the node type over the
+/// payload type. By default it's the `pub fn main(config)` the binary
+/// dispatches to by name; given `bin`, it's the `fn main()` of a standalone
+/// binary instead, configured by the command line. Given `pooled`, the node
+/// is a `pool::SharedNode` run on the worker pool
+///
+/// ```ignore
+/// maelstrom::service_main!("echo", Payload, EchoNode);
+/// maelstrom::service_main!(bin "echo", Payload, EchoNode);
+/// maelstrom::service_main!(pooled "echo", Payload, EchoNode);
+/// ```
+#[macro_export]
+macro_rules! service_main {
+    ($name:literal, $payload:ty, $node:ty) => {
+        /// Run the service until its input runs out
+        pub fn main(config: &$crate::config::Config) -> anyhow::Result<()> {
+            $crate::message::run::<$payload, $node>($name, config)
+        }
+    };
+    (pooled $name:literal, $payload:ty, $node:ty) => {
+        /// Run the service on the worker pool until its input runs out
+        pub fn main(config: &$crate::config::Config) -> anyhow::Result<()> {
+            $crate::pool::run::<$payload, $node>($name, config)
+        }
+    };
+    (bin $name:literal, $payload:ty, $node:ty) => {
+        fn main() -> anyhow::Result<()> {
+            let args: Vec<String> = std::env::args().skip(1).collect();
+            let config = $crate::config::Config::load(&args)?;
+            $crate::message::run::<$payload, $node>($name, &config)
+        }
+    };
+}
+
+/// Same as `main_loop`, but reads the messages from `input` and writes the
+/// responses to `output` instead of stdin and stdout.
+/// If `MAELSTROM_HISTORY` is set, client operations are recorded there,
+/// if `MAELSTROM_CHAOS` is set, faults are injected into outgoing messages and
+/// if `MAELSTROM_METRICS_PORT` is set, the metrics of the node are served.
+/// `debug_status` requests are answered here, without reaching the node, and
+/// messages past their deadline are dropped and requests the node is
+/// `unavailable` for are answered with an error. `node_join` and `node_leave`
+/// are handed to the node as its `membership`, and `export_state` and
+/// `import_state` move the state of the node over to another. A node told
+/// it joined the cluster first catches up by importing the state of one of
+/// the others, and refuses client requests until then. What handling a
+/// message leads to is sent replies first. Messages to the nodes that
+/// announced they decode `Config::codec` are encoded with it.
+/// Messages to the nodes longer than `Config::chunk_size` are sent in
+/// chunks. If `Config::secret` is set, messages between the nodes are
+/// signed with it and those with bad signatures are rejected. At the debug
+/// log level, client requests are traced through the requests they lead to.
+/// Once the input runs out, the node is given the chance to `shutdown`
+pub fn main_loop_with_io<P, N>(input: impl BufRead + Send + 'static,
+        output: &mut dyn Write, config: &Config) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    serve::<P, N>(input, output, config, &[])
+}
+
+/// Most messages handled between two flushes of the output
+const MAX_BATCH: usize = 64;
+
+/// Most bytes of the serialized state carried by a part of an export
+const STATE_PART: usize = 16 * 1024;
+
+/// An import of the state of another node underway
+struct Import {
+    /// The `import_state` request to answer once it's done, if it's not
+    /// the import of a catch-up
+    request: Option<Message<RuntimePayload>>,
+
+    /// The parts of the state received so far, by their index, and how
+    /// many there are
+    parts: BTreeMap<usize, String>,
+    count: Option<usize>,
+
+    /// When the node exporting is given up on
+    deadline: Instant,
+}
+
+/// Catching up with the cluster after joining it, by importing the state
+/// of one of the others. Client requests are refused until then
+struct CatchUp {
+    /// The `node_join` to acknowledge once caught up
+    join: Message<RuntimePayload>,
+
+    /// The others not asked yet, should the one asked fail
+    donors: VecDeque<String>,
+
+    /// The import from the node asked
+    asked: (String, usize),
+}
+
+/// What the main loop waits for
+enum Input {
+    Line(std::io::Result<String>),
 
-    // Build the node from the init message
-    let InitPayload::Init(init) = init_msg.body.payload else {
-        panic!("First message must be init!");
+    /// The input ran out
+    Closed,
+
+    Signal(i32),
+}
+
+/// Timers of the runtime
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Timer {
+    /// The node is due to tick
+    Tick,
+
+    /// A timer the node set by its name
+    Node(String),
+}
+
+/// Take in the timers the node set and cancelled since we last looked
+fn schedule(timers: &mut Timers<Timer>) {
+    let requests = timers::REQUESTS.take();
+    for (name, due) in requests {
+        match due {
+            Some(due) => timers.set(Timer::Node(name), due),
+            None => { timers.cancel(&Timer::Node(name)); },
+        }
+    }
+}
+
+/// A message taken in, parsed once up front
+enum Parsed<P> {
+    /// For the service
+    Service(Message<P>),
+
+    /// An init message, delivered once more
+    Init(MessageRef<'static, InitPayload>),
+
+    /// For the runtime
+    Runtime(MessageRef<'static, RuntimePayload>),
+
+    /// For nobody; what the service made of it
+    Malformed(anyhow::Error),
+}
+
+impl<P: DeserializeOwned> Parsed<P> {
+    fn new(line: &str) -> Self {
+        let e = match parse_line(line) {
+            Ok(msg) => return Self::Service(msg),
+            Err(e) => e,
+        };
+        if let Ok(msg) = parse_line_ref::<InitPayload>(line) {
+            return Self::Init(msg.into_static());
+        }
+        match parse_line_ref::<RuntimePayload>(line) {
+            Ok(msg) => Self::Runtime(msg.into_static()),
+            Err(_) => Self::Malformed(e),
+        }
+    }
+}
+
+/// Input taken in and ready to be handled
+enum Ready<P> {
+    /// A message as it was sent, out of whatever carried it, and what it
+    /// parsed to
+    Message(String, Box<Parsed<P>>),
+
+    /// The input ran out
+    Closed,
+
+    Signal(i32),
+}
+
+/// Input waiting to be handled, the urgent messages in a lane of their own
+/// that's always taken from first. Everything else keeps its order
+struct Lanes<P> {
+    urgent: VecDeque<Ready<P>>,
+    bulk: VecDeque<Ready<P>>,
+
+    /// Amount of messages in the lanes
+    messages: usize,
+}
+
+impl<P> Default for Lanes<P> {
+    fn default() -> Self {
+        Self { urgent: VecDeque::new(), bulk: VecDeque::new(), messages: 0 }
+    }
+}
+
+impl<P> Lanes<P> {
+    fn push(&mut self, ready: Ready<P>, urgent: bool) {
+        if let Ready::Message(..) = ready {
+            self.messages += 1;
+        }
+        match urgent {
+            true  => self.urgent.push_back(ready),
+            false => self.bulk.push_back(ready),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Ready<P>> {
+        let ready = self.urgent.pop_front()
+            .or_else(|| self.bulk.pop_front());
+        if let Some(Ready::Message(..)) = ready {
+            self.messages -= 1;
+        }
+        ready
+    }
+
+    fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.bulk.is_empty()
+    }
+
+    /// Amount of messages waiting
+    fn messages(&self) -> usize {
+        self.messages
+    }
+}
+
+/// Write the state `dump` of the node `node_id` to its file in the storage
+/// directory, or to stderr without one. A newer dump replaces the older one
+fn write_dump(node_id: &str, dump: &Value, config: &Config)
+        -> anyhow::Result<()> {
+    match &config.storage_dir {
+        Some(dir) => {
+            let path = dir.join(format!("{node_id}.dump.json"));
+            std::fs::write(&path, serde_json::to_vec_pretty(dump)?)
+                .with_context(|| format!("writing {}", path.display()))
+        },
+        None => {
+            eprintln!("dump {dump}");
+            Ok(())
+        },
+    }
+}
+
+/// Send the cumulative acks due for the `streams` received by `node_id`
+fn acknowledge(streams: &mut Streams, node_id: &str, output: &mut dyn Write)
+        -> anyhow::Result<()> {
+    for (node, stream, upto) in streams.acks() {
+        Message {
+            src:  node_id.into(),
+            dst:  node,
+            body: Body { id: None, reply_id: None, deadline: None, trace: None,
+                payload: RuntimePayload::StreamAck { stream, upto },
+            },
+        }.send(output)?;
+    }
+    Ok(())
+}
+
+/// Answer the runtime `request` with `payload`
+fn answer(request: Message<RuntimePayload>, payload: RuntimePayload,
+        output: &mut dyn Write) -> anyhow::Result<()> {
+    let id = request.body.id;
+    let mut reply = request.into_reply(id);
+    reply.body.payload = payload;
+    reply.send(output)
+}
+
+/// Answer the `request` to export the state of the node with `state`,
+/// serialized and split into parts of up to `STATE_PART` bytes
+fn export_state(request: Message<RuntimePayload>, state: Option<Value>,
+        output: &mut dyn Write) -> anyhow::Result<()> {
+    let Some(state) = state else {
+        return answer(request, RuntimePayload::Error {
+            code: error_code::NOT_SUPPORTED,
+            text: "the service can't export its state".into(),
+        }, output);
     };
-    let mut node = N::from_init(&init)?;
+    let state = serde_json::to_string(&state)?;
+    let mut parts = Vec::new();
+    let mut rest = state.as_str();
+    loop {
+        let mut end = rest.len().min(STATE_PART);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+        if rest.is_empty() { break; }
+    }
+
+    let id = request.body.id;
+    let mut reply = request.into_reply(id);
+    let total = parts.len();
+    for (part, data) in parts.into_iter().enumerate() {
+        reply.body.payload = RuntimePayload::ExportStateOk {
+            part,
+            parts: total,
+            data: data.into(),
+        };
+        reply.send(output)?;
+    }
+    Ok(())
+}
+
+/// Transfers of the state of other nodes to ours underway: the imports
+/// asked for, and the catch-up of a node that joined the cluster
+struct Transfers {
+    node_id: String,
+
+    /// How long a node is given to hand over its state
+    timeout: Duration,
+
+    /// Imports underway, by the node exporting and the ID of our request
+    imports: HashMap<(String, usize), Import>,
+
+    /// ID of the latest export we asked for
+    requested: usize,
+
+    catch_up: Option<CatchUp>,
+}
+
+impl Transfers {
+    fn new(node_id: &str, timeout: Duration) -> Self {
+        Self {
+            node_id:   node_id.into(),
+            timeout,
+            imports:   HashMap::new(),
+            requested: 0,
+            catch_up:  None,
+        }
+    }
+
+    /// Whether we're catching up with the cluster
+    fn catching_up(&self) -> bool {
+        self.catch_up.is_some()
+    }
+
+    /// Whether `from` answering our request `id` answers a request for its
+    /// state
+    fn awaits(&self, from: &str, id: Option<usize>) -> bool {
+        self.imports.contains_key(&(from.to_string(), id.unwrap_or_default()))
+    }
+
+    /// When the next node asked for its state is given up on
+    fn next_deadline(&self) -> Option<Instant> {
+        self.imports.values().map(|underway| underway.deadline).min()
+    }
+
+    /// Ask `donor` to export its state, on behalf of `request` if any.
+    /// Returns the key of the import
+    fn ask(&mut self, donor: &str, request: Option<Message<RuntimePayload>>,
+            output: &mut dyn Write) -> anyhow::Result<(String, usize)> {
+        self.requested += 1;
+        Message {
+            src:  self.node_id.clone(),
+            dst:  donor.into(),
+            body: Body { id: Some(self.requested), reply_id: None,
+                deadline: None, trace: None,
+                payload: RuntimePayload::ExportState,
+            },
+        }.send(output)?;
+        let key = (donor.to_string(), self.requested);
+        self.imports.insert(key.clone(), Import {
+            request,
+            parts:    BTreeMap::new(),
+            count:    None,
+            deadline: Instant::now() + self.timeout,
+        });
+        Ok(key)
+    }
+
+    /// Catch up with the others of the `nodes` we just `join`ed, asking them
+    /// for their state in turn. Without any others, there's nothing to
+    /// catch up with. A retry of the join while catching up is acknowledged
+    /// in place of the first once caught up
+    fn join(&mut self, nodes: &[String], join: Message<RuntimePayload>,
+            config: &Config, output: &mut dyn Write) -> anyhow::Result<()> {
+        if let Some(catch_up) = &mut self.catch_up {
+            catch_up.join = join;
+            return Ok(());
+        }
+        let donors = nodes.iter().filter(|id| **id != self.node_id)
+            .cloned()
+            .collect();
+        self.catch_up = Some(CatchUp {
+            join,
+            donors,
+            asked: Default::default(),
+        });
+        self.next_donor(config, output)
+    }
+
+    /// Ask the next donor to catch up with, or stop catching up if nobody's
+    /// left to ask
+    fn next_donor(&mut self, config: &Config, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let Some(mut catch_up) = self.catch_up.take() else {
+            return Ok(());
+        };
+        self.imports.remove(&catch_up.asked);
+        let Some(donor) = catch_up.donors.pop_front() else {
+            if !catch_up.asked.0.is_empty() {
+                config.log(LogLevel::Warn, format_args!("nobody handed \
+                    over their state, serving without catching up"));
+            }
+            return answer(catch_up.join, RuntimePayload::NodeJoinOk, output);
+        };
+        config.log(LogLevel::Info,
+            format_args!("catching up with the cluster from {donor}"));
+        catch_up.asked = self.ask(&donor, None, output)?;
+        self.catch_up = Some(catch_up);
+        Ok(())
+    }
+
+    /// Give up on the nodes taking too long to hand over their state. An
+    /// import asked for fails, and a catch-up moves on to the next node
+    fn expire(&mut self, now: Instant, config: &Config,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        let expired: Vec<(String, usize)> = self.imports.iter()
+            .filter(|(_, underway)| underway.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            let underway = self.imports.remove(&key)
+                .expect("expired import is gone");
+            config.log(LogLevel::Warn, format_args!("{} didn't hand over \
+                its state in time", key.0));
+            match underway.request {
+                Some(request) => answer(request, RuntimePayload::Error {
+                    code: error_code::TIMEOUT,
+                    text: format!("{} didn't hand over its state in time",
+                        key.0),
+                }, output)?,
+                None => self.next_donor(config, output)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Take in the `part` of the `parts` of the state `from` exported in
+    /// answer to our request `id`. Once every part is in, the state is
+    /// handed to `import`, and whoever asked for it is answered
+    #[allow(clippy::too_many_arguments)]
+    fn receive(&mut self, from: &str, id: Option<usize>, part: usize,
+            parts: usize, data: &str,
+            import: impl FnOnce(&str, Value) -> anyhow::Result<bool>,
+            config: &Config, output: &mut dyn Write) -> anyhow::Result<()> {
+        let key = (from.to_string(), id.unwrap_or_default());
+        let Some(underway) = self.imports.get_mut(&key) else {
+            return Ok(());
+        };
+
+        // A part that doesn't fit with the others fails the whole import
+        let fits = part < parts && underway.count.unwrap_or(parts) == parts;
+        if fits {
+            underway.count = Some(parts);
+            underway.parts.insert(part, data.into());
+            if underway.parts.len() < parts { return Ok(()); }
+        }
 
-    // Reply to the init message
+        let underway = self.imports.remove(&key)
+            .expect("finished import is gone");
+        let imported = if fits {
+            let state: String = underway.parts.into_values().collect();
+            serde_json::from_str(&state)
+                .map_err(anyhow::Error::from)
+                .and_then(|state| import(from, state))
+        } else {
+            Err(anyhow::anyhow!("part {part} of {parts} doesn't fit in"))
+        };
+        if let Ok(true) = imported {
+            config.log(LogLevel::Info,
+                format_args!("imported the state of {from}"));
+        }
+
+        let Some(request) = underway.request else {
+            return match imported {
+                Ok(true) => self.caught_up(output),
+                Ok(false) => {
+                    config.log(LogLevel::Warn, format_args!("the service \
+                        can't import state, serving without catching up"));
+                    self.caught_up(output)
+                },
+                Err(e) => {
+                    config.log(LogLevel::Warn, format_args!("importing the \
+                        state of {from}: {e:#}"));
+                    self.next_donor(config, output)
+                },
+            };
+        };
+        let payload = match imported {
+            Ok(true) => RuntimePayload::ImportStateOk,
+            Ok(false) => RuntimePayload::Error {
+                code: error_code::NOT_SUPPORTED,
+                text: "the service can't import state".into(),
+            },
+            Err(e) => RuntimePayload::Error {
+                code: error_code::MALFORMED_REQUEST,
+                text: format!("importing the state of {from}: {e:#}"),
+            },
+        };
+        answer(request, payload, output)
+    }
+
+    /// Note that `from` answered our request `id` with an error, if it was
+    /// asked for its state
+    fn failed(&mut self, from: &str, id: Option<usize>, code: usize,
+            text: &str, config: &Config, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let key = (from.to_string(), id.unwrap_or_default());
+        let Some(underway) = self.imports.remove(&key) else {
+            return Ok(());
+        };
+        match underway.request {
+            Some(request) => answer(request, RuntimePayload::Error {
+                code,
+                text: format!("{from}: {text}"),
+            }, output),
+            None => {
+                config.log(LogLevel::Warn, format_args!("{from} can't hand \
+                    over its state: {text}"));
+                self.next_donor(config, output)
+            },
+        }
+    }
+
+    /// Done catching up; the join is acknowledged
+    fn caught_up(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        match self.catch_up.take() {
+            Some(catch_up) =>
+                answer(catch_up.join, RuntimePayload::NodeJoinOk, output),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Answer the request `msg` with `TEMPORARILY_UNAVAILABLE` and `text`,
+/// so that it's retried later rather than left hanging. Nobody is waiting
+/// for the answer to a message without an ID
+pub(crate) fn refuse<P>(msg: Message<P>, text: String, output: &mut dyn Write)
+        -> anyhow::Result<()> {
+    let id = msg.body.id;
+    if id.is_none() { return Ok(()); }
     Message {
-        src: init_msg.dst,
-        dst: init_msg.src,
-        body: Body {
-            id: Some(0),
-            reply_id: init_msg.body.id,
-            payload: InitPayload::InitOk,
+        src:  msg.src,
+        dst:  msg.dst,
+        body: Body { id, reply_id: None, deadline: None,
+            trace: msg.body.trace,
+            payload: RuntimePayload::Error {
+                code: error_code::TEMPORARILY_UNAVAILABLE,
+                text,
+            },
+        },
+    }.into_reply(id).send(output)
+}
+
+/// The init message, kept to be acknowledged
+pub(crate) type InitMessage = MessageRef<'static, InitPayload>;
+
+/// Read `lines` up to the init message. Whatever arrives before it is held
+/// back and returned along with it
+pub(crate) fn read_init<L>(lines: &mut L)
+        -> anyhow::Result<(InitMessage, Init, Vec<String>)>
+where
+    L: Iterator<Item = std::io::Result<String>>,
+{
+    let mut early = Vec::new();
+    loop {
+        let line = lines.next()
+            .ok_or_else(|| anyhow::anyhow!("no init msg received"))??;
+        match parse_line_ref::<InitPayload>(&line) {
+            Ok(mut msg) => match core::mem::replace(&mut msg.body.payload,
+                    InitPayload::InitOk(Capabilities::default())) {
+                InitPayload::Init(init) => {
+                    return Ok((msg.into_static(), init, early));
+                },
+                InitPayload::InitOk(_) => early.push(line),
+            },
+            Err(_) => early.push(line),
+        }
+    }
+}
+
+/// The writers the output of the node goes through ahead of the outbox,
+/// from the first to the last. The runtime reaches into them by name
+struct Stack<'a> {
+    out: Dedupe<Throttle<Streamer<Chaos<Recorder<'a>>>>>,
+}
+
+impl<'a> Stack<'a> {
+    fn throttle(&self) -> &Throttle<Streamer<Chaos<Recorder<'a>>>> {
+        self.out.inner()
+    }
+
+    fn throttle_mut(&mut self)
+            -> &mut Throttle<Streamer<Chaos<Recorder<'a>>>> {
+        self.out.inner_mut()
+    }
+
+    fn streamer(&self) -> &Streamer<Chaos<Recorder<'a>>> {
+        self.throttle().inner()
+    }
+
+    fn streamer_mut(&mut self) -> &mut Streamer<Chaos<Recorder<'a>>> {
+        self.throttle_mut().inner_mut()
+    }
+
+    fn chaos(&self) -> &Chaos<Recorder<'a>> {
+        self.streamer().inner()
+    }
+
+    fn chaos_mut(&mut self) -> &mut Chaos<Recorder<'a>> {
+        self.streamer_mut().inner_mut()
+    }
+
+    fn recorder_mut(&mut self) -> &mut Recorder<'a> {
+        self.chaos_mut().inner_mut()
+    }
+}
+
+impl Write for Stack<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Same as `main_loop_with_io`, also handling the `signals`
+fn serve<P, N>(input: impl BufRead + Send + 'static, output: &mut dyn Write,
+        config: &Config, signals: &[i32]) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    let mut lines = input.lines();
+    let (init_msg, init, early) = read_init(&mut lines)?;
+
+    // Build the node from the init message and reply to it. Timers left
+    // behind by whatever ran on this thread before aren't the node's
+    timers::REQUESTS.take();
+    let mut node = N::from_init(&init, config)?;
+    config.log(LogLevel::Info, format_args!("started {}", serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "service": config.service,
+        "node_id": init.node_id,
+        "nodes":   init.node_ids.len(),
+        "config":  config.summary(),
+    })));
+    let codec = config.codec;
+    let capabilities = Capabilities {
+        service:    config.service.clone(),
+        codecs:     codec.announced(),
+        extensions: node.extensions(),
+        restored:   config.restore,
+    };
+    send_init_ok(&init_msg, &capabilities, output)?;
+
+    // Count what goes in and out. Whatever a message leads to is held in the
+    // outbox until we're done with it, then sent replies first, packed for
+    // the nodes that announced they decode what we'd pack, chunked if it's
+    // still too long and signed if the nodes share a secret
+    let metrics = Arc::new(Metrics::new(&init.node_id));
+    metrics::serve_from_env(&metrics, &init)?;
+    let peers = Peers::default();
+    let secret = config.secret.clone();
+    let signed: sign::Nodes = Default::default();
+    signed.borrow_mut().extend(init.node_ids.iter().cloned());
+    let verifier = Verifier::new(secret.clone(), signed.clone());
+    let mut signer = Signer::new(output, secret, signed.clone());
+    let mut chunker = Chunker::new(&mut signer, config.chunk_size,
+        &init.node_ids);
+    let mut packer = Packer::new(&mut chunker, peers.clone());
+    let mut outbox = Outbox::new(&mut packer);
+    let mut output = Counted::new(&mut outbox, metrics.clone());
+
+    // Record the client operations if we keep a history. Faults are injected
+    // before the recording, so that only what clients see gets recorded,
+    // and the rate is limited before the faults, as the node's own doing
+    let recorder = Recorder::new(&mut output, History::from_env()?,
+        &init.node_ids);
+    let chaos = Chaos::new(recorder, ChaosConfig::from_env()?);
+
+    // Messages to the other nodes are numbered ahead of the faults, and sent
+    // again until they're acknowledged
+    let streamer = Streamer::new(chaos, config.stream_retry,
+        config.stream_window, &init.node_ids, metrics.clone());
+    let throttle = Throttle::new(streamer, config.rate_limit,
+        config.rate_burst, metrics.clone());
+
+    // Repeats are suppressed before anything else, so that they neither take
+    // up the rate nor get counted as sent
+    let mut output = Stack {
+        out: Dedupe::new(throttle, config.dedupe_window, &init.node_ids,
+            metrics.clone()),
+    };
+
+    // Read the input and wait for the signals on their own threads, so that
+    // we can wake up to tick and to send out delayed messages. Lines read
+    // are counted until they're taken off the channel, to tell the load
+    let (tx, rx) = mpsc::channel();
+    let queued = Arc::new(AtomicUsize::new(early.len()));
+    for line in early {
+        tx.send(Input::Line(Ok(line)))?;
+    }
+    let signals = match signals {
+        [] => None,
+        signals => {
+            let mut signals = Signals::new(signals)?;
+            let handle = signals.handle();
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for signal in signals.forever() {
+                    if tx.send(Input::Signal(signal)).is_err() { break; }
+                }
+            });
+            Some(handle)
         },
-    }.send(&mut stdout)?;
+    };
+    let reader_queued = queued.clone();
+    std::thread::spawn(move || {
+        for line in lines {
+            reader_queued.fetch_add(1, Ordering::Relaxed);
+            if tx.send(Input::Line(line)).is_err() { return; }
+        }
+        let _ = tx.send(Input::Closed);
+    });
+
+    // The nodes of the cluster, as changed by joins and leaves since init,
+    // and what the others announced they're capable of
+    let mut nodes = init.node_ids.clone();
+    let mut announced = BTreeMap::new();
+
+    // Amount of client requests traced so far
+    let mut traces = 0;
+
+    // Imports of the state of other nodes underway, the catch-up after
+    // joining the cluster included
+    let mut transfers = Transfers::new(&init.node_id, config.retry_timeout);
+
+    // Messages of the other nodes we have some of the chunks of, and the
+    // streams of numbered messages we receive from them
+    let mut chunks = Chunks::new(config.chunk_size, config.chunk_timeout);
+    let mut streams = Streams::default();
 
-    // Re-lock the input
-    drop(stdin);
-    let stdin = std::io::stdin().lock();
+    // Tell the others what we decode and support, if there's anything. A
+    // restored node announces itself regardless, so that the others know
+    // it's back
+    if config.restore || !capabilities.codecs.is_empty() ||
+            !capabilities.extensions.is_empty() {
+        for other in nodes.iter().filter(|id| **id != init.node_id) {
+            Message {
+                src:  init.node_id.clone(),
+                dst:  other.clone(),
+                body: Body { id: None, reply_id: None, deadline: None,
+                    trace: None,
+                    payload: RuntimePayload::Hello(capabilities.clone()),
+                },
+            }.send(&mut output)?;
+        }
+        output.flush()?;
+    }
+
+    let tick_interval = node.tick_interval();
+    let mut timers = Timers::default();
+    schedule(&mut timers);
+    if let Some(interval) = tick_interval {
+        timers.set(Timer::Tick, Instant::now() + interval);
+    }
+
+    // Go through each message received and handle it. Whatever is available
+    // right away is handled as a batch before the output is flushed, so that
+    // bursts are flushed at once while sparse messages go out right away.
+    // Everything waiting is taken in first, out of the chunks, streams and
+    // batches it came in, so that its urgent messages go ahead of the rest
+    let mut batched = 0;
+    let mut lanes = Lanes::default();
+    loop {
+        schedule(&mut timers);
+        let mut waiting: Vec<Input> = rx.try_iter().collect();
+        if batched >= MAX_BATCH || (lanes.is_empty() && waiting.is_empty()) {
+            // Acknowledge what the batch received at once
+            acknowledge(&mut streams, &init.node_id, &mut output)?;
+            output.flush()?;
+            batched = 0;
+        }
+        if lanes.is_empty() && waiting.is_empty() {
+            let deadline = [output.throttle().next_deadline(),
+                output.streamer().next_deadline(),
+                output.chaos().next_deadline(),
+                timers.next_deadline(), transfers.next_deadline()]
+                .into_iter().flatten().min();
+            match deadline {
+                Some(deadline) => match rx.recv_timeout(
+                        deadline.saturating_duration_since(Instant::now())) {
+                    Ok(input) => waiting.push(input),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(input) => waiting.push(input),
+                    Err(_) => break,
+                },
+            }
+        }
+
+        for input in waiting {
+            let line = match input {
+                Input::Line(line) => {
+                    queued.fetch_sub(1, Ordering::Relaxed);
+                    line?
+                },
+                Input::Closed => {
+                    lanes.push(Ready::Closed, false);
+                    continue;
+                },
+                Input::Signal(signal) => {
+                    lanes.push(Ready::Signal(signal), false);
+                    continue;
+                },
+            };
+            let line = match verifier.verify(&line) {
+                Ok(verified) => verified.unwrap_or(line),
+                Err(e) => {
+                    Metrics::inc(&metrics.rejected);
+                    config.log(LogLevel::Warn,
+                        format_args!("rejected {line}: {e}"));
+                    continue;
+                },
+            };
+            let line = match chunks.reassemble(line) {
+                Ok(Some(line)) => line,
+                Ok(None) => continue,
+                Err(e) => {
+                    config.log(LogLevel::Warn,
+                        format_args!("dropped a chunk: {e}"));
+                    continue;
+                },
+            };
+            let line = match codec::unpack(&line) {
+                Ok(unpacked) => unpacked.unwrap_or(line),
+                Err(e) => {
+                    Metrics::inc(&metrics.undecodable);
+                    config.log(LogLevel::Warn,
+                        format_args!("couldn't unpack {line}: {e:#}"));
+                    continue;
+                },
+            };
+            let Some(line) = streams.receive(line) else { continue; };
+
+            // The messages of the batches the other nodes coalesced are
+            // taken in along with the first of them
+            let unbatched: Vec<String> =
+                std::iter::from_fn(|| streams.next_unbatched()).collect();
+            for line in std::iter::once(line).chain(unbatched) {
+                let parsed = Parsed::new(&line);
+                let urgent = match &parsed {
+                    Parsed::Service(msg) => node.urgent(msg),
+                    Parsed::Init(msg) =>
+                        matches!(msg.body.payload, InitPayload::Init(_)),
+                    _ => false,
+                };
+                lanes.push(Ready::Message(line, Box::new(parsed)), urgent);
+            }
+        }
+
+        let ready = lanes.pop();
+        batched += 1;
+        output.throttle_mut().release_due()?;
+        output.streamer_mut().release_due()?;
+        output.chaos_mut().release_due()?;
+        transfers.expire(Instant::now(), config, &mut output)?;
+
+        if let Some(timer) = timers.pop_due(Instant::now()) {
+            match timer {
+                Timer::Tick => {
+                    node.tick(&mut output)?;
+                    Metrics::inc(&metrics.ticks);
+                    metrics.set_links(node.links());
+                    if let Some(interval) = tick_interval {
+                        timers.set(Timer::Tick, Instant::now() + interval);
+                    }
+                },
+                Timer::Node(name) => node.timer(&name, &mut output)?,
+            }
+        }
+
+        let (line, parsed) = match ready {
+            None => continue,
+            Some(Ready::Message(line, parsed)) => (line, parsed),
+            Some(Ready::Closed) => break,
+            Some(Ready::Signal(SIGUSR1)) => {
+                let dump = serde_json::json!({
+                    "node_id":   init.node_id,
+                    "uptime_ms": metrics.uptime().as_millis() as u64,
+                    "state":     node.dump(),
+                });
+                if let Err(e) = write_dump(&init.node_id, &dump, config) {
+                    config.log(LogLevel::Warn,
+                        format_args!("failed to dump the state: {e:#}"));
+                }
+                continue;
+            },
+            Some(Ready::Signal(signal)) => {
+                config.log(LogLevel::Info,
+                    format_args!("shutting down on signal {signal}"));
+                break;
+            },
+        };
+        Metrics::inc(&metrics.received);
+        config.log(LogLevel::Debug, format_args!("received {line}"));
+
+        // Answers to our requests for the state of other nodes are ours,
+        // even where the service takes them for its own, like its errors
+        let parsed = match *parsed {
+            Parsed::Service(msg)
+                    if transfers.awaits(&msg.src, msg.body.reply_id) =>
+                match parse_line_ref::<RuntimePayload>(&line) {
+                    Ok(reply) => Parsed::Runtime(reply.into_static()),
+                    Err(_) => Parsed::Service(msg),
+                },
+            parsed => parsed,
+        };
+        let msg: Message<P> = match parsed {
+            Parsed::Service(msg) => msg,
+
+            // Init may be delivered more than once; we've been initialized
+            // already, so only acknowledge it again
+            Parsed::Init(again) => {
+                if matches!(again.body.payload, InitPayload::Init(_)) {
+                    send_init_ok(&again, &capabilities, &mut output)?;
+                }
+                continue;
+            },
+            Parsed::Malformed(e) => return Err(e),
+
+            // Not for the service, for us
+            Parsed::Runtime(request) => {
+                // The cluster after a node joined or left it, along with the
+                // acknowledgement of the change
+                let joined = matches!(&request.body.payload,
+                    RuntimePayload::NodeJoin { node } if *node == init.node_id);
+                let change = match &request.body.payload {
+                    RuntimePayload::DebugStatus => None,
+                    RuntimePayload::Hello(theirs) => {
+                        if codec.understood_by(&theirs.codecs) {
+                            peers.borrow_mut().insert(request.src.to_string());
+                        }
+                        if theirs.restored {
+                            output.streamer_mut().restart(&request.src)?;
+                        }
+                        node.hello(&request.src, theirs);
+                        announced.insert(request.src.into_owned(),
+                            theirs.clone());
+                        continue;
+                    },
+                    RuntimePayload::StreamAck { stream, upto } => {
+                        output.streamer_mut()
+                            .ack(&request.src, *stream, *upto)?;
+                        continue;
+                    },
+                    RuntimePayload::NodeJoin { node } => {
+                        let mut changed = nodes.clone();
+                        if !changed.contains(node) {
+                            changed.push(node.clone());
+                        }
+                        Some((changed, RuntimePayload::NodeJoinOk))
+                    },
+                    RuntimePayload::NodeLeave { node } => {
+                        let mut changed = nodes.clone();
+                        changed.retain(|id| id != node);
+                        Some((changed, RuntimePayload::NodeLeaveOk))
+                    },
+                    RuntimePayload::ExportState => {
+                        let request = request.into_owned();
+                        match node.export() {
+                            Ok(state) =>
+                                export_state(request, state, &mut output)?,
+                            Err(e) => {
+                                config.log(LogLevel::Warn, format_args!(
+                                    "failed to export the state: {e:#}"));
+                                answer(request, RuntimePayload::Error {
+                                    code: error_code::ABORT,
+                                    text: format!("exporting the state: \
+                                        {e:#}"),
+                                }, &mut output)?;
+                            },
+                        }
+                        continue;
+                    },
+                    RuntimePayload::ImportState { from } => {
+                        let from = from.clone();
+                        transfers.ask(&from, Some(request.into_owned()),
+                            &mut output)?;
+                        continue;
+                    },
+                    RuntimePayload::ExportStateOk { part, parts, data } => {
+                        transfers.receive(&request.src, request.body.reply_id,
+                            *part, *parts, data,
+                            |from, state| node.import(from, state),
+                            config, &mut output)?;
+                        continue;
+                    },
+                    RuntimePayload::Error { code, text } => {
+                        transfers.failed(&request.src, request.body.reply_id,
+                            *code, text, config, &mut output)?;
+                        continue;
+                    },
+                    _ => continue,
+                };
+                output.recorder_mut().record_request(&line)?;
+
+                let payload = match change {
+                    Some((changed, ok)) if node.membership(&changed) => {
+                        config.log(LogLevel::Info,
+                            format_args!("cluster is now {changed:?}"));
+                        nodes = changed;
+                        output.streamer_mut().set_nodes(&nodes);
+
+                        // Nodes that left are still signed for, so that
+                        // nobody passes for one of them either
+                        signed.borrow_mut().extend(nodes.iter().cloned());
+
+                        // We're the node that joined, and catch up with the
+                        // others before the join is acknowledged
+                        if joined {
+                            transfers.join(&nodes, request.into_owned(),
+                                config, &mut output)?;
+                            continue;
+                        }
+                        ok
+                    },
+                    Some(_) => RuntimePayload::Error {
+                        code: error_code::NOT_SUPPORTED,
+                        text: "the cluster of the service is fixed".into(),
+                    },
+                    None => RuntimePayload::DebugStatusOk(Status {
+                        node_id:   init.node_id.clone(),
+                        uptime_ms: metrics.uptime().as_millis() as u64,
+                        received:  metrics.received.load(Ordering::Relaxed),
+                        sent:      metrics.sent.load(Ordering::Relaxed),
+                        ticks:     metrics.ticks.load(Ordering::Relaxed),
+                        delayed:   output.chaos().delayed(),
+                        deferred:  output.throttle().deferred(),
+                        unacked:   output.streamer().unacked(),
+                        pending:   output.streamer().pending(),
+                        service:   node.status(),
+                        peers:     announced.clone(),
+                        links:     node.links(),
+                    }),
+                };
+                answer(request.into_owned(), payload, &mut output)?;
+                continue;
+            },
+        };
+        output.recorder_mut().record_request(&line)?;
+
+        // Nobody is waiting for the reply anymore
+        if msg.body.expired() {
+            config.log(LogLevel::Debug, format_args!("expired {line}"));
+            continue;
+        }
+
+        // Shed the client requests while too much is waiting behind them,
+        // and tell the requester to come back later rather than leave it
+        // hanging. The other nodes are always let through
+        let backlog = queued.load(Ordering::Relaxed) + lanes.messages();
+        let overloaded = !nodes.contains(&msg.src) &&
+            config.max_queue.is_some_and(|max| backlog >= max);
+        if overloaded {
+            Metrics::inc(&metrics.shed);
+            config.log(LogLevel::Debug, format_args!("shed {line}"));
+            refuse(msg, format!("overloaded, {backlog} messages queued"),
+                &mut output)?;
+            continue;
+        }
+        if transfers.catching_up() && !nodes.contains(&msg.src) {
+            config.log(LogLevel::Debug,
+                format_args!("catching up, refused {line}"));
+            refuse(msg, "catching up with the cluster".into(), &mut output)?;
+            continue;
+        }
+        if let Some(text) = node.unavailable(&msg) {
+            config.log(LogLevel::Warn, format_args!("{text}: {line}"));
+            refuse(msg, text, &mut output)?;
+            continue;
+        }
+
+        // Trace the client requests if we log the messages, and carry on
+        // the trace of whatever was traced already
+        let trace = msg.body.trace.clone().or_else(|| {
+            let client = !nodes.contains(&msg.src);
+            (client && config.log_level >= LogLevel::Debug).then(|| {
+                traces += 1;
+                format!("{}-{traces}", init.node_id)
+            })
+        });
+        if msg.body.trace.is_none() {
+            if let Some(trace) = &trace {
+                config.log(LogLevel::Debug, format_args!("tracing as {trace}"));
+            }
+        }
+        traced(trace, || node.step(msg, &mut output))?;
+    }
 
-    // Go through each message received and handle it
-    for line in stdin.lines() {
-        let msg: Message<P> = serde_json::from_str(&line?)?;
-        node.step(msg, &mut stdout)?;
+    // Nothing is coming anymore, let the node wrap up and get the delayed
+    // messages out
+    if let Some(signals) = signals {
+        signals.close();
     }
+    node.shutdown(&mut output)?;
+    acknowledge(&mut streams, &init.node_id, &mut output)?;
+    output.throttle_mut().release_all()?;
+    output.chaos_mut().release_all()?;
+    output.flush()?;
+    config.log(LogLevel::Info, format_args!("stopped {}", metrics.summary()));
 
     Ok(())
 }
@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::message::Init;
+
+/// Environment variable holding the port the metrics of the first node are
+/// served on. Every other node serves them on the port after its predecessor.
+/// Needs the `metrics` feature
+pub const METRICS_PORT_ENV: &str = "MAELSTROM_METRICS_PORT";
+
+/// How error payloads are tagged on the wire
+const ERROR_TAG: &[u8] = br#""type":"error""#;
+
+/// Counters of the link between a node and one of its neighbors, as kept by
+/// the services that gossip
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    /// Gossip sent to the neighbor
+    pub sent: u64,
+
+    /// Replies of the neighbor to our gossip
+    pub acked: u64,
+
+    /// Gossip sent while the previous one was still unanswered
+    pub retries: u64,
+
+    /// Milliseconds since the neighbor last answered our gossip, if ever
+    pub synced_ms_ago: Option<u64>,
+}
+
+/// Counters of the runtime of a node, shared with the metrics listener
+#[derive(Debug)]
+pub struct Metrics {
+    /// The node the metrics are of
+    node: String,
+
+    /// When the node started
+    start: Instant,
+
+    /// Messages received by the node
+    pub received: AtomicU64,
+
+    /// Messages sent by the node
+    pub sent: AtomicU64,
+
+    /// Times the node ticked
+    pub ticks: AtomicU64,
+
+    /// Errors the node replied with
+    pub errors: AtomicU64,
+
+    /// Requests held back by the rate limit, and dropped by it
+    pub deferred: AtomicU64,
+    pub dropped: AtomicU64,
+
+    /// Messages of the other nodes rejected for their signatures
+    pub rejected: AtomicU64,
+
+    /// Messages of the other nodes that couldn't be unpacked
+    pub undecodable: AtomicU64,
+
+    /// Requests to the other nodes suppressed as repeats
+    pub suppressed: AtomicU64,
+
+    /// Messages to the other nodes sent again for want of an ack
+    pub retransmitted: AtomicU64,
+
+    /// Client requests refused under overload
+    pub shed: AtomicU64,
+
+    /// Messages retained until they're acknowledged, and the slots of the
+    /// slab holding them, in use or not
+    pub retained: AtomicU64,
+    pub slots: AtomicU64,
+
+    /// Links to the neighbors of the node, as of its last tick
+    links: Mutex<BTreeMap<String, Link>>,
+}
+
+impl Metrics {
+    /// Build zeroed metrics of `node`
+    pub fn new(node: &str) -> Self {
+        Self {
+            node:     node.into(),
+            start:    Instant::now(),
+            received: AtomicU64::new(0),
+            sent:     AtomicU64::new(0),
+            ticks:    AtomicU64::new(0),
+            errors:   AtomicU64::new(0),
+            deferred: AtomicU64::new(0),
+            dropped:  AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            undecodable: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+            retransmitted: AtomicU64::new(0),
+            shed:     AtomicU64::new(0),
+            retained: AtomicU64::new(0),
+            slots:    AtomicU64::new(0),
+            links:    Mutex::default(),
+        }
+    }
+
+    /// Time since the node started
+    pub fn uptime(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Bump `counter` by one
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Replace the links to the neighbors with `links`
+    pub fn set_links(&self, links: BTreeMap<String, Link>) {
+        *self.links.lock().unwrap() = links;
+    }
+
+    /// Links to the neighbors, as last set
+    pub fn links(&self) -> BTreeMap<String, Link> {
+        self.links.lock().unwrap().clone()
+    }
+
+    /// Totals of the counters, logged when the node shuts down
+    pub fn summary(&self) -> serde_json::Value {
+        let mut summary = serde_json::json!({
+            "uptime_ms": self.uptime().as_millis() as u64,
+            "received":  self.received.load(Ordering::Relaxed),
+            "sent":      self.sent.load(Ordering::Relaxed),
+            "ticks":     self.ticks.load(Ordering::Relaxed),
+            "errors":    self.errors.load(Ordering::Relaxed),
+            "deferred":  self.deferred.load(Ordering::Relaxed),
+            "dropped":   self.dropped.load(Ordering::Relaxed),
+            "rejected":  self.rejected.load(Ordering::Relaxed),
+            "undecodable": self.undecodable.load(Ordering::Relaxed),
+            "suppressed": self.suppressed.load(Ordering::Relaxed),
+            "retransmitted": self.retransmitted.load(Ordering::Relaxed),
+            "shed":      self.shed.load(Ordering::Relaxed),
+            "retained":  self.retained.load(Ordering::Relaxed),
+            "slots":     self.slots.load(Ordering::Relaxed),
+        });
+        let links = self.links();
+        if !links.is_empty() {
+            summary["links"] = serde_json::json!(links);
+        }
+        summary
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let uptime = self.uptime().as_secs_f64();
+        let families: [(&str, &str, &str, f64); 14] = [
+            ("maelstrom_uptime_seconds", "gauge",
+                "Seconds since the node started", uptime),
+            ("maelstrom_messages_received_total", "counter",
+                "Messages received by the node",
+                self.received.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_sent_total", "counter",
+                "Messages sent by the node",
+                self.sent.load(Ordering::Relaxed) as f64),
+            ("maelstrom_ticks_total", "counter",
+                "Times the node ticked",
+                self.ticks.load(Ordering::Relaxed) as f64),
+            ("maelstrom_errors_total", "counter",
+                "Errors the node replied with",
+                self.errors.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_deferred_total", "counter",
+                "Requests held back by the rate limit",
+                self.deferred.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_dropped_total", "counter",
+                "Requests dropped by the rate limit",
+                self.dropped.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_rejected_total", "counter",
+                "Messages rejected for their signatures",
+                self.rejected.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_undecodable_total", "counter",
+                "Messages that couldn't be unpacked",
+                self.undecodable.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_suppressed_total", "counter",
+                "Requests suppressed as repeats",
+                self.suppressed.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_retransmitted_total", "counter",
+                "Messages sent again for want of an ack",
+                self.retransmitted.load(Ordering::Relaxed) as f64),
+            ("maelstrom_requests_shed_total", "counter",
+                "Client requests refused under overload",
+                self.shed.load(Ordering::Relaxed) as f64),
+            ("maelstrom_messages_retained", "gauge",
+                "Messages retained until they're acknowledged",
+                self.retained.load(Ordering::Relaxed) as f64),
+            ("maelstrom_slab_slots", "gauge",
+                "Slots allocated for the retained messages",
+                self.slots.load(Ordering::Relaxed) as f64),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in families {
+            out += &format!("# HELP {name} {help}\n# TYPE {name} {kind}\n\
+                {name}{{node=\"{}\"}} {value}\n", self.node);
+        }
+
+        // One series per neighbor, under a single family each
+        let links = self.links();
+        if links.is_empty() { return out; }
+        type Value = fn(&Link) -> Option<f64>;
+        let families: [(&str, &str, &str, Value); 4] = [
+            ("maelstrom_link_sent_total", "counter",
+                "Gossip sent to the neighbor", |link| Some(link.sent as f64)),
+            ("maelstrom_link_acked_total", "counter",
+                "Replies of the neighbor to our gossip",
+                |link| Some(link.acked as f64)),
+            ("maelstrom_link_retries_total", "counter",
+                "Gossip sent while the previous one was unanswered",
+                |link| Some(link.retries as f64)),
+            ("maelstrom_link_synced_seconds_ago", "gauge",
+                "Seconds since the neighbor last answered our gossip",
+                |link| link.synced_ms_ago.map(|ms| ms as f64 / 1000.)),
+        ];
+        for (name, kind, help, value) in families {
+            out += &format!("# HELP {name} {help}\n# TYPE {name} {kind}\n");
+            for (peer, link) in &links {
+                let Some(value) = value(link) else { continue; };
+                out += &format!("{name}{{node=\"{}\",peer=\"{peer}\"}} \
+                    {value}\n", self.node);
+            }
+        }
+        out
+    }
+}
+
+/// Writer counting the messages written through it into `Metrics::sent`,
+/// and the errors among them into `Metrics::errors`
+pub struct Counted<'a> {
+    out: &'a mut dyn Write,
+    metrics: Arc<Metrics>,
+}
+
+impl<'a> Counted<'a> {
+    pub fn new(out: &'a mut dyn Write, metrics: Arc<Metrics>) -> Self {
+        Self { out, metrics }
+    }
+}
+
+impl Write for Counted<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.out.write(buf)?;
+        let lines = buf[..written].iter().filter(|b| **b == b'\n').count();
+        self.metrics.sent.fetch_add(lines as u64, Ordering::Relaxed);
+
+        // Payloads are serialized compactly, their tag right in the body
+        let errors = buf[..written].windows(ERROR_TAG.len())
+            .filter(|window| *window == ERROR_TAG)
+            .count();
+        self.metrics.errors.fetch_add(errors as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Serve `metrics` if `MAELSTROM_METRICS_PORT` is set
+pub fn serve_from_env(metrics: &Arc<Metrics>, init: &Init)
+        -> anyhow::Result<()> {
+    let Ok(port) = std::env::var(METRICS_PORT_ENV) else { return Ok(()); };
+    let port: u16 = port.parse()
+        .map_err(|e| anyhow::anyhow!("invalid {METRICS_PORT_ENV}: {e}"))?;
+    let idx = init.node_ids.iter()
+        .position(|id| *id == init.node_id)
+        .unwrap_or(0);
+    let port = u16::try_from(idx).ok()
+        .and_then(|idx| port.checked_add(idx))
+        .ok_or_else(|| anyhow::anyhow!("no metrics port left for {}",
+            init.node_id))?;
+
+    serve(metrics.clone(), port)?;
+    Ok(())
+}
+
+/// Serve `metrics` over HTTP on `port` of localhost, from a thread of its
+/// own. Returns the address listened on
+#[cfg(feature = "metrics")]
+pub fn serve(metrics: Arc<Metrics>, port: u16)
+        -> anyhow::Result<std::net::SocketAddr> {
+    use std::io::{BufRead, BufReader};
+
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue; };
+
+            // Whatever was requested, skip up to the end of the headers and
+            // reply with the metrics
+            let Ok(reader) = stream.try_clone() else { continue; };
+            let _ = BufReader::new(reader).lines()
+                .map_while(Result::ok)
+                .find(|line| line.is_empty());
+
+            let body = metrics.render();
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len());
+        }
+    });
+    Ok(addr)
+}
+
+/// Without the `metrics` feature there's nothing to serve the metrics with
+#[cfg(not(feature = "metrics"))]
+pub fn serve(_metrics: Arc<Metrics>, _port: u16)
+        -> anyhow::Result<std::net::SocketAddr> {
+    anyhow::bail!("{METRICS_PORT_ENV} is set, but this binary was built \
+        without the `metrics` feature")
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::cluster::{Cluster, RawMessage};
+use crate::rng::Rng;
+
+/// Faults the router injects into the cluster over the course of a run
+#[derive(Debug, Default, Deserialize)]
+pub struct Scenario {
+    /// Seed of the random faults (loss and jitter). Picked from the time if
+    /// not given
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// The faults to inject, in any order
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+impl Scenario {
+    /// Load a scenario out of the JSON file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+/// A single fault injected at a point in time
+#[derive(Debug, Clone, Deserialize)]
+pub struct Event {
+    /// When to inject the fault, relative to the start of the run
+    pub at_ms: u64,
+
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Faults the nemesis knows how to inject
+pub enum Fault {
+    /// Split the nodes into groups which can't talk to each other. Nodes not
+    /// in any group can still talk to everyone
+    Partition { groups: Vec<Vec<String>> },
+
+    /// Degrade the links from `from` to `to`. A missing end matches every
+    /// node. Later link faults take precedence over earlier ones
+    Link {
+        from: Option<String>,
+        to:   Option<String>,
+
+        #[serde(default)]
+        latency_ms: u64,
+
+        #[serde(default)]
+        jitter_ms: u64,
+
+        /// Probability of a message getting lost
+        #[serde(default)]
+        loss: f64,
+    },
+
+    /// Remove all partitions and link faults
+    Heal,
+
+    /// Kill the process of `node`
+    Kill { node: String },
+
+    /// Start a fresh process for `node`, killing the old one if needed
+    Restart { node: String },
+}
+
+/// Degradation of the links between some nodes
+#[derive(Debug)]
+struct LinkFault {
+    from:    Option<String>,
+    to:      Option<String>,
+    latency: Duration,
+    jitter:  Duration,
+    loss:    f64,
+}
+
+impl LinkFault {
+    fn matches(&self, from: &str, to: &str) -> bool {
+        self.from.as_deref().is_none_or(|f| f == from) &&
+            self.to.as_deref().is_none_or(|t| t == to)
+    }
+}
+
+/// Plays a `Scenario` out against a cluster, deciding the fate of every
+/// message sent between the nodes
+pub struct Nemesis {
+    rng: Rng,
+
+    /// When the scenario started
+    start: Instant,
+
+    /// Events yet to be injected, the next one last
+    pending: Vec<Event>,
+
+    /// Current partition of the nodes
+    partition: Vec<Vec<String>>,
+
+    /// Currently degraded links
+    links: Vec<LinkFault>,
+
+    /// Messages held back by link latency, keyed by the time they are to be
+    /// delivered and a sequence number keeping the keys unique
+    delayed: BTreeMap<(Instant, u64), RawMessage>,
+
+    /// Sequence number of the next delayed message
+    seq: u64,
+}
+
+impl Nemesis {
+    /// Start playing out `scenario` right now
+    pub fn new(scenario: Scenario) -> Self {
+        let mut pending = scenario.events;
+        pending.sort_by_key(|event| core::cmp::Reverse(event.at_ms));
+
+        Self {
+            rng: scenario.seed.map(Rng::new).unwrap_or_else(Rng::from_time),
+            start: Instant::now(),
+            pending,
+            partition: Vec::new(),
+            links: Vec::new(),
+            delayed: BTreeMap::new(),
+            seq: 0,
+        }
+    }
+
+    /// When the nemesis next needs to be polled
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let event = self.pending.last()
+            .map(|event| self.start + Duration::from_millis(event.at_ms));
+        let delayed = self.delayed.keys().next().map(|(at, _)| *at);
+        event.into_iter().chain(delayed).min()
+    }
+
+    /// Returns `true` if there are messages still in flight
+    pub fn in_flight(&self) -> bool {
+        !self.delayed.is_empty()
+    }
+
+    /// Inject the faults that are due and deliver the delayed messages
+    /// whose time has come
+    pub fn poll(&mut self, cluster: &mut Cluster) -> anyhow::Result<()> {
+        let now = Instant::now();
+
+        while let Some(event) = self.pending.last() {
+            if self.start + Duration::from_millis(event.at_ms) > now { break; }
+            let event = self.pending.pop().unwrap();
+            eprintln!("nemesis: {:?}", event.fault);
+            self.inject(cluster, event.fault)?;
+        }
+
+        while let Some(entry) = self.delayed.first_entry() {
+            if entry.key().0 > now { break; }
+            cluster.send(&entry.remove())?;
+        }
+
+        Ok(())
+    }
+
+    fn inject(&mut self, cluster: &mut Cluster, fault: Fault)
+            -> anyhow::Result<()> {
+        let node_idx = |node: &str| cluster.node_idx(node)
+            .ok_or_else(|| anyhow::anyhow!("nemesis: no such node {node}"));
+
+        match fault {
+            Fault::Partition { groups } => self.partition = groups,
+            Fault::Link { from, to, latency_ms, jitter_ms, loss } => {
+                self.links.push(LinkFault {
+                    from,
+                    to,
+                    latency: Duration::from_millis(latency_ms),
+                    jitter:  Duration::from_millis(jitter_ms),
+                    loss,
+                });
+            },
+            Fault::Heal => {
+                self.partition.clear();
+                self.links.clear();
+            },
+            Fault::Kill { node } => cluster.kill(node_idx(&node)?),
+            Fault::Restart { node } => cluster.restart(node_idx(&node)?)?,
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the partition keeps `from` from reaching `to`
+    fn partitioned(&self, from: &str, to: &str) -> bool {
+        let group = |node: &str| self.partition.iter()
+            .position(|group| group.iter().any(|n| n == node));
+
+        match (group(from), group(to)) {
+            (Some(from), Some(to)) => from != to,
+            _ => false,
+        }
+    }
+
+    /// Send `msg` from one node to another, subject to the current faults
+    pub fn transmit(&mut self, cluster: &mut Cluster, msg: RawMessage)
+            -> anyhow::Result<()> {
+        if self.partitioned(&msg.src, &msg.dst) { return Ok(()); }
+
+        let Some(link) = self.links.iter().rev()
+                .find(|link| link.matches(&msg.src, &msg.dst)) else {
+            return cluster.send(&msg);
+        };
+
+        if self.rng.chance(link.loss) { return Ok(()); }
+
+        let jitter = link.jitter.as_micros() as u64;
+        let delay = link.latency +
+            Duration::from_micros(self.rng.below(jitter + 1));
+        if delay.is_zero() { return cluster.send(&msg); }
+
+        self.delayed.insert((Instant::now() + delay, self.seq), msg);
+        self.seq += 1;
+        Ok(())
+    }
+}
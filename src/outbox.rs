@@ -0,0 +1,87 @@
+use std::io::Write;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+/// How urgently a message has to go out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Replies and acks; someone, likely a client, is waiting for them
+    Reply,
+
+    /// Everything else, gossip included
+    Bulk,
+}
+
+/// Just enough of a message to tell its priority
+#[derive(Deserialize)]
+struct Peek {
+    body: PeekBody,
+}
+
+#[derive(Deserialize)]
+struct PeekBody {
+    #[serde(default)]
+    in_reply_to: Option<IgnoredAny>,
+}
+
+impl Priority {
+    /// Priority of the serialized message `line`. Lines that aren't messages
+    /// are bulk
+    pub fn of(line: &[u8]) -> Self {
+        match serde_json::from_slice::<Peek>(line) {
+            Ok(peek) if peek.body.in_reply_to.is_some() => Self::Reply,
+            _ => Self::Bulk,
+        }
+    }
+}
+
+/// Writer holding the messages written through it until it's flushed, then
+/// writing them replies first, so that a large round of gossip queued ahead
+/// of a reply doesn't hold it up. Messages of the same priority keep their
+/// order
+pub struct Outbox<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    /// Complete lines waiting for the flush
+    queued: Vec<Vec<u8>>,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Outbox<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, queued: Vec::new(), buf: Vec::new() }
+    }
+
+    /// Amount of messages waiting for the flush
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+}
+
+impl<W: Write> Write for Outbox<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            self.queued.push(self.buf.drain(..=end).collect());
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // A lone message has nothing to be reordered with
+        if self.queued.len() > 1 {
+            self.queued.sort_by_cached_key(|line| Priority::of(line));
+        }
+        for line in self.queued.drain(..) {
+            self.out.write_all(&line)?;
+        }
+        self.out.flush()
+    }
+}
@@ -0,0 +1,125 @@
+/// Declare a payload enum of a service. The enum gets the derives and the
+/// serde attributes every payload shares; it's tagged by `type`, named in
+/// snake case, along with `variant()` naming the variant and `is_reply()`
+/// telling replies, the `*Ok` variants and `Error`, from requests.
+///
+/// Variants are either unit or struct-like; fields may carry attributes of
+/// their own, such as `#[serde(default)]`. Every variant also gets a struct
+/// of its own by the same name, holding its fields, which turns into the
+/// payload with `into()`. The enum may be generic over plain type
+/// parameters, such as the commands of a replicated log; those get no
+/// structs, as not every variant has a use for every parameter:
+///
+/// ```
+/// maelstrom::payload! {
+///     /// Payloads handled by the echo server
+///     pub enum Payload {
+///         Echo   { echo: String },
+///         EchoOk { echo: String },
+///     }
+/// }
+///
+/// assert!(!Payload::Echo { echo: "hi".into() }.is_reply());
+/// assert!(Payload::EchoOk { echo: "hi".into() }.is_reply());
+///
+/// let reply: Payload = EchoOk { echo: "hi".into() }.into();
+/// assert_eq!(reply, Payload::EchoOk { echo: "hi".into() });
+/// ```
+#[macro_export]
+macro_rules! payload {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident <$($gen:ident),* $(,)?> { $($body:tt)* }
+    ) => {
+        $crate::payload!(@enum [$(#[$meta])*] $vis $name [$($gen),*]
+            { $($body)* });
+    };
+
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident { $($body:tt)* }
+    ) => {
+        $crate::payload!(@enum [$(#[$meta])*] $vis $name [] { $($body)* });
+        $crate::payload!(@structs $vis $name { $($body)* });
+    };
+
+    (@enum [$(#[$meta:meta])*] $vis:vis $name:ident [$($gen:ident),*] {
+        $(
+            $(#[$vmeta:meta])*
+            $variant:ident $({
+                $( $(#[$fmeta:meta])* $field:ident : $ty:ty ),* $(,)?
+            })?
+        ),* $(,)?
+    }) => {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, Clone,
+            PartialEq)]
+        #[serde(rename_all = "snake_case", tag = "type")]
+        $(#[$meta])*
+        $vis enum $name <$($gen),*> {
+            $(
+                $(#[$vmeta])*
+                $variant $({ $( $(#[$fmeta])* $field: $ty ),* })?
+            ),*
+        }
+
+        impl <$($gen),*> $name <$($gen),*> {
+            /// Name of the variant, as declared
+            pub fn variant(&self) -> &'static str {
+                match self {
+                    $( Self::$variant { .. } => stringify!($variant), )*
+                }
+            }
+
+            /// Returns `true` if the payload answers a request
+            pub fn is_reply(&self) -> bool {
+                let variant = self.variant();
+                variant.ends_with("Ok") || variant == "Error"
+            }
+        }
+    };
+
+    (@structs $vis:vis $name:ident {
+        $(
+            $(#[$vmeta:meta])*
+            $variant:ident $({
+                $( $(#[$fmeta:meta])* $field:ident : $ty:ty ),* $(,)?
+            })?
+        ),* $(,)?
+    }) => {
+        $(
+            $crate::payload!(@struct $vis $name $variant
+                $({ $( $(#[$fmeta])* $field: $ty ),* })?);
+        )*
+    };
+
+    (@struct $vis:vis $name:ident $variant:ident) => {
+        #[doc = concat!("The `", stringify!($variant), "` payload")]
+        #[derive(serde::Serialize, serde::Deserialize, Debug, Clone,
+            PartialEq)]
+        $vis struct $variant;
+
+        impl From<$variant> for $name {
+            fn from(_: $variant) -> Self {
+                Self::$variant
+            }
+        }
+    };
+
+    (@struct $vis:vis $name:ident $variant:ident {
+        $( $(#[$fmeta:meta])* $field:ident : $ty:ty ),*
+    }) => {
+        #[doc = concat!("The fields of the `", stringify!($variant),
+            "` payload")]
+        #[derive(serde::Serialize, serde::Deserialize, Debug, Clone,
+            PartialEq)]
+        $vis struct $variant {
+            $( $(#[$fmeta])* pub $field: $ty ),*
+        }
+
+        impl From<$variant> for $name {
+            fn from(data: $variant) -> Self {
+                Self::$variant { $( $field: data.$field ),* }
+            }
+        }
+    };
+}
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use serde::de::DeserializeOwned;
+use crate::config::{Config, LogLevel};
+use crate::message::{self, Message, Init, InitPayload, Capabilities};
+
+/// A node handling several messages at once, on the threads of the worker
+/// pool. It's stepped through a shared reference, so it keeps its state
+/// behind locks of its own, be it a single `Mutex<State>` or shards of it.
+/// Worth it for services with expensive handlers
+pub trait SharedNode<Payload>: Send + Sync + Sized + 'static {
+    /// Given the `init` struct and the `config`, creates a new node in the
+    /// cluster
+    fn from_init(init: &Init, config: &Config) -> anyhow::Result<Self>;
+
+    /// Handle the incoming `input` message, sending the responses through
+    /// `output`. Called from any of the workers, concurrently
+    fn step(&self, input: Message<Payload>, output: &mut dyn Write)
+        -> anyhow::Result<()>;
+
+    /// How often `tick` should be called, if at all
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called every `tick_interval`, alongside the workers
+    fn tick(&self, _output: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the input ran out and every message was handled
+    fn shutdown(&self, _output: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Orders what handling the messages led to, so that every sender gets the
+/// replies to its requests in the order it sent them, however the workers
+/// happened to finish them
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    /// Sequence numbers of the messages of each sender not released yet
+    pending: HashMap<String, VecDeque<u64>>,
+
+    /// What handling the messages led to, until the earlier messages of
+    /// their senders are released
+    handled: HashMap<u64, Vec<u8>>,
+
+    next_seq: u64,
+}
+
+impl Sequencer {
+    /// Note that a message from `src` is to be handled. Returns its
+    /// sequence number
+    pub fn start(&mut self, src: &str) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pending.entry(src.into()).or_default().push_back(seq);
+        seq
+    }
+
+    /// Note that handling the message `seq` from `src` led to `handled`,
+    /// writing out whatever of `src` is in order by now
+    pub fn finish(&mut self, seq: u64, src: &str, handled: Vec<u8>,
+            output: &mut dyn Write) -> std::io::Result<()> {
+        self.handled.insert(seq, handled);
+        let Some(pending) = self.pending.get_mut(src) else { return Ok(()); };
+        while let Some(handled) = pending.front()
+                .and_then(|seq| self.handled.remove(seq)) {
+            output.write_all(&handled)?;
+            pending.pop_front();
+        }
+        if pending.is_empty() {
+            self.pending.remove(src);
+        }
+        Ok(())
+    }
+
+    /// Amount of messages not released yet
+    pub fn len(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// What the main loop of the pool waits for
+enum Event {
+    Line(std::io::Result<String>),
+
+    /// The input ran out
+    Closed,
+
+    /// A worker handled the message `seq` from `src`, leading to `output`
+    Handled { seq: u64, src: String, output: anyhow::Result<Vec<u8>> },
+}
+
+/// Run the shared node `N` of the service `name` on the worker pool, over
+/// stdin and stdout. Same as `message::run` otherwise
+pub fn run<P, N>(name: &'static str, config: &Config) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    N: SharedNode<P>,
+{
+    message::run_service(name, config, |config| {
+        let stdin = BufReader::new(std::io::stdin());
+        let mut stdout = std::io::stdout().lock();
+        main_loop_with_io::<P, N>(stdin, &mut stdout, config)
+    })
+}
+
+/// Main loop handling the messages read from `input` on `Config::workers`
+/// threads, writing the responses to `output`. Responses to each sender are
+/// written in the order of its messages. Lean next to
+/// `message::main_loop_with_io`; none of the runtime messages, faults,
+/// histories or metrics are handled here
+pub fn main_loop_with_io<P, N>(input: impl BufRead + Send + 'static,
+        output: &mut dyn Write, config: &Config) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    N: SharedNode<P>,
+{
+    let mut lines = input.lines();
+    let (init_msg, init, early) = message::read_init(&mut lines)?;
+
+    let node = Arc::new(N::from_init(&init, config)?);
+    let capabilities = Capabilities {
+        service: config.service.clone(),
+        ..Capabilities::default()
+    };
+    message::send_init_ok(&init_msg, &capabilities, output)?;
+    config.log(LogLevel::Info, format_args!("started {} on {} workers",
+        init.node_id, config.workers));
+
+    // The workers take the messages off a shared queue and hand back what
+    // handling them led to. Panics are handed back as errors, so that nobody
+    // waits for the messages they were handling
+    let (events, rx) = mpsc::channel();
+    let (queue, jobs) = mpsc::channel::<(u64, Message<P>)>();
+    let jobs = Arc::new(Mutex::new(jobs));
+    let workers: Vec<_> = (0..config.workers).map(|_| {
+        let (node, jobs, events) = (node.clone(), jobs.clone(), events.clone());
+        std::thread::spawn(move || loop {
+            let Ok((seq, msg)) = jobs.lock().unwrap().recv() else { break; };
+            let src = msg.src.clone();
+            let mut out = Vec::new();
+            let output = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                node.step(msg, &mut out)
+            })).unwrap_or_else(|_| Err(anyhow::anyhow!("a worker panicked")));
+            let output = output.map(|()| out);
+            if events.send(Event::Handled { seq, src, output }).is_err() {
+                break;
+            }
+        })
+    }).collect();
+
+    for line in early {
+        events.send(Event::Line(Ok(line)))?;
+    }
+    std::thread::spawn(move || {
+        for line in lines {
+            if events.send(Event::Line(line)).is_err() { return; }
+        }
+        let _ = events.send(Event::Closed);
+    });
+
+    let tick_interval = node.tick_interval();
+    let mut next_tick = tick_interval.map(|interval| Instant::now() + interval);
+    let mut sequencer = Sequencer::default();
+    let mut closed = false;
+
+    // Hand the messages out until the input runs out and they're all handled
+    while !closed || !sequencer.is_empty() {
+        output.flush()?;
+        let event = match next_tick {
+            Some(tick) => match rx.recv_timeout(
+                    tick.saturating_duration_since(Instant::now())) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(event) => Some(event),
+                Err(_) => break,
+            },
+        };
+
+        if let (Some(tick), Some(interval)) = (next_tick, tick_interval) {
+            if Instant::now() >= tick {
+                node.tick(output)?;
+                next_tick = Some(Instant::now() + interval);
+            }
+        }
+
+        match event {
+            None => {},
+            Some(Event::Closed) => closed = true,
+            Some(Event::Handled { seq, src, output: handled }) => {
+                sequencer.finish(seq, &src, handled?, output)?;
+            },
+            Some(Event::Line(line)) => {
+                let line = line?;
+                let msg: Message<P> = match message::parse_line(&line) {
+                    Ok(msg) => msg,
+
+                    // Init may be delivered more than once; only acknowledge
+                    // it again
+                    Err(e) => match message::parse_line_ref::<InitPayload>(
+                            &line) {
+                        Ok(again) => {
+                            if matches!(again.body.payload,
+                                    InitPayload::Init(_)) {
+                                message::send_init_ok(&again, &capabilities,
+                                    output)?;
+                            }
+                            continue;
+                        },
+                        Err(_) => return Err(e),
+                    },
+                };
+                // Shed the client requests while the workers are behind.
+                // The refusal still waits for the earlier replies of its
+                // sender
+                let seq = sequencer.start(&msg.src);
+                let backlog = sequencer.len();
+                if !init.node_ids.contains(&msg.src) &&
+                        config.max_queue.is_some_and(|max| backlog > max) {
+                    let mut refusal = Vec::new();
+                    let src = msg.src.clone();
+                    message::refuse(msg, format!("overloaded, {backlog} \
+                        messages queued"), &mut refusal)?;
+                    sequencer.finish(seq, &src, refusal, output)?;
+                    continue;
+                }
+                queue.send((seq, msg))
+                    .map_err(|_| anyhow::anyhow!("the workers are gone"))?;
+            },
+        }
+    }
+
+    // Let the workers go once the queue runs dry
+    drop(queue);
+    for worker in workers {
+        worker.join()
+            .map_err(|_| anyhow::anyhow!("a worker panicked"))?;
+    }
+    node.shutdown(output)?;
+    output.flush()?;
+    config.log(LogLevel::Info, format_args!("stopped {}", init.node_id));
+
+    Ok(())
+}
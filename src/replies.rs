@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use crate::merkle;
+use crate::storage::{self, Storage};
+
+/// Replies kept per client. Clients have a handful of requests outstanding
+/// at most, so a retry never reaches further back than this
+const PER_CLIENT: usize = 64;
+
+/// A reply as logged, along with the request it answered
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Logged<P> {
+    client: String,
+    msg_id: usize,
+
+    /// Digest of the request, telling a retry apart from another request
+    /// reusing its ID
+    request: u64,
+
+    reply: P,
+}
+
+/// Replies to the client requests that changed something, by the client and
+/// the ID of its request. Every reply is logged before it's sent, so that a
+/// node restarted answers a retry of a request it already applied with the
+/// reply it gave, rather than apply it twice. Without a storage directory
+/// the log is kept in memory, which still catches duplicates within a run
+pub struct Replies<P> {
+    log: Box<dyn Storage<Logged<P>> + Send>,
+
+    /// The latest replies of each client, with the digests of their requests
+    clients: HashMap<String, BTreeMap<usize, (u64, P)>>,
+}
+
+impl<P> Replies<P>
+where
+    P: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    /// Open the log `name` in `dir`, picking up the replies an earlier run
+    /// logged if `restore` is set
+    pub fn open(dir: Option<&Path>, name: &str, restore: bool)
+            -> anyhow::Result<Self> {
+        let mut replies = Self {
+            log: if restore { storage::restore(dir, name)? }
+                else { storage::open(dir, name)? },
+            clients: HashMap::new(),
+        };
+        let mut logged = Vec::new();
+        replies.log.for_each(&mut |entry| logged.push(entry))?;
+        for Logged { client, msg_id, request, reply } in logged {
+            replies.remember(client, msg_id, request, reply);
+        }
+        Ok(replies)
+    }
+
+    /// The reply given to the `request` `msg_id` of `client`, if it was
+    /// answered already
+    pub fn get(&self, client: &str, msg_id: usize, request: &impl Serialize)
+            -> Option<&P> {
+        let (digest, reply) = self.clients.get(client)?.get(&msg_id)?;
+        (*digest == digest_of(request)).then_some(reply)
+    }
+
+    /// Log `reply` as the answer to the `request` `msg_id` of `client`
+    pub fn insert(&mut self, client: &str, msg_id: usize,
+            request: &impl Serialize, reply: P) -> anyhow::Result<()> {
+        let request = digest_of(request);
+        self.log.append(Logged {
+            client: client.into(),
+            msg_id,
+            request,
+            reply: reply.clone(),
+        })?;
+        self.remember(client.into(), msg_id, request, reply);
+        Ok(())
+    }
+
+    /// Amount of replies kept
+    pub fn len(&self) -> usize {
+        self.clients.values().map(BTreeMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    fn remember(&mut self, client: String, msg_id: usize, request: u64,
+            reply: P) {
+        let replies = self.clients.entry(client).or_default();
+        replies.insert(msg_id, (request, reply));
+        while replies.len() > PER_CLIENT {
+            replies.pop_first();
+        }
+    }
+}
+
+/// Digest of `request` as serialized. Object keys serialize sorted, so
+/// equal requests serialize the same
+fn digest_of(request: &impl Serialize) -> u64 {
+    merkle::hash(&serde_json::to_string(request).unwrap_or_default())
+}
@@ -0,0 +1,43 @@
+/// Small, seedable pseudo-random number generator for the tooling.
+/// This implements 64b xorshift
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator out of `seed`
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self { state: seed.max(1) }
+    }
+
+    /// Create a new generator seeded from the current time
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    /// Get the next 64bit pseudo-random integer
+    pub fn next_u64(&mut self) -> u64 {
+        let ret = self.state;
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        ret
+    }
+
+    /// Get a pseudo-random integer in the range `[0, max)`
+    pub fn below(&mut self, max: u64) -> u64 {
+        if max == 0 { 0 } else { self.next_u64() % max }
+    }
+
+    /// Returns `true` with the probability `prob`
+    pub fn chance(&mut self, prob: f64) -> bool {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        unit < prob
+    }
+}
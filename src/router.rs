@@ -0,0 +1,124 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+use crate::cluster::{self, Cluster};
+use crate::nemesis::{Nemesis, Scenario};
+
+/// Options of a router run
+#[derive(Debug)]
+pub struct Options {
+    /// Amount of nodes to spawn
+    pub nodes: usize,
+
+    /// How long to wait for the nodes to initialize
+    pub timeout: Duration,
+
+    /// How long to keep routing after the input was closed and the network
+    /// went quiet
+    pub linger: Duration,
+
+    /// Faults to inject into the cluster
+    pub scenario: Option<Scenario>,
+
+    /// Command spawning a node
+    pub cmd: Vec<String>,
+}
+
+impl Options {
+    /// Parse the options out of the command line arguments. Everything after
+    /// `--` is the command spawning a node
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut opts = Self {
+            nodes:   3,
+            timeout: Duration::from_secs(1),
+            linger:  Duration::from_secs(1),
+            scenario: None,
+            cmd:     Vec::new(),
+        };
+        let mut service = "broadcast".to_string();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                opts.cmd = args.cloned().collect();
+                break;
+            }
+
+            let value = args.next()
+                .ok_or_else(|| anyhow::anyhow!("`{arg}` needs a value"))?;
+            match arg.as_str() {
+                "--service"    => service.clone_from(value),
+                "--nodes"      => opts.nodes = value.parse()?,
+                "--timeout-ms" => opts.timeout =
+                    Duration::from_millis(value.parse()?),
+                "--linger-ms"  => opts.linger =
+                    Duration::from_millis(value.parse()?),
+                "--nemesis"    => opts.scenario =
+                    Some(Scenario::from_file(value)?),
+                _ => anyhow::bail!("unknown option `{arg}`"),
+            }
+        }
+
+        anyhow::ensure!(opts.nodes > 0, "at least one node is required");
+
+        if opts.cmd.is_empty() {
+            opts.cmd = cluster::self_command(&service)?;
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Spawn the cluster described by `opts` and route messages between its
+/// nodes. Messages read from `input` are delivered to the nodes they are
+/// addressed to and messages for anyone else are written to `output`.
+/// Messages between the nodes are subject to the faults of the scenario
+pub fn run(opts: Options, input: impl std::io::Read + Send + 'static,
+        output: &mut dyn Write) -> anyhow::Result<()> {
+    let mut cluster = Cluster::spawn(&opts.cmd, opts.nodes)?;
+    cluster.init(opts.timeout)?;
+    let input = cluster.attach_input(input);
+    let mut nemesis = opts.scenario.map(Nemesis::new);
+
+    let mut last_activity = Instant::now();
+    loop {
+        if let Some(nemesis) = &mut nemesis {
+            nemesis.poll(&mut cluster)?;
+        }
+        let in_flight = nemesis.as_ref().is_some_and(Nemesis::in_flight);
+
+        // Once the input is closed, stop when the network goes quiet
+        let mut wait = if input.is_finished() && !in_flight {
+            let quiet = last_activity.elapsed();
+            if quiet >= opts.linger { break; }
+            opts.linger - quiet
+        } else {
+            opts.linger
+        };
+        if let Some(deadline) = nemesis.as_ref()
+                .and_then(Nemesis::next_deadline) {
+            wait = wait.min(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        let Some(msg) = cluster.recv_timeout(wait)? else { continue; };
+        last_activity = Instant::now();
+
+        if let Some(nemesis) = &mut nemesis {
+            if cluster.is_node(&msg.src) && cluster.is_node(&msg.dst) {
+                nemesis.transmit(&mut cluster, msg)?;
+                continue;
+            }
+        }
+
+        if let Some(msg) = cluster.route(msg)? {
+            msg.send(output)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn main(args: &[String]) -> anyhow::Result<()> {
+    let opts = Options::from_args(args)?;
+    run(opts, std::io::stdin(), &mut std::io::stdout().lock())
+}
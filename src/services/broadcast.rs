@@ -1,36 +1,608 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
-use crate::message as msg;
+use crate::message::{self as msg, Message};
+use crate::bloom::Bloom;
+use crate::storage::{self, Storage};
+use crate::config::{Config, LogLevel};
+use crate::metrics::Link;
 
+/// False positive rate of the Bloom filters sent with gossip reads. Every
+/// round salts its filter differently, and reads are only acknowledged up to
+/// the first message the filter left out, so a message missed due to a false
+/// positive is picked up by a later round
+const BLOOM_FP_RATE: f64 = 0.01;
+
+/// Most gossip rounds an unresponsive neighbor is skipped for
+const MAX_BACKOFF_ROUNDS: u64 = 64;
+
+/// Reads in a row a neighbor has to miss to be suspected dead
+const SUSPECT_MISSES: u32 = 4;
+
+/// Shards of a `SeenSet`
+const SEEN_SHARDS: usize = 16;
+
+/// Most propagation times the latency is estimated from
+const PROPAGATION_SAMPLES: usize = 1024;
+
+crate::payload! {
+    /// Payloads handled by the broadcast server
+    pub enum Payload {
+        Topology {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            topology: Option<HashMap<String, Vec<String>>>,
+        },
+        TopologyOk,
+
+        Broadcast { message: usize },
+        BroadcastOk,
+
+        /// Reads from clients and gossip reads from the neighbors. The
+        /// neighbors may tell what they've `seen`, so that only the rest is
+        /// returned, and hand over the `messages` they saved since we last
+        /// read from them. They acknowledge having everything of our log
+        /// `upto` some length, so that only what's past it is returned
+        Read {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            seen: Option<Bloom>,
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            messages: Vec<usize>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            upto: Option<usize>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            epoch: Option<u64>,
+        },
+
+        /// The `messages` read, and the length of the log they were read
+        /// `upto`, for gossip reads. Gossip tells the `epoch` of its sender
+        ReadOk {
+            messages: Vec<usize>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            upto: Option<usize>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            epoch: Option<u64>,
+        },
+
+        /// Start the given `epoch`, or the one after ours, discarding every
+        /// message of the earlier ones. The others follow once they hear of
+        /// it through gossip. Lets a long-lived cluster be reused across
+        /// test scenarios
+        Reset {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            epoch: Option<u64>,
+        },
+        ResetOk { epoch: u64 },
+    }
+}
+
+/// State of a node as exported: its epoch and every message of it
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "snake_case", tag = "type")]
-/// Payloads handled by the broadcast server
-pub enum Payload {
-    Topology { topology: Option<HashMap<String, Vec<String>>> },
-    TopologyOk,
+struct Snapshot {
+    epoch: u64,
+    messages: Vec<usize>,
+}
+
+/// What gossip reads tell about the messages the sender has already seen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GossipFilter {
+    /// Nothing; every read returns everything
+    None,
+
+    /// A Bloom filter of the seen messages
+    Bloom,
+}
+
+impl GossipFilter {
+    /// Parse the filter out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "none"  => Self::None,
+            "bloom" => Self::Bloom,
+            _ => anyhow::bail!("unknown gossip filter `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None  => "none",
+            Self::Bloom => "bloom",
+        }
+    }
+}
+
+/// Order of the messages read replies return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadOrder {
+    /// The order we've first seen them in, which differs between the nodes
+    /// and the runs
+    Seen,
+
+    /// Ascending; makes for stable transcripts to diff and compare against
+    Sorted,
+}
+
+impl ReadOrder {
+    /// Parse the order out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "seen"   => Self::Seen,
+            "sorted" => Self::Sorted,
+            _ => anyhow::bail!("unknown read order `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Seen   => "seen",
+            Self::Sorted => "sorted",
+        }
+    }
+}
+
+/// Our latest gossip read to a neighbor, until it's answered
+#[derive(Debug, Clone, Copy)]
+struct PendingRead {
+    id: usize,
+
+    /// How many of the unsent messages it carried
+    carried: usize,
+
+    /// The round it was sent in
+    round: u64,
+}
+
+/// Our neighbors in the `topology` sent by Maelstrom, or in the one the
+/// config lays over the cluster `nodes` instead. We're never our own
+/// neighbor, and neighbors outside of the cluster are warned about. If the
+/// topology names no neighbors, every other node is one
+pub fn neighbors(id: &str, nodes: &[String],
+        topology: Option<HashMap<String, Vec<String>>>, config: &Config)
+        -> Vec<String> {
+    let topology = config.topology.overlay(nodes).or(topology);
+    let mut seen = HashSet::new();
+    let mut neighbors: Vec<String> = topology
+        .and_then(|mut topology| topology.remove(id))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|neighbor| neighbor != id && seen.insert(neighbor.clone()))
+        .collect();
+
+    for neighbor in &neighbors {
+        if !nodes.contains(neighbor) {
+            config.log(LogLevel::Warn,
+                format_args!("neighbor {neighbor} is not in the cluster"));
+        }
+    }
+    if neighbors.is_empty() {
+        config.log(LogLevel::Warn, format_args!("no neighbors for {id} in \
+            the topology, gossiping with every other node"));
+        neighbors = nodes.iter().filter(|node| *node != id).cloned().collect();
+    }
+    neighbors
+}
+
+/// Change our `neighbors` for the cluster going from `nodes` to `changed`.
+/// Nodes that joined are neighbors of everyone, so that they're caught up;
+/// nodes that left are nobody's neighbors anymore
+pub fn regroup(id: &str, neighbors: &mut Vec<String>, nodes: &[String],
+        changed: &[String]) {
+    neighbors.retain(|neighbor| changed.contains(neighbor));
+    for node in changed {
+        if node != id && !nodes.contains(node) && !neighbors.contains(node) {
+            neighbors.push(node.clone());
+        }
+    }
+}
+
+/// What we know of a neighbor in the topology
+#[derive(Debug, Clone, Default)]
+struct Peer {
+    /// When the neighbor last answered our gossip read
+    synced: Option<Instant>,
+
+    /// Messages we saved since the neighbor last read from us or answered a
+    /// read carrying them. Reads that go unanswered don't lose them; the next
+    /// read carries them along with everything saved since
+    unsent: Vec<usize>,
+
+    read: Option<PendingRead>,
+
+    /// Reads in a row the neighbor didn't answer
+    misses: u32,
+
+    /// The round from which on we gossip with the neighbor again
+    resume: u64,
+
+    /// Whether the neighbor missed so many reads it's suspected dead. It's
+    /// left out of the gossip rounds, and only probed with reads that carry
+    /// none of the messages parked for it in `unsent`
+    suspected: bool,
+
+    /// Whether the neighbor recovered from being suspected and is to be
+    /// gossiped with first thing next round
+    resync: bool,
+
+    /// Length of the log of the neighbor we have everything of
+    upto: usize,
+
+    /// Reads sent to the neighbor, answers received and reads sent while
+    /// the previous one was unanswered, for the statistics of the link
+    sent: u64,
+    acked: u64,
+    retries: u64,
+}
+
+impl Peer {
+    /// Note that the neighbor didn't answer our read by `round`; we back off
+    /// exponentially, up to `MAX_BACKOFF_ROUNDS` rounds. Returns `true` if
+    /// that's the miss that makes it a suspect
+    fn missed(&mut self, round: u64) -> bool {
+        self.misses += 1;
+        let backoff = 1u64.checked_shl(self.misses).unwrap_or(u64::MAX);
+        self.resume = round + backoff.min(MAX_BACKOFF_ROUNDS);
+        let suspected = !self.suspected && self.misses >= SUSPECT_MISSES;
+        self.suspected |= suspected;
+        suspected
+    }
+
+    /// Note that the neighbor got through to us. A suspect is synced in full
+    /// again: we read its log from the start, and the next round hands it
+    /// everything parked for it. Returns `true` if it was a suspect
+    fn recovered(&mut self) -> bool {
+        self.misses = 0;
+        self.resume = 0;
+        if !self.suspected { return false; }
+        self.suspected = false;
+        self.resync = true;
+        self.upto = 0;
+        true
+    }
+
+    /// Order in which the neighbors are gossiped with. Those to resync come
+    /// first; then those we went the longest without hearing from, as
+    /// they're likeliest to have what we're missing; then those we hold the
+    /// most messages for
+    fn priority(&self) -> (bool, Option<Instant>, std::cmp::Reverse<usize>) {
+        (!self.resync, self.synced, std::cmp::Reverse(self.unsent.len()))
+    }
+}
+
+/// Estimate of how long messages take to get around: for every message, the
+/// time from when we first saw it until the last of the neighbors we owed it
+/// had it. Messages still on their way when the topology changes are given
+/// up on
+#[derive(Debug, Default)]
+struct Propagation {
+    /// Messages on their way, along with when we first saw them and the
+    /// amount of neighbors still missing them
+    spreading: HashMap<usize, (Instant, usize)>,
+
+    /// Times the latest messages took
+    samples: VecDeque<Duration>,
+}
+
+impl Propagation {
+    /// Note that we first saw `message` just now and owe it to `neighbors`
+    fn started(&mut self, message: usize, neighbors: usize) {
+        if neighbors > 0 {
+            self.spreading.insert(message, (Instant::now(), neighbors));
+        }
+    }
+
+    /// Note that one more of the neighbors has `message`
+    fn delivered(&mut self, message: usize) {
+        let Some((since, missing)) = self.spreading.get_mut(&message) else {
+            return;
+        };
+        *missing -= 1;
+        if *missing > 0 { return; }
+
+        if self.samples.len() == PROPAGATION_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(since.elapsed());
+        self.spreading.remove(&message);
+    }
+
+    /// Give up on the messages on their way
+    fn forget(&mut self) {
+        self.spreading.clear();
+    }
+
+    /// The `p`th percentile of the times the messages took, in milliseconds
+    fn percentile(&self, p: f64) -> Option<f64> {
+        let mut samples: Vec<Duration> = self.samples.iter().copied()
+            .collect();
+        samples.sort_unstable();
+        let rank = ((p / 100. * samples.len() as f64).ceil() as usize)
+            .max(1);
+        samples.get(rank - 1).map(|took| took.as_secs_f64() * 1000.)
+    }
+
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "spreading": self.spreading.len(),
+            "samples":   self.samples.len(),
+            "p50_ms":    self.percentile(50.),
+            "p99_ms":    self.percentile(99.),
+        })
+    }
+}
+
+/// Set of the messages a node has seen, sharded over read-write locks so
+/// that it can be checked from any thread while the node saves into it
+#[derive(Debug)]
+pub struct SeenSet {
+    shards: Vec<RwLock<HashSet<usize>>>,
+}
+
+impl Default for SeenSet {
+    fn default() -> Self {
+        Self { shards: (0..SEEN_SHARDS).map(|_| RwLock::default()).collect() }
+    }
+}
+
+impl SeenSet {
+    fn shard(&self, message: usize) -> &RwLock<HashSet<usize>> {
+        &self.shards[message % SEEN_SHARDS]
+    }
+
+    /// Add `message` to the set. Returns `true` if it wasn't seen before
+    pub fn insert(&self, message: usize) -> bool {
+        self.shard(message).write().unwrap().insert(message)
+    }
+
+    pub fn contains(&self, message: usize) -> bool {
+        self.shard(message).read().unwrap().contains(&message)
+    }
 
-    Broadcast { message: usize },
-    BroadcastOk,
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take every message out of the set
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.write().unwrap().clear();
+        }
+    }
 
-    Read,
-    ReadOk { messages: Vec<usize> },
+    /// Call `f` with every message in the set, a shard at a time
+    pub fn for_each(&self, mut f: impl FnMut(usize)) {
+        for shard in &self.shards {
+            shard.read().unwrap().iter().for_each(|message| f(*message));
+        }
+    }
+}
+
+/// What gossip reads of the round `round` tell about `seen`, as `filter`
+/// has it
+pub fn gossip_filter(seen: &SeenSet, filter: GossipFilter, round: u64)
+        -> Option<Bloom> {
+    match filter {
+        GossipFilter::None => None,
+        GossipFilter::Bloom => {
+            let mut bloom = Bloom::new(seen.len(), BLOOM_FP_RATE, round);
+            seen.for_each(|message| bloom.insert(&message));
+            Some(bloom)
+        },
+    }
 }
 
-/// A node in the broadcast service cluster
+/// A node in the broadcast service cluster. Nodes regularly read the messages
+/// of their neighbors in the topology
 pub struct BroadcastNode {
-    _id:        String,
-    _neighbors: Option<Vec<String>>,
-    msgs:       Vec<usize>,
+    id: String,
+
+    /// Every node of the cluster
+    nodes: Vec<String>,
+
+    /// Nodes we gossip with, as given by the topology
+    neighbors: Vec<String>,
+
+    /// What we know of each of the `neighbors`
+    peers: HashMap<String, Peer>,
+
+    /// Most neighbors gossiped with per round
+    fanout: Option<usize>,
+
+    /// Messages in the order we've first seen them. Spilled to disk if the
+    /// config has a storage directory
+    msgs: Box<dyn Storage<usize> + Send>,
+
+    /// Set of `msgs`, shared with whoever wants to check it without going
+    /// through the node
+    seen: Arc<SeenSet>,
+
+    /// Epoch the messages are of, and the log of the epochs we started, so
+    /// that a restored node knows which one it's in
+    epoch: u64,
+    epochs: Box<dyn Storage<u64> + Send>,
+
+    /// What our gossip reads tell about `seen`
+    filter: GossipFilter,
+
+    /// Order of the messages our read replies return
+    order: ReadOrder,
+
+    /// ID of the next message we send
+    next_id: usize,
+
+    /// Amount of gossip rounds so far
+    rounds: u64,
+
+    /// How often the neighbors are asked for the messages they've seen
+    gossip_interval: Duration,
+
+    propagation: Propagation,
+
+    config: Config,
+}
+
+impl BroadcastNode {
+    /// The set of the messages the node has seen
+    pub fn seen(&self) -> Arc<SeenSet> {
+        self.seen.clone()
+    }
+
+    /// Save `message` unless we already have it. The neighbor it came `from`,
+    /// if any, obviously has it already
+    fn save(&mut self, message: usize, from: Option<&str>)
+            -> anyhow::Result<()> {
+        if self.seen.insert(message) {
+            self.msgs.append(message)?;
+            let mut owed = 0;
+            for (id, peer) in &mut self.peers {
+                if Some(id.as_str()) != from {
+                    peer.unsent.push(message);
+                    owed += 1;
+                }
+            }
+            self.propagation.started(message, owed);
+        }
+        Ok(())
+    }
+
+    /// Start `epoch`, discarding the messages of ours and whatever we
+    /// were about to gossip of them
+    fn reset(&mut self, epoch: u64) -> anyhow::Result<()> {
+        let name = format!("{}-broadcast", self.id);
+        self.epochs.append(epoch)?;
+        self.msgs = storage::open(self.config.storage_dir.as_deref(), &name)?;
+        self.seen.clear();
+        self.propagation.forget();
+        self.epoch = epoch;
+        for peer in self.peers.values_mut() {
+            peer.unsent.clear();
+            peer.read = None;
+            peer.upto = 0;
+        }
+        self.config.log(LogLevel::Info,
+            format_args!("{} is now in epoch {epoch}", self.id));
+        Ok(())
+    }
+
+    /// Collect the saved messages past the first `skip` for which `keep`
+    /// returns `true`, along with the index of the first one it didn't, or
+    /// the length of the log if it kept them all
+    fn collect(&mut self, skip: usize, keep: impl Fn(&usize) -> bool)
+            -> anyhow::Result<(Vec<usize>, usize)> {
+        let mut messages = Vec::with_capacity(self.msgs.len() - skip);
+        let mut left_out = None;
+        let mut index = 0;
+        self.msgs.for_each(&mut |message| {
+            if index >= skip {
+                match keep(&message) {
+                    true  => messages.push(message),
+                    false => { left_out.get_or_insert(index); },
+                }
+            }
+            index += 1;
+        })?;
+        Ok((messages, left_out.unwrap_or(index)))
+    }
+
+    /// Run a round of gossip, telling the neighbors what we've `seen`
+    pub fn gossip(&mut self, seen: Option<Bloom>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        if self.neighbors.is_empty() { return Ok(()); }
+        self.rounds += 1;
+
+        // Neighbors that didn't answer last round's read are backed off, and
+        // suspected dead once they've missed too many
+        let round = self.rounds;
+        for (id, peer) in &mut self.peers {
+            let missed = peer.read.is_some_and(|read| read.round + 1 == round);
+            if missed && peer.missed(round) {
+                self.config.log(LogLevel::Info, format_args!("suspecting \
+                    {id} to be down, parking {} messages for it",
+                    peer.unsent.len()));
+            }
+        }
+
+        // Suspects are probed on top of the neighbors gossiped with
+        let (suspects, mut neighbors): (Vec<&String>, Vec<&String>) =
+            self.neighbors.iter()
+                .filter(|id| self.peers[*id].resume <= round)
+                .partition(|id| self.peers[*id].suspected);
+        neighbors.sort_by_key(|id| self.peers[*id].priority());
+        neighbors.truncate(self.fanout.unwrap_or(usize::MAX));
+
+        for neighbor in neighbors.into_iter().chain(suspects) {
+            let peer = self.peers.get_mut(neighbor)
+                .expect("neighbor without a peer");
+            self.next_id += 1;
+            peer.sent += 1;
+            if peer.read.is_some() {
+                peer.retries += 1;
+            }
+            peer.resync = false;
+            let carried = if peer.suspected { 0 } else { peer.unsent.len() };
+            peer.read = Some(PendingRead { id: self.next_id, carried, round });
+            let read = Payload::Read {
+                seen:     seen.clone().filter(|_| !peer.suspected),
+                messages: peer.unsent[..carried].to_vec(),
+                upto:     Some(peer.upto),
+                epoch:    Some(self.epoch),
+            };
+            Message::new(&self.id, neighbor, self.next_id, read).send(output)?;
+        }
+        Ok(())
+    }
 }
 
 impl msg::Node<Payload> for BroadcastNode {
-    fn from_init(init: &msg::Init) -> anyhow::Result<Self> {
-        Ok(Self {
-            _id:        init.node_id.clone(),
-            _neighbors: None,
-            msgs:       Vec::with_capacity(1024),
-        })
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let name = format!("{}-broadcast", init.node_id);
+        let dir = config.storage_dir.as_deref();
+        let mut node = Self {
+            id:        init.node_id.clone(),
+            nodes:     init.node_ids.clone(),
+            neighbors: Vec::new(),
+            peers:     HashMap::new(),
+            fanout:    config.gossip_fanout,
+            msgs:      if config.restore { storage::restore(dir, &name)? }
+                else { storage::open(dir, &name)? },
+            epoch:     0,
+            epochs:    if config.restore {
+                storage::restore(dir, &format!("{name}-epochs"))?
+            } else {
+                storage::open(dir, &format!("{name}-epochs"))?
+            },
+            seen:      Arc::default(),
+            filter:    config.gossip_filter,
+            order:     config.read_order,
+            next_id:   0,
+            rounds:    0,
+            gossip_interval: config.gossip_interval,
+            propagation: Propagation::default(),
+            config:    config.clone(),
+        };
+
+        // A restored node doesn't get its topology again, so it catches up on
+        // what it missed by gossiping with everyone
+        if config.restore {
+            let mut epoch = 0;
+            node.epochs.for_each(&mut |started| epoch = started)?;
+            node.epoch = epoch;
+            let seen = node.seen.clone();
+            node.msgs.for_each(&mut |message| { seen.insert(message); })?;
+            node.neighbors = node.nodes.iter()
+                .filter(|id| **id != node.id)
+                .cloned()
+                .collect();
+            node.peers = node.neighbors.iter()
+                .map(|id| (id.clone(), Peer::default()))
+                .collect();
+        }
+        Ok(node)
     }
 
     fn step(&mut self, input: msg::Message<Payload>, output: &mut dyn Write)
@@ -39,36 +611,269 @@ impl msg::Node<Payload> for BroadcastNode {
         let mut input = input;
         let id = input.body.id;
 
+        // Gossip of a later epoch moves us on to it; gossip of an earlier one
+        // is stale, and its sender is told about ours instead
+        let epoch = match &input.body.payload {
+            Payload::Read { epoch, .. } | Payload::ReadOk { epoch, .. } =>
+                *epoch,
+            _ => None,
+        };
+        match epoch {
+            Some(epoch) if epoch > self.epoch => self.reset(epoch)?,
+            Some(epoch) if epoch < self.epoch => {
+                if !matches!(input.body.payload, Payload::Read { .. }) {
+                    return Ok(());
+                }
+                input.body.payload = Payload::ReadOk { messages: Vec::new(),
+                    upto: None, epoch: Some(self.epoch) };
+                return input.into_reply(id).send(output);
+            },
+            _ => {},
+        }
+
         match input.body.payload {
             // Ignore *Ok messages
-            Payload::TopologyOk | Payload::BroadcastOk |
-                Payload::ReadOk { .. } => Ok(()),
+            Payload::TopologyOk | Payload::BroadcastOk | Payload::ResetOk { .. }
+                => Ok(()),
+
+            Payload::Reset { epoch } => {
+                let epoch = epoch.unwrap_or(self.epoch + 1);
+                if epoch > self.epoch {
+                    self.reset(epoch)?;
+                }
+                input.body.payload = Payload::ResetOk { epoch: self.epoch };
+                input.into_reply(id).send(output)
+            },
 
-            // Ignore topology
-            Payload::Topology { .. } => {
+            // Replies to our gossip
+            Payload::ReadOk { messages, upto, .. } => {
+                if let Some(peer) = self.peers.get_mut(&input.src) {
+                    // Replies may arrive out of order; the later ones have
+                    // read further
+                    peer.upto = peer.upto.max(upto.unwrap_or(0));
+                    peer.synced = Some(Instant::now());
+                    peer.acked += 1;
+                    match peer.read {
+                        Some(read) if Some(read.id) == input.body.reply_id => {
+                            for message in peer.unsent.drain(..read.carried) {
+                                self.propagation.delivered(message);
+                            }
+                            peer.read = None;
+                        },
+                        _ => {},
+                    }
+                    if peer.recovered() {
+                        self.config.log(LogLevel::Info, format_args!(
+                            "{} is back, resyncing with it", input.src));
+                    }
+                }
+                for message in messages {
+                    self.save(message, Some(&input.src))?;
+                }
+                Ok(())
+            },
+
+            // Gossip with our neighbors in the topology
+            Payload::Topology { topology } => {
+                self.neighbors = neighbors(&self.id, &self.nodes, topology,
+                    &self.config);
+                self.peers = self.neighbors.iter()
+                    .map(|id| (id.clone(), Peer::default()))
+                    .collect();
+                self.propagation.forget();
                 input.body.payload = Payload::TopologyOk;
                 input.into_reply(id).send(output)
             },
 
             // Save the message that was broadcasted
             Payload::Broadcast { message } => {
-                self.msgs.push(message);
+                self.save(message, None)?;
                 input.body.payload = Payload::BroadcastOk;
                 input.into_reply(id).send(output)
             },
 
-            // Save the messages that were received
-            Payload::Read => {
-                // XXX: Surely there is a better way than clone?
-                input.body.payload = Payload::ReadOk {
-                    messages: self.msgs.clone()
+            // Send the messages the reader has not seen; that's everything
+            // we held for it
+            Payload::Read { seen, messages: carried, upto, epoch } => {
+                for message in &carried {
+                    self.save(*message, Some(&input.src))?;
+                }
+
+                // A reader that has more of our log than there is saw an
+                // earlier run of ours, and starts over. The filter may take
+                // messages for seen that the reader lacks, so the reader is
+                // only told it has the log up to the first one it left out,
+                // and holds on to what it left out of what we owe the reader
+                let len = self.msgs.len();
+                let skip = upto.filter(|upto| *upto <= len).unwrap_or(0);
+                let carried: HashSet<usize> = carried.into_iter().collect();
+                let (mut messages, read) = match &seen {
+                    Some(seen) => self.collect(skip, |message|
+                        !seen.contains(message) || carried.contains(message))?,
+                    None => self.collect(skip, |_| true)?,
                 };
+                if seen.is_some() {
+                    messages.retain(|message| !carried.contains(message));
+                }
+                let sent: Option<HashSet<usize>> = seen.as_ref().map(|_|
+                    messages.iter().chain(&carried).copied().collect());
+                if let Some(peer) = self.peers.get_mut(&input.src) {
+                    peer.unsent.retain(|message| {
+                        let sent = sent.as_ref()
+                            .is_none_or(|sent| sent.contains(message));
+                        if sent {
+                            self.propagation.delivered(*message);
+                        }
+                        !sent
+                    });
+                    peer.read = None;
+                    if peer.recovered() {
+                        self.config.log(LogLevel::Info, format_args!(
+                            "{} is back, resyncing with it", input.src));
+                    }
+                }
+                if self.order == ReadOrder::Sorted {
+                    messages.sort_unstable();
+                }
+                input.body.payload = Payload::ReadOk { messages,
+                    upto:  upto.map(|_| read),
+                    epoch: epoch.map(|_| self.epoch) };
                 input.into_reply(id).send(output)
             }
         }
     }
-}
 
-pub fn main() -> anyhow::Result<()> {
-    msg::main_loop::<Payload, BroadcastNode>()
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        regroup(&self.id, &mut self.neighbors, &self.nodes, nodes);
+        self.peers.retain(|id, _| self.neighbors.contains(id));
+        for neighbor in &self.neighbors {
+            self.peers.entry(neighbor.clone()).or_default();
+        }
+        self.propagation.forget();
+        self.nodes = nodes.to_vec();
+        true
+    }
+
+    /// A neighbor announcing itself was likely restarted; gossip with it
+    /// again right away rather than waiting out its backoff, and read its
+    /// log from the start
+    fn hello(&mut self, node: &str, _capabilities: &msg::Capabilities) {
+        if let Some(peer) = self.peers.get_mut(node) {
+            peer.recovered();
+            peer.read = None;
+            peer.upto = 0;
+        }
+    }
+
+    fn export(&mut self) -> anyhow::Result<Option<serde_json::Value>> {
+        let snapshot = Snapshot {
+            epoch:    self.epoch,
+            messages: self.collect(0, |_| true)?.0,
+        };
+        Ok(Some(serde_json::to_value(snapshot)?))
+    }
+
+    /// The messages of a later epoch than ours move us on to it, those of
+    /// an earlier one are stale
+    fn import(&mut self, from: &str, state: serde_json::Value)
+            -> anyhow::Result<bool> {
+        let snapshot: Snapshot = serde_json::from_value(state)?;
+        if snapshot.epoch > self.epoch {
+            self.reset(snapshot.epoch)?;
+        }
+        if snapshot.epoch == self.epoch {
+            for message in snapshot.messages {
+                self.save(message, Some(from))?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn links(&self) -> BTreeMap<String, Link> {
+        let now = Instant::now();
+        self.peers.iter().map(|(id, peer)| (id.clone(), Link {
+            sent:    peer.sent,
+            acked:   peer.acked,
+            retries: peer.retries,
+            synced_ms_ago: peer.synced
+                .map(|synced| (now - synced).as_millis() as u64),
+        })).collect()
+    }
+
+    fn extensions(&self) -> Vec<String> {
+        match self.filter {
+            GossipFilter::None  => Vec::new(),
+            GossipFilter::Bloom => vec!["bloom".into()],
+        }
+    }
+
+    /// The topology sets the node up, so it goes ahead of the broadcasts
+    fn urgent(&self, input: &Message<Payload>) -> bool {
+        matches!(input.body.payload, Payload::Topology { .. })
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "epoch":     self.epoch,
+            "messages":  self.msgs.len(),
+            "neighbors": self.neighbors,
+            "unsent":    self.peers.iter()
+                .map(|(id, peer)| (id.clone(), peer.unsent.len()))
+                .collect::<HashMap<_, _>>(),
+            "backoff":   self.peers.iter()
+                .filter(|(_, peer)| peer.misses > 0)
+                .map(|(id, peer)| (id.clone(), peer.misses))
+                .collect::<HashMap<_, _>>(),
+            "suspected": self.neighbors.iter()
+                .filter(|id| self.peers.get(*id)
+                    .is_some_and(|peer| peer.suspected))
+                .collect::<Vec<_>>(),
+            "propagation": self.propagation.summary(),
+        })
+    }
+
+    fn dump(&self) -> serde_json::Value {
+        let now = Instant::now();
+        let peers = self.peers.iter().map(|(id, peer)| (id.clone(),
+            serde_json::json!({
+                "synced_ms_ago": peer.synced
+                    .map(|synced| (now - synced).as_millis() as u64),
+                "unsent": peer.unsent,
+                "read":   peer.read.map(|read| serde_json::json!({
+                    "msg_id":  read.id,
+                    "carried": read.carried,
+                    "round":   read.round,
+                })),
+                "misses": peer.misses,
+                "resume": peer.resume,
+                "suspected": peer.suspected,
+                "resync": peer.resync,
+                "upto":   peer.upto,
+                "sent":    peer.sent,
+                "acked":   peer.acked,
+                "retries": peer.retries,
+            }))).collect::<HashMap<_, _>>();
+        serde_json::json!({
+            "nodes":     self.nodes,
+            "neighbors": self.neighbors,
+            "peers":     peers,
+            "messages":  self.msgs.len(),
+            "epoch":     self.epoch,
+            "rounds":    self.rounds,
+            "next_id":   self.next_id,
+            "propagation": self.propagation.summary(),
+        })
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.gossip_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        if self.neighbors.is_empty() { return Ok(()); }
+        let seen = gossip_filter(&self.seen, self.filter, self.rounds + 1);
+        self.gossip(seen, output)
+    }
 }
+
+crate::service_main!("broadcast", Payload, BroadcastNode);
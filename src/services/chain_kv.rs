@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::{self as msg, Message, error_code};
+use crate::services::lww_kv::Key;
+use crate::storage::{self, Storage, StorageEngine};
+use crate::config::Config;
+
+/// Timer passing the pending writes on again, once they went
+/// unacknowledged for the retry timeout
+const RETRY_TIMER: &str = "retry";
+
+/// Times in a row the writes are passed on again before the successor is
+/// taken for failed and dropped from the chain
+const SUSPECT_RETRIES: usize = 3;
+
+/// A client operation, as forwarded to the node that serves it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Op {
+    Read { key: Key },
+    Write { key: Key, value: Value },
+    Cas {
+        key: Key,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+/// A write flowing down the chain. It's the `seq`th write the head ordered
+/// and the tail answers `client` once it's applied there
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Update {
+    pub seq: u64,
+    pub key: Key,
+    pub value: Value,
+    pub client: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<usize>,
+
+    /// Whether the client asked for a CAS rather than a write
+    #[serde(default)]
+    pub cas: bool,
+}
+
+crate::payload! {
+    /// Payloads handled by the chain replication KV server
+    pub enum Payload {
+        Read { key: Key },
+        ReadOk { value: Value },
+        Write { key: Key, value: Value },
+        WriteOk,
+        Cas {
+            key: Key,
+            from: Value,
+            to: Value,
+            #[serde(default)]
+            create_if_not_exists: bool,
+        },
+        CasOk,
+
+        /// An operation of `client` handed to the head or the tail
+        Forward {
+            client: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            client_id: Option<usize>,
+            op: Op,
+        },
+
+        /// Writes passed to the successor in the chain
+        Propagate {
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            updates: Vec<Update>,
+        },
+
+        /// Passed up the chain once the tail applied every write up to `seq`
+        Ack { seq: u64 },
+
+        /// Replace the chain, if `epoch` is newer than the one we know. Sent by
+        /// whatever detects failed nodes
+        Reconfigure { epoch: u64, chain: Vec<String> },
+        ReconfigureOk,
+
+        Error { code: usize, text: String },
+    }
+}
+
+/// State of a node as exported: every write up to the `applied`th, as the
+/// values they left
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    applied: u64,
+    entries: Vec<(Key, Value)>,
+}
+
+/// A node in the chain replication KV cluster. The nodes form a chain; writes
+/// enter at the head, which orders them, and flow down to the tail, which
+/// answers the clients and serves the reads. Every node holds on to the
+/// writes it passed on until the tail acknowledges them, so that they can be
+/// passed on again when the chain is reconfigured
+pub struct ChainKvNode<E = Box<dyn StorageEngine<Key, Value>>> {
+    id: String,
+
+    /// The chain, from the head to the tail
+    chain: Vec<String>,
+
+    /// Version of the chain
+    epoch: u64,
+
+    next_id: usize,
+
+    data: E,
+
+    /// Sequence number of the last write applied, and the log of them, so
+    /// that a restored node knows where it left off
+    applied: u64,
+    applied_log: Box<dyn Storage<u64> + Send>,
+
+    /// Writes passed on but not yet acknowledged by the tail
+    pending: BTreeMap<u64, Update>,
+
+    /// How long to wait for an acknowledgement before passing `pending` on
+    /// again. The `RETRY_TIMER` is set for it whenever they make progress
+    retry_timeout: Duration,
+
+    /// Times `pending` was passed on again since it last made progress
+    retries: usize,
+}
+
+impl<E: StorageEngine<Key, Value>> ChainKvNode<E> {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.chain.iter().position(|node| *node == self.id)
+    }
+
+    fn head(&self) -> Option<&String> {
+        self.chain.first()
+    }
+
+    fn tail(&self) -> Option<&String> {
+        self.chain.last()
+    }
+
+    fn is_tail(&self) -> bool {
+        self.tail() == Some(&self.id)
+    }
+
+    fn predecessor(&self) -> Option<String> {
+        self.position()
+            .and_then(|pos| pos.checked_sub(1))
+            .map(|pos| self.chain[pos].clone())
+    }
+
+    fn successor(&self) -> Option<String> {
+        self.position().and_then(|pos| self.chain.get(pos + 1)).cloned()
+    }
+
+    /// Send `payload` to `dst` as a new message, replying to `reply_id`
+    fn send(&mut self, dst: &str, reply_id: Option<usize>, payload: Payload,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        let id = self.next_id();
+        let mut msg = Message::new(&self.id, dst, id, payload);
+        msg.body.reply_id = reply_id;
+        msg.send(output)
+    }
+
+    /// Serve `op` of `client` if we're the node to do so, hand it to that
+    /// node otherwise
+    fn serve(&mut self, client: String, client_id: Option<usize>, op: Op,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        let target = match op {
+            Op::Read { .. } => self.tail(),
+            _ => self.head(),
+        };
+        let Some(target) = target.cloned() else {
+            return self.send(&client, client_id, Payload::Error {
+                code: error_code::TEMPORARILY_UNAVAILABLE,
+                text: "the chain is empty".into(),
+            }, output);
+        };
+        if target != self.id {
+            let forward = Payload::Forward { client, client_id, op };
+            return self.send(&target, None, forward, output);
+        }
+
+        let (key, value, cas) = match op {
+            Op::Read { key } => {
+                let reply = match self.data.get(&key)? {
+                    Some(value) => Payload::ReadOk { value },
+                    None => Payload::Error {
+                        code: error_code::KEY_DOES_NOT_EXIST,
+                        text: "key does not exist".into(),
+                    },
+                };
+                return self.send(&client, client_id, reply, output);
+            },
+            Op::Write { key, value } => (key, value, false),
+            Op::Cas { key, from, to, create_if_not_exists } => {
+                // The head has every write ordered so far, so that's what
+                // the CAS is checked against
+                let error = match self.data.get(&key)? {
+                    Some(current) if current == from => None,
+                    Some(current) => Some((error_code::PRECONDITION_FAILED,
+                        format!("expected {from}, had {current}"))),
+                    None if create_if_not_exists => None,
+                    None => Some((error_code::KEY_DOES_NOT_EXIST,
+                        "key does not exist".into())),
+                };
+                if let Some((code, text)) = error {
+                    let error = Payload::Error { code, text };
+                    return self.send(&client, client_id, error, output);
+                }
+                (key, to, true)
+            },
+        };
+
+        let update = Update { seq: self.applied + 1, key, value, client,
+            client_id, cas };
+        self.apply(update, output)
+    }
+
+    /// Note that every write up to the `seq`th is applied. The write
+    /// itself is saved first: should we go down in between, it's passed on
+    /// to us again, and applying it twice does no harm
+    fn set_applied(&mut self, seq: u64) -> anyhow::Result<()> {
+        self.applied_log.append(seq)?;
+        self.applied = seq;
+        Ok(())
+    }
+
+    /// Apply the next write and pass it on, or answer it if we're the tail
+    fn apply(&mut self, update: Update, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        self.data.put(update.key.clone(), update.value.clone())?;
+        self.set_applied(update.seq)?;
+
+        if self.is_tail() {
+            self.answer(&update, output)?;
+            return self.ack(self.applied, output);
+        }
+
+        if self.pending.is_empty() {
+            msg::set_timer(RETRY_TIMER, self.retry_timeout);
+        }
+        self.pending.insert(update.seq, update.clone());
+        if let Some(successor) = self.successor() {
+            let propagate = Payload::Propagate { updates: vec![update] };
+            self.send(&successor, None, propagate, output)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the client of `update` it went through
+    fn answer(&mut self, update: &Update, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let reply = if update.cas { Payload::CasOk } else { Payload::WriteOk };
+        self.send(&update.client.clone(), update.client_id, reply, output)
+    }
+
+    /// Forget the writes up to `seq` and pass the acknowledgement up
+    fn ack(&mut self, seq: u64, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        if self.pending.first_key_value().is_some_and(|(first, _)|
+                *first <= seq) {
+            msg::set_timer(RETRY_TIMER, self.retry_timeout);
+            self.retries = 0;
+        }
+        self.pending.retain(|pending, _| *pending > seq);
+        match self.predecessor() {
+            Some(predecessor) =>
+                self.send(&predecessor, None, Payload::Ack { seq }, output),
+            None => Ok(()),
+        }
+    }
+
+    /// Pass every pending write on again. If we're the tail now, they went as
+    /// far as they could and are answered
+    fn resend(&mut self, output: &mut dyn io::Write) -> anyhow::Result<()> {
+        if self.pending.is_empty() { return Ok(()); }
+
+        if self.is_tail() {
+            for update in core::mem::take(&mut self.pending).into_values() {
+                self.answer(&update, output)?;
+            }
+            return self.ack(self.applied, output);
+        }
+
+        msg::set_timer(RETRY_TIMER, self.retry_timeout);
+        if let Some(successor) = self.successor() {
+            let updates = self.pending.values().cloned().collect();
+            self.send(&successor, None, Payload::Propagate { updates },
+                output)?;
+        }
+        Ok(())
+    }
+
+    /// Take on `chain` as the next version of the chain
+    fn rechain(&mut self, chain: Vec<String>) {
+        self.epoch += 1;
+        self.chain = chain;
+        self.retries = 0;
+    }
+
+    /// Drop the successor that kept ignoring our writes from the chain, and
+    /// tell everyone in it, the successor included
+    fn suspect(&mut self, successor: &str, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let others: Vec<String> = self.chain.iter()
+            .filter(|id| **id != self.id)
+            .cloned()
+            .collect();
+        self.rechain(self.chain.iter()
+            .filter(|id| *id != successor)
+            .cloned()
+            .collect());
+        let reconfigure = Payload::Reconfigure {
+            epoch: self.epoch,
+            chain: self.chain.clone(),
+        };
+        for other in others {
+            self.send(&other, None, reconfigure.clone(), output)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: StorageEngine<Key, Value>> msg::Node<Payload> for ChainKvNode<E> {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let mut chain = init.node_ids.clone();
+        chain.sort();
+        let name = format!("{}-chain-kv", init.node_id);
+        let dir = config.storage_dir.as_deref();
+
+        let mut node = Self {
+            id:        init.node_id.clone(),
+            chain,
+            epoch:     0,
+            next_id:   0,
+            data:      if config.restore { E::restore(dir, &name)? }
+                else { E::open(dir, &name)? },
+            applied:   0,
+            applied_log: if config.restore {
+                storage::restore(dir, &format!("{name}-applied"))?
+            } else {
+                storage::open(dir, &format!("{name}-applied"))?
+            },
+            pending:   BTreeMap::new(),
+            retry_timeout: config.retry_timeout,
+            retries:   0,
+        };
+
+        // Writes we passed on but weren't acknowledged are still pending
+        // at the nodes before us, which pass them on again
+        if config.restore {
+            let mut applied = 0;
+            node.applied_log.for_each(&mut |seq| applied = seq)?;
+            node.applied = applied;
+        }
+        Ok(node)
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let client = input.src;
+        let client_id = input.body.id;
+
+        match input.body.payload {
+            // Ignore *Ok messages and errors
+            Payload::ReadOk { .. } | Payload::WriteOk | Payload::CasOk |
+                Payload::ReconfigureOk | Payload::Error { .. } => Ok(()),
+
+            Payload::Read { key } =>
+                self.serve(client, client_id, Op::Read { key }, output),
+            Payload::Write { key, value } =>
+                self.serve(client, client_id, Op::Write { key, value },
+                    output),
+            Payload::Cas { key, from, to, create_if_not_exists } => {
+                let op = Op::Cas { key, from, to, create_if_not_exists };
+                self.serve(client, client_id, op, output)
+            },
+
+            Payload::Forward { client, client_id, op } =>
+                self.serve(client, client_id, op, output),
+
+            // Apply the writes in order. Ones we have already are only
+            // acknowledged again by the tail, ones past a gap wait for the
+            // predecessor to pass them on again
+            Payload::Propagate { updates } => {
+                let mut duplicate = false;
+                for update in updates {
+                    if update.seq == self.applied + 1 {
+                        self.apply(update, output)?;
+                    } else if update.seq <= self.applied {
+                        duplicate = true;
+                    }
+                }
+                if duplicate && self.is_tail() {
+                    self.ack(self.applied, output)?;
+                }
+                Ok(())
+            },
+
+            Payload::Ack { seq } => self.ack(seq, output),
+
+            Payload::Reconfigure { epoch, chain } => {
+                if epoch > self.epoch {
+                    self.epoch = epoch;
+                    self.chain = chain;
+                    self.retries = 0;
+                    self.resend(output)?;
+                }
+                self.send(&client, client_id, Payload::ReconfigureOk, output)
+            },
+        }
+    }
+
+    fn timer(&mut self, name: &str, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        // The writes or their acknowledgements got lost along the way. If
+        // that keeps happening, the successor is likely gone
+        match name {
+            RETRY_TIMER => {
+                if !self.pending.is_empty() {
+                    self.retries += 1;
+                }
+                if let Some(successor) = self.successor()
+                        .filter(|_| self.retries >= SUSPECT_RETRIES) {
+                    self.suspect(&successor, output)?;
+                }
+                self.resend(output)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn export(&mut self) -> anyhow::Result<Option<Value>> {
+        let snapshot = Snapshot {
+            applied: self.applied,
+            entries: self.data.snapshot()?,
+        };
+        Ok(Some(serde_json::to_value(snapshot)?))
+    }
+
+    /// The head orders every write, so a node that applied more than we
+    /// did has every write we applied as well. Its state is taken on only
+    /// then; whatever it didn't apply yet reaches us down the chain
+    fn import(&mut self, _from: &str, state: Value) -> anyhow::Result<bool> {
+        let snapshot: Snapshot = serde_json::from_value(state)?;
+        if snapshot.applied > self.applied {
+            for (key, value) in snapshot.entries {
+                self.data.put(key, value)?;
+            }
+            self.set_applied(snapshot.applied)?;
+        }
+        Ok(true)
+    }
+
+    /// Nodes that left are dropped from the chain, and whatever they didn't
+    /// acknowledge is passed on again. Nodes that joined stay out of it, as
+    /// they'd miss the writes ordered before, and hand their clients to it
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        let chain: Vec<String> = self.chain.iter()
+            .filter(|id| nodes.contains(id))
+            .cloned()
+            .collect();
+        if chain != self.chain {
+            self.rechain(chain);
+            msg::set_timer(RETRY_TIMER, Duration::ZERO);
+        }
+        true
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({
+            "chain":   self.chain,
+            "epoch":   self.epoch,
+            "applied": self.applied,
+            "pending": self.pending.len(),
+        })
+    }
+}
+
+crate::service_main!("chain-kv", Payload, ChainKvNode);
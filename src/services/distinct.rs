@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use crate::message::{self as msg, Message};
+use crate::hll::Hll;
+use crate::services::broadcast::{neighbors, regroup};
+use crate::config::Config;
+
+/// Registers of the sketches are `2^PRECISION`, for an error of about 3%
+const PRECISION: u32 = 10;
+
+/// Every this many gossip rounds the sketch is sent even if it didn't change,
+/// making up for lost gossip
+const RESYNC_ROUNDS: u64 = 10;
+
+crate::payload! {
+    /// Payloads handled by the distinct count server
+    pub enum Payload {
+        Topology {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            topology: Option<HashMap<String, Vec<String>>>,
+        },
+        TopologyOk,
+
+        Broadcast { message: usize },
+        BroadcastOk,
+
+        /// Approximate amount of distinct messages broadcast so far
+        Count,
+        CountOk { count: u64 },
+
+        /// The sketch of a neighbor, to be merged into ours
+        Gossip { sketch: Hll },
+    }
+}
+
+/// A node in the distinct count service cluster. Instead of the messages, a
+/// node keeps a HyperLogLog sketch of them and gossips it to its neighbors;
+/// what it remembers and sends stays the same size however many messages are
+/// broadcast
+pub struct DistinctNode {
+    id: String,
+
+    /// Every node of the cluster
+    nodes: Vec<String>,
+
+    /// Nodes we gossip with, as given by the topology
+    neighbors: Vec<String>,
+
+    /// Sketch of every message we've heard of
+    sketch: Hll,
+
+    /// The sketch changed since we last gossiped it
+    changed: bool,
+
+    /// ID of the next message we send
+    next_id: usize,
+
+    /// Amount of gossip rounds so far
+    rounds: u64,
+
+    gossip_interval: Duration,
+
+    config: Config,
+}
+
+impl msg::Node<Payload> for DistinctNode {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self {
+            id:        init.node_id.clone(),
+            nodes:     init.node_ids.clone(),
+            neighbors: Vec::new(),
+            sketch:    Hll::new(PRECISION),
+            changed:   false,
+            next_id:   0,
+            rounds:    0,
+            gossip_interval: config.gossip_interval,
+            config:    config.clone(),
+        })
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        // We will change the input into a reply later on, so mark it mutable
+        let mut input = input;
+        let id = input.body.id;
+
+        match input.body.payload {
+            // Ignore *Ok messages
+            Payload::TopologyOk | Payload::BroadcastOk |
+                Payload::CountOk { .. } => Ok(()),
+
+            Payload::Topology { topology } => {
+                self.neighbors = neighbors(&self.id, &self.nodes, topology,
+                    &self.config);
+                input.body.payload = Payload::TopologyOk;
+                input.into_reply(id).send(output)
+            },
+
+            Payload::Broadcast { message } => {
+                self.changed |= self.sketch.insert(&message);
+                input.body.payload = Payload::BroadcastOk;
+                input.into_reply(id).send(output)
+            },
+
+            Payload::Count => {
+                input.body.payload = Payload::CountOk {
+                    count: self.sketch.count(),
+                };
+                input.into_reply(id).send(output)
+            },
+
+            // Whatever the neighbor taught us is passed on in the next round
+            Payload::Gossip { sketch } => {
+                self.changed |= self.sketch.merge(&sketch)?;
+                Ok(())
+            },
+        }
+    }
+
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        regroup(&self.id, &mut self.neighbors, &self.nodes, nodes);
+        self.nodes = nodes.to_vec();
+
+        // Whoever joined has yet to hear of our sketch
+        self.changed = true;
+        true
+    }
+
+    fn export(&mut self) -> anyhow::Result<Option<serde_json::Value>> {
+        Ok(Some(serde_json::to_value(&self.sketch)?))
+    }
+
+    /// The sketch is merged as if it was gossiped
+    fn import(&mut self, _from: &str, state: serde_json::Value)
+            -> anyhow::Result<bool> {
+        let sketch: Hll = serde_json::from_value(state)?;
+        self.changed |= self.sketch.merge(&sketch)?;
+        Ok(true)
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "count":     self.sketch.count(),
+            "neighbors": self.neighbors,
+        })
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.gossip_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        if self.neighbors.is_empty() { return Ok(()); }
+        self.rounds += 1;
+        if !self.changed && !self.rounds.is_multiple_of(RESYNC_ROUNDS) {
+            return Ok(());
+        }
+        self.changed = false;
+
+        for neighbor in &self.neighbors {
+            self.next_id += 1;
+            let gossip = Payload::Gossip { sketch: self.sketch.clone() };
+            Message::new(&self.id, neighbor, self.next_id, gossip)
+                .send(output)?;
+        }
+        Ok(())
+    }
+}
+
+crate::service_main!("distinct-count", Payload, DistinctNode);
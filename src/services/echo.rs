@@ -1,24 +1,50 @@
 use std::io::Write;
-use serde::{Serialize, Deserialize};
+use std::time::{Duration, Instant};
+use serde_json::Value;
 use crate::message as msg;
+use crate::rng::Rng;
+use crate::config::Config;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "snake_case", tag = "type")]
-/// Payloads handled by the echo server
-pub enum Payload {
-    Echo   { echo: String },
-    EchoOk { echo: String },
+crate::payload! {
+    /// Payloads handled by the echo server. Whatever JSON is sent is echoed
+    pub enum Payload {
+        Echo   { echo: Value },
+        EchoOk { echo: Value },
+    }
 }
 
+/// Finest granularity replies are held back with
+const DELAY_RESOLUTION: Duration = Duration::from_millis(1);
+
 /// A node in the echo service cluster
 pub struct EchoNode {
     _id: String,
+
+    /// How long replies are held back, plus up to `jitter` at random
+    delay: Duration,
+    jitter: Duration,
+    rng: Rng,
+
+    /// Replies held back, along with when they're due
+    delayed: Vec<(Instant, msg::Message<Payload>)>,
+}
+
+impl EchoNode {
+    /// Whether replies are held back at all
+    fn delays(&self) -> bool {
+        !self.delay.is_zero() || !self.jitter.is_zero()
+    }
 }
 
 impl msg::Node<Payload> for EchoNode {
-    fn from_init(init: &msg::Init) -> anyhow::Result<Self> {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
         Ok(Self {
-            _id: init.node_id.clone(),
+            _id:     init.node_id.clone(),
+            delay:   config.echo_delay,
+            jitter:  config.echo_jitter,
+            rng:     Rng::from_time(),
+            delayed: Vec::new(),
         })
     }
 
@@ -31,13 +57,37 @@ impl msg::Node<Payload> for EchoNode {
         match input.body.payload {
             Payload::Echo { echo } => {
                 input.body.payload = Payload::EchoOk { echo };
-                input.into_reply(id).send(output)
+                let reply = input.into_reply(id);
+                if !self.delays() { return reply.send(output); }
+
+                let jitter = self.rng.below(self.jitter.as_millis() as u64 + 1);
+                let due = Instant::now() + self.delay
+                    + Duration::from_millis(jitter);
+                self.delayed.push((due, reply));
+                Ok(())
             },
             Payload::EchoOk { .. } => Ok(()),
         }
     }
-}
 
-pub fn main() -> anyhow::Result<()> {
-    msg::main_loop::<Payload, EchoNode>()
+    fn tick_interval(&self) -> Option<Duration> {
+        self.delays().then_some(DELAY_RESOLUTION)
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let (due, delayed): (Vec<_>, _) = std::mem::take(&mut self.delayed)
+            .into_iter().partition(|(due, _)| *due <= now);
+        self.delayed = delayed;
+        for (_, reply) in due {
+            reply.send(output)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({ "delayed": self.delayed.len() })
+    }
 }
+
+crate::service_main!("echo", Payload, EchoNode);
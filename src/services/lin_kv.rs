@@ -0,0 +1,708 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::{self as msg, Message, error_code};
+use crate::services::lww_kv::Key;
+use crate::state_machine::{StateMachine, Stamped};
+use crate::session::Sessions;
+use crate::vr::{self, Replica, Client, ClientPayload};
+use crate::config::Config;
+
+/// Most commands the primary proposes in a single batch, and most bytes of
+/// them. Past either, the batch is proposed before its window is up
+const MAX_BATCH: usize = 64;
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// An operation of a batch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Op {
+    Read { key: Key },
+    Write { key: Key, value: Value },
+    Cas {
+        key: Key,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+/// Operations of the clients, as ordered by the replicas. Writes made within
+/// a session are fenced; see `Sessions`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Command {
+    Read { key: Key },
+    Write {
+        key: Key,
+        value: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<u64>,
+    },
+    Cas {
+        key: Key,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<u64>,
+    },
+    Batch {
+        ops: Vec<Op>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<u64>,
+    },
+    CreateIndex { field: String },
+    QueryIndex { field: String, value: Value },
+    OpenSession { ttl: u64 },
+    KeepAlive { session: u64 },
+    CloseSession { session: u64 },
+}
+
+/// How up to date the value returned by a read has to be
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Consistency {
+    /// Whatever the replica asked has applied
+    One,
+
+    /// The most recent value applied by a majority of the replicas asked
+    Quorum,
+
+    /// The most recent value applied by any of the replicas, all of them
+    /// asked
+    All,
+
+    /// Ordered with every write through the log
+    #[default]
+    Linearizable,
+}
+
+crate::payload! {
+    /// Requests and replies of the clients of the linearizable KV
+    pub enum KvPayload {
+        /// Read `key`. With `max_staleness_ms`, a backup that heard from the
+        /// primary within that long answers right away, whatever `consistency`
+        Read {
+            key: Key,
+            #[serde(default)]
+            consistency: Consistency,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            max_staleness_ms: Option<u64>,
+        },
+        ReadOk { value: Value },
+        Write {
+            key: Key,
+            value: Value,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            session: Option<u64>,
+        },
+        WriteOk,
+        Cas {
+            key: Key,
+            from: Value,
+            to: Value,
+            #[serde(default)]
+            create_if_not_exists: bool,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            session: Option<u64>,
+        },
+        CasOk,
+
+        /// Apply the `ops` in order, all of them or none. Each sees the writes
+        /// of those before it. Spares the clients a round trip per key
+        Batch {
+            ops: Vec<Op>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            session: Option<u64>,
+        },
+
+        /// For each of the ops, the value read; null for the writes, the CASes
+        /// and the keys that don't exist
+        BatchOk { values: Vec<Option<Value>> },
+
+        /// Index the keys by the `field` of their values, for the values that
+        /// are objects with that field. Kept up to date with every write from
+        /// then on
+        CreateIndex { field: String },
+        CreateIndexOk,
+
+        /// The keys whose values have `field` set to `value`, in order
+        QueryIndex { field: String, value: Value },
+        QueryIndexOk { keys: Vec<Key> },
+
+        /// Open a session whose lease lasts `ttl` milliseconds
+        OpenSession { ttl: u64 },
+
+        /// The ID of the session opened, which is also its fencing token
+        OpenSessionOk { session: u64 },
+
+        /// Renew the lease of `session`
+        KeepAlive { session: u64 },
+        KeepAliveOk,
+
+        CloseSession { session: u64 },
+        CloseSessionOk,
+
+        /// A command of `client` handed to the primary
+        Forward {
+            client: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            client_id: Option<usize>,
+            command: Command,
+        },
+
+        /// Ask a replica for its value of `key`, for the read `read`
+        Query { read: usize, key: Key },
+
+        /// The value of the replica and the amount of operations it committed
+        QueryOk {
+            read: usize,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            value: Option<Value>,
+            commit: usize,
+        },
+
+        /// Errors of requests sent to a replica that isn't the primary carry
+        /// the primary it knows of, for the client to go to instead
+        Error {
+            code: usize,
+            text: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            leader: Option<String>,
+        },
+    }
+}
+
+impl ClientPayload<Command> for KvPayload {
+    fn forward(client: Client, command: Command) -> Self {
+        Self::Forward { client: client.src, client_id: client.id, command }
+    }
+
+    fn not_primary(primary: &str) -> Self {
+        Self::Error {
+            code:   error_code::TEMPORARILY_UNAVAILABLE,
+            text:   "not the primary".into(),
+            leader: Some(primary.into()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+/// Payloads handled by the linearizable KV server; those of the clients and
+/// those of the replication protocol
+pub enum Payload {
+    Kv(KvPayload),
+    Vr(vr::Payload<Stamped<Command>>),
+}
+
+/// The KV, as replicated by the consensus module
+#[derive(Debug, Clone, Default)]
+pub struct KvMachine {
+    data: BTreeMap<Key, Value>,
+    sessions: Sessions<Key>,
+
+    /// Secondary indexes by the field they index. Each maps the values of
+    /// the field, as serialized, to the keys holding them
+    indexes: BTreeMap<String, BTreeMap<String, BTreeSet<Key>>>,
+
+    /// The latest time a command was proposed at. Primaries of different
+    /// views may disagree on the time, the clock never goes back
+    clock: u64,
+}
+
+impl KvMachine {
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.data.get(key)
+    }
+
+    /// Put `value` under `key`, moving the key to where the value belongs
+    /// in the indexes along with it
+    fn put(&mut self, key: Key, value: Value) {
+        let old = self.data.get(&key);
+        for (field, index) in &mut self.indexes {
+            if let Some(indexed) = old.and_then(|old| indexed(old, field)) {
+                let emptied = index.get_mut(&indexed)
+                    .is_some_and(|keys| keys.remove(&key) && keys.is_empty());
+                if emptied {
+                    index.remove(&indexed);
+                }
+            }
+            if let Some(indexed) = indexed(&value, field) {
+                index.entry(indexed).or_default().insert(key.clone());
+            }
+        }
+        self.data.insert(key, value);
+    }
+
+    /// Index the keys by `field`, unless they are already
+    fn create_index(&mut self, field: String) {
+        if self.indexes.contains_key(&field) { return; }
+        let mut index: BTreeMap<String, BTreeSet<Key>> = BTreeMap::new();
+        for (key, value) in &self.data {
+            if let Some(indexed) = indexed(value, &field) {
+                index.entry(indexed).or_default().insert(key.clone());
+            }
+        }
+        self.indexes.insert(field, index);
+    }
+
+    /// Write `value` to `key` if the `session` writing it, if any, may, and
+    /// reply with `ok`
+    fn write(&mut self, key: Key, value: Value, session: Option<u64>,
+            ok: KvPayload) -> KvPayload {
+        if let Some(session) = session {
+            if let Err(err) = self.sessions.fence(key.clone(), session,
+                    self.clock) {
+                return KvPayload::Error {
+                    code: error_code::PRECONDITION_FAILED,
+                    text: err.to_string(),
+                    leader: None,
+                };
+            }
+        }
+        self.put(key, value);
+        ok
+    }
+
+    /// Apply the `ops` of a batch of the `session`, if any. The writes are
+    /// collected and only applied once every op succeeded, so that a failed
+    /// op leaves the KV as it was
+    fn batch(&mut self, ops: Vec<Op>, session: Option<u64>) -> KvPayload {
+        let error = |code, text| KvPayload::Error { code, text, leader: None };
+        let mut written: BTreeMap<Key, Value> = BTreeMap::new();
+        let mut values = Vec::with_capacity(ops.len());
+        for (idx, op) in ops.into_iter().enumerate() {
+            match op {
+                Op::Read { key } => values.push(written.get(&key)
+                    .or_else(|| self.data.get(&key))
+                    .cloned()),
+                Op::Write { key, value } => {
+                    written.insert(key, value);
+                    values.push(None);
+                },
+                Op::Cas { key, from, to, create_if_not_exists } => {
+                    match written.get(&key).or_else(|| self.data.get(&key)) {
+                        Some(current) if *current == from => {},
+                        Some(current) => return error(
+                            error_code::PRECONDITION_FAILED, format!(
+                                "op {idx}: expected {from}, had {current}")),
+                        None if create_if_not_exists => {},
+                        None => return error(error_code::KEY_DOES_NOT_EXIST,
+                            format!("op {idx}: key does not exist")),
+                    }
+                    written.insert(key, to);
+                    values.push(None);
+                },
+            }
+        }
+
+        // Fence the keys on a copy of the sessions, which is only kept if
+        // the session may write all of them
+        if let Some(session) = session {
+            let mut sessions = self.sessions.clone();
+            for key in written.keys() {
+                if let Err(err) = sessions.fence(key.clone(), session,
+                        self.clock) {
+                    return error(error_code::PRECONDITION_FAILED,
+                        err.to_string());
+                }
+            }
+            self.sessions = sessions;
+        }
+        for (key, value) in written {
+            self.put(key, value);
+        }
+        KvPayload::BatchOk { values }
+    }
+}
+
+impl StateMachine for KvMachine {
+    type Command = Stamped<Command>;
+    type Output = KvPayload;
+
+    fn apply(&mut self, Stamped { now_ms, command }: Stamped<Command>)
+            -> KvPayload {
+        let missing = || KvPayload::Error {
+            code: error_code::KEY_DOES_NOT_EXIST,
+            text: "key does not exist".into(),
+            leader: None,
+        };
+        self.clock = self.clock.max(now_ms);
+        self.sessions.expire(self.clock);
+
+        match command {
+            Command::Read { key } => match self.data.get(&key) {
+                Some(value) => KvPayload::ReadOk { value: value.clone() },
+                None => missing(),
+            },
+            Command::Write { key, value, session } =>
+                self.write(key, value, session, KvPayload::WriteOk),
+            Command::Cas { key, from, to, create_if_not_exists, session } => {
+                match self.data.get(&key) {
+                    Some(current) if *current == from => {},
+                    Some(current) => return KvPayload::Error {
+                        code: error_code::PRECONDITION_FAILED,
+                        text: format!("expected {from}, had {current}"),
+                        leader: None,
+                    },
+                    None if create_if_not_exists => {},
+                    None => return missing(),
+                }
+                self.write(key, to, session, KvPayload::CasOk)
+            },
+            Command::Batch { ops, session } => self.batch(ops, session),
+            Command::CreateIndex { field } => {
+                self.create_index(field);
+                KvPayload::CreateIndexOk
+            },
+            Command::QueryIndex { field, value } => {
+                let Some(index) = self.indexes.get(&field) else {
+                    return KvPayload::Error {
+                        code: error_code::KEY_DOES_NOT_EXIST,
+                        text: format!("no index on `{field}`"),
+                        leader: None,
+                    };
+                };
+                let keys = index.get(&value.to_string())
+                    .map(|keys| keys.iter().cloned().collect())
+                    .unwrap_or_default();
+                KvPayload::QueryIndexOk { keys }
+            },
+            Command::OpenSession { ttl } => KvPayload::OpenSessionOk {
+                session: self.sessions.open(ttl, self.clock),
+            },
+            Command::KeepAlive { session } => {
+                match self.sessions.keep_alive(session, self.clock) {
+                    Ok(()) => KvPayload::KeepAliveOk,
+                    Err(err) => KvPayload::Error {
+                        code: error_code::PRECONDITION_FAILED,
+                        text: err.to_string(),
+                        leader: None,
+                    },
+                }
+            },
+            Command::CloseSession { session } => {
+                self.sessions.close(session);
+                KvPayload::CloseSessionOk
+            },
+        }
+    }
+}
+
+/// The `field` of `value` as indexed, if it's an object with that field
+fn indexed(value: &Value, field: &str) -> Option<String> {
+    value.get(field).map(Value::to_string)
+}
+
+/// A read asking several replicas
+struct QuorumRead {
+    client: Client,
+
+    /// Answers still needed
+    needed: usize,
+
+    /// The most recent answer so far, by the commit of the replica
+    latest: (usize, Option<Value>),
+
+    started: Instant,
+}
+
+/// A node in the linearizable KV cluster. Every write, and every read unless
+/// the client settles for less, is ordered by the consensus module before
+/// it's applied to the KV. The module is Viewstamped Replication; any other
+/// module driving a `StateMachine` could take its place
+pub struct LinKvNode {
+    id: String,
+    replica: Replica<KvMachine>,
+
+    /// Every other node of the cluster
+    peers: Vec<String>,
+
+    /// Reads waiting for the answers of the replicas, by their ID
+    reads: HashMap<usize, QuorumRead>,
+
+    /// Commands queued by the primary to be proposed together, along with
+    /// their size in bytes and when the first of them was queued
+    batch: Vec<(Stamped<Command>, Option<Client>)>,
+    batch_bytes: usize,
+    batch_started: Instant,
+
+    /// How long commands are queued before they're proposed. Each is
+    /// proposed right away without it
+    batch_window: Duration,
+
+    retry_timeout: Duration,
+}
+
+impl LinKvNode {
+    /// Reply to `client` with the value `value` of a read
+    fn answer_read(&mut self, client: Client, value: Option<Value>,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        let reply = match value {
+            Some(value) => KvPayload::ReadOk { value },
+            None => KvPayload::Error {
+                code: error_code::KEY_DOES_NOT_EXIST,
+                text: "key does not exist".into(),
+                leader: None,
+            },
+        };
+        self.replica.send_client(&client.src, client.id, reply, output)
+    }
+
+    /// Read `key` off our replica and the replicas of `asked` of the others,
+    /// answering with the most recent value
+    fn quorum_read(&mut self, client: Client, key: Key, asked: usize,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        let value = self.replica.machine().get(&key).cloned();
+        if asked == 0 {
+            return self.answer_read(client, value, output);
+        }
+
+        let read = self.replica.next_id();
+        self.reads.insert(read, QuorumRead {
+            client,
+            needed:  asked,
+            latest:  (self.replica.commit(), value),
+            started: Instant::now(),
+        });
+        for peer in self.peers.clone() {
+            let query = KvPayload::Query { read, key: key.clone() };
+            self.replica.send_client(&peer, None, query, output)?;
+        }
+        Ok(())
+    }
+
+    /// Propose the queued commands at once. Their clients are told to go
+    /// elsewhere if we stopped being the primary since they were queued
+    fn propose_batch(&mut self, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        if self.batch.is_empty() { return Ok(()); }
+        let batch = core::mem::take(&mut self.batch);
+        self.batch_bytes = 0;
+
+        let clients: Vec<Client> = batch.iter()
+            .filter_map(|(_, client)| client.clone())
+            .collect();
+        match self.replica.propose_all(batch, output)? {
+            Some(applied) => self.replica.answer(applied, output),
+            None => clients.into_iter().try_for_each(|client|
+                self.replica.not_primary(client, output)),
+        }
+    }
+
+    /// Queue `command` of `client` to be proposed along with the batch if
+    /// we're the primary and batch the commands, submit it to the replica
+    /// otherwise
+    fn submit(&mut self, client: Client, command: Command, forwarded: bool,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        if self.replica.is_primary() && !self.batch_window.is_zero() {
+            let stamped = Stamped { now_ms: msg::now_ms(), command };
+            if self.batch.is_empty() {
+                self.batch_started = Instant::now();
+            }
+            self.batch_bytes += serde_json::to_string(&stamped)?.len();
+            self.batch.push((stamped, Some(client)));
+            if self.batch.len() >= MAX_BATCH ||
+                    self.batch_bytes >= MAX_BATCH_BYTES {
+                self.propose_batch(output)?;
+            }
+            return Ok(());
+        }
+        self.replica.submit(client, command, forwarded, output)
+    }
+}
+
+impl msg::Node<Payload> for LinKvNode {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self {
+            id:      init.node_id.clone(),
+            replica: vr::open(init, config, "lin-kv", KvMachine::default())?,
+            peers:   init.node_ids.iter()
+                .filter(|id| **id != init.node_id)
+                .cloned()
+                .collect(),
+            reads:   HashMap::new(),
+            batch:   Vec::new(),
+            batch_bytes:   0,
+            batch_started: Instant::now(),
+            batch_window:  config.batch_window,
+            retry_timeout: config.retry_timeout,
+        })
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let client = Client { src: input.src, id: input.body.id };
+
+        let command = match input.body.payload {
+            Payload::Vr(payload) => {
+                let applied = self.replica.step(client.src, payload, output)?;
+                return self.replica.answer(applied, output);
+            },
+
+            Payload::Kv(KvPayload::Forward { client, client_id, command }) => {
+                let client = Client { src: client, id: client_id };
+                return self.submit(client, command, true, output);
+            },
+
+            // Ignore *Ok messages and errors
+            Payload::Kv(KvPayload::ReadOk { .. } | KvPayload::WriteOk |
+                KvPayload::CasOk | KvPayload::BatchOk { .. } |
+                KvPayload::CreateIndexOk | KvPayload::QueryIndexOk { .. } |
+                KvPayload::OpenSessionOk { .. } |
+                KvPayload::KeepAliveOk | KvPayload::CloseSessionOk |
+                KvPayload::Error { .. }) => return Ok(()),
+
+            Payload::Kv(KvPayload::Query { read, key }) => {
+                let query_ok = KvPayload::QueryOk {
+                    read,
+                    value:  self.replica.machine().get(&key).cloned(),
+                    commit: self.replica.commit(),
+                };
+                return self.replica.send_client(&client.src, client.id,
+                    query_ok, output);
+            },
+
+            Payload::Kv(KvPayload::QueryOk { read, value, commit }) => {
+                let Some(pending) = self.reads.get_mut(&read) else {
+                    return Ok(());
+                };
+                if commit > pending.latest.0 {
+                    pending.latest = (commit, value);
+                }
+                pending.needed -= 1;
+                if pending.needed > 0 { return Ok(()); }
+
+                let pending = self.reads.remove(&read)
+                    .expect("finished read is gone");
+                return self.answer_read(pending.client, pending.latest.1,
+                    output);
+            },
+
+            Payload::Kv(KvPayload::Read { key, consistency,
+                    max_staleness_ms }) => {
+                let fresh = max_staleness_ms.zip(self.replica.staleness())
+                    .is_some_and(|(max, staleness)|
+                        staleness <= Duration::from_millis(max));
+                if fresh {
+                    return self.quorum_read(client, key, 0, output);
+                }
+
+                // Along with ours, the answers of a majority of the cluster
+                let quorum = self.peers.len().div_ceil(2);
+                match consistency {
+                    Consistency::One =>
+                        return self.quorum_read(client, key, 0, output),
+                    Consistency::Quorum =>
+                        return self.quorum_read(client, key, quorum, output),
+                    Consistency::All => {
+                        let all = self.peers.len();
+                        return self.quorum_read(client, key, all, output);
+                    },
+                    Consistency::Linearizable => Command::Read { key },
+                }
+            },
+            Payload::Kv(KvPayload::Write { key, value, session }) =>
+                Command::Write { key, value, session },
+            Payload::Kv(KvPayload::Cas { key, from, to,
+                    create_if_not_exists, session }) =>
+                Command::Cas { key, from, to, create_if_not_exists, session },
+            Payload::Kv(KvPayload::Batch { ops, session }) =>
+                Command::Batch { ops, session },
+            Payload::Kv(KvPayload::CreateIndex { field }) =>
+                Command::CreateIndex { field },
+            Payload::Kv(KvPayload::QueryIndex { field, value }) =>
+                Command::QueryIndex { field, value },
+            Payload::Kv(KvPayload::OpenSession { ttl }) =>
+                Command::OpenSession { ttl },
+            Payload::Kv(KvPayload::KeepAlive { session }) =>
+                Command::KeepAlive { session },
+            Payload::Kv(KvPayload::CloseSession { session }) =>
+                Command::CloseSession { session },
+        };
+        self.submit(client, command, false, output)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        let retry = self.retry_timeout / 5;
+        if self.batch_window.is_zero() {
+            Some(retry)
+        } else {
+            Some(retry.min(self.batch_window))
+        }
+    }
+
+    fn tick(&mut self, output: &mut dyn io::Write) -> anyhow::Result<()> {
+        // Not enough replicas answered the reads in time
+        let expired: Vec<usize> = self.reads.iter()
+            .filter(|(_, read)| read.started.elapsed() > self.retry_timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let read = self.reads.remove(&id).expect("expired read is gone");
+            let client = read.client;
+            self.replica.send_client(&client.src, client.id, KvPayload::Error {
+                code: error_code::TEMPORARILY_UNAVAILABLE,
+                text: "not enough replicas answered".into(),
+                leader: None,
+            }, output)?;
+        }
+
+        let applied = self.replica.tick(output)?;
+        self.replica.answer(applied, output)?;
+
+        // After the heartbeats, which would send the batch over again
+        if !self.batch.is_empty() &&
+                self.batch_started.elapsed() >= self.batch_window {
+            self.propose_batch(output)?;
+        }
+        Ok(())
+    }
+
+    /// The replicas follow the cluster through the log, one node at a time
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        self.replica.reconfigure(nodes);
+        self.peers = nodes.iter().filter(|id| **id != self.id).cloned()
+            .collect();
+        true
+    }
+
+    fn unavailable(&self, input: &Message<Payload>) -> Option<String> {
+        let client = matches!(input.body.payload, Payload::Kv(
+            KvPayload::Read { .. } | KvPayload::Write { .. } |
+            KvPayload::Cas { .. } | KvPayload::Batch { .. } |
+            KvPayload::CreateIndex { .. } | KvPayload::QueryIndex { .. } |
+            KvPayload::OpenSession { .. } |
+            KvPayload::KeepAlive { .. } | KvPayload::CloseSession { .. }));
+        (client && self.replica.status() == vr::Status::ViewChange)
+            .then(|| "view change in progress".into())
+    }
+
+    fn urgent(&self, input: &Message<Payload>) -> bool {
+        matches!(&input.body.payload, Payload::Vr(vr) if vr.changes_view())
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({
+            "view":     self.replica.view(),
+            "primary":  self.replica.primary(),
+            "replicas": self.replica.replicas(),
+            "op":       self.replica.op(),
+            "commit":   self.replica.commit(),
+            "reads":    self.reads.len(),
+            "batch":    self.batch.len(),
+            "sessions": self.replica.machine().sessions.len(),
+            "indexes":  self.replica.machine().indexes.keys()
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+crate::service_main!("lin-kv", Payload, LinKvNode);
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::{self as msg, Message, error_code};
+use crate::services::lww_kv::Key;
+use crate::state_machine::{StateMachine, Stamped};
+use crate::session::Sessions;
+use crate::vr::{self, Replica, Client, ClientPayload};
+use crate::config::Config;
+
+/// Operations on the locks, as ordered by the replicas
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Command {
+    Acquire { key: Key, ttl: u64, holder: String },
+    Release { key: Key, token: u64 },
+}
+
+crate::payload! {
+    /// Requests and replies of the clients of the lock service
+    pub enum LockPayload {
+        /// Take the lock `key` for `ttl` milliseconds. Acquiring a lock already
+        /// held renews its lease
+        Acquire { key: Key, ttl: u64 },
+
+        /// The fencing token of the lock; every acquisition gets a larger one
+        AcquireOk { token: u64 },
+
+        Release { key: Key, token: u64 },
+        ReleaseOk,
+
+        /// A command of `client` handed to the primary
+        Forward {
+            client: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            client_id: Option<usize>,
+            command: Command,
+        },
+
+        /// Errors of requests sent to a replica that isn't the primary carry
+        /// the primary it knows of, for the client to go to instead
+        Error {
+            code: usize,
+            text: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            leader: Option<String>,
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+/// Payloads handled by the lock server; those of the clients and those of the
+/// replication protocol
+pub enum Payload {
+    Lock(LockPayload),
+    Vr(vr::Payload<Stamped<Command>>),
+}
+
+impl ClientPayload<Command> for LockPayload {
+    fn forward(client: Client, command: Command) -> Self {
+        Self::Forward { client: client.src, client_id: client.id, command }
+    }
+
+    fn not_primary(primary: &str) -> Self {
+        Self::Error {
+            code:   error_code::TEMPORARILY_UNAVAILABLE,
+            text:   "not the primary".into(),
+            leader: Some(primary.into()),
+        }
+    }
+}
+
+/// Who holds a lock
+#[derive(Debug, Clone, PartialEq)]
+struct Holder {
+    client: String,
+
+    /// The session the lock was acquired in
+    token: u64,
+}
+
+/// The locks, as replicated by the consensus module. Every acquisition opens
+/// a session; the lock is free again once it's released or the lease of the
+/// session runs out
+#[derive(Debug, Clone, Default)]
+pub struct LockMachine {
+    locks: BTreeMap<Key, Holder>,
+    sessions: Sessions<Key>,
+
+    /// The latest time a command was proposed at
+    clock: u64,
+}
+
+impl LockMachine {
+    /// The lock `key`, if it's held
+    fn held(&self, key: &Key) -> Option<&Holder> {
+        self.locks.get(key)
+            .filter(|holder| self.sessions.lease(holder.token).is_some())
+    }
+}
+
+impl StateMachine for LockMachine {
+    type Command = Stamped<Command>;
+    type Output = LockPayload;
+
+    fn apply(&mut self, Stamped { now_ms, command }: Stamped<Command>)
+            -> LockPayload {
+        self.clock = self.clock.max(now_ms);
+        self.sessions.expire(self.clock);
+
+        match command {
+            Command::Acquire { key, ttl, holder } => match self.held(&key) {
+                Some(held) if held.client == holder => {
+                    let token = held.token;
+                    self.sessions.renew(token, ttl, self.clock)
+                        .expect("lease of a held lock expired");
+                    LockPayload::AcquireOk { token }
+                },
+                Some(held) => LockPayload::Error {
+                    code: error_code::PRECONDITION_FAILED,
+                    text: format!("lock held by {}", held.client),
+                    leader: None,
+                },
+                None => {
+                    let token = self.sessions.open(ttl, self.clock);
+                    self.locks.insert(key, Holder { client: holder, token });
+                    LockPayload::AcquireOk { token }
+                },
+            },
+            Command::Release { key, token } => match self.held(&key) {
+                Some(held) if held.token == token => {
+                    self.locks.remove(&key);
+                    self.sessions.close(token);
+                    LockPayload::ReleaseOk
+                },
+                _ => LockPayload::Error {
+                    code: error_code::PRECONDITION_FAILED,
+                    text: format!("lock not held with token {token}"),
+                    leader: None,
+                },
+            },
+        }
+    }
+}
+
+/// A node of the lock service. Locks are kept in a state machine replicated
+/// by Viewstamped Replication, the same as the linearizable KV
+pub struct LockNode {
+    replica: Replica<LockMachine>,
+    tick_interval: Duration,
+}
+
+impl msg::Node<Payload> for LockNode {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self {
+            replica: vr::open(init, config, "lock", LockMachine::default())?,
+            tick_interval: config.retry_timeout / 5,
+        })
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let client = Client { src: input.src, id: input.body.id };
+
+        let command = match input.body.payload {
+            Payload::Vr(payload) => {
+                let applied = self.replica.step(client.src, payload, output)?;
+                return self.replica.answer(applied, output);
+            },
+
+            Payload::Lock(LockPayload::Forward { client, client_id,
+                    command }) => {
+                let client = Client { src: client, id: client_id };
+                return self.replica.submit(client, command, true, output);
+            },
+
+            // Ignore *Ok messages and errors
+            Payload::Lock(LockPayload::AcquireOk { .. } |
+                LockPayload::ReleaseOk | LockPayload::Error { .. }) =>
+                return Ok(()),
+
+            Payload::Lock(LockPayload::Acquire { key, ttl }) =>
+                Command::Acquire { key, ttl, holder: client.src.clone() },
+            Payload::Lock(LockPayload::Release { key, token }) =>
+                Command::Release { key, token },
+        };
+        self.replica.submit(client, command, false, output)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.tick_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let applied = self.replica.tick(output)?;
+        self.replica.answer(applied, output)
+    }
+
+    fn unavailable(&self, input: &Message<Payload>) -> Option<String> {
+        let client = matches!(input.body.payload, Payload::Lock(
+            LockPayload::Acquire { .. } | LockPayload::Release { .. }));
+        (client && self.replica.status() == vr::Status::ViewChange)
+            .then(|| "view change in progress".into())
+    }
+
+    /// The replicas follow the cluster, one replica at a time
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        self.replica.reconfigure(nodes);
+        true
+    }
+
+    fn urgent(&self, input: &Message<Payload>) -> bool {
+        matches!(&input.body.payload, Payload::Vr(vr) if vr.changes_view())
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({
+            "view":     self.replica.view(),
+            "primary":  self.replica.primary(),
+            "replicas": self.replica.replicas(),
+            "commit":   self.replica.commit(),
+            "locks":    self.replica.machine().locks.len(),
+        })
+    }
+}
+
+crate::service_main!("lock", Payload, LockNode);
@@ -0,0 +1,810 @@
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::io;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::{self as msg, Message, error_code};
+use crate::hlc::{self, Hlc, Timestamp};
+use crate::merkle::{self, Merkle};
+use crate::replies::Replies;
+use crate::storage::StorageEngine;
+use crate::config::{Config, LogLevel};
+
+/// Every this many gossip rounds, the replica is compared with one of the
+/// peers to repair whatever got lost
+const ANTI_ENTROPY_ROUNDS: usize = 10;
+
+/// Every this many gossip rounds, expired entries are swept
+const SWEEP_ROUNDS: usize = 10;
+
+/// How long expired entries are kept around after expiring. They shadow older
+/// values of the key until every replica has seen them expire
+const TOMBSTONE_GRACE_MS: u64 = 10_000;
+
+/// How long requests wait for the replica to catch up with their session
+const SESSION_WAIT: Duration = Duration::from_secs(1);
+
+/// Most requests held back waiting for their sessions at once. Past it, new
+/// requests are turned away until the replica catches up
+const MAX_WAITING: usize = 1024;
+
+/// Most pairs returned by a single scan
+const SCAN_LIMIT: usize = 1000;
+
+/// Keys of the KV. Maelstrom uses integers, but strings are accepted as well.
+/// All integers sort before all strings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord,
+    Hash)]
+#[serde(untagged)]
+pub enum Key {
+    Int(i64),
+    Str(String),
+}
+
+/// Session token of a client. For every node the client talked to, the version
+/// of the node's replica the client has observed. A replica serves the client
+/// once it includes all those versions, giving read-your-writes and monotonic
+/// reads
+pub type Session = BTreeMap<String, Timestamp>;
+
+/// A replicated entry of the KV
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: Key,
+    pub value: Value,
+
+    /// When the value was written
+    pub ts: Timestamp,
+
+    /// The node that wrote the value, breaking ties between equal timestamps
+    pub writer: String,
+
+    /// Wall-clock milliseconds since the UNIX epoch when the entry expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    /// Returns `true` if the entry expired by the wall-clock time `now_ms`
+    fn expired(&self, now_ms: u64) -> bool {
+        self.expires_at.is_some_and(|at| now_ms >= at)
+    }
+
+    /// Hash of the entry in the Merkle tree of the replica
+    fn digest(&self) -> u64 {
+        merkle::hash(&(&self.key, self.ts, &self.writer))
+    }
+
+    /// Returns `true` if this entry wins over `other` on merge
+    fn newer_than(&self, other: &Entry) -> bool {
+        (self.ts, &self.writer) > (other.ts, &other.writer)
+    }
+}
+
+crate::payload! {
+    /// Payloads handled by the LWW KV server
+    pub enum Payload {
+        Read {
+            key: Key,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+        ReadOk {
+            value: Value,
+
+            /// Version of the value, for a later write to be conditional on
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            version: Option<Timestamp>,
+
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        Write {
+            key: Key,
+            value: Value,
+
+            /// Milliseconds after which the written value expires
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            expires_ms: Option<u64>,
+
+            /// Version the key has to be at for the write to go through, as
+            /// checked against the local replica; zero if it may not exist
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            if_version: Option<Timestamp>,
+
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        /// The `version` of the value written
+        WriteOk {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            version: Option<Timestamp>,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        Cas {
+            key: Key,
+            from: Value,
+            to: Value,
+            #[serde(default)]
+            create_if_not_exists: bool,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+        CasOk {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            version: Option<Timestamp>,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        /// Scan the keys in `[from, to)` in order, returning at most `limit`
+        /// pairs. Missing bounds leave the range open on that side
+        Scan {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            from: Option<Key>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            to: Option<Key>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            limit: Option<usize>,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        /// Pairs found by a scan. If there were more pairs than returned,
+        /// `next` is the `from` continuing the scan
+        ScanOk {
+            pairs: Vec<(Key, Value)>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            next: Option<Key>,
+            #[serde(default, skip_serializing_if = "Session::is_empty")]
+            session: Session,
+        },
+
+        /// Register the sender to be notified of changes to `key`
+        Watch { key: Key },
+        WatchOk,
+
+        /// Pushed to the watchers of `key` whenever its value changes.
+        /// `version` orders the notifications of a key
+        Changed { key: Key, value: Value, version: Timestamp },
+
+        /// Start of anti-entropy. Hashes of the top subtrees of the sender's
+        /// Merkle tree at its replica `version`
+        Digest { version: Timestamp, hashes: Vec<u64> },
+
+        /// Hashes of the leaves of the subtrees that differ from the digest
+        DigestLeaves { version: Timestamp, subtrees: Vec<(usize, Vec<u64>)> },
+
+        /// Entries gossiped between the replicas. Once merged, the receiver
+        /// includes the sender's replica at version `through`, as long as it
+        /// already included the version `prev` the sender previously sent
+        Replicate {
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            entries: Vec<Entry>,
+            #[serde(default)]
+            prev: Timestamp,
+            #[serde(default)]
+            through: Timestamp,
+        },
+
+        /// A write or CAS of `client` a read-only replica hands to a primary,
+        /// which serves it as if the client sent it there
+        Forward {
+            client: String,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            client_id: Option<usize>,
+            request: Box<Payload>,
+        },
+
+        Error { code: usize, text: String },
+    }
+}
+
+impl Payload {
+    /// The session token of client requests and replies
+    fn session_mut(&mut self) -> Option<&mut Session> {
+        match self {
+            Self::Read { session, .. } | Self::ReadOk { session, .. } |
+            Self::Write { session, .. } | Self::WriteOk { session, .. } |
+            Self::Cas { session, .. } | Self::CasOk { session, .. } |
+            Self::Scan { session, .. } | Self::ScanOk { session, .. } =>
+                Some(session),
+            _ => None,
+        }
+    }
+}
+
+/// A node in the last-write-wins replicated KV cluster. Every write is
+/// stamped with a hybrid logical timestamp and the replicas gossip their
+/// entries; the higher timestamp wins on merge. Writes may expire, after which
+/// the key reads as missing. Clients may watch keys to be told of every change
+/// seen by the node they registered with. Clients presenting a session token
+/// are only served once the replica caught up with it.
+/// The data lives in the storage engine `E`, by default the one selected by
+/// the storage directory of the config
+pub struct LwwKvNode<E = Box<dyn StorageEngine<Key, Entry>>> {
+    id: String,
+
+    /// All the other nodes in the cluster
+    peers: Vec<String>,
+
+    /// ID of the next message we send
+    next_id: usize,
+
+    hlc: Hlc,
+
+    /// The replicated data
+    data: E,
+
+    /// Merkle tree of `data`, compared during anti-entropy
+    tree: Merkle,
+
+    /// Keys written or merged since the last gossip round
+    dirty: HashSet<Key>,
+
+    /// Amount of gossip rounds so far
+    rounds: usize,
+
+    /// Clients watching each of the keys
+    watchers: HashMap<Key, BTreeSet<String>>,
+
+    /// Version of our replica, bumped on every change to it
+    version: Timestamp,
+
+    /// Versions of the other replicas that ours is known to include
+    seen: HashMap<String, Timestamp>,
+
+    /// The version sent to each peer in the last gossip round
+    sent: HashMap<String, Timestamp>,
+
+    /// Requests waiting until their deadline for the replica to catch up
+    /// with their session
+    waiting: Vec<(Instant, Message<Payload>)>,
+
+    /// How often the written entries are gossiped to the peers
+    gossip_interval: Duration,
+
+    /// Nodes of the cluster that apply the writes, if we're a read-only
+    /// replica forwarding ours to them; empty if we apply them ourselves
+    primaries: Vec<String>,
+
+    /// Replies to the writes, so that retries aren't applied twice
+    replies: Replies<Payload>,
+}
+
+impl<E: StorageEngine<Key, Entry>> LwwKvNode<E> {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Store `value` under `key` as a fresh local write, expiring after
+    /// `ttl_ms` if given. Returns the version written
+    fn write(&mut self, key: Key, value: Value, ttl_ms: Option<u64>)
+            -> anyhow::Result<Timestamp> {
+        let ts = self.hlc.now();
+        let entry = Entry {
+            key: key.clone(),
+            value,
+            ts,
+            writer: self.id.clone(),
+            expires_at: ttl_ms.map(|ttl| ts.physical_ms() + ttl),
+        };
+        self.insert(entry)?;
+        self.version = ts;
+        Ok(ts)
+    }
+
+    /// Put `entry` into the replica, replacing what was there for its key
+    fn insert(&mut self, entry: Entry) -> anyhow::Result<()> {
+        let bucket = merkle::bucket(&entry.key);
+        self.tree.toggle(bucket, entry.digest());
+        self.dirty.insert(entry.key.clone());
+        if let Some(old) = self.data.put(entry.key.clone(), entry)? {
+            self.tree.toggle(bucket, old.digest());
+        }
+        Ok(())
+    }
+
+    /// Get the live entry of `key`. Expired entries read as missing
+    fn get(&mut self, key: &Key) -> anyhow::Result<Option<Entry>> {
+        Ok(self.data.get(key)?
+            .filter(|entry| !entry.expired(hlc::wall_clock_ms())))
+    }
+
+    /// Collect up to `limit` live pairs with keys in `[from, to)` into a
+    /// `ScanOk`, along with the key to continue from if there are more
+    fn scan(&mut self, from: Option<Key>, to: Option<Key>, limit: usize)
+            -> anyhow::Result<Payload> {
+        let mut pairs = Vec::new();
+        let mut next = None;
+
+        let lower = from.as_ref().map_or(Bound::Unbounded, Bound::Included);
+        let upper = to.as_ref().map_or(Bound::Unbounded, Bound::Excluded);
+        if matches!((lower, upper), (Bound::Included(a), Bound::Excluded(b))
+                if a >= b) {
+            return Ok(Payload::ScanOk { pairs, next, session: Session::new() });
+        }
+
+        let now = hlc::wall_clock_ms();
+        self.data.scan((lower, upper), &mut |key, entry| {
+            if entry.expired(now) { return true; }
+            if pairs.len() == limit {
+                next = Some(key);
+                return false;
+            }
+            pairs.push((key, entry.value));
+            true
+        })?;
+        Ok(Payload::ScanOk { pairs, next, session: Session::new() })
+    }
+
+    /// Forget the entries that expired long enough ago
+    fn sweep(&mut self) -> anyhow::Result<()> {
+        let horizon = hlc::wall_clock_ms().saturating_sub(TOMBSTONE_GRACE_MS);
+        for (key, entry) in self.data.snapshot()? {
+            if entry.expired(horizon) {
+                self.tree.toggle(merkle::bucket(&key), entry.digest());
+                self.data.remove(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge an entry gossiped by another replica. Returns `true` if the
+    /// entry replaced what we had
+    fn merge(&mut self, entry: Entry) -> anyhow::Result<bool> {
+        self.hlc.update(entry.ts);
+        if self.data.get(&entry.key)?
+                .is_some_and(|cur| !entry.newer_than(&cur)) {
+            return Ok(false);
+        }
+        self.insert(entry)?;
+        self.version = self.hlc.now();
+        Ok(true)
+    }
+
+    /// Rebuild what's derived from the replica after it was restored. The
+    /// clock is moved past every entry, so that later writes win over them
+    fn recover(&mut self) -> anyhow::Result<()> {
+        for (key, entry) in self.data.snapshot()? {
+            self.tree.toggle(merkle::bucket(&key), entry.digest());
+            self.hlc.update(entry.ts);
+        }
+        self.version = self.hlc.now();
+        Ok(())
+    }
+
+    /// Returns `true` if our replica includes everything `session` observed
+    fn covers(&self, session: &Session) -> bool {
+        session.iter().all(|(node, version)| if *node == self.id {
+            self.version >= *version
+        } else {
+            self.seen.get(node).is_some_and(|seen| seen >= version)
+        })
+    }
+
+    /// Serve the waiting requests our replica caught up with and give up on
+    /// the ones past their deadline
+    fn serve_waiting(&mut self, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let now = Instant::now();
+        for (deadline, mut input) in core::mem::take(&mut self.waiting) {
+            // The client gave up on it already
+            if input.body.expired() { continue; }
+
+            let ready = input.body.payload.session_mut()
+                .is_none_or(|session| self.covers(session));
+            if ready {
+                msg::Node::step(self, input, output)?;
+            } else if now >= deadline {
+                let id = input.body.id;
+                input.body.payload = Payload::Error {
+                    code: error_code::TEMPORARILY_UNAVAILABLE,
+                    text: "replica did not catch up with the session".into(),
+                };
+                input.into_reply(id).send(output)?;
+            } else {
+                self.waiting.push((deadline, input));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell the watchers of `key` about its current value
+    fn notify(&mut self, key: &Key, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let Some(watchers) = self.watchers.get(key) else { return Ok(()); };
+        let Some(entry) = self.data.get(key)? else { return Ok(()); };
+        let payload = Payload::Changed {
+            key:     key.clone(),
+            value:   entry.value.clone(),
+            version: entry.ts,
+        };
+
+        for watcher in watchers.clone() {
+            self.send(&watcher, payload.clone(), output)?;
+        }
+        Ok(())
+    }
+
+    /// The primary the writes of `key` are forwarded to. Each key goes to
+    /// the same one, so that its CASes are checked against the same replica
+    fn primary(&self, key: &Key) -> &str {
+        &self.primaries[merkle::hash(key) as usize % self.primaries.len()]
+    }
+
+    /// Send `payload` to `peer`
+    fn send(&mut self, peer: &str, payload: Payload, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        let id = self.next_id();
+        Message::new(&self.id, peer, id, payload).send(output)
+    }
+
+    /// Collect the entries in the leaf buckets of the subtrees that differ
+    /// from `subtrees`
+    fn differing(&mut self, subtrees: &[(usize, Vec<u64>)])
+            -> anyhow::Result<Vec<Entry>> {
+        let buckets: HashSet<usize> = subtrees.iter()
+            .filter(|(subtree, _)| *subtree < merkle::FANOUT)
+            .flat_map(|(subtree, leaves)|
+                merkle::diff(self.tree.leaves(*subtree), leaves)
+                    .map(move |leaf| subtree * merkle::FANOUT + leaf))
+            .collect();
+
+        Ok(self.data.snapshot()?.into_iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| buckets.contains(&merkle::bucket(&entry.key)))
+            .collect())
+    }
+}
+
+impl<E: StorageEngine<Key, Entry>> msg::Node<Payload> for LwwKvNode<E> {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let name = format!("{}-lww-kv", init.node_id);
+        let dir = config.storage_dir.as_deref();
+        let mut node = Self {
+            id:      init.node_id.clone(),
+            peers:   init.node_ids.iter()
+                .filter(|id| **id != init.node_id)
+                .cloned()
+                .collect(),
+            next_id: 0,
+            hlc:     Hlc::new(),
+            data:    if config.restore { E::restore(dir, &name)? }
+                else { E::open(dir, &name)? },
+            tree:    Merkle::new(),
+            dirty:   HashSet::new(),
+            rounds:  0,
+            watchers: HashMap::new(),
+            version: Timestamp::default(),
+            seen:    HashMap::new(),
+            sent:    HashMap::new(),
+            waiting: Vec::new(),
+            gossip_interval: config.gossip_interval,
+            primaries: Vec::new(),
+            replies: Replies::open(dir, &format!("{name}-replies"),
+                config.restore)?,
+        };
+
+        // Primaries outside of the cluster are no use; without any, every
+        // node applies its own writes
+        let primaries: Vec<String> = config.primaries.iter()
+            .filter(|primary| init.node_ids.contains(primary))
+            .cloned()
+            .collect();
+        if primaries.is_empty() && !config.primaries.is_empty() {
+            config.log(LogLevel::Warn, format_args!("none of the primaries \
+                {:?} is in the cluster", config.primaries));
+        }
+        if !primaries.contains(&node.id) {
+            node.primaries = primaries;
+        }
+        if config.restore {
+            node.recover()?;
+        }
+        Ok(node)
+    }
+
+    fn step(&mut self, input: msg::Message<Payload>, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        // We will change the input into a reply later on, so mark it mutable
+        let mut input = input;
+
+        // A forwarded write is served as if its client sent it to us
+        if let Payload::Forward { client, client_id, request } =
+                input.body.payload {
+            input.src = client;
+            input.body.id = client_id;
+            input.body.payload = *request;
+        }
+        let id = input.body.id;
+
+        // Read-only replicas hand the writes to the primaries, which reply
+        // to the client directly
+        let key = match &input.body.payload {
+            Payload::Write { key, .. } | Payload::Cas { key, .. } => Some(key),
+            _ => None,
+        };
+        if let Some(key) = key.filter(|_| !self.primaries.is_empty()) {
+            let primary = self.primary(key).to_string();
+            let forward = Payload::Forward {
+                client:    input.src,
+                client_id: id,
+                request:   Box::new(input.body.payload),
+            };
+            let id = self.next_id();
+            return Message::new(&self.id, &primary, id, forward)
+                .with_deadline(input.body.deadline)
+                .send(output);
+        }
+
+        // A retry of a write we applied already, maybe before we restarted,
+        // gets the reply it got then
+        let mutating = matches!(input.body.payload,
+            Payload::Write { .. } | Payload::Cas { .. });
+        let request = mutating.then(|| input.body.payload.clone());
+        if let (Some(request), Some(id)) = (&request, id) {
+            if let Some(reply) = self.replies.get(&input.src, id, request) {
+                input.body.payload = reply.clone();
+                return input.into_reply(Some(id)).send(output);
+            }
+        }
+
+        // Hold the request back until our replica caught up with its session
+        let session = match input.body.payload.session_mut() {
+            Some(session) if !self.covers(session) => {
+                self.waiting.push((Instant::now() + SESSION_WAIT, input));
+                return Ok(());
+            },
+            Some(session) => core::mem::take(session),
+            None => Session::new(),
+        };
+
+        let mut reply = match input.body.payload {
+            // Ignore *Ok messages and errors
+            Payload::ReadOk { .. } | Payload::WriteOk { .. } |
+                Payload::CasOk { .. } | Payload::ScanOk { .. } |
+                Payload::WatchOk | Payload::Changed { .. } |
+                Payload::Error { .. } => return Ok(()),
+
+            // Forwarded twice over, which no replica does
+            Payload::Forward { .. } => return Ok(()),
+
+            Payload::Read { key, .. } => match self.get(&key)? {
+                Some(entry) => Payload::ReadOk {
+                    value:   entry.value,
+                    version: Some(entry.ts),
+                    session: Session::new(),
+                },
+                None => Payload::Error {
+                    code: error_code::KEY_DOES_NOT_EXIST,
+                    text: "key does not exist".into(),
+                },
+            },
+
+            // Conditional writes go through if the key is at the version
+            // expected, missing keys being at version zero
+            Payload::Write { key, value, expires_ms, if_version, .. } => {
+                let current = self.get(&key)?
+                    .map_or(Timestamp::default(), |entry| entry.ts);
+                match if_version {
+                    Some(expected) if expected != current => Payload::Error {
+                        code: error_code::PRECONDITION_FAILED,
+                        text: format!("expected version {}, had {}",
+                            expected.0, current.0),
+                    },
+                    _ => {
+                        let version = self.write(key.clone(), value,
+                            expires_ms)?;
+                        self.notify(&key, output)?;
+                        Payload::WriteOk {
+                            version: Some(version),
+                            session: Session::new(),
+                        }
+                    },
+                }
+            },
+
+            // Compare and swap against the local replica
+            Payload::Cas { key, from, to, create_if_not_exists, .. } => {
+                match self.get(&key)? {
+                    Some(entry) if entry.value == from => {
+                        let version = self.write(key.clone(), to, None)?;
+                        self.notify(&key, output)?;
+                        Payload::CasOk {
+                            version: Some(version),
+                            session: Session::new(),
+                        }
+                    },
+                    Some(entry) => Payload::Error {
+                        code: error_code::PRECONDITION_FAILED,
+                        text: format!("expected {from}, had {}", entry.value),
+                    },
+                    None if create_if_not_exists => {
+                        let version = self.write(key.clone(), to, None)?;
+                        self.notify(&key, output)?;
+                        Payload::CasOk {
+                            version: Some(version),
+                            session: Session::new(),
+                        }
+                    },
+                    None => Payload::Error {
+                        code: error_code::KEY_DOES_NOT_EXIST,
+                        text: "key does not exist".into(),
+                    },
+                }
+            },
+
+            Payload::Scan { from, to, limit, .. } => {
+                let limit = limit.unwrap_or(SCAN_LIMIT).min(SCAN_LIMIT);
+                self.scan(from, to, limit)?
+            },
+
+            Payload::Watch { key } => {
+                self.watchers.entry(key).or_default()
+                    .insert(input.src.clone());
+                Payload::WatchOk
+            },
+
+            Payload::Replicate { entries, prev, through } => {
+                for entry in entries {
+                    let key = entry.key.clone();
+                    if self.merge(entry)? {
+                        self.notify(&key, output)?;
+                    }
+                }
+
+                // Only a complete chain of entries brings us up to `through`
+                let seen = self.seen.entry(input.src).or_default();
+                if *seen >= prev {
+                    *seen = through.max(*seen);
+                }
+                return self.serve_waiting(output);
+            },
+
+            Payload::Digest { version, hashes } => {
+                let subtrees: Vec<_> = merkle::diff(&self.tree.top(), &hashes)
+                    .filter(|subtree| *subtree < merkle::FANOUT)
+                    .map(|subtree|
+                        (subtree, self.tree.leaves(subtree).to_vec()))
+                    .collect();
+                if !subtrees.is_empty() {
+                    let payload = Payload::DigestLeaves { version, subtrees };
+                    return self.send(&input.src, payload, output);
+                }
+
+                // Nothing differs, we include the sender's replica
+                let seen = self.seen.entry(input.src).or_default();
+                *seen = version.max(*seen);
+                return self.serve_waiting(output);
+            },
+
+            // The peer is missing or differs in these buckets, send it ours.
+            // Together with the buckets that did not differ, that brings the
+            // peer up to the version of the digest
+            Payload::DigestLeaves { version, subtrees } => {
+                let entries = self.differing(&subtrees)?;
+                if !entries.is_empty() {
+                    let payload = Payload::Replicate {
+                        entries,
+                        prev:    Timestamp::default(),
+                        through: version,
+                    };
+                    self.send(&input.src, payload, output)?;
+                }
+                return Ok(());
+            },
+        };
+
+        // Hand the client back its session, now including what it observed
+        // of our replica
+        if let Some(token) = reply.session_mut() {
+            *token = session;
+            token.insert(self.id.clone(), self.version);
+        }
+        if let (Some(request), Some(id)) = (request, id) {
+            self.replies.insert(&input.src, id, &request, reply.clone())?;
+        }
+        input.body.payload = reply;
+        input.into_reply(id).send(output)
+    }
+
+    fn unavailable(&self, input: &Message<Payload>) -> Option<String> {
+        let request = matches!(input.body.payload, Payload::Read { .. } |
+            Payload::Write { .. } | Payload::Cas { .. } | Payload::Scan { .. });
+        (request && self.waiting.len() >= MAX_WAITING)
+            .then(|| "too many requests waiting for their sessions".into())
+    }
+
+    /// Nodes that joined are caught up by the anti-entropy rounds
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        self.peers = nodes.iter().filter(|id| **id != self.id).cloned()
+            .collect();
+        self.sent.retain(|id, _| nodes.contains(id));
+        true
+    }
+
+    /// Every entry of the replica, expired ones included so that they keep
+    /// shadowing what they replaced
+    fn export(&mut self) -> anyhow::Result<Option<Value>> {
+        let entries: Vec<Entry> = self.data.snapshot()?.into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        Ok(Some(serde_json::to_value(entries)?))
+    }
+
+    /// The entries are merged as if they were gossiped
+    fn import(&mut self, _from: &str, state: Value) -> anyhow::Result<bool> {
+        let entries: Vec<Entry> = serde_json::from_value(state)?;
+        for entry in entries {
+            self.merge(entry)?;
+        }
+        Ok(true)
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "version":  self.version,
+            "dirty":    self.dirty.len(),
+            "waiting":  self.waiting.len(),
+            "watchers": self.watchers.values().map(BTreeSet::len)
+                .sum::<usize>(),
+            "seen":     self.seen,
+            "primaries": self.primaries,
+        })
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.gossip_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn io::Write) -> anyhow::Result<()> {
+        self.serve_waiting(output)?;
+        self.rounds += 1;
+        if self.rounds.is_multiple_of(SWEEP_ROUNDS) {
+            self.sweep()?;
+        }
+        if self.peers.is_empty() { return Ok(()); }
+
+        // Push what changed since the last round to everyone
+        let mut dirty = Vec::with_capacity(self.dirty.len());
+        for key in core::mem::take(&mut self.dirty) {
+            dirty.extend(self.data.get(&key)?);
+        }
+        if !dirty.is_empty() {
+            for peer in self.peers.clone() {
+                let prev = self.sent.insert(peer.clone(), self.version)
+                    .unwrap_or_default();
+                let payload = Payload::Replicate {
+                    entries: dirty.clone(),
+                    prev,
+                    through: self.version,
+                };
+                self.send(&peer, payload, output)?;
+            }
+        }
+
+        // Every now and then, compare our Merkle tree with one of the peers
+        // in turn
+        if self.rounds.is_multiple_of(ANTI_ENTROPY_ROUNDS) {
+            let peer = self.peers[(self.rounds / ANTI_ENTROPY_ROUNDS) %
+                self.peers.len()].clone();
+            let payload = Payload::Digest {
+                version: self.version,
+                hashes:  self.tree.top(),
+            };
+            self.send(&peer, payload, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+crate::service_main!("lww-kv", Payload, LwwKvNode);
@@ -1,3 +1,11 @@
 pub mod echo;
 pub mod uuid;
 pub mod broadcast;
+pub mod shared_broadcast;
+pub mod sequencer;
+pub mod lww_kv;
+pub mod vclock_kv;
+pub mod chain_kv;
+pub mod lin_kv;
+pub mod lock;
+pub mod distinct;
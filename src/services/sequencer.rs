@@ -0,0 +1,372 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use crate::message::{self as msg, Message, error_code};
+use crate::config::Config;
+
+/// The Maelstrom service holding the sequence checkpoint
+const KV: &str = "lin-kv";
+
+/// Key of the last allocated sequence number in the KV
+const KEY: &str = "sequencer";
+
+/// Most client requests queued up for a sequence number at once. Past it, new
+/// clients are turned away until the queue drains
+const MAX_WAITING: usize = 1024;
+
+crate::payload! {
+    /// Payloads handled by the sequencer server
+    pub enum Payload {
+        Next,
+        NextOk { seq: u64 },
+
+        // Requests and replies of the lin-kv service
+        Read { key: String },
+        ReadOk { value: u64 },
+        Cas { key: String, from: u64, to: u64, create_if_not_exists: bool },
+        CasOk,
+
+        Error { code: usize, text: String },
+    }
+}
+
+/// Someone waiting for a sequence number
+#[derive(Debug)]
+struct Requester {
+    src: String,
+    id:  Option<usize>,
+
+    /// Deadline of the request, in milliseconds since the Unix epoch
+    deadline: Option<u64>,
+}
+
+/// A request forwarded to the leader on behalf of a client
+#[derive(Debug)]
+struct Forward {
+    client: Requester,
+    sent:   Instant,
+}
+
+/// An outstanding request to the KV
+#[derive(Debug)]
+struct KvRequest {
+    id:   usize,
+    sent: Instant,
+
+    /// Amount of sequence numbers a CAS allocates; zero for reads
+    batch: usize,
+}
+
+/// A node in the sequencer service cluster. The first node (by ID) that
+/// isn't suspected to be dead is the leader and allocates the sequence
+/// numbers; everyone else forwards their requests to it. Every allocation is
+/// checkpointed to lin-kv before it's handed out, so that a new leader can
+/// resume exactly where the old one stopped
+pub struct SequencerNode {
+    id: String,
+
+    /// All nodes in the cluster, sorted
+    nodes: Vec<String>,
+
+    /// Index of the node we consider the leader
+    leader: usize,
+
+    /// ID of the next message we send
+    next_id: usize,
+
+    /// Requests forwarded to the leader, keyed by the ID of the forward
+    forwarded: HashMap<usize, Forward>,
+
+    /// Those waiting for a sequence number from us, in arrival order
+    waiting: VecDeque<Requester>,
+
+    /// Last allocated sequence number, if known
+    current: Option<u64>,
+
+    /// Request to the KV we are waiting on
+    kv_request: Option<KvRequest>,
+
+    /// CAS we don't know the outcome of: the sequence it moved on from and
+    /// the amount of numbers it allocated. If the sequence is found right
+    /// where it would have left it, the numbers are ours to hand out
+    unsure: Option<(u64, usize)>,
+
+    /// When the batch of waiting requests started to collect
+    batch_start: Option<Instant>,
+
+    /// How long to collect a batch before allocating for it
+    batch_window: Duration,
+
+    /// How long to wait for the leader or the KV before giving up on them
+    retry_timeout: Duration,
+}
+
+impl SequencerNode {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn is_leader(&self) -> bool {
+        self.nodes[self.leader] == self.id
+    }
+
+    /// Send the payload to `dst` as a new message, returning its ID
+    fn send(&mut self, dst: &str, payload: Payload, output: &mut dyn Write)
+            -> anyhow::Result<usize> {
+        let id = self.next_id();
+        Message::new(&self.id, dst, id, payload).send(output)?;
+        Ok(id)
+    }
+
+    /// Reply to `to` with `payload`
+    fn reply(&mut self, to: &Requester, payload: Payload,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        let id = self.next_id();
+        let mut msg = Message::new(&self.id, &to.src, id, payload);
+        msg.body.reply_id = to.id;
+        msg.send(output)
+    }
+
+    /// Ask the leader for a sequence number on behalf of `client`
+    fn forward(&mut self, client: Requester, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let leader = self.nodes[self.leader].clone();
+        let id = self.next_id();
+        Message::new(&self.id, &leader, id, Payload::Next)
+            .with_deadline(client.deadline)
+            .send(output)?;
+        self.forwarded.insert(id, Forward { client, sent: Instant::now() });
+        Ok(())
+    }
+
+    /// Move the allocation forward, if there's anyone waiting and we aren't
+    /// already waiting on the KV
+    fn pump(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        if self.kv_request.is_some() { return Ok(()); }
+
+        // Don't allocate for the requests nobody waits for anymore. An
+        // outstanding CAS would hand its numbers out from the front of the
+        // queue, so this can only be done while there's none
+        if self.unsure.is_none() {
+            let now = msg::now_ms();
+            self.waiting.retain(|req| req.deadline.is_none_or(|d| now < d));
+        }
+        if self.waiting.is_empty() { return Ok(()); }
+
+        // Let the batch fill up for a while before allocating for it
+        let start = *self.batch_start.get_or_insert_with(Instant::now);
+        if self.current.is_some() && start.elapsed() < self.batch_window {
+            return Ok(());
+        }
+
+        // Find out where the sequence stands before allocating anything
+        let (payload, batch) = match self.current {
+            None => (Payload::Read { key: KEY.into() }, 0),
+            Some(from) => {
+                self.batch_start = None;
+                let batch = self.waiting.len();
+                (Payload::Cas {
+                    key: KEY.into(),
+                    from,
+                    to: from + batch as u64,
+                    create_if_not_exists: true,
+                }, batch)
+            },
+        };
+
+        let id = self.send(KV, payload, output)?;
+        self.kv_request = Some(KvRequest { id, sent: Instant::now(), batch });
+        Ok(())
+    }
+
+    /// Hand the `batch` numbers past `from` out to the front of the queue,
+    /// once their checkpoint went through
+    fn allocated(&mut self, from: u64, batch: usize, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        for seq in from + 1..=from + batch as u64 {
+            let to = self.waiting.pop_front()
+                .expect("batch larger than the waiting queue");
+            self.reply(&to, Payload::NextOk { seq }, output)?;
+        }
+        self.current = Some(from + batch as u64);
+        Ok(())
+    }
+
+    /// Give up on the CAS of `req`, which may or may not have gone through
+    fn lost(&mut self, req: &KvRequest) {
+        if req.batch > 0 {
+            let from = self.current.expect("CAS of an unknown sequence");
+            self.unsure = Some((from, req.batch));
+        }
+        self.current = None;
+    }
+
+    /// Take the pending KV request if `reply_id` answers it
+    fn take_kv_request(&mut self, reply_id: Option<usize>)
+            -> Option<KvRequest> {
+        match &self.kv_request {
+            Some(req) if Some(req.id) == reply_id => self.kv_request.take(),
+            _ => None,
+        }
+    }
+}
+
+impl msg::Node<Payload> for SequencerNode {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let mut nodes = init.node_ids.clone();
+        nodes.sort();
+
+        Ok(Self {
+            id:         init.node_id.clone(),
+            nodes,
+            leader:     0,
+            next_id:    0,
+            forwarded:  HashMap::new(),
+            waiting:    VecDeque::new(),
+            current:    None,
+            kv_request: None,
+            unsure:     None,
+            batch_start: None,
+            batch_window: config.batch_window,
+            retry_timeout: config.retry_timeout,
+        })
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let requester = Requester {
+            src:      input.src,
+            id:       input.body.id,
+            deadline: input.body.deadline,
+        };
+        let reply_id = input.body.reply_id;
+
+        match input.body.payload {
+            // Requests forwarded by other nodes are always served; they
+            // consider us the leader
+            Payload::Next => {
+                if self.is_leader() || self.nodes.contains(&requester.src) {
+                    self.waiting.push_back(requester);
+                    self.pump(output)
+                } else {
+                    self.forward(requester, output)
+                }
+            },
+
+            // The leader allocated a number for one of our clients
+            Payload::NextOk { seq } => {
+                let Some(fwd) = reply_id
+                        .and_then(|id| self.forwarded.remove(&id)) else {
+                    return Ok(());
+                };
+                self.reply(&fwd.client, Payload::NextOk { seq }, output)
+            },
+
+            // A CAS we lost track of went through if the sequence is where
+            // it would have left it
+            Payload::ReadOk { value } => {
+                if self.take_kv_request(reply_id).is_some() {
+                    match self.unsure.take() {
+                        Some((from, batch)) if from + batch as u64 == value =>
+                            self.allocated(from, batch, output)?,
+                        _ => self.current = Some(value),
+                    }
+                }
+                self.pump(output)
+            },
+
+            Payload::CasOk => {
+                let Some(req) = self.take_kv_request(reply_id) else {
+                    return Ok(());
+                };
+
+                // The checkpoint went through, hand the numbers out
+                let from = self.current.expect("CAS of an unknown sequence");
+                self.allocated(from, req.batch, output)?;
+                self.pump(output)
+            },
+
+            Payload::Error { code, .. } => {
+                let Some(req) = self.take_kv_request(reply_id) else {
+                    return Ok(());
+                };
+
+                // Nothing was allocated yet if the key is missing. For any
+                // other error, someone else may have allocated in the
+                // meantime, so find out where the sequence stands again.
+                // Only a failed precondition tells us the CAS didn't apply
+                match (req.batch, code) {
+                    (0, error_code::KEY_DOES_NOT_EXIST) =>
+                        self.current = Some(0),
+                    (_, error_code::PRECONDITION_FAILED) => self.current = None,
+                    _ => self.lost(&req),
+                }
+                self.pump(output)
+            },
+
+            // We never serve the KV requests
+            Payload::Read { .. } | Payload::Cas { .. } => Ok(()),
+        }
+    }
+
+    fn unavailable(&self, input: &Message<Payload>) -> Option<String> {
+        let client = !self.nodes.contains(&input.src);
+        (client && input.body.payload == Payload::Next &&
+                self.waiting.len() + self.forwarded.len() >= MAX_WAITING)
+            .then(|| "too many requests waiting for a sequence number".into())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "leader":     self.nodes.get(self.leader),
+            "waiting":    self.waiting.len(),
+            "forwarded":  self.forwarded.len(),
+            "current":    self.current,
+            "kv_pending": self.kv_request.is_some(),
+            "unsure":     self.unsure.is_some(),
+        })
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        let retry = self.retry_timeout / 5;
+        if self.batch_window.is_zero() {
+            Some(retry)
+        } else {
+            Some(retry.min(self.batch_window))
+        }
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        // Retry KV requests that went unanswered
+        if let Some(req) = self.kv_request.take_if(|req|
+                req.sent.elapsed() > self.retry_timeout) {
+            self.lost(&req);
+        }
+
+        // Allocate for the batches whose window closed
+        self.pump(output)?;
+
+        // The leader didn't answer in time, suspect it and move on to the
+        // next candidate
+        let expired: Vec<usize> = self.forwarded.iter()
+            .filter(|(_, fwd)| fwd.sent.elapsed() > self.retry_timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        if expired.is_empty() { return Ok(()); }
+        self.leader = (self.leader + 1) % self.nodes.len();
+
+        for id in expired {
+            let fwd = self.forwarded.remove(&id).expect("expired forward");
+            if self.is_leader() {
+                self.waiting.push_back(fwd.client);
+            } else {
+                self.forward(fwd.client, output)?;
+            }
+        }
+        self.pump(output)
+    }
+}
+
+crate::service_main!("sequencer", Payload, SequencerNode);
@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use crate::message::{self as msg, Node};
+use crate::pool::SharedNode;
+use crate::config::Config;
+use super::broadcast::{self, BroadcastNode, GossipFilter, Payload, SeenSet};
+
+/// A broadcast node run on the worker pool. The seen set is shared between
+/// the workers and the gossip, so broadcasts of messages we already have are
+/// acknowledged by any worker without waiting for the node, while it gossips
+/// or saves what another worker handed it
+pub struct SharedBroadcastNode {
+    shared: Arc<Shared>,
+    gossip_interval: Duration,
+}
+
+/// What the workers share with the gossip thread
+struct Shared {
+    seen: Arc<SeenSet>,
+    node: Mutex<BroadcastNode>,
+
+    /// What the gossip thread sent, until the runtime writes it out on the
+    /// next tick, or why it stopped
+    gossip: Mutex<anyhow::Result<Vec<u8>>>,
+}
+
+/// Gossip every `interval` on a thread of its own, for as long as the node
+/// is around. The filter of the seen set, the bulk of the work, is built
+/// without holding up the workers; the node is only locked to pick the
+/// neighbors and note what they were sent
+fn gossip(shared: Weak<Shared>, filter: GossipFilter, interval: Duration) {
+    let mut round = 0;
+    loop {
+        std::thread::sleep(interval);
+        let Some(shared) = shared.upgrade() else { return; };
+        round += 1;
+        let seen = broadcast::gossip_filter(&shared.seen, filter, round);
+        let mut out = Vec::new();
+        let sent = shared.node.lock().unwrap().gossip(seen, &mut out);
+        let mut gossip = shared.gossip.lock().unwrap();
+        match (&mut *gossip, sent) {
+            (Ok(gossip), Ok(())) => gossip.extend(out),
+            (Ok(_), Err(e)) => {
+                *gossip = Err(e);
+                return;
+            },
+            (Err(_), _) => return,
+        }
+    }
+}
+
+impl SharedNode<Payload> for SharedBroadcastNode {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let node = BroadcastNode::from_init(init, config)?;
+        let shared = Arc::new(Shared {
+            seen:   node.seen(),
+            node:   Mutex::new(node),
+            gossip: Mutex::new(Ok(Vec::new())),
+        });
+        let (weak, filter) = (Arc::downgrade(&shared), config.gossip_filter);
+        let interval = config.gossip_interval;
+        std::thread::spawn(move || gossip(weak, filter, interval));
+        Ok(Self { shared, gossip_interval: interval })
+    }
+
+    fn step(&self, input: msg::Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        match input.body.payload {
+            Payload::Broadcast { message }
+                    if self.shared.seen.contains(message) => {
+                let mut input = input;
+                let id = input.body.id;
+                input.body.payload = Payload::BroadcastOk;
+                input.into_reply(id).send(output)
+            },
+            _ => self.shared.node.lock().unwrap().step(input, output),
+        }
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.gossip_interval)
+    }
+
+    /// Write out what the gossip thread sent since the last tick
+    fn tick(&self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let mut gossip = self.shared.gossip.lock().unwrap();
+        match &mut *gossip {
+            Ok(gossip) => Ok(output.write_all(&core::mem::take(gossip))?),
+            Err(e) => Err(anyhow::anyhow!("gossip failed: {e:#}")),
+        }
+    }
+
+    fn shutdown(&self, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.tick(output)?;
+        self.shared.node.lock().unwrap().shutdown(output)
+    }
+}
+
+crate::service_main!(pooled "shared-broadcast", Payload, SharedBroadcastNode);
@@ -1,25 +1,150 @@
 use core::arch::x86_64::_rdtsc;
+use std::collections::HashMap;
 use std::io::Write;
-use serde::{Serialize, Deserialize};
-use crate::message as msg;
+use std::time::Duration;
+use crate::message::{self as msg, Message};
+use crate::hlc::{self, Hlc};
+use crate::ulid::Ulids;
+use crate::config::{Config, LogLevel};
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "snake_case", tag = "type")]
-/// Payloads handled by the UUID server
-pub enum Payload {
-    Generate,
-    GenerateOk { id: u128 },
+crate::payload! {
+    /// Payloads handled by the UUID server
+    pub enum Payload {
+        Generate,
+        GenerateOk { id: u128 },
+
+        /// IDs the sender generated since it last told us, as decimal
+        /// strings. Only sent by nodes auditing for collisions
+        Issued { ids: Vec<String> },
+    }
+}
+
+/// Where the generated IDs come from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdSource {
+    /// 128 pseudo-random bits
+    Random,
+
+    /// A hybrid logical timestamp in the upper 64 bits and the index of the
+    /// node in the lower ones. IDs of a node are strictly increasing
+    Hlc,
+
+    /// ULID-style IDs; a millisecond timestamp, the index of the node and
+    /// random bits counting up within the millisecond. IDs sort by time and
+    /// those of a node are strictly increasing
+    Ulid,
+}
+
+impl IdSource {
+    /// Parse the source out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "random" => Self::Random,
+            "hlc"    => Self::Hlc,
+            "ulid"   => Self::Ulid,
+            _ => anyhow::bail!("unknown ID source `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Random => "random",
+            Self::Hlc    => "hlc",
+            Self::Ulid   => "ulid",
+        }
+    }
+}
+
+/// IDs generated in the cluster, for the collision audit
+#[derive(Debug, Default)]
+struct Audit {
+    /// Every ID heard of, along with the node that generated it
+    known: HashMap<u128, String>,
+
+    /// IDs we generated since we last told the others
+    unsent: Vec<u128>,
+
+    /// IDs generated more than once
+    collisions: usize,
+}
+
+impl Audit {
+    /// Remember that `node` generated `id`, logging a collision if someone
+    /// generated it before
+    fn record(&mut self, id: u128, node: &str, config: &Config) {
+        if let Some(first) = self.known.insert(id, node.to_string()) {
+            self.collisions += 1;
+            config.log(LogLevel::Error, format_args!("ID {id} was generated \
+                by both {first} and {node}"));
+        }
+    }
 }
 
 /// A node in the UUID service cluster
 pub struct UUIDNode {
-    _id: String,
+    id: String,
+
+    /// Every node of the cluster
+    nodes: Vec<String>,
+
+    /// ID of the next message we send
+    next_msg_id: usize,
+
+    /// Index of this node in the cluster
+    idx: usize,
+
+    /// Where the IDs come from
+    source: IdSource,
 
     /// Internal PRNG state
     state: u128,
+
+    /// Clock of the HLC IDs
+    hlc: Hlc,
+
+    /// Generator of the ULIDs
+    ulids: Ulids,
+
+    /// The wall clock is behind the last timestamp handed out
+    behind: bool,
+
+    /// The collision audit, if it's on
+    audit: Option<Audit>,
+
+    config: Config,
 }
 
 impl UUIDNode {
+    /// Get the next ID from the configured source
+    fn next_id(&mut self) -> u128 {
+        match self.source {
+            IdSource::Random => self.next_rng(),
+            IdSource::Hlc => {
+                let wall_ms = self.wall_clock(self.hlc.last().physical_ms());
+                ((self.hlc.now_at(wall_ms).0 as u128) << 64) | self.idx as u128
+            },
+            IdSource::Ulid => {
+                let wall_ms = self.wall_clock(self.ulids.last_ms());
+                self.ulids.next_at(wall_ms)
+            },
+        }
+    }
+
+    /// Read the wall clock, warning when it went back behind `last_ms`, the
+    /// millisecond of the last timestamp handed out. The timestamps keep
+    /// counting on from the last one until the clock catches up
+    fn wall_clock(&mut self, last_ms: u64) -> u64 {
+        let wall_ms = hlc::wall_clock_ms();
+        let behind = wall_ms < last_ms;
+        if behind && !self.behind {
+            self.config.log(LogLevel::Warn, format_args!("the wall clock \
+                went back {}ms, counting on from the last timestamp",
+                last_ms - wall_ms));
+        }
+        self.behind = behind;
+        wall_ms
+    }
+
     /// Get the next 128bit pseudo-random integer. This implements 128b xorshift
     fn next_rng(&mut self) -> u128 {
         let ret = self.state;
@@ -31,10 +156,23 @@ impl UUIDNode {
 }
 
 impl msg::Node<Payload> for UUIDNode {
-    fn from_init(init: &msg::Init) -> anyhow::Result<Self> {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let idx = init.node_ids.iter().position(|id| *id == init.node_id)
+            .unwrap_or(0);
+        anyhow::ensure!(idx <= u16::MAX as usize, "too many nodes for ULIDs");
         Ok(Self {
-            _id: init.node_id.clone(),
+            id:    init.node_id.clone(),
+            nodes: init.node_ids.clone(),
+            next_msg_id: 0,
+            idx,
+            source: config.id_source,
             state: unsafe { ((_rdtsc() as u128) << 64) + (_rdtsc() as u128) },
+            hlc: Hlc::new(),
+            ulids: Ulids::new(idx as u16, unsafe { _rdtsc() }),
+            behind: false,
+            audit: config.id_audit.then(Audit::default),
+            config: config.clone(),
         })
     }
 
@@ -46,16 +184,55 @@ impl msg::Node<Payload> for UUIDNode {
 
         match input.body.payload {
             Payload::Generate => {
-                input.body.payload = Payload::GenerateOk {
-                    id: self.next_rng(),
-                };
+                let generated = self.next_id();
+                if let Some(audit) = &mut self.audit {
+                    audit.record(generated, &self.id, &self.config);
+                    audit.unsent.push(generated);
+                }
+                input.body.payload = Payload::GenerateOk { id: generated };
                 input.into_reply(id).send(output)
             },
             Payload::GenerateOk{ .. } => Ok(()),
+
+            Payload::Issued { ids } => {
+                let Some(audit) = &mut self.audit else { return Ok(()); };
+                for id in ids {
+                    audit.record(id.parse()?, &input.src, &self.config);
+                }
+                Ok(())
+            },
         }
     }
-}
 
-pub fn main() -> anyhow::Result<()> {
-    msg::main_loop::<Payload, UUIDNode>()
+    fn tick_interval(&self) -> Option<Duration> {
+        self.audit.as_ref().map(|_| self.config.gossip_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Some(audit) = &mut self.audit else { return Ok(()); };
+        if audit.unsent.is_empty() { return Ok(()); }
+
+        let ids: Vec<String> = audit.unsent.drain(..)
+            .map(|id| id.to_string())
+            .collect();
+        for node in self.nodes.iter().filter(|node| **node != self.id) {
+            self.next_msg_id += 1;
+            let issued = Payload::Issued { ids: ids.clone() };
+            Message::new(&self.id, node, self.next_msg_id, issued)
+                .send(output)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        match &self.audit {
+            Some(audit) => serde_json::json!({
+                "known":      audit.known.len(),
+                "collisions": audit.collisions,
+            }),
+            None => serde_json::Value::Null,
+        }
+    }
 }
+
+crate::service_main!("unique-ids", Payload, UUIDNode);
@@ -0,0 +1,347 @@
+use std::collections::HashSet;
+use std::io;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::message::{self as msg, Message, error_code};
+use crate::services::lww_kv::Key;
+use crate::storage::StorageEngine;
+use crate::vclock::VClock;
+use crate::config::Config;
+
+/// Every this many gossip rounds, the whole replica is pushed to one of the
+/// peers to repair whatever got lost
+const ANTI_ENTROPY_ROUNDS: usize = 10;
+
+/// A value of a key. The write that produced it was the `counter`th event of
+/// `writer` and overwrote everything its `context` included
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sibling {
+    pub value: Value,
+    pub writer: String,
+    pub counter: u64,
+    pub context: VClock,
+}
+
+impl Sibling {
+    /// Returns `true` if `clock` includes the write of the sibling
+    pub fn covered_by(&self, clock: &VClock) -> bool {
+        clock.get(&self.writer) >= self.counter
+    }
+
+    /// Clock of the sibling; its context along with its own write
+    pub fn clock(&self) -> VClock {
+        let mut clock = self.context.clone();
+        if self.counter > clock.get(&self.writer) {
+            clock.0.insert(self.writer.clone(), self.counter);
+        }
+        clock
+    }
+}
+
+crate::payload! {
+    /// Payloads handled by the vector clock KV server
+    pub enum Payload {
+        Read { key: Key },
+
+        /// The values of the concurrent siblings of the key and the
+        /// `context` including all of them. Writing with the context
+        /// resolves the siblings
+        ReadOk { values: Vec<Value>, context: VClock },
+
+        /// Write `value` over everything the `context` of an earlier read
+        /// included. Without a context, the value becomes a sibling of whatever
+        /// the key holds
+        Write {
+            key: Key,
+            value: Value,
+            #[serde(default)]
+            context: VClock,
+        },
+        WriteOk { context: VClock },
+
+        /// Siblings gossiped between the replicas
+        Replicate {
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            entries: Vec<(Key, Vec<Sibling>)>,
+        },
+
+        Error { code: usize, text: String },
+    }
+}
+
+/// How reads resolve the siblings of a key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SiblingMerge {
+    /// They don't; every sibling is returned for the client to resolve
+    None,
+
+    /// Into a single set of the elements of all siblings, as `union` does
+    Union,
+}
+
+impl SiblingMerge {
+    /// Parse the merge out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "none"  => Self::None,
+            "union" => Self::Union,
+            _ => anyhow::bail!("unknown sibling merge `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None  => "none",
+            Self::Union => "union",
+        }
+    }
+}
+
+/// Drop the duplicate siblings and the ones some other sibling overwrote,
+/// leaving only the concurrent ones, ordered by their writers
+pub fn reconcile(mut siblings: Vec<Sibling>) -> Vec<Sibling> {
+    let dot = |sibling: &Sibling| (sibling.writer.clone(), sibling.counter);
+    siblings.sort_by_key(dot);
+    siblings.dedup_by_key(|sibling| dot(sibling));
+    let mut kept = siblings;
+
+    let contexts: Vec<VClock> = kept.iter()
+        .map(|sibling| sibling.context.clone())
+        .collect();
+    kept.retain(|sibling| !contexts.iter().any(|c| sibling.covered_by(c)));
+    kept
+}
+
+/// Merge `values` into the set of their elements, in order of appearance.
+/// Arrays contribute their elements, anything else contributes itself
+pub fn union(values: Vec<Value>) -> Value {
+    let mut set = Vec::new();
+    for value in values {
+        let elements = match value {
+            Value::Array(elements) => elements,
+            other => vec![other],
+        };
+        for element in elements {
+            if !set.contains(&element) {
+                set.push(element);
+            }
+        }
+    }
+    Value::Array(set)
+}
+
+/// A node in the Dynamo-style KV cluster. Every write is versioned by a
+/// vector clock and writes the replicas see as concurrent are kept side by
+/// side as siblings, until a client writes over them with the context of a
+/// read. The data lives in the storage engine `E`, by default the one
+/// selected by the storage directory of the config
+pub struct VClockKvNode<E = Box<dyn StorageEngine<Key, Vec<Sibling>>>> {
+    id: String,
+
+    /// Every other node of the cluster
+    peers: Vec<String>,
+
+    next_id: usize,
+
+    /// The concurrent siblings of every key
+    data: E,
+
+    /// Keys changed since the last gossip round
+    dirty: HashSet<Key>,
+
+    /// Amount of gossip rounds so far
+    rounds: usize,
+
+    /// How reads resolve the siblings
+    merge: SiblingMerge,
+
+    /// How often the changes are pushed to the peers
+    gossip_interval: Duration,
+}
+
+impl<E: StorageEngine<Key, Vec<Sibling>>> VClockKvNode<E> {
+    /// Write `value` to `key` over everything `context` includes, returning
+    /// the clock of the write
+    fn write(&mut self, key: Key, value: Value, context: VClock)
+            -> anyhow::Result<VClock> {
+        let mut siblings = self.data.get(&key)?.unwrap_or_default();
+
+        // Count past every write of ours to the key, including the ones the
+        // context didn't see
+        let counter = siblings.iter()
+            .map(|sibling| sibling.clock().get(&self.id))
+            .chain([context.get(&self.id)])
+            .max()
+            .unwrap_or(0) + 1;
+
+        let sibling = Sibling { value, writer: self.id.clone(), counter,
+            context };
+        let clock = sibling.clock();
+        siblings.push(sibling);
+        self.data.put(key.clone(), reconcile(siblings))?;
+        self.dirty.insert(key);
+        Ok(clock)
+    }
+
+    /// Merge the `siblings` of `key` gossiped by another replica
+    fn merge(&mut self, key: Key, siblings: Vec<Sibling>)
+            -> anyhow::Result<()> {
+        let current = self.data.get(&key)?.unwrap_or_default();
+        let mut merged = current.clone();
+        merged.extend(siblings);
+        let merged = reconcile(merged);
+        if merged != current {
+            self.data.put(key, merged)?;
+        }
+        Ok(())
+    }
+
+    /// Push `entries` to `dst`
+    fn replicate(&mut self, dst: &str, entries: Vec<(Key, Vec<Sibling>)>,
+            output: &mut dyn io::Write) -> anyhow::Result<()> {
+        self.next_id += 1;
+        Message::new(&self.id, dst, self.next_id,
+            Payload::Replicate { entries }).send(output)
+    }
+}
+
+impl<E: StorageEngine<Key, Vec<Sibling>>> msg::Node<Payload>
+        for VClockKvNode<E> {
+    fn from_init(init: &msg::Init, config: &Config)
+            -> anyhow::Result<Self> {
+        let name = format!("{}-vclock-kv", init.node_id);
+        let dir = config.storage_dir.as_deref();
+        let mut node = Self {
+            id:      init.node_id.clone(),
+            peers:   init.node_ids.iter()
+                .filter(|id| **id != init.node_id)
+                .cloned()
+                .collect(),
+            next_id: 0,
+            data:    if config.restore { E::restore(dir, &name)? }
+                else { E::open(dir, &name)? },
+            dirty:   HashSet::new(),
+            rounds:  0,
+            merge:   config.sibling_merge,
+            gossip_interval: config.gossip_interval,
+        };
+
+        // Writes we saved but may not have pushed before going down are
+        // pushed again; what we missed comes with the anti-entropy rounds
+        if config.restore {
+            for (key, _) in node.data.snapshot()? {
+                node.dirty.insert(key);
+            }
+        }
+        Ok(node)
+    }
+
+    fn step(&mut self, input: msg::Message<Payload>, output: &mut dyn io::Write)
+            -> anyhow::Result<()> {
+        // We will change the input into a reply later on, so mark it mutable
+        let mut input = input;
+        let id = input.body.id;
+
+        input.body.payload = match input.body.payload {
+            // Ignore *Ok messages and errors
+            Payload::ReadOk { .. } | Payload::WriteOk { .. } |
+                Payload::Error { .. } => return Ok(()),
+
+            Payload::Read { key } => {
+                let siblings = self.data.get(&key)?.unwrap_or_default();
+                if siblings.is_empty() {
+                    Payload::Error {
+                        code: error_code::KEY_DOES_NOT_EXIST,
+                        text: "key does not exist".into(),
+                    }
+                } else {
+                    let mut context = VClock::new();
+                    let mut values = Vec::with_capacity(siblings.len());
+                    for sibling in siblings {
+                        context.merge(&sibling.clock());
+                        values.push(sibling.value);
+                    }
+                    if self.merge == SiblingMerge::Union {
+                        values = vec![union(values)];
+                    }
+                    Payload::ReadOk { values, context }
+                }
+            },
+
+            Payload::Write { key, value, context } => {
+                let context = self.write(key, value, context)?;
+                Payload::WriteOk { context }
+            },
+
+            Payload::Replicate { entries } => {
+                for (key, siblings) in entries {
+                    self.merge(key, siblings)?;
+                }
+                return Ok(());
+            },
+        };
+        input.into_reply(id).send(output)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.gossip_interval)
+    }
+
+    fn tick(&mut self, output: &mut dyn io::Write) -> anyhow::Result<()> {
+        self.rounds += 1;
+        if self.peers.is_empty() { return Ok(()); }
+
+        // Push what changed since the last round to everyone
+        let mut entries = Vec::with_capacity(self.dirty.len());
+        for key in core::mem::take(&mut self.dirty) {
+            let siblings = self.data.get(&key)?.unwrap_or_default();
+            entries.push((key, siblings));
+        }
+        if !entries.is_empty() {
+            for peer in self.peers.clone() {
+                self.replicate(&peer, entries.clone(), output)?;
+            }
+        }
+
+        // Every now and then, push everything to one of the peers in turn
+        if self.rounds.is_multiple_of(ANTI_ENTROPY_ROUNDS) {
+            let peer = self.peers[(self.rounds / ANTI_ENTROPY_ROUNDS) %
+                self.peers.len()].clone();
+            let entries = self.data.snapshot()?;
+            if !entries.is_empty() {
+                self.replicate(&peer, entries, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Nodes that joined are caught up by the anti-entropy rounds
+    fn membership(&mut self, nodes: &[String]) -> bool {
+        self.peers = nodes.iter().filter(|id| **id != self.id).cloned()
+            .collect();
+        true
+    }
+
+    fn export(&mut self) -> anyhow::Result<Option<Value>> {
+        Ok(Some(serde_json::to_value(self.data.snapshot()?)?))
+    }
+
+    /// The siblings are merged as if they were replicated
+    fn import(&mut self, _from: &str, state: Value) -> anyhow::Result<bool> {
+        let entries: Vec<(Key, Vec<Sibling>)> = serde_json::from_value(state)?;
+        for (key, siblings) in entries {
+            self.merge(key, siblings)?;
+        }
+        Ok(true)
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({
+            "dirty":  self.dirty.len(),
+            "rounds": self.rounds,
+        })
+    }
+}
+
+crate::service_main!("vclock-kv", Payload, VClockKvNode);
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use anyhow::bail;
+
+/// Lease of an open session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    /// How long a keep-alive extends the lease by
+    pub ttl_ms: u64,
+
+    /// When the session expires unless it's kept alive
+    pub expires_ms: u64,
+}
+
+/// Sessions of the clients, each held open by a lease the client has to keep
+/// renewing. The ID of a session doubles as its fencing token; later sessions
+/// get larger IDs, so a resource refuses writes of a session older than the
+/// last one that wrote it. Time is passed in, which keeps the sessions
+/// deterministic when they are part of a replicated state machine
+#[derive(Debug, Clone)]
+pub struct Sessions<R> {
+    /// The ID of the next session opened
+    next: u64,
+
+    /// Leases of the open sessions, by their IDs
+    leases: BTreeMap<u64, Lease>,
+
+    /// The largest fencing token each resource was written with
+    fences: BTreeMap<R, u64>,
+}
+
+impl<R> Default for Sessions<R> {
+    fn default() -> Self {
+        Self { next: 1, leases: BTreeMap::new(), fences: BTreeMap::new() }
+    }
+}
+
+impl<R: Ord> Sessions<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lease of the open session `id`
+    pub fn lease(&self, id: u64) -> Option<Lease> {
+        self.leases.get(&id).copied()
+    }
+
+    /// Amount of open sessions
+    pub fn len(&self) -> usize {
+        self.leases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leases.is_empty()
+    }
+
+    /// Open a session with a lease of `ttl_ms` at `now_ms`. Returns the ID of
+    /// the session, which is its fencing token as well
+    pub fn open(&mut self, ttl_ms: u64, now_ms: u64) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.leases.insert(id, Lease { ttl_ms, expires_ms: now_ms + ttl_ms });
+        id
+    }
+
+    /// Renew the lease of the session `id` at `now_ms`
+    pub fn keep_alive(&mut self, id: u64, now_ms: u64) -> anyhow::Result<()> {
+        let ttl_ms = self.leases.get(&id).map_or(0, |lease| lease.ttl_ms);
+        self.renew(id, ttl_ms, now_ms)
+    }
+
+    /// Renew the lease of the session `id` at `now_ms`, for `ttl_ms` from
+    /// then on
+    pub fn renew(&mut self, id: u64, ttl_ms: u64, now_ms: u64)
+            -> anyhow::Result<()> {
+        self.expire(now_ms);
+        let Some(lease) = self.leases.get_mut(&id) else {
+            bail!("session {id} expired");
+        };
+        lease.ttl_ms = ttl_ms;
+        lease.expires_ms = now_ms + ttl_ms;
+        Ok(())
+    }
+
+    /// Close the session `id`. Its token stays fenced off
+    pub fn close(&mut self, id: u64) {
+        self.leases.remove(&id);
+    }
+
+    /// Close every session whose lease ran out before `now_ms`
+    pub fn expire(&mut self, now_ms: u64) {
+        self.leases.retain(|_, lease| lease.expires_ms >= now_ms);
+    }
+
+    /// Check that the session `id` may write `resource` at `now_ms`; it has
+    /// to be open and no later session may have written the resource. The
+    /// resource is fenced off from earlier sessions
+    pub fn fence(&mut self, resource: R, id: u64, now_ms: u64)
+            -> anyhow::Result<()> {
+        self.expire(now_ms);
+        if !self.leases.contains_key(&id) {
+            bail!("session {id} expired");
+        }
+
+        let fence = self.fences.entry(resource).or_default();
+        if *fence > id {
+            bail!("session {id} fenced off by session {fence}");
+        }
+        *fence = id;
+        Ok(())
+    }
+}
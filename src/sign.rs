@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::rc::Rc;
+use serde_json::Value;
+
+/// Field of the body the signature is kept in
+const FIELD: &str = "hmac";
+
+/// The nodes of the cluster, shared by the signer and the verifier so that
+/// both follow the nodes joining and leaving it
+pub type Nodes = Rc<RefCell<HashSet<String>>>;
+
+/// HMAC-SHA256 of the message `msg`, keyed by `secret`. JSON objects keep
+/// their keys sorted, so every node serializes the message the same
+#[cfg(feature = "hmac")]
+fn mac(secret: &[u8], msg: &Value) -> [u8; 32] {
+    hmac_sha256::HMAC::mac(msg.to_string(), secret)
+}
+
+#[cfg(not(feature = "hmac"))]
+fn mac(_secret: &[u8], _msg: &Value) -> [u8; 32] {
+    unreachable!("secrets need the `hmac` feature")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<[u8; 32]> {
+    let mut bytes = [0; 32];
+    if text.len() != 2 * bytes.len() { return None; }
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Sign the message `msg` with `secret`
+pub fn sign(secret: &[u8], msg: &mut Value) {
+    let mac = hex(&mac(secret, msg));
+    if let Some(body) = msg.get_mut("body").and_then(Value::as_object_mut) {
+        body.insert(FIELD.into(), mac.into());
+    }
+}
+
+/// Check the signature of the message `msg` against `secret`, taking it off
+/// the message. Compares in constant time
+pub fn verify(secret: &[u8], msg: &mut Value) -> anyhow::Result<()> {
+    let signature = msg.get_mut("body")
+        .and_then(Value::as_object_mut)
+        .and_then(|body| body.remove(FIELD))
+        .ok_or_else(|| anyhow::anyhow!("unsigned"))?;
+    let signature = signature.as_str().and_then(unhex)
+        .ok_or_else(|| anyhow::anyhow!("malformed signature"))?;
+
+    let mac = mac(secret, msg);
+    let differ = mac.iter().zip(signature).fold(0, |acc, (a, b)| acc | (a ^ b));
+    anyhow::ensure!(differ == 0, "forged signature");
+    Ok(())
+}
+
+/// Writer signing the messages to the `nodes`, passing the rest through
+/// untouched. Without a secret, nothing is signed
+pub struct Signer<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    secret: Option<Vec<u8>>,
+
+    nodes: Nodes,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Signer<W> {
+    pub fn new(out: W, secret: Option<Vec<u8>>, nodes: Nodes) -> Self {
+        Self { out, secret, nodes, buf: Vec::new() }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Write the complete `line`, signed if it's sent to one of the nodes
+    fn sign_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let (Some(secret), Ok(mut msg)) =
+                (&self.secret, serde_json::from_slice::<Value>(line)) else {
+            return self.out.write_all(line);
+        };
+        let dst = msg["dest"].as_str();
+        if !dst.is_some_and(|dst| self.nodes.borrow().contains(dst)) {
+            return self.out.write_all(line);
+        }
+
+        sign(secret, &mut msg);
+        serde_json::to_writer(&mut self.out, &msg)?;
+        self.out.write_all(b"\n")
+    }
+}
+
+impl<W: Write> Write for Signer<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.secret.is_none() { return self.out.write(data); }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.sign_line(&line)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Checks the signatures of the messages received from the `nodes`. Messages
+/// from clients aren't signed. Without a secret, everything is accepted
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    secret: Option<Vec<u8>>,
+    nodes: Nodes,
+}
+
+impl Verifier {
+    pub fn new(secret: Option<Vec<u8>>, nodes: Nodes) -> Self {
+        Self { secret, nodes }
+    }
+
+    /// Check the received `line`. Returns it without its signature if it
+    /// was signed and is genuine, `None` if it needn't be signed, or why it's
+    /// rejected
+    pub fn verify(&self, line: &str) -> anyhow::Result<Option<String>> {
+        let Some(secret) = &self.secret else { return Ok(None); };
+        let Ok(mut msg) = serde_json::from_str::<Value>(line) else {
+            return Ok(None);
+        };
+        let src = msg["src"].as_str();
+        if !src.is_some_and(|src| self.nodes.borrow().contains(src)) {
+            return Ok(None);
+        }
+
+        verify(secret, &mut msg)?;
+        Ok(Some(msg.to_string()))
+    }
+}
@@ -0,0 +1,71 @@
+/// Slots holding the serialized messages a layer retains, such as those
+/// waiting for an ack. A freed slot keeps its buffer for the next message,
+/// so that a long run retaining a steady stream of messages settles on a
+/// fixed set of buffers rather than allocating one per message. The layers
+/// keep the slots by their own message IDs
+#[derive(Debug, Default)]
+pub struct Slab {
+    slots: Vec<Vec<u8>>,
+
+    /// Slots not in use, their buffers cleared but not freed
+    free: Vec<usize>,
+}
+
+impl Slab {
+    /// Take a slot and fill its buffer with `fill`. The slot is freed again
+    /// if filling it fails
+    pub fn insert_with<E>(&mut self,
+            fill: impl FnOnce(&mut Vec<u8>) -> Result<(), E>)
+            -> Result<usize, E> {
+        let slot = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(Vec::new());
+            self.slots.len() - 1
+        });
+        let buf = &mut self.slots[slot];
+        buf.clear();
+        match fill(buf) {
+            Ok(()) => Ok(slot),
+            Err(e) => {
+                self.free.push(slot);
+                Err(e)
+            },
+        }
+    }
+
+    /// Keep a copy of `line` in a slot
+    pub fn insert(&mut self, line: &[u8]) -> usize {
+        let filled = self.insert_with(|buf| {
+            buf.extend_from_slice(line);
+            Ok::<_, std::convert::Infallible>(())
+        });
+        match filled {
+            Ok(slot) => slot,
+            Err(never) => match never {},
+        }
+    }
+
+    /// The message kept in `slot`
+    pub fn get(&self, slot: usize) -> &[u8] {
+        &self.slots[slot]
+    }
+
+    /// Free `slot` for the next message
+    pub fn remove(&mut self, slot: usize) {
+        debug_assert!(!self.free.contains(&slot), "slot {slot} freed twice");
+        self.free.push(slot);
+    }
+
+    /// Amount of slots in use
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Amount of slots allocated, in use or not
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+/// Deterministic state replicated by the consensus modules. Every replica
+/// applies the same commands in the same order, so every replica ends up in
+/// the same state; the modules differ only in how they agree on the order
+pub trait StateMachine {
+    /// Commands the state changes by
+    type Command: Serialize + DeserializeOwned + Clone;
+
+    /// Result of applying a command, handed back to whoever proposed it
+    type Output;
+
+    /// Apply the next command in the agreed order
+    fn apply(&mut self, command: Self::Command) -> Self::Output;
+}
+
+/// A command along with the time the primary proposed it at. Machines that
+/// measure time, such as leases, measure it in these times, which keeps every
+/// replica agreeing on when something expired
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Stamped<C> {
+    pub now_ms: u64,
+    pub command: C,
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Write, BufRead, BufReader, BufWriter, Seek, SeekFrom};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize, de::DeserializeOwned, de::IgnoredAny};
+
+/// Append-only log of items
+pub trait Storage<T> {
+    /// Append `item` to the end of the log
+    fn append(&mut self, item: T) -> anyhow::Result<()>;
+
+    /// Amount of items in the log
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the log has no items
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `f` with every item of the log, in order
+    fn for_each(&mut self, f: &mut dyn FnMut(T)) -> anyhow::Result<()>;
+}
+
+/// Open the log `name`; a file in `dir` if given, memory otherwise. Logs
+/// start out empty. Either can be moved over to another thread
+pub fn open<T>(dir: Option<&Path>, name: &str)
+        -> anyhow::Result<Box<dyn Storage<T> + Send>>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    match dir {
+        Some(dir) => Ok(Box::new(FileStorage::create(
+            dir.join(format!("{name}.jsonl")))?)),
+        None => Ok(Box::new(MemStorage::default())),
+    }
+}
+
+/// Open the log `name` as an earlier run left it in `dir`. Logs kept in
+/// memory start out empty, as nothing of them survived
+pub fn restore<T>(dir: Option<&Path>, name: &str)
+        -> anyhow::Result<Box<dyn Storage<T> + Send>>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    match dir {
+        Some(dir) => Ok(Box::new(FileStorage::reopen(
+            dir.join(format!("{name}.jsonl")))?)),
+        None => Ok(Box::new(MemStorage::default())),
+    }
+}
+
+/// Call `f` with the lines of the file at `path`, newline included, until
+/// one is cut short or `f` rejects it. Returns the length of the lines
+/// accepted, in bytes and in lines
+fn complete_lines(path: &Path, mut f: impl FnMut(&str) -> bool)
+        -> anyhow::Result<(u64, usize)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut end, mut len) = (0, 0);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if !line.ends_with('\n') || !f(&line) { break; }
+        end += line.len() as u64;
+        len += 1;
+        line.clear();
+    }
+    Ok((end, len))
+}
+
+/// Cut `file` down to its first `end` bytes, dropping whatever a crash left
+/// half-written past them
+fn truncated(file: File, end: u64) -> anyhow::Result<BufWriter<File>> {
+    if file.metadata()?.len() > end {
+        file.set_len(end)?;
+    }
+    Ok(BufWriter::new(file))
+}
+
+/// Log kept in memory
+#[derive(Debug, Clone)]
+pub struct MemStorage<T> {
+    items: Vec<T>,
+}
+
+impl<T> Default for MemStorage<T> {
+    fn default() -> Self {
+        Self { items: Vec::with_capacity(1024) }
+    }
+}
+
+impl<T: Clone> Storage<T> for MemStorage<T> {
+    fn append(&mut self, item: T) -> anyhow::Result<()> {
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(T)) -> anyhow::Result<()> {
+        self.items.iter().cloned().for_each(f);
+        Ok(())
+    }
+}
+
+/// Log appended to a file of JSON lines, keeping nothing but its length in
+/// memory. Every item is handed to the OS as it's appended, so the log
+/// survives the process being killed
+pub struct FileStorage<T> {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    len: usize,
+    _items: core::marker::PhantomData<T>,
+}
+
+impl<T> FileStorage<T> {
+    /// Create the log at `path`, truncating whatever was there
+    pub fn create(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = File::create(&path).map_err(|e|
+            anyhow::anyhow!("can't create {}: {e}", path.display()))?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            len: 0,
+            _items: core::marker::PhantomData,
+        })
+    }
+
+    /// Open the log at `path` to append to whatever is there, creating it if
+    /// there's nothing
+    pub fn reopen(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = File::options().create(true).append(true).open(&path)
+            .map_err(|e| anyhow::anyhow!("can't open {}: {e}",
+                path.display()))?;
+        let (end, len) = complete_lines(&path, |line|
+            serde_json::from_str::<IgnoredAny>(line).is_ok())?;
+        Ok(Self {
+            path,
+            writer: truncated(file, end)?,
+            len,
+            _items: core::marker::PhantomData,
+        })
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storage<T> for FileStorage<T> {
+    fn append(&mut self, item: T) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, &item)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(T)) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        let reader = BufReader::new(File::open(&self.path)?);
+        for line in reader.lines().take(self.len) {
+            f(serde_json::from_str(&line?)?);
+        }
+        Ok(())
+    }
+}
+
+/// Ordered key-value store backing the KV services
+pub trait StorageEngine<K, V> {
+    /// Open the store `name`, keeping its files in `dir` if it has any.
+    /// Stores start out empty
+    fn open(dir: Option<&Path>, name: &str) -> anyhow::Result<Self>
+        where Self: Sized;
+
+    /// Open the store `name` as an earlier run left it in `dir`. Stores
+    /// without files start out empty, as nothing of them survived
+    fn restore(dir: Option<&Path>, name: &str) -> anyhow::Result<Self>
+        where Self: Sized
+    {
+        Self::open(dir, name)
+    }
+
+    /// Get the value of `key`
+    fn get(&mut self, key: &K) -> anyhow::Result<Option<V>>;
+
+    /// Set `key` to `value`, returning the previous value
+    fn put(&mut self, key: K, value: V) -> anyhow::Result<Option<V>>;
+
+    /// Remove `key`, returning its value
+    fn remove(&mut self, key: &K) -> anyhow::Result<Option<V>>;
+
+    /// Set `key` to `to` if its value is `from`, with `None` standing for a
+    /// missing key. Returns the current value if it isn't `from`
+    fn cas(&mut self, key: K, from: Option<&V>, to: V)
+            -> anyhow::Result<Result<(), Option<V>>>
+        where V: PartialEq
+    {
+        let current = self.get(&key)?;
+        if current.as_ref() != from {
+            return Ok(Err(current));
+        }
+        self.put(key, to)?;
+        Ok(Ok(()))
+    }
+
+    /// Call `f` with the pairs with keys within `range`, in key order, until
+    /// it returns `false`
+    fn scan(&mut self, range: (Bound<&K>, Bound<&K>),
+        f: &mut dyn FnMut(K, V) -> bool) -> anyhow::Result<()>;
+
+    /// All the pairs of the store, in key order
+    fn snapshot(&mut self) -> anyhow::Result<Vec<(K, V)>> {
+        let mut pairs = Vec::new();
+        self.scan((Bound::Unbounded, Bound::Unbounded), &mut |key, value| {
+            pairs.push((key, value));
+            true
+        })?;
+        Ok(pairs)
+    }
+}
+
+/// A store picked at runtime
+impl<K, V> StorageEngine<K, V> for Box<dyn StorageEngine<K, V>>
+where
+    K: Serialize + DeserializeOwned + Ord + Clone + 'static,
+    V: Serialize + DeserializeOwned + Clone + 'static,
+{
+    /// Open the store `name` as a file in `dir` if given, in memory
+    /// otherwise
+    fn open(dir: Option<&Path>, name: &str) -> anyhow::Result<Self> {
+        Ok(match dir {
+            Some(_) => Box::new(LogEngine::open(dir, name)?),
+            None => Box::new(MemEngine::open(dir, name)?),
+        })
+    }
+
+    fn restore(dir: Option<&Path>, name: &str) -> anyhow::Result<Self> {
+        Ok(match dir {
+            Some(_) => Box::new(LogEngine::restore(dir, name)?),
+            None => Box::new(MemEngine::open(dir, name)?),
+        })
+    }
+
+    fn get(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        (**self).get(key)
+    }
+
+    fn put(&mut self, key: K, value: V) -> anyhow::Result<Option<V>> {
+        (**self).put(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        (**self).remove(key)
+    }
+
+    fn scan(&mut self, range: (Bound<&K>, Bound<&K>),
+            f: &mut dyn FnMut(K, V) -> bool) -> anyhow::Result<()> {
+        (**self).scan(range, f)
+    }
+}
+
+/// Store kept in memory
+#[derive(Debug, Clone)]
+pub struct MemEngine<K, V> {
+    map: BTreeMap<K, V>,
+}
+
+impl<K: Ord + Clone, V: Clone> StorageEngine<K, V> for MemEngine<K, V> {
+    fn open(_dir: Option<&Path>, _name: &str) -> anyhow::Result<Self> {
+        Ok(Self { map: BTreeMap::new() })
+    }
+
+    fn get(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        Ok(self.map.get(key).cloned())
+    }
+
+    fn put(&mut self, key: K, value: V) -> anyhow::Result<Option<V>> {
+        Ok(self.map.insert(key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        Ok(self.map.remove(key))
+    }
+
+    fn scan(&mut self, range: (Bound<&K>, Bound<&K>),
+            f: &mut dyn FnMut(K, V) -> bool) -> anyhow::Result<()> {
+        for (key, value) in self.map.range::<K, _>(range) {
+            if !f(key.clone(), value.clone()) { break; }
+        }
+        Ok(())
+    }
+}
+
+/// A single write to the log of a `LogEngine`; removals carry no value
+#[derive(Serialize, Deserialize)]
+struct Record<K, V> {
+    key: K,
+    value: Option<V>,
+}
+
+/// Log-structured store. Every write is appended to a file of JSON lines and
+/// only the offsets of the latest writes of the keys are kept in memory. The
+/// log is never compacted, and survives the process being killed
+pub struct LogEngine<K, V> {
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+
+    /// Offset at which the next record is written
+    end: u64,
+
+    /// Offset of the latest record of every key
+    index: BTreeMap<K, u64>,
+
+    _values: core::marker::PhantomData<V>,
+}
+
+impl<K, V> LogEngine<K, V>
+where
+    K: Serialize + DeserializeOwned + Ord + Clone,
+    V: Serialize + DeserializeOwned,
+{
+    /// Create the log at `path`, truncating whatever was there
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e|
+            anyhow::anyhow!("can't create {}: {e}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            reader: BufReader::new(File::open(path)?),
+            end: 0,
+            index: BTreeMap::new(),
+            _values: core::marker::PhantomData,
+        })
+    }
+
+    /// Open the log at `path` to append to whatever is there, creating it if
+    /// there's nothing. The index is rebuilt out of the records
+    pub fn reopen(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::options().create(true).append(true).open(path)
+            .map_err(|e| anyhow::anyhow!("can't open {}: {e}",
+                path.display()))?;
+
+        let mut index = BTreeMap::new();
+        let mut offset = 0;
+        let (end, _) = complete_lines(path, |line| {
+            let Ok(record) = serde_json::from_str::<Record<K, V>>(line) else {
+                return false;
+            };
+            match record.value {
+                Some(_) => index.insert(record.key, offset),
+                None => index.remove(&record.key),
+            };
+            offset += line.len() as u64;
+            true
+        })?;
+
+        Ok(Self {
+            writer: truncated(file, end)?,
+            reader: BufReader::new(File::open(path)?),
+            end,
+            index,
+            _values: core::marker::PhantomData,
+        })
+    }
+
+    /// Read the value of the record at `offset`
+    fn read_at(&mut self, offset: u64) -> anyhow::Result<V> {
+        self.writer.flush()?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let record: Record<K, V> = serde_json::from_str(&line)?;
+        record.value
+            .ok_or_else(|| anyhow::anyhow!("index points at a removal"))
+    }
+
+    /// Append `record`, returning its offset
+    fn append(&mut self, record: &Record<K, V>) -> anyhow::Result<u64> {
+        let line = serde_json::to_string(record)?;
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+
+        let offset = self.end;
+        self.end += line.len() as u64 + 1;
+        Ok(offset)
+    }
+}
+
+impl<K, V> StorageEngine<K, V> for LogEngine<K, V>
+where
+    K: Serialize + DeserializeOwned + Ord + Clone,
+    V: Serialize + DeserializeOwned,
+{
+    /// Create the store in `dir`, or the temporary directory if not given
+    fn open(dir: Option<&Path>, name: &str) -> anyhow::Result<Self> {
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        Self::create(dir.join(format!("{name}.jsonl")))
+    }
+
+    fn restore(dir: Option<&Path>, name: &str) -> anyhow::Result<Self> {
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        Self::reopen(dir.join(format!("{name}.jsonl")))
+    }
+
+    fn get(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        match self.index.get(key) {
+            Some(&offset) => self.read_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, key: K, value: V) -> anyhow::Result<Option<V>> {
+        let old = self.get(&key)?;
+        let offset = self.append(&Record { key: key.clone(),
+            value: Some(value) })?;
+        self.index.insert(key, offset);
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &K) -> anyhow::Result<Option<V>> {
+        let old = self.get(key)?;
+        if old.is_some() {
+            self.append(&Record { key: key.clone(), value: None })?;
+            self.index.remove(key);
+        }
+        Ok(old)
+    }
+
+    fn scan(&mut self, range: (Bound<&K>, Bound<&K>),
+            f: &mut dyn FnMut(K, V) -> bool) -> anyhow::Result<()> {
+        let offsets: Vec<(K, u64)> = self.index.range::<K, _>(range)
+            .map(|(key, offset)| (key.clone(), *offset))
+            .collect();
+        for (key, offset) in offsets {
+            let value = self.read_at(offset)?;
+            if !f(key, value) { break; }
+        }
+        Ok(())
+    }
+}
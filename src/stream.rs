@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use crate::metrics::Metrics;
+use crate::slab::Slab;
+
+/// Fields of the body the stream and the sequence number are kept in
+const STREAM_FIELD: &str = "stream";
+const SEQ_FIELD: &str = "seq";
+
+/// Type of the cumulative acks, which are never sequenced themselves
+pub const ACK_TYPE: &str = "stream_ack";
+
+/// Type of the messages coalescing others, held back by a full window, into
+/// one
+pub const BATCH_TYPE: &str = "stream_batch";
+
+/// Most messages coalesced into a batch
+pub const MAX_BATCH: usize = 64;
+
+/// Earlier streams of a node remembered, to drop what's still on its way
+/// from them
+const RETIRED: usize = 16;
+
+/// Messages sent to a node but not acknowledged yet, by sequence number
+#[derive(Debug)]
+struct Unacked {
+    /// ID of our stream to the node, random so that it's told apart from
+    /// the ones of our earlier runs however the clock moved in between
+    stream: u64,
+
+    next_seq: u64,
+
+    /// Slots of the messages in the slab, and when they were last sent
+    lines: BTreeMap<u64, (usize, Instant)>,
+
+    /// Messages held back while the window is full, not numbered yet
+    pending: Vec<Value>,
+}
+
+impl Unacked {
+    /// Start a new stream
+    fn new() -> Self {
+        Self {
+            stream:   RandomState::new().build_hasher().finish(),
+            next_seq: 0,
+            lines:    BTreeMap::new(),
+            pending:  Vec::new(),
+        }
+    }
+
+    /// Number `msg`, write it to `out` and keep it in `slab` until it's
+    /// acknowledged
+    fn send(&mut self, mut msg: Value, slab: &mut Slab,
+            out: &mut impl Write) -> std::io::Result<()> {
+        self.next_seq += 1;
+        let body = msg.get_mut("body").and_then(Value::as_object_mut);
+        if let Some(body) = body {
+            body.insert(STREAM_FIELD.into(), self.stream.into());
+            body.insert(SEQ_FIELD.into(), self.next_seq.into());
+        }
+        let slot = slab.insert_with(|buf| {
+            serde_json::to_writer(&mut *buf, &msg)?;
+            buf.push(b'\n');
+            Ok::<_, std::io::Error>(())
+        })?;
+        out.write_all(slab.get(slot))?;
+        self.lines.insert(self.next_seq, (slot, Instant::now()));
+        Ok(())
+    }
+}
+
+/// Writer numbering the messages to each of the `nodes`, requests and
+/// replies alike, and holding on to them until the receiving `Streams`
+/// acknowledge them. Those unacknowledged for `retry` are sent again, so
+/// that every message eventually gets through once. At most `window`
+/// messages to a node go unacknowledged; past it, the rest are held back and
+/// coalesced into batches once acks come in. Messages to clients are passed
+/// through untouched, and so is everything without a `retry`
+pub struct Streamer<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    retry: Option<Duration>,
+
+    window: Option<usize>,
+
+    nodes: HashSet<String>,
+
+    unacked: HashMap<String, Unacked>,
+
+    /// The messages not acknowledged yet, of every node
+    slab: Slab,
+
+    /// The incomplete line written so far
+    buf: Vec<u8>,
+
+    metrics: Arc<Metrics>,
+}
+
+impl<W: Write> Streamer<W> {
+    pub fn new(out: W, retry: Option<Duration>, window: Option<usize>,
+            nodes: &[String], metrics: Arc<Metrics>) -> Self {
+        Self {
+            out,
+            retry,
+            window,
+            nodes: nodes.iter().cloned().collect(),
+            unacked: HashMap::new(),
+            slab: Slab::default(),
+            buf: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner(&self) -> &W {
+        &self.out
+    }
+
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Number the messages to the `nodes` from now on. What the nodes that
+    /// left didn't acknowledge is dropped, as they never will
+    pub fn set_nodes(&mut self, nodes: &[String]) {
+        self.nodes = nodes.iter().cloned().collect();
+        let left: Vec<String> = self.unacked.keys()
+            .filter(|node| !self.nodes.contains(*node))
+            .cloned()
+            .collect();
+        for node in left {
+            let unacked = self.unacked.remove(&node).expect("node is gone");
+            for (slot, _) in unacked.lines.values() {
+                self.slab.remove(*slot);
+            }
+        }
+        self.occupancy();
+    }
+
+    /// Amount of messages not acknowledged yet
+    pub fn unacked(&self) -> usize {
+        self.unacked.values().map(|unacked| unacked.lines.len()).sum()
+    }
+
+    /// Amount of messages held back by full windows
+    pub fn pending(&self) -> usize {
+        self.unacked.values().map(|unacked| unacked.pending.len()).sum()
+    }
+
+    /// Note that `node` received everything of `stream` up to `upto`, and
+    /// send what its window has room for by now
+    pub fn ack(&mut self, node: &str, stream: u64, upto: u64)
+            -> std::io::Result<()> {
+        let Some(unacked) = self.unacked.get_mut(node)
+            .filter(|unacked| unacked.stream == stream) else {
+            return Ok(());
+        };
+        let rest = unacked.lines.split_off(&(upto + 1));
+        for (slot, _) in std::mem::replace(&mut unacked.lines, rest).values() {
+            self.slab.remove(*slot);
+        }
+        self.fill(node)
+    }
+
+    /// Start a new stream to `node`, which restarted and lost track of the
+    /// old one. What it didn't acknowledge of the old one is sent again on
+    /// the new one, unbatched and batched anew
+    pub fn restart(&mut self, node: &str) -> std::io::Result<()> {
+        let Some(old) = self.unacked.remove(node) else { return Ok(()); };
+        let mut unacked = Unacked::new();
+        for (slot, _) in old.lines.values() {
+            let msg = serde_json::from_slice::<Value>(self.slab.get(*slot));
+            self.slab.remove(*slot);
+            let Ok(mut msg) = msg else { continue; };
+            if msg["body"]["type"] == BATCH_TYPE {
+                if let Value::Array(batch) = msg["body"]["messages"].take() {
+                    unacked.pending.extend(batch);
+                }
+                continue;
+            }
+            if let Some(body) = msg["body"].as_object_mut() {
+                body.remove(STREAM_FIELD);
+                body.remove(SEQ_FIELD);
+            }
+            unacked.pending.push(msg);
+        }
+        unacked.pending.extend(old.pending);
+        self.unacked.insert(node.into(), unacked);
+        self.fill(node)
+    }
+
+    /// Send what the window of `node` has room for of what's held back
+    fn fill(&mut self, node: &str) -> std::io::Result<()> {
+        let Some(unacked) = self.unacked.get_mut(node) else {
+            return Ok(());
+        };
+        let window = self.window.unwrap_or(usize::MAX);
+        while unacked.lines.len() < window && !unacked.pending.is_empty() {
+            let count = unacked.pending.len().min(MAX_BATCH);
+            let mut batch: Vec<Value> = unacked.pending.drain(..count)
+                .collect();
+            let msg = match batch.len() {
+                1 => batch.pop().expect("batch of one"),
+                _ => serde_json::json!({
+                    "src":  batch[0]["src"],
+                    "dest": node,
+                    "body": { "type": BATCH_TYPE, "messages": batch },
+                }),
+            };
+            unacked.send(msg, &mut self.slab, &mut self.out)?;
+        }
+        self.occupancy();
+        Ok(())
+    }
+
+    /// Tell the metrics how much of the slab is in use
+    fn occupancy(&self) {
+        self.metrics.retained.store(self.slab.len() as u64,
+            Ordering::Relaxed);
+        self.metrics.slots.store(self.slab.slots() as u64, Ordering::Relaxed);
+    }
+
+    /// When the next unacknowledged message is due to be sent again
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let retry = self.retry?;
+        self.unacked.values()
+            .flat_map(|unacked| unacked.lines.values())
+            .map(|(_, sent)| *sent + retry)
+            .min()
+    }
+
+    /// Send the messages unacknowledged for too long again
+    pub fn release_due(&mut self) -> std::io::Result<()> {
+        let Some(retry) = self.retry else { return Ok(()); };
+        let now = Instant::now();
+        for unacked in self.unacked.values_mut() {
+            for (slot, sent) in unacked.lines.values_mut() {
+                if now - *sent < retry { continue; }
+                *sent = now;
+                Metrics::inc(&self.metrics.retransmitted);
+                self.out.write_all(self.slab.get(*slot))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the complete `line`, numbered if it's sent to one of the nodes
+    fn number(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let Ok(msg) = serde_json::from_slice::<Value>(line) else {
+            return self.out.write_all(line);
+        };
+        let Some(dest) = msg["dest"].as_str()
+                .filter(|dest| self.nodes.contains(*dest))
+                .map(String::from) else {
+            return self.out.write_all(line);
+        };
+        if msg["body"]["type"] == ACK_TYPE || !msg["body"].is_object() {
+            return self.out.write_all(line);
+        }
+
+        let unacked = self.unacked.entry(dest).or_insert_with(Unacked::new);
+        if unacked.lines.len() >= self.window.unwrap_or(usize::MAX) {
+            unacked.pending.push(msg);
+            return Ok(());
+        }
+        unacked.send(msg, &mut self.slab, &mut self.out)?;
+        self.occupancy();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Streamer<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.retry.is_none() { return self.out.write(data); }
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.number(&line)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// What we received of the stream of a node
+#[derive(Debug, Default)]
+struct Received {
+    stream: u64,
+
+    /// Everything up to it was received
+    upto: u64,
+
+    /// What was received past `upto`, with gaps before it
+    ahead: BTreeSet<u64>,
+
+    /// Whether we received anything since we last acknowledged
+    unacked: bool,
+
+    /// The streams of the earlier runs of the node
+    retired: VecDeque<u64>,
+}
+
+/// Streams of numbered messages received from the other nodes. Every
+/// message is let through once, however often it was sent, and what was
+/// received is acknowledged cumulatively. A node restarted starts a new
+/// stream; whatever is still on its way from the old ones is dropped
+#[derive(Debug, Default)]
+pub struct Streams {
+    received: HashMap<String, Received>,
+
+    /// Messages of the batches received, but not let through yet
+    unbatched: VecDeque<String>,
+}
+
+impl Streams {
+    /// Take the received `line` in. Returns it without its number if it's
+    /// the first time it was received, the line itself if it's not numbered
+    /// and `None` if it's a repeat. Of a batch, the first of its messages is
+    /// returned and the rest are left for `next_unbatched`
+    pub fn receive(&mut self, line: String) -> Option<String> {
+        // Only look closer at what may be numbered
+        if !line.contains("\"seq\"") { return Some(line); }
+        let Ok(mut msg) = serde_json::from_str::<Value>(&line) else {
+            return Some(line);
+        };
+        let Some(src) = msg["src"].as_str().map(String::from) else {
+            return Some(line);
+        };
+        let Some(body) = msg.get_mut("body").and_then(Value::as_object_mut)
+            else { return Some(line); };
+        let (Some(stream), Some(seq)) = (
+                body.get(STREAM_FIELD).and_then(Value::as_u64),
+                body.get(SEQ_FIELD).and_then(Value::as_u64)) else {
+            return Some(line);
+        };
+        body.remove(STREAM_FIELD);
+        body.remove(SEQ_FIELD);
+
+        let received = self.received.entry(src).or_default();
+        if received.retired.contains(&stream) { return None; }
+        if stream != received.stream {
+            let mut retired = std::mem::take(&mut received.retired);
+            if received.stream != 0 {
+                retired.push_back(received.stream);
+            }
+            if retired.len() > RETIRED {
+                retired.pop_front();
+            }
+            *received = Received { stream, retired, ..Received::default() };
+        }
+
+        // Repeats are acknowledged again, the earlier ack may have been lost
+        received.unacked = true;
+        if seq <= received.upto || !received.ahead.insert(seq) {
+            return None;
+        }
+        while received.ahead.remove(&(received.upto + 1)) {
+            received.upto += 1;
+        }
+
+        if msg["body"]["type"] != BATCH_TYPE { return Some(msg.to_string()); }
+        let Value::Array(batch) = msg["body"]["messages"].take() else {
+            return None;
+        };
+        self.unbatched.extend(batch.iter().map(Value::to_string));
+        self.unbatched.pop_front()
+    }
+
+    /// Take the next message of the batches received
+    pub fn next_unbatched(&mut self) -> Option<String> {
+        self.unbatched.pop_front()
+    }
+
+    /// Take the acknowledgements due, as `(node, stream, upto)`
+    pub fn acks(&mut self) -> Vec<(String, u64, u64)> {
+        self.received.iter_mut()
+            .filter(|(_, received)| received.unacked)
+            .map(|(node, received)| {
+                received.unacked = false;
+                (node.clone(), received.stream, received.upto)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+use crate::metrics::Metrics;
+
+/// Most messages deferred per destination. Past it, the oldest are dropped;
+/// gossip and retries supersede them anyway
+pub const MAX_DEFERRED: usize = 1024;
+
+/// Just enough of a message to tell whether it's throttled, and where to
+#[derive(Deserialize)]
+struct Peek {
+    dest: String,
+    body: PeekBody,
+}
+
+#[derive(Deserialize)]
+struct PeekBody {
+    #[serde(default)]
+    in_reply_to: Option<IgnoredAny>,
+}
+
+/// Token bucket of a destination, along with the messages waiting for its
+/// tokens
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+
+    /// When `tokens` was last refilled
+    refilled: Instant,
+
+    deferred: VecDeque<Vec<u8>>,
+}
+
+/// Writer limiting the rate of the requests written through it, per
+/// destination, with a token bucket. Requests over the rate are deferred
+/// until they fit, and dropped once too many of them pile up. Replies are
+/// never held back, as someone is waiting for them. Without a rate,
+/// everything is passed through untouched
+pub struct Throttle<W> {
+    /// Where the messages are actually written
+    out: W,
+
+    /// Messages per second allowed to each destination, and how many of them
+    /// may be sent at once
+    rate: Option<f64>,
+    burst: f64,
+
+    buckets: HashMap<String, Bucket>,
+
+    /// Partially written line
+    buf: Vec<u8>,
+
+    metrics: Arc<Metrics>,
+}
+
+impl<W: Write> Throttle<W> {
+    /// Build a throttle of `rate` messages per second per destination, up to
+    /// `burst` of them at once
+    pub fn new(out: W, rate: Option<u32>, burst: u32, metrics: Arc<Metrics>)
+            -> Self {
+        Self {
+            out,
+            rate: rate.map(f64::from),
+            burst: f64::from(burst.max(1)),
+            buckets: HashMap::new(),
+            buf: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Get the writer the messages are written to
+    pub fn inner(&self) -> &W {
+        &self.out
+    }
+
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Amount of messages currently deferred
+    pub fn deferred(&self) -> usize {
+        self.buckets.values().map(|bucket| bucket.deferred.len()).sum()
+    }
+
+    /// When the next deferred message fits into the rate
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let rate = self.rate?;
+        self.buckets.values()
+            .filter(|bucket| !bucket.deferred.is_empty())
+            .map(|bucket| bucket.refilled + Duration::from_secs_f64(
+                (1. - bucket.tokens).max(0.) / rate))
+            .min()
+    }
+
+    /// Write out the deferred messages that fit into the rate by now. They're
+    /// left for the next flush
+    pub fn release_due(&mut self) -> std::io::Result<()> {
+        let Some(rate) = self.rate else { return Ok(()); };
+        let now = Instant::now();
+        for bucket in self.buckets.values_mut() {
+            bucket.refill(rate, self.burst, now);
+            while bucket.tokens >= 1. {
+                let Some(line) = bucket.deferred.pop_front() else { break; };
+                bucket.tokens -= 1.;
+                self.out.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out all the deferred messages right away, rate or not
+    pub fn release_all(&mut self) -> std::io::Result<()> {
+        for bucket in self.buckets.values_mut() {
+            for line in bucket.deferred.drain(..) {
+                self.out.write_all(&line)?;
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Send the complete `line` now, later or never
+    fn limit(&mut self, rate: f64, line: Vec<u8>) -> std::io::Result<()> {
+        let dest = match serde_json::from_slice::<Peek>(&line) {
+            Ok(peek) if peek.body.in_reply_to.is_none() => peek.dest,
+            _ => return self.out.write_all(&line),
+        };
+
+        let now = Instant::now();
+        let bucket = self.buckets.entry(dest).or_insert_with(|| Bucket {
+            tokens:   self.burst,
+            refilled: now,
+            deferred: VecDeque::new(),
+        });
+        bucket.refill(rate, self.burst, now);
+
+        // Whatever is deferred already goes first
+        if bucket.deferred.is_empty() && bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            return self.out.write_all(&line);
+        }
+        if bucket.deferred.len() == MAX_DEFERRED {
+            bucket.deferred.pop_front();
+            Metrics::inc(&self.metrics.dropped);
+        }
+        bucket.deferred.push_back(line);
+        Metrics::inc(&self.metrics.deferred);
+        Ok(())
+    }
+}
+
+impl Bucket {
+    /// Add the tokens of the time passed until `now`
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        self.refilled = now;
+    }
+}
+
+impl<W: Write> Write for Throttle<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let Some(rate) = self.rate else { return self.out.write(data); };
+
+        self.buf.extend_from_slice(data);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.limit(rate, line)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,81 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::time::Instant;
+
+/// Timers keyed by `K`, each due at some instant, kept in a heap ordered by
+/// when they're due. Setting a timer already set moves it, and cancelling
+/// one leaves its entry in the heap to be skipped once it comes up, so that
+/// neither has to look for it
+#[derive(Debug)]
+pub struct Timers<K> {
+    heap: BinaryHeap<Reverse<(Instant, u64, K)>>,
+
+    /// When each timer set is due, and the generation of its entry in the
+    /// heap; entries of other generations were moved or cancelled
+    due: HashMap<K, (Instant, u64)>,
+
+    generation: u64,
+}
+
+impl<K> Default for Timers<K> {
+    fn default() -> Self {
+        Self { heap: BinaryHeap::new(), due: HashMap::new(), generation: 0 }
+    }
+}
+
+impl<K: Clone + Eq + Hash + Ord> Timers<K> {
+    /// Set the timer `key` to be due at `at`, moving it if it's set already
+    pub fn set(&mut self, key: K, at: Instant) {
+        self.generation += 1;
+        self.due.insert(key.clone(), (at, self.generation));
+        self.heap.push(Reverse((at, self.generation, key)));
+        self.settle();
+    }
+
+    /// Cancel the timer `key`. Returns `true` if it was set
+    pub fn cancel(&mut self, key: &K) -> bool {
+        let cancelled = self.due.remove(key).is_some();
+        self.settle();
+        cancelled
+    }
+
+    /// When the timer `key` is due, if it's set
+    pub fn due(&self, key: &K) -> Option<Instant> {
+        self.due.get(key).map(|(at, _)| *at)
+    }
+
+    /// When the next timer is due
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.heap.peek().map(|Reverse((at, _, _))| *at)
+    }
+
+    /// Take the next timer due by `now`, if any
+    pub fn pop_due(&mut self, now: Instant) -> Option<K> {
+        if self.next_deadline()? > now { return None; }
+        let Reverse((_, _, key)) = self.heap.pop()?;
+        self.due.remove(&key);
+        self.settle();
+        Some(key)
+    }
+
+    /// Amount of timers set
+    pub fn len(&self) -> usize {
+        self.due.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.due.is_empty()
+    }
+
+    /// Drop the moved and cancelled entries off the top of the heap, so that
+    /// the top is always a timer that's set
+    fn settle(&mut self) {
+        while let Some(Reverse((_, generation, key))) = self.heap.peek() {
+            let live = self.due.get(key)
+                .is_some_and(|(_, live)| live == generation);
+            if live { break; }
+            self.heap.pop();
+        }
+    }
+}
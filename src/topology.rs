@@ -0,0 +1,136 @@
+//! Overlays the gossiping services can lay over the cluster in place of the
+//! topology Maelstrom hands out. They trade how many hops a message takes to
+//! get everywhere, the diameter, against how many neighbors each node
+//! gossips with, the fanout. Over `n` nodes:
+//!
+//! | Strategy | Diameter         | Fanout                          |
+//! |----------|------------------|---------------------------------|
+//! | `hubs`   | 3                | `2√n` for hubs, 1 for leaves    |
+//! | `ring`   | `⌈log₂ n⌉`       | `2⌈log₂ n⌉` at most             |
+//! | `grid`   | `2√n`            | 4 at most                       |
+//!
+//! The fewer the hops, the lower the latency; the smaller the fanout, the
+//! fewer messages each round of gossip takes, and the fewer nodes a
+//! partition cuts off. Hubs carry most of the traffic of theirs, and take
+//! their leaves down with them
+
+use std::collections::HashMap;
+
+/// A topology, as the neighbors of every node
+pub type Topology = HashMap<String, Vec<String>>;
+
+/// Overlay the gossiping services lay over the cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Whatever topology Maelstrom hands out
+    Given,
+
+    /// `⌈√n⌉` hubs, each a neighbor of every other hub, with the rest of the
+    /// nodes attached to one hub each
+    Hubs,
+
+    /// A ring, with chords to the nodes a power of two away
+    Ring,
+
+    /// A square grid, each node a neighbor of the ones next to it
+    Grid,
+}
+
+impl Strategy {
+    /// Parse the strategy out of its lowercase name
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "given" => Self::Given,
+            "hubs"  => Self::Hubs,
+            "ring"  => Self::Ring,
+            "grid"  => Self::Grid,
+            _ => anyhow::bail!("unknown topology `{name}`"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Given => "given",
+            Self::Hubs  => "hubs",
+            Self::Ring  => "ring",
+            Self::Grid  => "grid",
+        }
+    }
+
+    /// The topology laid over `nodes`, unless the given one is used. Every
+    /// node builds the same one, as long as it's handed the nodes in the
+    /// same order
+    pub fn overlay(&self, nodes: &[String]) -> Option<Topology> {
+        match self {
+            Self::Given => None,
+            Self::Hubs  => Some(hubs(nodes)),
+            Self::Ring  => Some(ring(nodes)),
+            Self::Grid  => Some(grid(nodes)),
+        }
+    }
+}
+
+/// Two-tier topology over `nodes`. The first `⌈√n⌉` are hubs, connected to
+/// each other, and the rest are leaves, spread over the hubs in turn. Any
+/// two nodes are at most three hops apart, while a leaf only ever talks to
+/// its hub
+pub fn hubs(nodes: &[String]) -> Topology {
+    let count = (nodes.len() as f64).sqrt().ceil() as usize;
+    let (hubs, leaves) = nodes.split_at(count.min(nodes.len()));
+
+    let mut topology: Topology = hubs.iter().map(|hub| {
+        let others = hubs.iter().filter(|other| *other != hub).cloned();
+        (hub.clone(), others.collect())
+    }).collect();
+    for (idx, leaf) in leaves.iter().enumerate() {
+        let hub = &hubs[idx % hubs.len()];
+        topology.entry(hub.clone()).or_default().push(leaf.clone());
+        topology.insert(leaf.clone(), vec![hub.clone()]);
+    }
+    topology
+}
+
+/// Ring over `nodes` in their order, each node also a neighbor of the ones
+/// 2, 4, 8 and so on ahead of it. Any node is reached by halving the way
+/// there with every hop
+pub fn ring(nodes: &[String]) -> Topology {
+    let mut topology = unlinked(nodes);
+    for from in 0..nodes.len() {
+        let steps = std::iter::successors(Some(1), |step| Some(step * 2));
+        for step in steps.take_while(|step| *step < nodes.len()) {
+            link(&mut topology, nodes, from, (from + step) % nodes.len());
+        }
+    }
+    topology
+}
+
+/// Grid over `nodes` in their order, row by row, `⌈√n⌉` nodes wide. The
+/// last row may be cut short
+pub fn grid(nodes: &[String]) -> Topology {
+    let width = (nodes.len() as f64).sqrt().ceil() as usize;
+    let mut topology = unlinked(nodes);
+    for from in 0..nodes.len() {
+        if (from + 1) % width != 0 && from + 1 < nodes.len() {
+            link(&mut topology, nodes, from, from + 1);
+        }
+        if from + width < nodes.len() {
+            link(&mut topology, nodes, from, from + width);
+        }
+    }
+    topology
+}
+
+/// Every node of `nodes`, without any neighbors yet
+fn unlinked(nodes: &[String]) -> Topology {
+    nodes.iter().map(|node| (node.clone(), Vec::new())).collect()
+}
+
+/// Make the nodes at `a` and `b` of `nodes` neighbors of each other
+fn link(topology: &mut Topology, nodes: &[String], a: usize, b: usize) {
+    for (from, to) in [(a, b), (b, a)] {
+        let neighbors = topology.entry(nodes[from].clone()).or_default();
+        if from != to && !neighbors.contains(&nodes[to]) {
+            neighbors.push(nodes[to].clone());
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::rng::Rng;
+
+/// Bits of an ID below its millisecond timestamp
+const TIMESTAMP_SHIFT: u32 = 80;
+
+/// Bits of an ID below the index of the node generating it
+const NODE_SHIFT: u32 = 64;
+
+/// Generator of ULID-style IDs; 48 bits of wall-clock milliseconds, followed
+/// by 16 bits of the index of the node and 64 random bits. IDs generated
+/// within the same millisecond count up from the random bits, so the IDs of a
+/// node are strictly increasing and those of different nodes never collide.
+/// IDs sort by the time they were generated at
+#[derive(Debug, Clone)]
+pub struct Ulids {
+    node: u16,
+    rng: Rng,
+
+    /// Millisecond of the last ID generated
+    last_ms: u64,
+
+    /// The last ID generated
+    last: u128,
+}
+
+impl Ulids {
+    /// Build a generator for the node of index `node`, seeded with `seed`
+    pub fn new(node: u16, seed: u64) -> Self {
+        Self { node, rng: Rng::new(seed), last_ms: 0, last: 0 }
+    }
+
+    /// Millisecond of the last ID generated
+    pub fn last_ms(&self) -> u64 {
+        self.last_ms
+    }
+
+    /// Get the ID of the wall clock reading `wall_ms`. A wall clock behind
+    /// the last ID, stepped back or resumed from a pause, doesn't take the
+    /// IDs back with it; they count on from the last one
+    pub fn next_at(&mut self, wall_ms: u64) -> u128 {
+        let random = self.last as u64;
+        if wall_ms > self.last_ms || self.last == 0 || random == u64::MAX {
+            // A new millisecond, or no room left in the current one
+            self.last_ms = wall_ms.max(self.last_ms + 1);
+            self.last = ((self.last_ms as u128) << TIMESTAMP_SHIFT)
+                | ((self.node as u128) << NODE_SHIFT)
+                | self.rng.next_u64() as u128;
+        } else {
+            self.last += 1;
+        }
+        self.last
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+/// Vector clock; for every node, the amount of its events that happened
+/// before. Clocks are only partially ordered; neither of two clocks of
+/// concurrent events is before the other
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VClock(pub BTreeMap<String, u64>);
+
+impl VClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Amount of events of `node` the clock includes
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    /// Count an event of `node`
+    pub fn increment(&mut self, node: &str) {
+        *self.0.entry(node.into()).or_default() += 1;
+    }
+
+    /// Include every event of `other`
+    pub fn merge(&mut self, other: &VClock) {
+        for (node, count) in &other.0 {
+            if *count > self.get(node) {
+                self.0.insert(node.clone(), *count);
+            }
+        }
+    }
+
+    /// Returns `true` if the clock includes every event of `other`
+    pub fn descends(&self, other: &VClock) -> bool {
+        other.0.iter().all(|(node, count)| self.get(node) >= *count)
+    }
+
+    /// Returns `true` if neither clock includes the other
+    pub fn concurrent(&self, other: &VClock) -> bool {
+        !self.descends(other) && !other.descends(self)
+    }
+}
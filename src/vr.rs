@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::config::Config;
+use crate::message::{Message, Init, now_ms};
+use crate::state_machine::{StateMachine, Stamped};
+use crate::storage::{self, Storage};
+
+/// Most operations sent to a backup in a single prepare. A backup far behind
+/// catches up over several heartbeats
+const MAX_PREPARE: usize = 256;
+
+/// Someone waiting for the result of an operation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Client {
+    pub src: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+}
+
+/// What an operation in the log does
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Op<C> {
+    /// Applied to the state machine once committed
+    Command(C),
+
+    /// The replicas become `replicas`, each as soon as it has the operation
+    /// in its log. Changes are a single replica at a time, so that the
+    /// majorities of the old and the new replicas overlap
+    Reconfigure(Vec<String>),
+}
+
+/// An operation in the log of a replica
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry<C> {
+    pub op: Op<C>,
+
+    /// Who is told the result once the operation commits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<Client>,
+}
+
+crate::payload! {
+    /// Messages exchanged by the replicas. Operations are numbered from 1 by
+    /// their position in the log
+    pub enum Payload<C> {
+        /// The operations of the primary's log from `first` on, along with the
+        /// last operation committed. Sent with no operations as a heartbeat
+        Prepare {
+            view: u64,
+            first: usize,
+            #[serde(default = "Vec::new",
+                skip_serializing_if = "Vec::is_empty")]
+            entries: Vec<Entry<C>>,
+            commit: usize,
+        },
+
+        /// The log of the sender matches the primary's up to `op`
+        PrepareOk { view: u64, op: usize },
+
+        /// The sender gave up on the primary of the view before `view`
+        StartViewChange { view: u64 },
+
+        /// The state of the sender, handed to the primary of `view`.
+        /// `last_normal` is the last view in which the sender's log was in sync
+        DoViewChange {
+            view: u64,
+            log: Vec<Entry<C>>,
+            last_normal: u64,
+            commit: usize,
+        },
+
+        /// The primary of `view` took over with `log`
+        StartView { view: u64, log: Vec<Entry<C>>, commit: usize },
+    }
+}
+
+impl<C> Payload<C> {
+    /// Returns `true` if the payload takes part in a view change, which
+    /// stalls the replicas until it's done
+    pub fn changes_view(&self) -> bool {
+        matches!(self, Self::StartViewChange { .. } |
+            Self::DoViewChange { .. } | Self::StartView { .. })
+    }
+}
+
+/// A change to the state a replica has to remember across restarts, as kept
+/// in its write-ahead log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Record<C> {
+    View { view: u64, last_normal: u64 },
+
+    /// Everything past the first `len` operations of the log was dropped
+    Truncate { len: usize },
+
+    Append { entries: Vec<Entry<C>> },
+}
+
+/// Build the replica of the node of `init` for the service `name`, starting
+/// from `machine`. With a storage directory, the replica writes its view and
+/// log ahead to it, and picks them up again when restored
+pub fn open<M>(init: &Init, config: &Config, name: &str, machine: M)
+        -> anyhow::Result<Replica<M>>
+where
+    M: StateMachine,
+    M::Command: Send + 'static,
+{
+    let replica = Replica::new(&init.node_id, &init.node_ids, machine,
+        config.retry_timeout);
+    let Some(dir) = config.storage_dir.as_deref() else { return Ok(replica); };
+
+    let name = format!("{}-{name}", init.node_id);
+    let wal = if config.restore { storage::restore(Some(dir), &name)? }
+        else { storage::open(Some(dir), &name)? };
+    replica.with_wal(wal)
+}
+
+/// What a replica is up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Following the primary of the view, or being it
+    Normal,
+
+    /// Agreeing on the primary of the next view
+    ViewChange,
+}
+
+/// The state a replica hands to the primary of a new view
+#[derive(Debug, Clone)]
+struct ViewState<C> {
+    last_normal: u64,
+    log: Vec<Entry<C>>,
+    commit: usize,
+}
+
+/// The result of a committed operation, for its client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Applied<O> {
+    pub client: Client,
+    pub output: O,
+}
+
+/// Payloads the clients of a replicated state machine are answered with.
+/// Replicas other than the primary hand the commands `C` of their clients
+/// to it, and a primary that isn't one anymore points the clients at it
+pub trait ClientPayload<C>: Serialize {
+    /// The `command` of `client`, handed to the primary
+    fn forward(client: Client, command: C) -> Self;
+
+    /// The refusal of a replica that isn't the primary, naming the one it
+    /// knows of
+    fn not_primary(primary: &str) -> Self;
+}
+
+/// A replica of the state machine `M`, kept in sync by Viewstamped
+/// Replication. The primary of every view orders the operations and commits
+/// them once a majority of the replicas has them in its log. When the backups
+/// stop hearing from the primary, they move on to the next view, whose
+/// primary takes over the most up to date log of a majority of the replicas
+pub struct Replica<M: StateMachine> {
+    id: String,
+
+    /// Every replica, in the order they become primaries. Those we started
+    /// with, unless the log reconfigured them
+    replicas: Vec<String>,
+    initial: Vec<String>,
+
+    /// The replicas we were told to become, until the log gets there
+    target: Option<Vec<String>>,
+
+    view: u64,
+    status: Status,
+
+    /// Primary of the view we're in. Pinned when we enter the view, so
+    /// reconfiguring the replicas within it doesn't move the primary
+    leader: String,
+
+    /// Last view in which `log` was in sync with the primary
+    last_normal: u64,
+
+    log: Vec<Entry<M::Command>>,
+
+    /// Operations committed and applied so far
+    commit: usize,
+    applied: usize,
+
+    machine: M,
+
+    /// The primary's idea of how far the log of every backup matches its own,
+    /// and how far it has sent its log to them
+    acked: HashMap<String, usize>,
+    sent: HashMap<String, usize>,
+
+    /// Replicas that gave up on the primary of the view before this one
+    view_changes: Vec<String>,
+
+    /// States handed to us as the primary of the view, by replica
+    do_view_changes: HashMap<String, ViewState<M::Command>>,
+
+    /// When we last heard from the primary or moved on to a new view
+    heard: Instant,
+
+    /// How long the backups wait for the primary before giving up on it
+    timeout: Duration,
+
+    next_id: usize,
+
+    /// Where the view and the log are written ahead of every message that
+    /// depends on them, if anywhere
+    wal: Option<Box<dyn Storage<Record<M::Command>>>>,
+}
+
+impl<M: StateMachine> Replica<M> {
+    /// Build the replica `id` of `replicas`, starting from `machine`
+    pub fn new(id: &str, replicas: &[String], machine: M, timeout: Duration)
+            -> Self {
+        let mut replicas = replicas.to_vec();
+        replicas.sort();
+
+        Self {
+            id:          id.into(),
+            leader:      replicas[0].clone(),
+            replicas:    replicas.clone(),
+            initial:     replicas,
+            target:      None,
+            view:        0,
+            status:      Status::Normal,
+            last_normal: 0,
+            log:         Vec::new(),
+            commit:      0,
+            applied:     0,
+            machine,
+            acked:       HashMap::new(),
+            sent:        HashMap::new(),
+            view_changes:    Vec::new(),
+            do_view_changes: HashMap::new(),
+            heard:       Instant::now(),
+            timeout,
+            next_id:     0,
+            wal:         None,
+        }
+    }
+
+    /// Keep the view and the log in `wal`, picking up where the records
+    /// already in it left off. The clients of the operations picked up are
+    /// forgotten, as they were answered or gave up long ago
+    pub fn with_wal(mut self,
+            mut wal: Box<dyn Storage<Record<M::Command>>>)
+            -> anyhow::Result<Self> {
+        wal.for_each(&mut |record| match record {
+            Record::View { view, last_normal } => {
+                self.view = view;
+                self.last_normal = last_normal;
+            },
+            Record::Truncate { len } => self.log.truncate(len),
+            Record::Append { entries } => {
+                self.log.extend(entries.into_iter()
+                    .map(|entry| Entry { client: None, ..entry }));
+            },
+        })?;
+
+        self.configure();
+        self.leader = self.replicas[self.view as usize % self.replicas.len()]
+            .clone();
+
+        // A view we crashed changing to is only ever finished by others
+        if self.last_normal < self.view {
+            self.status = Status::ViewChange;
+        }
+        self.wal = Some(wal);
+        Ok(self)
+    }
+
+    /// Write `record` ahead, if there's a WAL
+    fn persist(&mut self, record: Record<M::Command>) -> anyhow::Result<()> {
+        match &mut self.wal {
+            Some(wal) => wal.append(record),
+            None => Ok(()),
+        }
+    }
+
+    /// Append `entries` to the log
+    fn extend_log(&mut self, entries: Vec<Entry<M::Command>>)
+            -> anyhow::Result<()> {
+        if entries.is_empty() { return Ok(()); }
+        if self.wal.is_some() {
+            self.persist(Record::Append { entries: entries.clone() })?;
+        }
+        let reconfigures = entries.iter()
+            .any(|entry| matches!(entry.op, Op::Reconfigure(_)));
+        self.log.extend(entries);
+        if reconfigures {
+            self.configure();
+        }
+        Ok(())
+    }
+
+    /// Drop the log past its first `len` operations
+    fn truncate_log(&mut self, len: usize) -> anyhow::Result<()> {
+        if len >= self.log.len() { return Ok(()); }
+        self.persist(Record::Truncate { len })?;
+        self.log.truncate(len);
+        self.configure();
+        Ok(())
+    }
+
+    /// Take on the replicas of the latest reconfiguration in the log
+    fn configure(&mut self) {
+        self.replicas = self.log.iter().rev()
+            .find_map(|entry| match &entry.op {
+                Op::Reconfigure(replicas) => Some(replicas.clone()),
+                Op::Command(_) => None,
+            })
+            .unwrap_or_else(|| self.initial.clone());
+    }
+
+    /// Replace the log with `log`. Committed operations are the same in
+    /// every log, so only what follows them is rewritten
+    fn replace_log(&mut self, mut log: Vec<Entry<M::Command>>)
+            -> anyhow::Result<()> {
+        let common = self.commit.min(log.len());
+        self.truncate_log(common)?;
+        self.extend_log(log.split_off(common))
+    }
+
+    pub fn view(&self) -> u64 {
+        self.view
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Amount of operations in the log
+    pub fn op(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Amount of operations committed
+    pub fn commit(&self) -> usize {
+        self.commit
+    }
+
+    pub fn machine(&self) -> &M {
+        &self.machine
+    }
+
+    /// How long ago we last heard from the primary, as a backup following
+    /// it. Our state is at most that much older than the primary's
+    pub fn staleness(&self) -> Option<Duration> {
+        (self.status == Status::Normal && self.primary() != self.id)
+            .then(|| self.heard.elapsed())
+    }
+
+    /// Every replica, as far as our log knows
+    pub fn replicas(&self) -> &[String] {
+        &self.replicas
+    }
+
+    /// Change the replicas to `replicas`. The primary gets there one replica
+    /// at a time, through the log; the others leave it to the primary
+    pub fn reconfigure(&mut self, replicas: &[String]) {
+        if replicas.is_empty() { return; }
+        let mut replicas = replicas.to_vec();
+        replicas.sort();
+        self.target = Some(replicas);
+    }
+
+    /// ID for the next message sent by the node of the replica
+    pub fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// The primary of the current view. Until the view starts, the one the
+    /// replicas we know of take turns on
+    pub fn primary(&self) -> &str {
+        match self.status {
+            Status::Normal => &self.leader,
+            Status::ViewChange =>
+                &self.replicas[self.view as usize % self.replicas.len()],
+        }
+    }
+
+    /// Returns `true` if we're the primary and can take operations
+    pub fn is_primary(&self) -> bool {
+        self.status == Status::Normal && self.primary() == self.id
+    }
+
+    /// Every replica but us
+    fn others(&self) -> Vec<String> {
+        self.replicas.iter().filter(|r| **r != self.id).cloned().collect()
+    }
+
+    /// Amount of other replicas that have to agree with us for a majority
+    fn quorum(&self) -> usize {
+        self.replicas.len() / 2
+    }
+
+    fn send(&mut self, dst: &str, payload: Payload<M::Command>,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        let id = self.next_id();
+        Message::new(&self.id, dst, id, payload).send(output)
+    }
+
+    /// Order `command` after everything else and replicate it. Returns
+    /// `None` if we aren't the primary
+    pub fn propose(&mut self, command: M::Command, client: Option<Client>,
+            output: &mut dyn Write)
+            -> anyhow::Result<Option<Vec<Applied<M::Output>>>> {
+        self.propose_all(vec![(command, client)], output)
+    }
+
+    /// Order `commands` after everything else, in order, and replicate them
+    /// together. Returns `None` if we aren't the primary
+    pub fn propose_all(&mut self,
+            commands: Vec<(M::Command, Option<Client>)>,
+            output: &mut dyn Write)
+            -> anyhow::Result<Option<Vec<Applied<M::Output>>>> {
+        if !self.is_primary() { return Ok(None); }
+        let entries = commands.into_iter()
+            .map(|(command, client)| Entry { op: Op::Command(command), client })
+            .collect();
+        self.append(entries, output)?;
+        Ok(Some(self.apply_committed()))
+    }
+
+    /// Append `entries` to our log as the primary and replicate them
+    fn append(&mut self, entries: Vec<Entry<M::Command>>,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        self.extend_log(entries)?;
+        for backup in self.others() {
+            self.prepare(&backup, output)?;
+        }
+        self.advance_commit();
+        Ok(())
+    }
+
+    /// As the primary, take the replicas a step closer to the target, once
+    /// the previous step committed. Joins go before leaves
+    fn step_target(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Some(target) = &self.target else { return Ok(()); };
+        if *target == self.replicas {
+            self.target = None;
+            return Ok(());
+        }
+        if self.log[self.commit..].iter()
+                .any(|entry| matches!(entry.op, Op::Reconfigure(_))) {
+            return Ok(());
+        }
+
+        let mut replicas = self.replicas.clone();
+        match target.iter().find(|id| !replicas.contains(id)) {
+            Some(joined) => {
+                replicas.push(joined.clone());
+                replicas.sort();
+            },
+            None => {
+                let left = replicas.iter()
+                    .position(|id| !target.contains(id))
+                    .expect("no replica to leave");
+                replicas.remove(left);
+            },
+        }
+        self.append(vec![Entry { op: Op::Reconfigure(replicas), client: None }],
+            output)
+    }
+
+    /// Send `backup` the part of our log it wasn't sent yet
+    fn prepare(&mut self, backup: &str, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let sent = self.sent.get(backup).copied().unwrap_or(0)
+            .min(self.log.len());
+        let until = (sent + MAX_PREPARE).min(self.log.len());
+        let payload = Payload::Prepare {
+            view:    self.view,
+            first:   sent + 1,
+            entries: self.log[sent..until].to_vec(),
+            commit:  self.commit,
+        };
+        self.sent.insert(backup.into(), until);
+        self.send(backup, payload, output)
+    }
+
+    /// Commit everything a majority of the replicas has. A primary that's
+    /// leaving the replicas doesn't count towards it
+    fn advance_commit(&mut self) {
+        let mut acked: Vec<usize> = self.others().iter()
+            .map(|backup| self.acked.get(backup).copied().unwrap_or(0))
+            .collect();
+        acked.sort_unstable_by(|a, b| b.cmp(a));
+        let quorum = match self.replicas.contains(&self.id) {
+            true => self.quorum(),
+            false => self.quorum() + 1,
+        };
+        let majority = match quorum {
+            0 => self.log.len(),
+            quorum => acked[quorum - 1].min(self.log.len()),
+        };
+        self.commit = self.commit.max(majority);
+    }
+
+    /// Apply the operations committed since the last time. The results are
+    /// only handed out by the primary, which answers the clients
+    fn apply_committed(&mut self) -> Vec<Applied<M::Output>> {
+        let mut results = Vec::new();
+        while self.applied < self.commit {
+            let entry = self.log[self.applied].clone();
+            self.applied += 1;
+            let Op::Command(command) = entry.op else { continue; };
+            let output = self.machine.apply(command);
+            if let (Some(client), true) = (entry.client, self.is_primary()) {
+                results.push(Applied { client, output });
+            }
+        }
+        results
+    }
+
+    /// Move on to `view` and get the other replicas to do the same
+    fn start_view_change(&mut self, view: u64, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        self.persist(Record::View { view, last_normal: self.last_normal })?;
+        self.view = view;
+        self.status = Status::ViewChange;
+        self.view_changes.clear();
+        self.do_view_changes.clear();
+        self.heard = Instant::now();
+        for other in self.others() {
+            self.send(&other, Payload::StartViewChange { view }, output)?;
+        }
+        self.check_view_changes(output)
+    }
+
+    /// Once a majority gave up on the old primary, hand our state to the new
+    /// one
+    fn check_view_changes(&mut self, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        if self.view_changes.len() != self.quorum() { return Ok(()); }
+
+        let primary = self.primary().to_string();
+        if primary == self.id {
+            let state = ViewState {
+                last_normal: self.last_normal,
+                log:         self.log.clone(),
+                commit:      self.commit,
+            };
+            self.do_view_changes.insert(self.id.clone(), state);
+            return self.check_do_view_changes(output);
+        }
+        let payload = Payload::DoViewChange {
+            view:        self.view,
+            log:         self.log.clone(),
+            last_normal: self.last_normal,
+            commit:      self.commit,
+        };
+        self.send(&primary, payload, output)
+    }
+
+    /// Once a majority handed us its state, take over as the primary with
+    /// the most up to date log of them
+    fn check_do_view_changes(&mut self, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        if self.do_view_changes.len() <= self.quorum() { return Ok(()); }
+
+        let states = core::mem::take(&mut self.do_view_changes);
+        let commit = states.values().map(|state| state.commit).max();
+        let latest = states.into_values()
+            .max_by_key(|state| (state.last_normal, state.log.len()))
+            .expect("a majority of no states");
+
+        self.replace_log(latest.log)?;
+        self.commit = self.commit.max(commit.unwrap_or(0));
+        self.enter_view(self.view, self.id.clone())?;
+        self.acked.clear();
+        self.sent.clear();
+
+        let payload = Payload::StartView {
+            view:   self.view,
+            log:    self.log.clone(),
+            commit: self.commit,
+        };
+        for backup in self.others() {
+            self.sent.insert(backup.clone(), self.log.len());
+            self.send(&backup, payload.clone(), output)?;
+        }
+        Ok(())
+    }
+
+    /// Go back to normal operation in `view`, following `primary`
+    fn enter_view(&mut self, view: u64, primary: String)
+            -> anyhow::Result<()> {
+        self.persist(Record::View { view, last_normal: view })?;
+        self.view = view;
+        self.status = Status::Normal;
+        self.leader = primary;
+        self.last_normal = view;
+        self.view_changes.clear();
+        self.do_view_changes.clear();
+        self.heard = Instant::now();
+        Ok(())
+    }
+
+    /// Handle a message from the replica `src`. Returns the results of the
+    /// operations committed as a consequence
+    pub fn step(&mut self, src: String, payload: Payload<M::Command>,
+            output: &mut dyn Write)
+            -> anyhow::Result<Vec<Applied<M::Output>>> {
+        // Replicas that left, or didn't join yet as far as we know, don't
+        // get a say in the views
+        let changes_view = matches!(payload, Payload::StartViewChange { .. } |
+            Payload::DoViewChange { .. });
+        if changes_view && !self.replicas.contains(&src) {
+            return Ok(Vec::new());
+        }
+
+        match payload {
+            Payload::Prepare { view, first, entries, commit } => {
+                if view < self.view { return Ok(Vec::new()); }
+
+                // We missed the start of the view. Whatever we have past
+                // the commit may have been replaced by the new primary
+                if view > self.view || self.status != Status::Normal {
+                    self.truncate_log(self.commit)?;
+                    self.enter_view(view, src.clone())?;
+                }
+                self.heard = Instant::now();
+
+                // Within a view, logs only ever grow, so whatever overlaps
+                // with what we have is the same
+                if first <= self.log.len() + 1 {
+                    let have = self.log.len() + 1 - first;
+                    self.extend_log(entries.into_iter().skip(have).collect())?;
+                }
+                self.commit = self.commit.max(commit.min(self.log.len()));
+
+                let ok = Payload::PrepareOk { view, op: self.log.len() };
+                self.send(&src, ok, output)?;
+            },
+
+            Payload::PrepareOk { view, op } => {
+                if view != self.view || !self.is_primary() {
+                    return Ok(Vec::new());
+                }
+                let acked = self.acked.entry(src).or_default();
+                *acked = op.max(*acked);
+                self.advance_commit();
+            },
+
+            Payload::StartViewChange { view } => {
+                if view > self.view {
+                    self.start_view_change(view, output)?;
+                }
+                if view == self.view && self.status == Status::ViewChange &&
+                        !self.view_changes.contains(&src) {
+                    self.view_changes.push(src);
+                    self.check_view_changes(output)?;
+                }
+            },
+
+            Payload::DoViewChange { view, log, last_normal, commit } => {
+                if view > self.view {
+                    self.start_view_change(view, output)?;
+                }
+                if view == self.view && self.status == Status::ViewChange {
+                    self.do_view_changes.insert(src,
+                        ViewState { last_normal, log, commit });
+                    self.check_do_view_changes(output)?;
+                }
+            },
+
+            Payload::StartView { view, log, commit } => {
+                if view < self.view ||
+                        (view == self.view && self.status == Status::Normal) {
+                    return Ok(Vec::new());
+                }
+                self.replace_log(log)?;
+                self.commit = self.commit.max(commit.min(self.log.len()));
+                self.enter_view(view, src.clone())?;
+
+                let ok = Payload::PrepareOk { view, op: self.log.len() };
+                self.send(&src, ok, output)?;
+            },
+        }
+        Ok(self.apply_committed())
+    }
+
+    /// Called regularly. The primary sends the backups what they didn't
+    /// acknowledge yet and works on reconfiguring the replicas, the backups
+    /// give up on a silent primary. A primary that committed its own leave
+    /// stops, and the replicas left move on to the next view without it
+    pub fn tick(&mut self, output: &mut dyn Write)
+            -> anyhow::Result<Vec<Applied<M::Output>>> {
+        if self.is_primary() && !self.replicas.contains(&self.id) &&
+                self.commit == self.log.len() {
+            self.status = Status::ViewChange;
+        } else if self.is_primary() {
+            for backup in self.others() {
+                let acked = self.acked.get(&backup).copied().unwrap_or(0);
+                self.sent.insert(backup.clone(), acked);
+                self.prepare(&backup, output)?;
+            }
+            self.step_target(output)?;
+        } else if self.replicas.contains(&self.id) &&
+                self.heard.elapsed() > self.timeout {
+            self.start_view_change(self.view + 1, output)?;
+        }
+        Ok(self.apply_committed())
+    }
+}
+
+/// The front end of the replicas to the clients: the commands go to the
+/// primary, stamped with the time it proposed them at, and the clients are
+/// answered once they're applied
+impl<C, M> Replica<M>
+where
+    C: Clone,
+    M: StateMachine<Command = Stamped<C>>,
+    M::Output: ClientPayload<C>,
+{
+    /// Send `payload` to `dst` as a new message, replying to `reply_id`
+    pub fn send_client(&mut self, dst: &str, reply_id: Option<usize>,
+            payload: M::Output, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let id = self.next_id();
+        let mut msg = Message::new(&self.id, dst, id, payload);
+        msg.body.reply_id = reply_id;
+        msg.send(output)
+    }
+
+    /// Answer the clients of the committed commands
+    pub fn answer(&mut self, applied: Vec<Applied<M::Output>>,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        for Applied { client, output: reply } in applied {
+            self.send_client(&client.src, client.id, reply, output)?;
+        }
+        Ok(())
+    }
+
+    /// Tell `client` to go to the primary instead
+    pub fn not_primary(&mut self, client: Client, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let refusal = M::Output::not_primary(self.primary());
+        self.send_client(&client.src, client.id, refusal, output)
+    }
+
+    /// Propose `command` of `client` if we're the primary, hand it to the
+    /// primary otherwise. Forwarded commands are only forwarded once
+    pub fn submit(&mut self, client: Client, command: C, forwarded: bool,
+            output: &mut dyn Write) -> anyhow::Result<()> {
+        let stamped = Stamped {
+            now_ms:  now_ms(),
+            command: command.clone(),
+        };
+        if let Some(applied) = self.propose(stamped, Some(client.clone()),
+                output)? {
+            return self.answer(applied, output);
+        }
+
+        if forwarded {
+            return self.not_primary(client, output);
+        }
+        let primary = self.primary().to_string();
+        let forward = M::Output::forward(client, command);
+        self.send_client(&primary, None, forward, output)
+    }
+}
@@ -0,0 +1,97 @@
+//! Refusing client requests under overload
+
+use std::io::Write;
+use std::time::Duration;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message};
+use maelstrom::pool::{self, SharedNode};
+use maelstrom::services::{broadcast, echo};
+
+fn lines(msgs: &[Value]) -> String {
+    msgs.iter().map(|msg| format!("{msg}\n")).collect()
+}
+
+fn parse(output: Vec<u8>) -> Vec<Value> {
+    String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn config(max_queue: &str) -> Config {
+    let mut config = Config::default();
+    config.apply_args(&["--max-queue".into(), max_queue.into()]).unwrap();
+    config
+}
+
+#[test]
+fn client_requests_are_shed_while_the_queue_is_long() {
+    // Whatever arrives ahead of init is queued up all at once
+    let broadcast = |src: &str, id: u64| json!({"src": src, "dest": "n1",
+        "body": {"type": "broadcast", "msg_id": id, "message": id}});
+    let input = lines(&[
+        broadcast("c1", 1),
+        broadcast("n2", 2),
+        broadcast("c1", 3),
+        broadcast("c1", 4),
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+    ]);
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<broadcast::Payload, broadcast::BroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &config("2")).unwrap();
+
+    let replies: Vec<(String, Value)> = parse(output).into_iter()
+        .filter(|msg| msg["body"]["type"] != "init_ok")
+        .map(|msg| (msg["dest"].as_str().unwrap().into(),
+            msg["body"]["type"].clone()))
+        .collect();
+    assert_eq!(replies, [
+        ("c1".into(), json!("error")),
+        ("n2".into(), json!("broadcast_ok")),
+        ("c1".into(), json!("broadcast_ok")),
+        ("c1".into(), json!("broadcast_ok")),
+    ]);
+}
+
+/// Node taking its time to echo
+struct Slow;
+
+impl SharedNode<echo::Payload> for Slow {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    fn step(&self, input: Message<echo::Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        std::thread::sleep(Duration::from_millis(50));
+        let mut input = input;
+        let id = input.body.id;
+        let echo::Payload::Echo { echo } = input.body.payload else {
+            return Ok(());
+        };
+        input.body.payload = echo::Payload::EchoOk { echo };
+        input.into_reply(id).send(output)
+    }
+}
+
+#[test]
+fn the_pool_sheds_what_its_workers_are_behind_on() {
+    let mut msgs = vec![json!({"src": "c0", "dest": "n1", "body": {
+        "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}})];
+    msgs.extend((1..=4).map(|id| json!({"src": "c1", "dest": "n1",
+        "body": {"type": "echo", "msg_id": id, "echo": id}})));
+    let mut config = config("2");
+    config.workers = 1;
+    let mut output = Vec::new();
+    pool::main_loop_with_io::<echo::Payload, Slow>(
+        std::io::Cursor::new(lines(&msgs)), &mut output, &config).unwrap();
+
+    // Refusals wait for the replies ahead of them
+    let replies: Vec<Value> = parse(output).into_iter()
+        .filter(|msg| msg["body"]["type"] != "init_ok")
+        .map(|msg| msg["body"]["type"].clone())
+        .collect();
+    assert_eq!(replies, ["echo_ok", "echo_ok", "error", "error"]);
+}
@@ -0,0 +1,27 @@
+//! Membership queries of the Bloom filter
+
+use maelstrom::bloom::Bloom;
+
+#[test]
+fn never_misses_and_rarely_lies() {
+    let mut bloom = Bloom::new(1000, 0.01, 7);
+    for item in 0..1000usize {
+        bloom.insert(&item);
+    }
+
+    assert!((0..1000usize).all(|item| bloom.contains(&item)));
+    let false_positives = (1000..11000usize)
+        .filter(|item| bloom.contains(item))
+        .count();
+    assert!(false_positives < 300, "{false_positives} false positives");
+}
+
+#[test]
+fn salts_change_the_false_positives() {
+    let build = |salt| {
+        let mut bloom = Bloom::new(100, 0.1, salt);
+        (0..100usize).for_each(|item| bloom.insert(&item));
+        (100..1100usize).filter(|item| bloom.contains(item)).collect::<Vec<_>>()
+    };
+    assert_ne!(build(1), build(2));
+}
@@ -0,0 +1,461 @@
+//! Gossip between broadcast nodes
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::bloom::Bloom;
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::broadcast::{self, Payload, BroadcastNode};
+use common::{Cluster, step};
+
+fn node(id: &str) -> BroadcastNode {
+    node_with(id, &Config::default())
+}
+
+fn node_with(id: &str, config: &Config) -> BroadcastNode {
+    BroadcastNode::from_init(&msg::Init {
+        node_id:  id.into(),
+        node_ids: vec!["n0".into(), "n1".into()],
+    }, config).unwrap()
+}
+
+fn broadcast(node: &mut BroadcastNode, dst: &str, message: usize) {
+    step(node, json!({"src": "c1", "dest": dst,
+        "body": {"type": "broadcast", "msg_id": 1, "message": message}}));
+}
+
+#[test]
+fn neighbors_gossip_their_messages() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    for (node, id) in [(&mut n0, "n0"), (&mut n1, "n1")] {
+        step(node, json!({"src": "c1", "dest": id, "body": {
+            "type": "topology", "msg_id": 1, "topology": topology}}));
+    }
+    broadcast(&mut n0, "n0", 1);
+    broadcast(&mut n0, "n0", 2);
+
+    // n1 pulls from n0
+    let mut out = Vec::new();
+    n1.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(read["dest"], "n0");
+    let reply = step(&mut n0, read);
+    assert_eq!(reply[0]["body"]["messages"], json!([1, 2]));
+    assert!(step(&mut n1, reply[0].clone()).is_empty());
+
+    let read = step(&mut n1, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "read", "msg_id": 2}}));
+    assert_eq!(read[0]["body"]["messages"], json!([1, 2]));
+}
+
+#[test]
+fn filtered_reads_skip_what_the_reader_has() {
+    let mut n0 = node("n0");
+    for message in 0..100 {
+        broadcast(&mut n0, "n0", message);
+    }
+
+    let mut seen = Bloom::new(90, 0.001, 0);
+    (0..90usize).for_each(|message| seen.insert(&message));
+    let reply = step(&mut n0, json!({"src": "n1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 1, "seen": seen}}));
+    let missing: Vec<usize> = (90..100).collect();
+    assert_eq!(reply[0]["body"]["messages"], json!(missing));
+}
+
+#[test]
+fn messages_hidden_by_false_positives_are_read_later() {
+    let mut n0 = node("n0");
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1, "topology": {"n0": ["n1"]}}}));
+    for message in 0..10 {
+        broadcast(&mut n0, "n0", message);
+    }
+
+    // A saturated filter takes every message for seen
+    let mut saturated = Bloom::new(1, 0.5, 0);
+    (100..1000usize).for_each(|message| saturated.insert(&message));
+    assert!((0..10usize).all(|message| saturated.contains(&message)));
+    let reply = step(&mut n0, json!({"src": "n1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 1, "seen": saturated,
+        "upto": 0}}));
+    assert_eq!(reply[0]["body"]["messages"], json!([]));
+    assert_eq!(reply[0]["body"]["upto"], 0);
+    assert_eq!(n0.status()["unsent"]["n1"], 10);
+
+    // So the reader isn't told it has any of the log, and the next round's
+    // filter lets the messages through
+    let reply = step(&mut n0, json!({"src": "n1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 2,
+        "seen": Bloom::new(10, 0.01, 1), "upto": 0}}));
+    let all: Vec<usize> = (0..10).collect();
+    assert_eq!(reply[0]["body"]["messages"], json!(all));
+    assert_eq!(reply[0]["body"]["upto"], 10);
+    assert_eq!(n0.status()["unsent"]["n1"], 0);
+}
+
+#[test]
+fn stalest_neighbors_are_gossiped_with_first() {
+    let config = Config { gossip_fanout: Some(1), ..Config::default() };
+    let mut n0 = BroadcastNode::from_init(&msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into(), "n1".into(), "n2".into()],
+    }, &config).unwrap();
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1,
+        "topology": {"n0": ["n1", "n2"]}}}));
+    broadcast(&mut n0, "n0", 7);
+
+    // One neighbor per round, each handed what we hold for it
+    let round = |n0: &mut BroadcastNode| {
+        let mut out = Vec::new();
+        n0.tick(&mut out).unwrap();
+        let read: Value = serde_json::from_slice(&out).unwrap();
+        step(n0, json!({"src": read["dest"], "dest": "n0", "body": {
+            "type": "read_ok", "in_reply_to": read["body"]["msg_id"],
+            "messages": []}}));
+        read
+    };
+    let first = round(&mut n0);
+    assert_eq!(first["body"]["messages"], json!([7]));
+    let second = round(&mut n0);
+    assert_ne!(second["dest"], first["dest"]);
+    assert_eq!(second["body"]["messages"], json!([7]));
+
+    // Both answered, the one that answered longest ago is next
+    let third = round(&mut n0);
+    assert_eq!(third["dest"], first["dest"]);
+    assert!(third["body"].get("messages").is_none());
+
+    // A neighbor reading from us takes what we held for it
+    broadcast(&mut n0, "n0", 8);
+    let reply = step(&mut n0, json!({"src": "n2", "dest": "n0",
+        "body": {"type": "read", "msg_id": 1, "messages": [9]}}));
+    assert_eq!(reply[0]["body"]["messages"], json!([7, 8, 9]));
+    let status = n0.status();
+    assert_eq!(status["unsent"]["n2"], 0);
+    assert_eq!(status["unsent"]["n1"], 2);
+}
+
+#[test]
+fn unresponsive_neighbors_are_backed_off() {
+    let mut n0 = node("n0");
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1, "topology": {"n0": ["n1"]}}}));
+
+    // n1 never answers; the reads get further and further apart, each
+    // carrying everything the earlier ones did
+    let mut reads = Vec::new();
+    for round in 1..=20 {
+        broadcast(&mut n0, "n0", round);
+        let mut out = Vec::new();
+        n0.tick(&mut out).unwrap();
+        if !out.is_empty() {
+            let read: Value = serde_json::from_slice(&out).unwrap();
+            reads.push((round, read));
+        }
+    }
+    let rounds: Vec<usize> = reads.iter().map(|(round, _)| *round).collect();
+    assert_eq!(rounds, [1, 4, 9, 18]);
+    let (_, last) = reads.last().unwrap();
+    let all: Vec<usize> = (1..=18).collect();
+    assert_eq!(last["body"]["messages"], json!(all));
+    assert_eq!(n0.status()["backoff"]["n1"], 4);
+
+    // Once it answers, even late, it's gossiped with every round again
+    step(&mut n0, json!({"src": "n1", "dest": "n0", "body": {
+        "type": "read_ok", "in_reply_to": last["body"]["msg_id"],
+        "messages": []}}));
+    assert_eq!(n0.status()["unsent"]["n1"], 2);
+    let mut out = Vec::new();
+    n0.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(read["body"]["messages"], json!([19, 20]));
+}
+
+#[test]
+fn suspected_neighbors_are_probed_and_resynced_once_back() {
+    let mut n0 = node("n0");
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1, "topology": {"n0": ["n1"]}}}));
+
+    // After four missed reads n1 is suspected, and only probed; what's saved
+    // in the meantime is parked for it
+    let mut reads = Vec::new();
+    for round in 1..=40 {
+        broadcast(&mut n0, "n0", round);
+        let mut out = Vec::new();
+        n0.tick(&mut out).unwrap();
+        if !out.is_empty() {
+            let read: Value = serde_json::from_slice(&out).unwrap();
+            reads.push((round, read));
+        }
+    }
+    let rounds: Vec<usize> = reads.iter().map(|(round, _)| *round).collect();
+    assert_eq!(rounds, [1, 4, 9, 18, 35]);
+    let (_, probe) = reads.last().unwrap();
+    assert_eq!(probe["body"]["messages"], json!(null));
+    assert_eq!(n0.status()["suspected"], json!(["n1"]));
+    assert_eq!(n0.status()["unsent"]["n1"], 40);
+
+    // Once the probe is answered, the next round reads the whole log of n1
+    // and hands it everything parked
+    step(&mut n0, json!({"src": "n1", "dest": "n0", "body": {
+        "type": "read_ok", "in_reply_to": probe["body"]["msg_id"],
+        "messages": [], "upto": 5}}));
+    assert_eq!(n0.status()["suspected"], json!([]));
+    let mut out = Vec::new();
+    n0.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    let all: Vec<usize> = (1..=40).collect();
+    assert_eq!(read["body"]["messages"], json!(all));
+    assert_eq!(read["body"]["upto"], 0);
+}
+
+#[test]
+fn topologies_are_validated() {
+    let config = Config::default();
+    let nodes: Vec<String> = ["n0", "n1", "n2"].map(String::from).into();
+    let topology = |neighbors: Value| Some(serde_json::from_value(
+        json!({"n0": neighbors})).unwrap());
+
+    // We're not our own neighbor, nor anyone's twice
+    let neighbors = broadcast::neighbors("n0", &nodes,
+        topology(json!(["n0", "n1", "n1", "n7"])), &config);
+    assert_eq!(neighbors, ["n1", "n7"]);
+
+    // Without neighbors, everyone else is one
+    for empty in [topology(json!([])), topology(json!(["n0"])), None] {
+        let neighbors = broadcast::neighbors("n0", &nodes, empty, &config);
+        assert_eq!(neighbors, ["n1", "n2"]);
+    }
+}
+
+#[test]
+fn reads_may_be_sorted() {
+    let mut config = Config::default();
+    config.apply_args(&["--read-order".into(), "sorted".into()]).unwrap();
+    let mut n0 = node_with("n0", &config);
+    for message in [3, 1, 2] {
+        broadcast(&mut n0, "n0", message);
+    }
+
+    let read = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 2}}));
+    assert_eq!(read[0]["body"]["messages"], json!([1, 2, 3]));
+}
+
+#[test]
+fn dumps_show_what_each_neighbor_lacks() {
+    let mut n0 = node("n0");
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1, "topology": {"n0": ["n1"]}}}));
+    broadcast(&mut n0, "n0", 7);
+    n0.tick(&mut Vec::new()).unwrap();
+
+    // The read carrying 7 is still out
+    let dump = n0.dump();
+    assert_eq!(dump["rounds"], 1);
+    assert_eq!(dump["peers"]["n1"]["unsent"], json!([7]));
+    assert_eq!(dump["peers"]["n1"]["read"]["carried"], 1);
+    assert_eq!(dump["peers"]["n1"]["synced_ms_ago"], Value::Null);
+}
+
+#[test]
+fn restored_nodes_pick_up_their_messages() {
+    let dir = std::env::temp_dir()
+        .join(format!("maelstrom-broadcast-restore-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into(), "n1".into()],
+    };
+    let mut config = Config::default();
+    config.apply_args(&["--storage-dir".into(),
+        dir.display().to_string()]).unwrap();
+
+    let mut n0 = BroadcastNode::from_init(&init, &config).unwrap();
+    broadcast(&mut n0, "n0", 1);
+    broadcast(&mut n0, "n0", 2);
+    drop(n0);
+
+    config.restore = true;
+    let mut n0 = BroadcastNode::from_init(&init, &config).unwrap();
+    let read = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 2}}));
+    assert_eq!(read[0]["body"]["messages"], json!([1, 2]));
+
+    // It doesn't wait for a topology to catch up with the others
+    let mut out = Vec::new();
+    n0.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(read["dest"], "n1");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn partitioned_nodes_converge_once_healed() {
+    let ids = ["n0", "n1", "n2"];
+    let mut cluster = Cluster::<Payload, BroadcastNode>::new(&ids,
+        &Config::default());
+    let topology = json!({"n0": ["n1"], "n1": ["n0", "n2"], "n2": ["n1"]});
+    for id in ids {
+        cluster.send(id, json!({"type": "topology", "topology": topology}));
+    }
+
+    // n0 is cut off from n1, its only neighbor
+    cluster.inject(|msg| {
+        let (src, dst) = (&msg["src"], &msg["dest"]);
+        (src == "n0" && dst == "n1") || (src == "n1" && dst == "n0")
+    });
+    for (message, id) in [(1, "n0"), (2, "n1"), (3, "n2")] {
+        cluster.send(id, json!({"type": "broadcast", "message": message}));
+    }
+    cluster.deliver();
+    for _ in 0..5 {
+        cluster.tick();
+    }
+    let read = json!({"type": "read"});
+    let messages = cluster.read_all(&read, &common::sorted("messages"));
+    assert_eq!(messages["n0"], json!([1]));
+    assert_eq!(messages["n2"], json!([2, 3]));
+
+    let agreed = cluster.converge(read, common::sorted("messages"), 3, 20);
+    assert_eq!(agreed, json!([1, 2, 3]));
+}
+
+#[test]
+fn links_count_the_gossip_of_each_neighbor() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    let mut out = Vec::new();
+    n0.tick(&mut out).unwrap();
+    assert!(n0.links().is_empty(), "no topology, no neighbors");
+
+    step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "topology", "msg_id": 1, "topology": {"n0": ["n1"]}}}));
+
+    // n1 misses the first read, which is sent again once n1 is backed off
+    let mut out = Vec::new();
+    for _ in 0..4 {
+        out.clear();
+        n0.tick(&mut out).unwrap();
+    }
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    let reply = step(&mut n1, read);
+    step(&mut n0, reply[0].clone());
+
+    let links = n0.links();
+    assert_eq!(links["n1"].sent, 2);
+    assert_eq!(links["n1"].acked, 1);
+    assert_eq!(links["n1"].retries, 1);
+    assert!(links["n1"].synced_ms_ago.is_some());
+}
+
+#[test]
+fn gossip_reads_only_return_what_was_not_acknowledged() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    for (node, id) in [(&mut n0, "n0"), (&mut n1, "n1")] {
+        step(node, json!({"src": "c1", "dest": id, "body": {
+            "type": "topology", "msg_id": 1,
+            "topology": {"n0": ["n1"], "n1": ["n0"]}}}));
+    }
+    broadcast(&mut n0, "n0", 1);
+    broadcast(&mut n0, "n0", 2);
+
+    // n1 has everything of n0 up to its length after the first read
+    let pull = |n1: &mut BroadcastNode, n0: &mut BroadcastNode| {
+        let mut out = Vec::new();
+        n1.tick(&mut out).unwrap();
+        let read: Value = serde_json::from_slice(&out).unwrap();
+        let reply = step(n0, read.clone());
+        step(n1, reply[0].clone());
+        (read["body"]["upto"].clone(), reply[0]["body"].clone())
+    };
+    let (upto, reply) = pull(&mut n1, &mut n0);
+    assert_eq!(upto, 0);
+    assert_eq!(reply["messages"], json!([1, 2]));
+    assert_eq!(reply["upto"], 2);
+
+    broadcast(&mut n0, "n0", 3);
+    let (upto, reply) = pull(&mut n1, &mut n0);
+    assert_eq!(upto, 2);
+    assert_eq!(reply["messages"], json!([3]));
+    assert_eq!(n1.dump()["peers"]["n0"]["upto"], 3);
+
+    // A reader ahead of the log saw an earlier run, and gets everything
+    let reply = step(&mut n0, json!({"src": "n1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 9, "upto": 10}}));
+    assert_eq!(reply[0]["body"]["messages"], json!([1, 2, 3]));
+
+    // Clients are told nothing of the log
+    let reply = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 10}}));
+    assert!(reply[0]["body"].get("upto").is_none());
+}
+
+#[test]
+fn epochs_discard_what_came_before() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    for (node, id) in [(&mut n0, "n0"), (&mut n1, "n1")] {
+        step(node, json!({"src": "c1", "dest": id, "body": {
+            "type": "topology", "msg_id": 1,
+            "topology": {"n0": ["n1"], "n1": ["n0"]}}}));
+        broadcast(node, id, 1);
+    }
+
+    let reply = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "reset", "msg_id": 2}}));
+    assert_eq!(reply[0]["body"]["type"], "reset_ok");
+    assert_eq!(reply[0]["body"]["epoch"], 1);
+    assert_eq!(n0.status()["messages"], 0);
+    broadcast(&mut n0, "n0", 2);
+
+    // n1 is still in the earlier epoch; its gossip is stale
+    let mut out = Vec::new();
+    n1.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(read["body"]["epoch"], 0);
+    let reply = step(&mut n0, read);
+    assert_eq!(reply[0]["body"]["messages"], json!([]));
+    assert_eq!(reply[0]["body"]["epoch"], 1);
+
+    // Hearing of the later epoch moves n1 on, and it catches up from there
+    step(&mut n1, reply[0].clone());
+    assert_eq!(n1.status()["epoch"], 1);
+    let mut out = Vec::new();
+    n1.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    let reply = step(&mut n0, read);
+    step(&mut n1, reply[0].clone());
+    let read = step(&mut n1, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "read", "msg_id": 3}}));
+    assert_eq!(read[0]["body"]["messages"], json!([2]));
+    assert!(read[0]["body"].get("epoch").is_none());
+}
+
+#[test]
+fn propagation_is_timed_until_every_neighbor_has_the_message() {
+    let ids = ["n0", "n1", "n2"];
+    let mut cluster = Cluster::<Payload, BroadcastNode>::new(&ids,
+        &Config::default());
+    let topology = json!({"n0": ["n1", "n2"], "n1": ["n0"], "n2": ["n0"]});
+    for id in ids {
+        cluster.send(id, json!({"type": "topology", "topology": topology}));
+    }
+    cluster.deliver();
+    cluster.send("n0", json!({"type": "broadcast", "message": 1}));
+    cluster.deliver();
+    let propagation = cluster.nodes["n0"].status()["propagation"].clone();
+    assert_eq!(propagation["spreading"], 1);
+    assert!(propagation["p50_ms"].is_null());
+
+    // n1 and n2 read it off n0, which owed it to nobody else
+    cluster.tick();
+    cluster.deliver();
+    let propagation = cluster.nodes["n0"].status()["propagation"].clone();
+    assert_eq!(propagation["spreading"], 0);
+    assert_eq!(propagation["samples"], 1);
+    assert!(propagation["p99_ms"].as_f64().unwrap() >= 0.);
+}
@@ -0,0 +1,67 @@
+//! Capabilities announced in `init_ok` and to the other nodes
+
+use std::io::Write;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Capabilities, Node};
+use maelstrom::services::echo;
+
+/// Node supporting a made up extension, remembering who announced theirs
+struct Extended {
+    heard: Vec<(String, Capabilities)>,
+}
+
+impl Node<echo::Payload> for Extended {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self { heard: Vec::new() })
+    }
+
+    fn step(&mut self, _input: Message<echo::Payload>, _output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn extensions(&self) -> Vec<String> {
+        vec!["batching".into()]
+    }
+
+    fn hello(&mut self, node: &str, capabilities: &Capabilities) {
+        self.heard.push((node.into(), capabilities.clone()));
+    }
+
+    fn status(&self) -> Value {
+        json!(self.heard.iter().map(|(node, _)| node).collect::<Vec<_>>())
+    }
+}
+
+#[test]
+fn capabilities_are_announced_and_heard() {
+    let input = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+        json!({"src": "n2", "dest": "n1", "body": {"type": "hello",
+            "service": "test", "extensions": ["batching", "other"]}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "debug_status",
+            "msg_id": 2}}),
+    ];
+    let input: String = input.iter().map(|msg| format!("{msg}\n")).collect();
+    let config = Config { service: Some("test".into()), ..Config::default() };
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<echo::Payload, Extended>(std::io::Cursor::new(input),
+        &mut output, &config).unwrap();
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(output[0]["body"], json!({"type": "init_ok", "msg_id": 0,
+        "in_reply_to": 1, "service": "test", "extensions": ["batching"]}));
+    assert_eq!(output[1]["dest"], "n2");
+    assert_eq!(output[1]["body"], json!({"type": "hello", "service": "test",
+        "extensions": ["batching"]}));
+
+    let status = &output[2]["body"];
+    assert_eq!(status["service"], json!(["n2"]));
+    assert_eq!(status["peers"]["n2"]["extensions"],
+        json!(["batching", "other"]));
+}
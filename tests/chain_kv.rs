@@ -0,0 +1,170 @@
+//! Writes and reads flowing through the chain of the chain replication KV
+
+mod common;
+
+use std::collections::HashMap;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::chain_kv::ChainKvNode;
+
+fn cluster() -> HashMap<String, ChainKvNode> {
+    let ids = ["n0", "n1", "n2"];
+    ids.iter().map(|id| (id.to_string(), ChainKvNode::from_init(&msg::Init {
+        node_id:  id.to_string(),
+        node_ids: ids.iter().map(|id| id.to_string()).collect(),
+    }, &Config::default()).unwrap())).collect()
+}
+
+/// Feed the JSON message `msg` to its destination and collect what it sends
+fn step(nodes: &mut HashMap<String, ChainKvNode>, msg: Value) -> Vec<Value> {
+    let node = nodes.get_mut(msg["dest"].as_str().unwrap()).unwrap();
+    common::step(node, msg)
+}
+
+/// Deliver `msg` and everything it leads to between the nodes, returning what
+/// was sent to the clients
+fn deliver(nodes: &mut HashMap<String, ChainKvNode>, msg: Value)
+        -> Vec<Value> {
+    let mut queue = vec![msg];
+    let mut replies = Vec::new();
+    while let Some(msg) = queue.pop() {
+        for out in step(nodes, msg) {
+            if nodes.contains_key(out["dest"].as_str().unwrap()) {
+                queue.push(out);
+            } else {
+                replies.push(out);
+            }
+        }
+    }
+    replies
+}
+
+#[test]
+fn writes_flow_from_head_to_tail() {
+    let mut nodes = cluster();
+
+    // The write enters at the middle of the chain and is answered by the tail
+    let replies = deliver(&mut nodes, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "write", "msg_id": 4, "key": 1, "value": 7}}));
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["src"], "n2");
+    assert_eq!(replies[0]["dest"], "c1");
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+    assert_eq!(replies[0]["body"]["in_reply_to"], 4);
+
+    // Reads are served by the tail, wherever they enter
+    let replies = deliver(&mut nodes, json!({"src": "c2", "dest": "n0",
+        "body": {"type": "read", "msg_id": 1, "key": 1}}));
+    assert_eq!(replies[0]["src"], "n2");
+    assert_eq!(replies[0]["body"]["value"], 7);
+
+    // The head checks CAS against everything it ordered
+    let replies = deliver(&mut nodes, json!({"src": "c2", "dest": "n0",
+        "body": {"type": "cas", "msg_id": 2, "key": 1, "from": 8, "to": 9}}));
+    assert_eq!(replies[0]["src"], "n0");
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::PRECONDITION_FAILED);
+}
+
+#[test]
+fn new_tail_answers_pending_writes() {
+    let mut nodes = cluster();
+
+    // The write reaches the middle, but never the tail
+    let out = step(&mut nodes, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 1, "key": 1, "value": 7}}));
+    let out = step(&mut nodes, out[0].clone());
+    assert_eq!(out[0]["dest"], "n2");
+
+    // The tail is taken out of the chain, the middle answers in its stead
+    let replies = deliver(&mut nodes, json!({"src": "fd", "dest": "n1",
+        "body": {"type": "reconfigure", "msg_id": 1, "epoch": 1,
+            "chain": ["n0", "n1"]}}));
+    let types: Vec<_> = replies.iter()
+        .map(|reply| (reply["dest"].clone(), reply["body"]["type"].clone()))
+        .collect();
+    assert!(types.contains(&(json!("c1"), json!("write_ok"))));
+    assert!(types.contains(&(json!("fd"), json!("reconfigure_ok"))));
+}
+
+#[test]
+fn restored_nodes_keep_their_writes() {
+    let dir = std::env::temp_dir()
+        .join(format!("maelstrom-chain-kv-restore-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into()],
+    };
+    let mut config = Config::default();
+    config.apply_args(&["--storage-dir".into(),
+        dir.display().to_string()]).unwrap();
+
+    let mut n0: ChainKvNode = ChainKvNode::from_init(&init, &config).unwrap();
+    let replies = common::step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 1, "key": 1, "value": 7}}));
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+    drop(n0);
+
+    // The restored node has the write, and knows it ordered it already
+    config.restore = true;
+    let mut n0: ChainKvNode = ChainKvNode::from_init(&init, &config).unwrap();
+    assert_eq!(n0.status()["applied"], 1);
+    let replies = common::step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "read", "msg_id": 2, "key": 1}}));
+    assert_eq!(replies[0]["body"]["value"], 7);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn nodes_that_left_are_dropped_from_the_chain() {
+    let mut nodes = cluster();
+    let out = step(&mut nodes, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 1, "key": 1, "value": 7}}));
+    step(&mut nodes, out[0].clone());
+
+    // The tail leaves before the write reaches it
+    nodes.remove("n2");
+    let cluster = ["n0".to_string(), "n1".to_string()];
+    for node in nodes.values_mut() {
+        assert!(node.membership(&cluster));
+        assert_eq!(node.status()["chain"], json!(cluster));
+        assert_eq!(node.status()["epoch"], 1);
+    }
+    let mut out = Vec::new();
+    nodes.get_mut("n1").unwrap().timer("retry", &mut out).unwrap();
+    let reply: Value = serde_json::from_str(
+        String::from_utf8(out).unwrap().lines().next().unwrap()).unwrap();
+    assert_eq!(reply["dest"], "c1");
+    assert_eq!(reply["body"]["type"], "write_ok");
+}
+
+#[test]
+fn silent_successors_are_suspected() {
+    let mut nodes = cluster();
+    let out = step(&mut nodes, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 1, "key": 1, "value": 7}}));
+    step(&mut nodes, out[0].clone());
+
+    // The tail never acknowledges, until n1 gives up on it
+    let n1 = nodes.get_mut("n1").unwrap();
+    for _ in 1..3 {
+        let mut out = Vec::new();
+        n1.timer("retry", &mut out).unwrap();
+        assert_eq!(n1.status()["epoch"], 0);
+    }
+    let mut out = Vec::new();
+    n1.timer("retry", &mut out).unwrap();
+    assert_eq!(n1.status()["chain"], json!(["n0", "n1"]));
+    let sent: Vec<Value> = String::from_utf8(out).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let reconfigures: Vec<_> = sent.iter()
+        .filter(|msg| msg["body"]["type"] == "reconfigure")
+        .map(|msg| msg["dest"].clone())
+        .collect();
+    assert_eq!(reconfigures, [json!("n0"), json!("n2")]);
+    assert!(sent.iter().any(|msg| msg["dest"] == "c1" &&
+        msg["body"]["type"] == "write_ok"));
+}
@@ -0,0 +1,55 @@
+//! Fault injection into the outgoing messages of a node
+
+use std::io::Write;
+use std::time::Duration;
+use maelstrom::chaos::{Chaos, ChaosConfig};
+
+const LINE: &[u8] = b"{\"src\":\"n1\",\"dest\":\"c1\",\"body\":{}}\n";
+
+/// Write `LINE` through a chaos layer configured by `spec`
+fn inject(spec: &str) -> (Chaos<Vec<u8>>, usize) {
+    let config = ChaosConfig::parse(spec).unwrap();
+    let mut chaos = Chaos::new(Vec::new(), Some(config));
+
+    // Write the line in pieces, just like the serializer does
+    chaos.write_all(&LINE[..10]).unwrap();
+    chaos.write_all(&LINE[10..]).unwrap();
+
+    let written = chaos.inner_mut().len() / LINE.len();
+    (chaos, written)
+}
+
+#[test]
+fn parses_config() {
+    let config = ChaosConfig::parse("drop=0.5,dup=0.25,delay=1,delay_ms=7,\
+        seed=3").unwrap();
+    assert_eq!(config, ChaosConfig {
+        drop:      0.5,
+        dup:       0.25,
+        delay:     1.,
+        max_delay: Duration::from_millis(7),
+        seed:      Some(3),
+    });
+
+    assert!(ChaosConfig::parse("drop").is_err());
+    assert!(ChaosConfig::parse("explode=1").is_err());
+}
+
+#[test]
+fn drops_and_duplicates() {
+    assert_eq!(inject("seed=1").1, 1);
+    assert_eq!(inject("drop=1,seed=1").1, 0);
+    assert_eq!(inject("dup=1,seed=1").1, 2);
+}
+
+#[test]
+fn delays() {
+    let (mut chaos, written) = inject("delay=1,delay_ms=20,seed=1");
+    assert_eq!(written, 0);
+    assert!(chaos.next_deadline().is_some());
+
+    std::thread::sleep(Duration::from_millis(25));
+    chaos.release_due().unwrap();
+    assert_eq!(chaos.inner_mut().as_slice(), LINE);
+    assert!(chaos.next_deadline().is_none());
+}
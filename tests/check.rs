@@ -0,0 +1,77 @@
+//! Runs the checkers against hand-written histories
+
+use serde_json::{json, Value};
+use maelstrom::check::Checker;
+use maelstrom::history::{Op, OpType};
+
+/// Build an operation of `process` on the node `node` at `time`
+fn op(kind: OpType, process: &str, msg_id: usize, f: &str, value: Value,
+        node: &str, time: u128) -> Op {
+    Op {
+        kind,
+        process: process.into(),
+        f: f.into(),
+        msg_id: Some(msg_id),
+        value,
+        node: node.into(),
+        time,
+    }
+}
+
+#[test]
+fn broadcast_convergence() {
+    let mut ops = vec![
+        op(OpType::Invoke, "c1", 1, "broadcast", json!({"message": 1}), "n0", 0),
+        op(OpType::Ok, "c1", 1, "broadcast", json!({}), "n0", 1),
+        op(OpType::Invoke, "c2", 1, "read", json!({}), "n0", 2),
+        op(OpType::Ok, "c2", 1, "read", json!({"messages": [1]}), "n0", 3),
+        op(OpType::Invoke, "c3", 1, "read", json!({}), "n1", 2),
+        op(OpType::Ok, "c3", 1, "read", json!({"messages": [1]}), "n1", 3),
+    ];
+    assert!(Checker::Broadcast.check(&ops).is_valid());
+
+    // n1 forgets the message in its final read
+    ops.push(op(OpType::Invoke, "c3", 2, "read", json!({}), "n1", 4));
+    ops.push(op(OpType::Ok, "c3", 2, "read", json!({"messages": []}), "n1", 5));
+    let verdict = Checker::Broadcast.check(&ops);
+    assert_eq!(verdict.problems.len(), 1, "{verdict}");
+    assert!(verdict.problems[0].starts_with("n1 lost 1"));
+
+    // Reading something that was never broadcast
+    ops.push(op(OpType::Invoke, "c2", 2, "read", json!({}), "n0", 6));
+    ops.push(op(OpType::Ok, "c2", 2, "read", json!({"messages": [1, 7]}),
+        "n0", 7));
+    assert_eq!(Checker::Broadcast.check(&ops).problems.len(), 2);
+}
+
+#[test]
+fn unique_ids() {
+    let mut ops = vec![
+        op(OpType::Invoke, "c1", 1, "generate", json!({}), "n0", 0),
+        op(OpType::Ok, "c1", 1, "generate", json!({"id": "a"}), "n0", 1),
+        op(OpType::Invoke, "c2", 1, "generate", json!({}), "n1", 0),
+        op(OpType::Ok, "c2", 1, "generate", json!({"id": "b"}), "n1", 1),
+    ];
+    assert!(Checker::UniqueIds.check(&ops).is_valid());
+
+    ops.push(op(OpType::Invoke, "c2", 2, "generate", json!({}), "n1", 2));
+    ops.push(op(OpType::Ok, "c2", 2, "generate", json!({"id": "a"}), "n1", 3));
+    assert!(!Checker::UniqueIds.check(&ops).is_valid());
+}
+
+#[test]
+fn counter_bounds() {
+    let mut ops = vec![
+        op(OpType::Invoke, "c1", 1, "add", json!({"delta": 2}), "n0", 0),
+        op(OpType::Ok, "c1", 1, "add", json!({}), "n0", 1),
+        // Concurrent with the read, may or may not be visible
+        op(OpType::Invoke, "c1", 2, "add", json!({"delta": 3}), "n0", 2),
+        op(OpType::Invoke, "c2", 1, "read", json!({}), "n1", 3),
+        op(OpType::Ok, "c2", 1, "read", json!({"value": 5}), "n1", 4),
+    ];
+    assert!(Checker::Counter.check(&ops).is_valid());
+
+    ops.push(op(OpType::Invoke, "c2", 2, "read", json!({}), "n1", 5));
+    ops.push(op(OpType::Ok, "c2", 2, "read", json!({"value": 1}), "n1", 6));
+    assert!(!Checker::Counter.check(&ops).is_valid());
+}
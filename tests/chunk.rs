@@ -0,0 +1,104 @@
+//! Chunking of long messages between the nodes
+
+use std::io::Write;
+use serde_json::{json, Value};
+use maelstrom::chunk::{Chunker, Chunks};
+use maelstrom::config::Config;
+use maelstrom::message as msg;
+use maelstrom::services::echo::Payload;
+
+fn line(src: &str, dest: &str, echo: &str) -> String {
+    format!("{}\n", json!({"src": src, "dest": dest,
+        "body": {"type": "echo", "msg_id": 1, "echo": echo}}))
+}
+
+fn received() -> Chunks {
+    Chunks::new(None, Config::default().chunk_timeout)
+}
+
+/// Write `line` through a chunker of `size` and return the lines written
+fn chunk(size: usize, line: &str) -> Vec<String> {
+    let nodes = ["n1".to_string(), "n2".to_string(), "n3".to_string()];
+    let mut chunker = Chunker::new(Vec::new(), Some(size), &nodes);
+    chunker.write_all(line.as_bytes()).unwrap();
+    String::from_utf8(chunker.inner_mut().clone()).unwrap().lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn long_messages_are_chunked_and_put_back_together() {
+    let line = line("n1", "n2", &"\"ünï\tcödé\" ".repeat(20));
+    let chunks = chunk(128, &line);
+    assert!(chunks.len() > 1);
+    for chunk in &chunks {
+        assert!(chunk.len() <= 128, "envelopes fit in the chunk size");
+        let chunk: Value = serde_json::from_str(chunk).unwrap();
+        assert_eq!(chunk["dest"], "n2");
+        assert_eq!(chunk["body"]["type"], "chunk");
+    }
+
+    // Chunks may come in any order, even twice
+    let mut received = received();
+    let mut whole = Vec::new();
+    for chunk in chunks.last().into_iter().chain(chunks.iter().rev()) {
+        whole.extend(received.reassemble(chunk.clone()).unwrap());
+    }
+    assert_eq!(whole, [line.trim_end()]);
+    assert!(received.is_empty());
+}
+
+#[test]
+fn short_and_client_messages_are_left_whole() {
+    let short = line("n1", "n2", "hi");
+    assert_eq!(chunk(1024, &short), [short.trim_end()]);
+    let client = line("n1", "c1", &"x".repeat(100));
+    assert_eq!(chunk(32, &client), [client.trim_end()]);
+
+    let mut received = received();
+    assert_eq!(received.reassemble(short.clone()).unwrap(), Some(short));
+}
+
+#[test]
+fn nodes_handle_chunked_messages() {
+    let mut input = format!("{}\n", json!({"src": "c0", "dest": "n1",
+        "body": {"type": "init", "msg_id": 1, "node_id": "n1",
+            "node_ids": ["n1", "n3"]}}));
+    for chunk in chunk(128, &line("n3", "n1", &"y".repeat(300))) {
+        input += &format!("{chunk}\n");
+    }
+
+    // Chunks that can't be of any message are dropped
+    input += &format!("{}\n", json!({"src": "n3", "dest": "n1",
+        "body": {"type": "chunk", "chunk_id": 9, "index": 0,
+            "count": usize::MAX, "data": "z"}}));
+
+    let config = Config { chunk_size: Some(128), ..Config::default() };
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<Payload, maelstrom::services::echo::EchoNode>(
+        std::io::Cursor::new(input), &mut output, &config).unwrap();
+
+    // The reply is chunked on its way back
+    let mut received = received();
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .filter_map(|line| received.reassemble(line.into()).unwrap())
+        .map(|line| serde_json::from_str(&line).unwrap())
+        .collect();
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[1]["dest"], "n3");
+    assert_eq!(output[1]["body"]["echo"], "y".repeat(300));
+}
+
+#[test]
+fn chunks_of_impossible_messages_are_rejected() {
+    let chunk = |index: usize, count: usize| format!("{}", json!({
+        "src": "n1", "dest": "n2", "body": {"type": "chunk", "chunk_id": 1,
+            "index": index, "count": count, "data": "x"}}));
+    let mut received = Chunks::new(Some(1024),
+        Config::default().chunk_timeout);
+    assert!(received.reassemble(chunk(0, usize::MAX)).is_err());
+    assert!(received.reassemble(chunk(3, 2)).is_err());
+    assert_eq!(received.reassemble(chunk(0, 2)).unwrap(), None);
+    assert!(received.reassemble(chunk(1, 3)).is_err());
+    assert_eq!(received.reassemble(chunk(1, 2)).unwrap(), Some("xx".into()));
+}
@@ -0,0 +1,79 @@
+//! Encoding of the messages between the nodes
+
+use serde_json::json;
+use maelstrom::codec::{self, Codec};
+use maelstrom::config::Config;
+use maelstrom::message as msg;
+use maelstrom::services::echo;
+
+#[test]
+fn only_matching_codecs_are_understood() {
+    assert!(Codec::MsgPack.understood_by(&Codec::MsgPack.announced()));
+    assert!(!Codec::MsgPack.understood_by(&Codec::Json.announced()));
+    assert!(!Codec::Json.understood_by(&Codec::MsgPack.announced()));
+}
+
+#[test]
+fn plain_messages_are_left_alone() {
+    let line = json!({"src": "n0", "dest": "n1",
+        "body": {"type": "read", "msg_id": 1}}).to_string();
+    assert!(codec::unpack(&line).unwrap().is_none());
+
+    // Looking packed isn't enough
+    let line = json!({"src": "n0", "dest": "n1",
+        "body": {"type": "echo", "echo": "packed"}}).to_string();
+    assert!(codec::unpack(&line).unwrap().is_none());
+}
+
+#[test]
+fn messages_that_dont_unpack_are_dropped() {
+    let input = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+        json!({"src": "n2", "dest": "n1",
+            "body": {"type": "packed", "data": "not base64"}}),
+        json!({"src": "c1", "dest": "n1",
+            "body": {"type": "echo", "msg_id": 2, "echo": "hi"}}),
+    ];
+    let input: String = input.iter().map(|msg| format!("{msg}\n")).collect();
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<echo::Payload, echo::EchoNode>(
+        std::io::Cursor::new(input), &mut output, &Config::default())
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let last: serde_json::Value =
+        serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(last["body"]["type"], "echo_ok");
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn packed_messages_unpack_into_the_original() {
+    use std::io::Write;
+    use serde_json::Value;
+    use maelstrom::codec::{Packer, Peers};
+
+    let peers = Peers::default();
+    peers.borrow_mut().insert("n1".into());
+    let mut out = Vec::new();
+    let mut packer = Packer::new(&mut out, peers);
+
+    let gossip = json!({"src": "n0", "dest": "n1", "body": {"type": "read_ok",
+        "msg_id": 2, "in_reply_to": 1, "messages": [1, 2, 3]}});
+    let reply = json!({"src": "n0", "dest": "c1",
+        "body": {"type": "broadcast_ok", "in_reply_to": 4}});
+    for msg in [&gossip, &reply] {
+        writeln!(packer, "{msg}").unwrap();
+    }
+
+    // Only the message to the peer is packed, its IDs left outside
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    let packed: Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(packed["body"]["type"], "packed");
+    assert_eq!(packed["body"]["in_reply_to"], 1);
+    assert_eq!(serde_json::from_str::<Value>(lines[1]).unwrap(), reply);
+
+    let unpacked = codec::unpack(lines[0]).unwrap().unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&unpacked).unwrap(), gossip);
+}
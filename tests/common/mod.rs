@@ -0,0 +1,239 @@
+//! Shared utilities for the integration tests
+
+// Not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, VecDeque};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Node};
+
+/// Path to the fixture file `name` in `tests/fixtures`
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// Normalize a single output line so it can be compared against a transcript.
+/// `msg_id` is always ignored and so are the body fields in `masked`
+pub fn normalize(line: &str, masked: &[&str]) -> Value {
+    let mut val: Value = serde_json::from_str(line)
+        .unwrap_or_else(|e| panic!("invalid JSON line {line:?}: {e}"));
+
+    if let Some(body) = val.get_mut("body").and_then(Value::as_object_mut) {
+        for field in core::iter::once(&"msg_id").chain(masked) {
+            if let Some(v) = body.get_mut(*field) {
+                *v = Value::Null;
+            }
+        }
+    }
+
+    val
+}
+
+/// Feed the JSON message `msg` to `node` and collect what it sends
+pub fn step<P, N>(node: &mut N, msg: Value) -> Vec<Value>
+where
+    P: DeserializeOwned,
+    N: Node<P>,
+{
+    let msg: Message<P> = serde_json::from_value(msg).unwrap();
+    let mut out = Vec::new();
+    node.step(msg, &mut out).unwrap();
+    String::from_utf8(out).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Run the node `N` over the JSON messages `input`, returning its output
+pub fn run<P, N>(input: &[Value]) -> Vec<Value>
+where
+    P: DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    let input: String = input.iter().map(|msg| format!("{msg}\n")).collect();
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<P, N>(std::io::Cursor::new(input), &mut output,
+        &Config::default()).unwrap();
+    String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Run the `<name>.in.jsonl` fixture through the service `N` and compare its
+/// normalized output against the `<name>.out.jsonl` transcript
+pub fn run_golden<P, N>(name: &str, masked: &[&str])
+where
+    P: DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    let input = std::fs::read(fixture_path(&format!("{name}.in.jsonl")))
+        .expect("failed to read the input fixture");
+    let expected = std::fs::read_to_string(
+        fixture_path(&format!("{name}.out.jsonl")))
+        .expect("failed to read the expected transcript");
+
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<P, N>(std::io::Cursor::new(input), &mut output,
+        &Config::default()).expect("service failed on the fixture");
+    let output = String::from_utf8(output).expect("output is not UTF-8");
+
+    let got: Vec<Value> = output.lines()
+        .map(|l| normalize(l, masked))
+        .collect();
+    let expected: Vec<Value> = expected.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| normalize(l, masked))
+        .collect();
+
+    assert_eq!(got.len(), expected.len(),
+        "{name}: transcript length differs\n--- got ---\n{output}");
+    for (idx, (got, exp)) in got.iter().zip(&expected).enumerate() {
+        assert_eq!(got, exp, "{name}: line {} differs", idx + 1);
+    }
+}
+
+/// Tells which messages between the nodes are dropped
+pub type Fault = Box<dyn FnMut(&Value) -> bool>;
+
+/// Cluster of nodes `N` run in process, with the messages between them
+/// delivered by hand and subject to a fault, until it's healed
+pub struct Cluster<P, N> {
+    pub nodes: BTreeMap<String, N>,
+
+    /// Messages in flight
+    pub queue: VecDeque<Value>,
+
+    /// Drops the messages between the nodes it returns `true` for
+    fault: Option<Fault>,
+
+    next_id: usize,
+    _payload: PhantomData<fn(P)>,
+}
+
+impl<P: DeserializeOwned, N: Node<P>> Cluster<P, N> {
+    /// Start the nodes `ids` with `config`
+    pub fn new(ids: &[&str], config: &Config) -> Self {
+        let node_ids: Vec<String> = ids.iter().map(|id| id.to_string())
+            .collect();
+        let nodes = node_ids.iter().map(|id| (id.clone(),
+            N::from_init(&msg::Init {
+                node_id:  id.clone(),
+                node_ids: node_ids.clone(),
+            }, config).unwrap())).collect();
+        Self { nodes, queue: VecDeque::new(), fault: None, next_id: 0,
+            _payload: PhantomData }
+    }
+
+    /// Drop the messages between the nodes `fault` returns `true` for
+    pub fn inject(&mut self, fault: impl FnMut(&Value) -> bool + 'static) {
+        self.fault = Some(Box::new(fault));
+    }
+
+    /// Stop dropping messages
+    pub fn heal(&mut self) {
+        self.fault = None;
+    }
+
+    fn collect(&mut self, out: Vec<u8>) {
+        for line in String::from_utf8(out).unwrap().lines() {
+            self.queue.push_back(serde_json::from_str(line).unwrap());
+        }
+    }
+
+    /// Send the client request `body` to `dst`, without delivering it
+    pub fn send(&mut self, dst: &str, body: Value) {
+        self.next_id += 1;
+        let mut body = body;
+        body["msg_id"] = self.next_id.into();
+        self.queue.push_back(serde_json::json!({"src": "c1", "dest": dst,
+            "body": body}));
+    }
+
+    /// Deliver everything in flight, returning what was sent to the clients
+    pub fn deliver(&mut self) -> Vec<Value> {
+        let mut replies = Vec::new();
+        while let Some(msg) = self.queue.pop_front() {
+            let src = msg["src"].as_str().unwrap();
+            let dst = msg["dest"].as_str().unwrap().to_string();
+            let between_nodes = self.nodes.contains_key(src);
+            if between_nodes && self.fault.as_mut().is_some_and(|f| f(&msg)) {
+                continue;
+            }
+            let Some(node) = self.nodes.get_mut(&dst) else {
+                replies.push(msg);
+                continue;
+            };
+            let msg: Message<P> = serde_json::from_value(msg).unwrap();
+            let mut out = Vec::new();
+            node.step(msg, &mut out).unwrap();
+            self.collect(out);
+        }
+        replies
+    }
+
+    /// Tick every node once and deliver what that leads to
+    pub fn tick(&mut self) -> Vec<Value> {
+        let mut out = Vec::new();
+        for node in self.nodes.values_mut() {
+            node.tick(&mut out).unwrap();
+        }
+        self.collect(out);
+        self.deliver()
+    }
+
+    /// Ask every node for `read` and take what `view` makes of the bodies of
+    /// their replies
+    pub fn read_all(&mut self, read: &Value, view: &impl Fn(&Value) -> Value)
+            -> BTreeMap<String, Value> {
+        let ids: Vec<String> = self.nodes.keys().cloned().collect();
+        ids.into_iter().map(|id| {
+            self.send(&id, read.clone());
+            let replies = self.deliver();
+            let reply = replies.iter()
+                .find(|reply| reply["src"] == id.as_str())
+                .unwrap_or_else(|| panic!("{id} didn't answer {read}"));
+            (id, view(&reply["body"]))
+        }).collect()
+    }
+
+    /// Heal the cluster and tick it until every node answers `read` the
+    /// same, as far as `view` can tell, for `stable` rounds in a row. Panics
+    /// with the answers if it doesn't within `max_rounds`. Returns the
+    /// answer the nodes agree on
+    pub fn converge(&mut self, read: Value, view: impl Fn(&Value) -> Value,
+            stable: usize, max_rounds: usize) -> Value {
+        self.heal();
+        let mut agreed = 0;
+        let mut answers = BTreeMap::new();
+        for _ in 0..max_rounds {
+            self.tick();
+            let previous = answers;
+            answers = self.read_all(&read, &view);
+            let first = answers.values().next().cloned();
+            let same = answers.values().all(|answer| Some(answer) ==
+                first.as_ref());
+            agreed = if same && (agreed == 0 || answers == previous) {
+                agreed + 1
+            } else {
+                0
+            };
+            if agreed == stable {
+                return first.unwrap_or_default();
+            }
+        }
+        panic!("no convergence within {max_rounds} rounds: {answers:#?}");
+    }
+}
+
+/// Sort the array under `field` of a reply body, for views of replies whose
+/// nodes order them differently
+pub fn sorted(field: &'static str) -> impl Fn(&Value) -> Value {
+    move |body| {
+        let mut items = body[field].as_array().cloned().unwrap_or_default();
+        items.sort_by_key(|item| item.to_string());
+        Value::Array(items)
+    }
+}
@@ -0,0 +1,100 @@
+//! Layering of the config sources
+
+use std::time::Duration;
+use maelstrom::config::{Config, LogLevel};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn flags_override_the_defaults() {
+    let mut config = Config::default();
+    config.apply_args(&args(&["--gossip-interval-ms", "250",
+        "--storage-dir", "/tmp/x", "--log", "debug",
+        "--echo-jitter-ms", "20"])).unwrap();
+
+    assert_eq!(config.gossip_interval, Duration::from_millis(250));
+    assert_eq!(config.storage_dir.as_deref(), Some("/tmp/x".as_ref()));
+    assert_eq!(config.log_level, LogLevel::Debug);
+    assert_eq!(config.echo_jitter, Duration::from_millis(20));
+    assert_eq!(config.echo_delay, Duration::ZERO);
+    assert_eq!(config.retry_timeout, Config::default().retry_timeout);
+}
+
+#[test]
+fn summaries_are_keyed_by_flag() {
+    let mut config = Config::default();
+    config.apply_args(&args(&["--gossip-fanout", "3"])).unwrap();
+    let summary = config.summary();
+    assert_eq!(summary["gossip-fanout"], 3);
+    assert_eq!(summary["retry-timeout-ms"], 500);
+    assert_eq!(summary["log"], "warn");
+    assert_eq!(summary["primaries"], serde_json::json!([]));
+    assert_eq!(summary["sibling-merge"], "none");
+    assert_eq!(summary["codec"], "json");
+}
+
+#[test]
+fn primaries_are_listed_by_commas() {
+    let mut config = Config::default();
+    config.apply_args(&args(&["--primaries", "n0,n2,"])).unwrap();
+    assert_eq!(config.primaries, ["n0", "n2"]);
+}
+
+#[test]
+fn bad_flags_are_rejected() {
+    let mut config = Config::default();
+    assert!(config.apply_args(&args(&["--nope", "1"])).is_err());
+    assert!(config.apply_args(&args(&["--retry-timeout-ms"])).is_err());
+    assert!(config.apply_args(&args(&["--retry-timeout-ms", "0"])).is_err());
+    assert!(config.apply_args(&args(&["--log", "loud"])).is_err());
+    assert!(config.apply_args(&args(&["--gossip-fanout", "0"])).is_err());
+    assert!(config.apply_args(&args(&["--sibling-merge", "max"])).is_err());
+    assert!(config.apply_args(&args(&["--codec", "xml"])).is_err());
+    assert!(config.apply_args(&args(&["stray"])).is_err());
+}
+
+#[test]
+fn rate_limits_need_a_rate() {
+    let mut config = Config::default();
+    assert_eq!(config.rate_limit, None);
+    config.apply_args(&args(&["--rate-limit", "50"])).unwrap();
+    assert_eq!(config.rate_limit, Some(50));
+    assert_eq!(config.summary()["rate-burst"], 10);
+    assert!(config.apply_args(&args(&["--rate-limit", "0"])).is_err());
+}
+
+#[test]
+fn workers_are_positive() {
+    let mut config = Config::default();
+    config.apply_args(&args(&["--workers", "8"])).unwrap();
+    assert_eq!(config.workers, 8);
+    assert!(config.apply_args(&args(&["--workers", "0"])).is_err());
+}
+
+#[test]
+fn restores_read_the_storage_dir() {
+    let mut config = Config::default();
+    assert!(!config.restore);
+    assert!(config.summary()["restore"].is_null());
+    config.apply_args(&args(&["--restore", "/tmp/x"])).unwrap();
+    assert!(config.restore);
+    assert_eq!(config.storage_dir.as_deref(), Some("/tmp/x".as_ref()));
+    assert_eq!(config.summary()["restore"], "/tmp/x");
+}
+
+#[test]
+fn secrets_are_kept_out_of_the_summary() {
+    let mut config = Config::default();
+    assert!(config.summary()["secret"].is_null());
+    let set = config.apply_args(&args(&["--secret", "hunter2"]));
+    if cfg!(feature = "hmac") {
+        set.unwrap();
+        assert_eq!(config.secret.as_deref(), Some(&b"hunter2"[..]));
+        assert_eq!(config.summary()["secret"], "<redacted>");
+    } else {
+        assert!(set.is_err());
+    }
+    assert!(config.apply_args(&args(&["--secret", ""])).is_err());
+}
@@ -0,0 +1,68 @@
+//! Suppressing repeated requests to the other nodes
+
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use maelstrom::dedupe::Dedupe;
+use maelstrom::metrics::Metrics;
+
+fn gossip(dest: &str, id: u64, messages: &str) -> String {
+    format!("{{\"src\":\"n1\",\"dest\":\"{dest}\",\"body\":{{\"type\":\"read\",\
+        \"msg_id\":{id},\"messages\":[{messages}]}}}}\n")
+}
+
+fn windowed(window: Duration) -> Dedupe<Vec<u8>> {
+    Dedupe::new(Vec::new(), window, &["n1".into(), "n2".into(), "n3".into()],
+        Arc::new(Metrics::new("n1")))
+}
+
+fn sent(dedupe: &Dedupe<Vec<u8>>) -> usize {
+    dedupe.inner().iter().filter(|b| **b == b'\n').count()
+}
+
+#[test]
+fn repeats_within_the_window_are_suppressed() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut dedupe = Dedupe::new(Vec::new(), Duration::from_secs(60),
+        &["n2".into(), "n3".into()], metrics.clone());
+    dedupe.write_all(gossip("n2", 1, "1,2").as_bytes()).unwrap();
+    dedupe.write_all(gossip("n2", 2, "1,2").as_bytes()).unwrap();
+    assert_eq!(sent(&dedupe), 1);
+    assert_eq!(metrics.suppressed.load(Ordering::Relaxed), 1);
+
+    // Other contents and other destinations are different requests
+    dedupe.write_all(gossip("n2", 3, "1,2,3").as_bytes()).unwrap();
+    dedupe.write_all(gossip("n3", 4, "1,2").as_bytes()).unwrap();
+    assert_eq!(sent(&dedupe), 3);
+    assert_eq!(dedupe.len(), 3);
+}
+
+#[test]
+fn replies_and_clients_are_never_suppressed() {
+    let mut dedupe = windowed(Duration::from_secs(60));
+    let reply = "{\"src\":\"n1\",\"dest\":\"n2\",\"body\":{\
+        \"type\":\"read_ok\",\"in_reply_to\":1,\"messages\":[]}}\n";
+    for _ in 0..2 {
+        dedupe.write_all(reply.as_bytes()).unwrap();
+        dedupe.write_all(gossip("c1", 1, "").as_bytes()).unwrap();
+    }
+    assert_eq!(sent(&dedupe), 4);
+    assert!(dedupe.is_empty());
+}
+
+#[test]
+fn repeats_past_the_window_are_sent() {
+    let mut dedupe = windowed(Duration::from_millis(20));
+    dedupe.write_all(gossip("n2", 1, "1").as_bytes()).unwrap();
+    std::thread::sleep(Duration::from_millis(30));
+    dedupe.write_all(gossip("n2", 2, "1").as_bytes()).unwrap();
+    assert_eq!(sent(&dedupe), 2);
+
+    // Without a window, nothing is suppressed
+    let mut unsuppressed = windowed(Duration::ZERO);
+    for id in 0..3 {
+        unsuppressed.write_all(gossip("n2", id, "1").as_bytes()).unwrap();
+    }
+    assert_eq!(sent(&unsuppressed), 3);
+}
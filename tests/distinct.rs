@@ -0,0 +1,63 @@
+//! Sketches gossiped by the distinct count service
+
+mod common;
+
+use std::collections::HashMap;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::distinct::DistinctNode;
+
+/// Feed the JSON message `msg` to its destination and collect what it sends
+fn step(nodes: &mut HashMap<String, DistinctNode>, msg: Value) -> Vec<Value> {
+    let node = nodes.get_mut(msg["dest"].as_str().unwrap()).unwrap();
+    common::step(node, msg)
+}
+
+#[test]
+fn counts_converge_along_the_topology() {
+    let ids = ["n0", "n1", "n2"];
+    let mut nodes: HashMap<_, _> = ids.iter().map(|id| (id.to_string(),
+        DistinctNode::from_init(&msg::Init {
+            node_id:  id.to_string(),
+            node_ids: ids.iter().map(|id| id.to_string()).collect(),
+        }, &Config::default()).unwrap())).collect();
+
+    // A line; n0 and n2 only hear of each other through n1
+    let topology = json!({"n0": ["n1"], "n1": ["n0", "n2"], "n2": ["n1"]});
+    for id in ids {
+        step(&mut nodes, json!({"src": "c1", "dest": id,
+            "body": {"type": "topology", "topology": topology}}));
+    }
+    for message in 0..3000 {
+        let dest = ids[message % 3];
+        step(&mut nodes, json!({"src": "c1", "dest": dest,
+            "body": {"type": "broadcast", "message": message % 2000}}));
+    }
+
+    // Two rounds carry everything across the line
+    for _ in 0..2 {
+        let mut gossip = Vec::new();
+        for node in nodes.values_mut() {
+            let mut out = Vec::new();
+            node.tick(&mut out).unwrap();
+            gossip.extend(String::from_utf8(out).unwrap().lines()
+                .map(|line| serde_json::from_str::<Value>(line).unwrap()));
+        }
+        for msg in gossip {
+            assert!(step(&mut nodes, msg).is_empty());
+        }
+    }
+
+    for id in ids {
+        let reply = step(&mut nodes, json!({"src": "c1", "dest": id,
+            "body": {"type": "count", "msg_id": 1}}));
+        let count = reply[0]["body"]["count"].as_u64().unwrap();
+        assert!((1900..2100).contains(&count), "{id} counted {count}");
+    }
+
+    // Nothing changed, so nothing is gossiped until the resync
+    let mut out = Vec::new();
+    nodes.get_mut("n1").unwrap().tick(&mut out).unwrap();
+    assert!(out.is_empty());
+}
@@ -0,0 +1,53 @@
+//! Echo replies held back by the configured delay
+
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{Message, Init, Node};
+use maelstrom::services::echo::{Payload, EchoNode};
+
+fn init() -> Init {
+    Init { node_id: "n1".into(), node_ids: vec!["n1".into()] }
+}
+
+fn echo(msg_id: usize, echo: Value) -> Message<Payload> {
+    Message::new("c1", "n1", msg_id, Payload::Echo { echo })
+}
+
+#[test]
+fn replies_are_immediate_by_default() {
+    let mut node = EchoNode::from_init(&init(), &Config::default()).unwrap();
+    assert_eq!(node.tick_interval(), None);
+
+    let mut out = Vec::new();
+    node.step(echo(1, json!([1, {"a": null}])), &mut out).unwrap();
+    let reply: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(reply["body"]["echo"], json!([1, {"a": null}]));
+    assert_eq!(reply["body"]["in_reply_to"], 1);
+}
+
+#[test]
+fn replies_are_delayed_and_jittered() {
+    let config = Config {
+        echo_delay:  Duration::from_millis(20),
+        echo_jitter: Duration::from_millis(10),
+        ..Config::default()
+    };
+    let mut node = EchoNode::from_init(&init(), &config).unwrap();
+    assert!(node.tick_interval().is_some());
+
+    let start = Instant::now();
+    let mut out = Vec::new();
+    node.step(echo(1, json!("hi")), &mut out).unwrap();
+    node.step(echo(2, json!("there")), &mut out).unwrap();
+    node.tick(&mut out).unwrap();
+    assert!(out.is_empty());
+
+    while out.iter().filter(|&&b| b == b'\n').count() < 2 {
+        assert!(start.elapsed() < Duration::from_secs(1));
+        std::thread::sleep(Duration::from_millis(1));
+        node.tick(&mut out).unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(20), "{elapsed:?}");
+}
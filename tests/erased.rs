@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use maelstrom::message::{Message, Init};
+use maelstrom::config::Config;
+use maelstrom::erased::{self, Constructor, ErasedNode};
+use maelstrom::services::{echo, uuid};
+
+/// The raw message `body` sent by `c1` to `n1`
+fn raw(body: Value) -> Message<Value> {
+    serde_json::from_value(json!({ "src": "c1", "dest": "n1", "body": body }))
+        .unwrap()
+}
+
+#[test]
+fn erased_nodes_of_different_services_coexist() {
+    let init = Init { node_id: "n1".into(), node_ids: vec!["n1".into()] };
+    let registry: BTreeMap<&str, Constructor> = BTreeMap::from([
+        ("echo", erased::from_init::<echo::Payload, echo::EchoNode>
+            as Constructor),
+        ("unique-ids", erased::from_init::<uuid::Payload, uuid::UUIDNode>),
+    ]);
+    let mut nodes: BTreeMap<&str, Box<dyn ErasedNode>> = registry.iter()
+        .map(|(name, new)| (*name, new(&init, &Config::default()).unwrap()))
+        .collect();
+
+    let mut out = Vec::new();
+    nodes.get_mut("echo").unwrap().step(raw(json!({
+        "type": "echo", "msg_id": 1, "echo": "hi",
+    })), &mut out).unwrap();
+    let reply: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(reply["body"]["type"], "echo_ok");
+    assert_eq!(reply["body"]["echo"], "hi");
+    assert_eq!(reply["body"]["in_reply_to"], 1);
+
+    // Payloads of another service are rejected, not misread
+    let err = nodes.get_mut("unique-ids").unwrap().step(raw(json!({
+        "type": "echo", "msg_id": 2, "echo": "hi",
+    })), &mut out);
+    assert!(err.is_err());
+}
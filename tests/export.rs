@@ -0,0 +1,177 @@
+//! Moving the state of a node over to another
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::{broadcast, chain_kv, distinct, echo, lww_kv,
+    vclock_kv};
+use common::run;
+
+fn init(id: &str) -> Value {
+    json!({"src": "c0", "dest": id, "body": {"type": "init", "msg_id": 1,
+        "node_id": id, "node_ids": ["n1", "n2"]}})
+}
+
+fn of_type<'a>(output: &'a [Value], kind: &str) -> Vec<&'a Value> {
+    output.iter().filter(|msg| msg["body"]["type"] == kind).collect()
+}
+
+#[test]
+fn state_is_exported_in_parts_and_imported_whole() {
+    // Enough messages to take more than a part
+    let mut input = vec![init("n1")];
+    input.extend((0..5000).map(|message| json!({"src": "c1", "dest": "n1",
+        "body": {"type": "broadcast", "msg_id": message, "message": message}
+    })));
+    input.push(json!({"src": "n2", "dest": "n1",
+        "body": {"type": "export_state", "msg_id": 1}}));
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&input);
+    let parts = of_type(&output, "export_state_ok");
+    assert_eq!(parts.len(), 2);
+    assert!(parts.iter().all(|part| part["body"]["parts"] == 2 &&
+        part["body"]["in_reply_to"] == 1));
+
+    // n2 asks n1 for its state, which answers the first request of n2
+    let mut input = vec![init("n2"), json!({"src": "c1", "dest": "n2",
+        "body": {"type": "import_state", "msg_id": 7, "from": "n1"}})];
+    input.extend(parts.into_iter().cloned());
+    input.push(json!({"src": "c1", "dest": "n2",
+        "body": {"type": "read", "msg_id": 8}}));
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&input);
+    let request = of_type(&output, "export_state");
+    assert_eq!(request[0]["dest"], "n1");
+    assert_eq!(request[0]["body"]["msg_id"], 1);
+    let done = of_type(&output, "import_state_ok");
+    assert_eq!(done[0]["body"]["in_reply_to"], 7);
+    let read = of_type(&output, "read_ok");
+    assert_eq!(read[0]["body"]["messages"].as_array().unwrap().len(), 5000);
+}
+
+/// Have n1 of `N` handle the `requests` and export its state, which n2
+/// imports before it handles `then`. Returns what n2 sent
+fn hand_over<P, N>(requests: &[Value], then: &[Value]) -> Vec<Value>
+where
+    P: serde::de::DeserializeOwned + core::fmt::Debug,
+    N: Node<P>,
+{
+    let mut input = vec![init("n1")];
+    input.extend(requests.iter().cloned());
+    input.push(json!({"src": "n2", "dest": "n1",
+        "body": {"type": "export_state", "msg_id": 1}}));
+    let output = run::<P, N>(&input);
+
+    let mut input = vec![init("n2"), json!({"src": "c1", "dest": "n2",
+        "body": {"type": "import_state", "msg_id": 1, "from": "n1"}})];
+    input.extend(of_type(&output, "export_state_ok").into_iter().cloned());
+    input.extend(then.iter().cloned());
+    let output = run::<P, N>(&input);
+    assert_eq!(of_type(&output, "import_state_ok").len(), 1);
+    output
+}
+
+fn request(dest: &str, body: Value) -> Value {
+    json!({"src": "c1", "dest": dest, "body": body})
+}
+
+#[test]
+fn every_service_with_state_of_its_own_hands_it_over() {
+    let output = hand_over::<vclock_kv::Payload, vclock_kv::VClockKvNode>(
+        &[request("n1", json!({"type": "write", "msg_id": 2, "key": 1,
+            "value": 7}))],
+        &[request("n2", json!({"type": "read", "msg_id": 2, "key": 1}))]);
+    assert_eq!(of_type(&output, "read_ok")[0]["body"]["values"], json!([7]));
+
+    let broadcasts: Vec<Value> = (0..50).map(|message| request("n1",
+        json!({"type": "broadcast", "msg_id": message, "message": message})))
+        .collect();
+    let output = hand_over::<distinct::Payload, distinct::DistinctNode>(
+        &broadcasts, &[request("n2", json!({"type": "count", "msg_id": 2}))]);
+    let count = of_type(&output, "count_ok")[0]["body"]["count"].as_u64();
+    assert!(count.is_some_and(|count| count.abs_diff(50) < 5));
+
+    // n1 heads the chain and applies the write first
+    let output = hand_over::<chain_kv::Payload, chain_kv::ChainKvNode>(
+        &[request("n1", json!({"type": "write", "msg_id": 2, "key": 1,
+            "value": 7}))],
+        &[request("n2", json!({"type": "read", "msg_id": 2, "key": 1}))]);
+    assert_eq!(of_type(&output, "read_ok")[0]["body"]["value"], 7);
+}
+
+#[test]
+fn parts_that_dont_fit_fail_the_import() {
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&[
+        init("n2"),
+        json!({"src": "c1", "dest": "n2",
+            "body": {"type": "import_state", "msg_id": 7, "from": "n1"}}),
+        json!({"src": "n1", "dest": "n2", "body": {"type": "export_state_ok",
+            "in_reply_to": 1, "part": 2, "parts": 2, "data": "{}"}}),
+    ]);
+    let failed = of_type(&output, "error");
+    assert_eq!(failed[0]["body"]["in_reply_to"], 7);
+    assert_eq!(failed[0]["body"]["code"], msg::error_code::MALFORMED_REQUEST);
+}
+
+#[test]
+fn donors_refusing_to_export_fail_the_import() {
+    // lww-kv has errors of its own, which the refusal parses as
+    let output = run::<lww_kv::Payload, lww_kv::LwwKvNode>(&[
+        init("n2"),
+        json!({"src": "c1", "dest": "n2",
+            "body": {"type": "import_state", "msg_id": 7, "from": "n1"}}),
+        json!({"src": "n1", "dest": "n2", "body": {"type": "error",
+            "in_reply_to": 1, "code": 10, "text": "no"}}),
+    ]);
+    let failed = of_type(&output, "error");
+    assert_eq!(failed[0]["dest"], "c1");
+    assert_eq!(failed[0]["body"]["in_reply_to"], 7);
+    assert_eq!(failed[0]["body"]["code"], msg::error_code::NOT_SUPPORTED);
+}
+
+#[test]
+fn services_without_state_refuse_to_export_it() {
+    let output = run::<echo::Payload, echo::EchoNode>(&[init("n1"),
+        json!({"src": "n2", "dest": "n1",
+            "body": {"type": "export_state", "msg_id": 3}})]);
+    assert_eq!(output[1]["body"]["code"], msg::error_code::NOT_SUPPORTED);
+    assert_eq!(output[1]["body"]["in_reply_to"], 3);
+}
+
+#[test]
+fn joining_nodes_catch_up_before_serving() {
+    let mut input = vec![init("n2")];
+    input.extend((0..3).map(|message| json!({"src": "c1", "dest": "n2",
+        "body": {"type": "broadcast", "msg_id": message, "message": message}
+    })));
+    input.push(json!({"src": "n3", "dest": "n2",
+        "body": {"type": "export_state", "msg_id": 2}}));
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&input);
+    let state = of_type(&output, "export_state_ok")[0].clone();
+
+    // n1 can't hand over its state, so n3 moves on to n2
+    let read = |msg_id: usize| json!({"src": "c1", "dest": "n3",
+        "body": {"type": "read", "msg_id": msg_id}});
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&[
+        json!({"src": "c0", "dest": "n3", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n3", "node_ids": ["n1", "n2"]}}),
+        json!({"src": "c0", "dest": "n3",
+            "body": {"type": "node_join", "msg_id": 2, "node": "n3"}}),
+        read(3),
+        json!({"src": "n1", "dest": "n3", "body": {"type": "error",
+            "in_reply_to": 1, "code": 10, "text": "no"}}),
+        state,
+        read(4),
+    ]);
+    let asked: Vec<&Value> = of_type(&output, "export_state").iter()
+        .map(|msg| &msg["dest"])
+        .collect();
+    assert_eq!(asked, ["n1", "n2"]);
+    let refused = of_type(&output, "error");
+    assert_eq!(refused[0]["body"]["in_reply_to"], 3);
+    assert_eq!(refused[0]["body"]["code"],
+        msg::error_code::TEMPORARILY_UNAVAILABLE);
+    assert_eq!(of_type(&output, "node_join_ok").len(), 1);
+    let read = of_type(&output, "read_ok");
+    assert_eq!(read[0]["body"]["in_reply_to"], 4);
+    assert_eq!(read[0]["body"]["messages"], json!([0, 1, 2]));
+}
@@ -0,0 +1,30 @@
+//! Golden transcript tests; every service is fed a fixed input and its output
+//! is compared against the expected transcript
+
+mod common;
+
+use maelstrom::services::{echo, uuid, broadcast};
+
+#[test]
+fn echo() {
+    common::run_golden::<echo::Payload, echo::EchoNode>("echo", &[]);
+}
+
+#[test]
+fn echo_before_and_after_repeated_init() {
+    // Messages before init are handled once it's done, repeated init is only
+    // acknowledged
+    common::run_golden::<echo::Payload, echo::EchoNode>("echo_early", &[]);
+}
+
+#[test]
+fn uuid() {
+    // The generated IDs are random, only the shape of the replies matters
+    common::run_golden::<uuid::Payload, uuid::UUIDNode>("uuid", &["id"]);
+}
+
+#[test]
+fn broadcast() {
+    common::run_golden::<broadcast::Payload, broadcast::BroadcastNode>(
+        "broadcast", &[]);
+}
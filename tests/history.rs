@@ -0,0 +1,33 @@
+//! Records the operation history of a service run
+
+mod common;
+
+use maelstrom::history::{History, OpType, HISTORY_ENV};
+use maelstrom::config::Config;
+use maelstrom::message as msg;
+use maelstrom::services::echo;
+
+#[test]
+fn records_client_operations() {
+    let path = std::env::temp_dir()
+        .join(format!("maelstrom-history-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    std::env::set_var(HISTORY_ENV, &path);
+
+    let input = std::fs::read(common::fixture_path("echo.in.jsonl")).unwrap();
+    msg::main_loop_with_io::<echo::Payload, echo::EchoNode>(
+        std::io::Cursor::new(input), &mut std::io::sink(), &Config::default())
+        .unwrap();
+
+    let ops = History::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let kinds: Vec<OpType> = ops.iter().map(|op| op.kind).collect();
+    assert_eq!(kinds, [OpType::Invoke, OpType::Ok, OpType::Invoke, OpType::Ok]);
+
+    assert!(ops.iter().all(|op| op.f == "echo" && op.node == "n1"));
+    assert_eq!(ops[0].process, "c1");
+    assert_eq!(ops[0].msg_id, ops[1].msg_id);
+    assert_eq!(ops[1].value["echo"], "Please echo 35");
+    assert!(ops[0].time <= ops[1].time);
+}
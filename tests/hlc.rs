@@ -0,0 +1,47 @@
+//! Ordering guarantees of the hybrid logical clock
+
+use maelstrom::hlc::{Hlc, Timestamp};
+
+#[test]
+fn follows_the_wall_clock() {
+    let mut hlc = Hlc::new();
+    assert_eq!(hlc.now_at(1000), Timestamp::new(1000, 0));
+    assert_eq!(hlc.now_at(1005), Timestamp::new(1005, 0));
+}
+
+#[test]
+fn counts_when_the_wall_clock_stalls_or_regresses() {
+    let mut hlc = Hlc::new();
+    hlc.now_at(1000);
+    assert_eq!(hlc.now_at(1000), Timestamp::new(1000, 1));
+    assert_eq!(hlc.now_at(900), Timestamp::new(1000, 2));
+    assert_eq!(hlc.now_at(1001), Timestamp::new(1001, 0));
+}
+
+#[test]
+fn receives_are_ordered_after_remote_sends() {
+    let mut hlc = Hlc::new();
+    hlc.now_at(1000);
+
+    // The remote clock runs ahead of ours
+    let remote = Timestamp::new(2000, 4);
+    let ts = hlc.update_at(remote, 1001);
+    assert!(ts > remote);
+    assert_eq!(ts, Timestamp::new(2000, 5));
+
+    // Our wall clock catches up eventually
+    assert_eq!(hlc.update_at(Timestamp::new(10, 0), 3000),
+        Timestamp::new(3000, 0));
+}
+
+#[test]
+fn logical_overflow_stays_monotonic() {
+    let mut hlc = Hlc::new();
+    let mut last = hlc.now_at(7);
+    for _ in 0..=u16::MAX as usize + 1 {
+        let ts = hlc.now_at(7);
+        assert!(ts > last);
+        last = ts;
+    }
+    assert_eq!(last.physical_ms(), 8);
+}
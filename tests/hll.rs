@@ -0,0 +1,38 @@
+//! Estimates of the HyperLogLog sketch
+
+use maelstrom::hll::Hll;
+
+/// Relative error of `count` against `exact`
+fn error(count: u64, exact: u64) -> f64 {
+    (count as f64 - exact as f64).abs() / exact as f64
+}
+
+#[test]
+fn estimates_distinct_items() {
+    for exact in [10u64, 1000, 100_000] {
+        let mut hll = Hll::new(10);
+        for item in 0..exact {
+            hll.insert(&item);
+            hll.insert(&item);
+        }
+        assert!(error(hll.count(), exact) < 0.1,
+            "{} for {exact} items", hll.count());
+    }
+    assert_eq!(Hll::new(10).count(), 0);
+}
+
+#[test]
+fn merges_are_unions() {
+    let (mut a, mut b) = (Hll::new(10), Hll::new(10));
+    (0..6000u64).for_each(|item| { a.insert(&item); });
+    (4000..10000u64).for_each(|item| { b.insert(&item); });
+
+    let mut union = Hll::new(10);
+    (0..10000u64).for_each(|item| { union.insert(&item); });
+    assert!(a.merge(&b).unwrap());
+    assert_eq!(a, union);
+
+    // Merging again teaches nothing new
+    assert!(!a.merge(&b).unwrap());
+    assert!(a.merge(&Hll::new(12)).is_err());
+}
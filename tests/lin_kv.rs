@@ -0,0 +1,462 @@
+//! The linearizable KV replicated by Viewstamped Replication
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Node};
+use maelstrom::services::lin_kv::{Payload, LinKvNode};
+
+/// Nodes of a cluster along with the messages in flight between them
+struct Net {
+    nodes: HashMap<String, LinKvNode>,
+    queue: VecDeque<Value>,
+
+    /// Node cut off from everyone else
+    isolated: Option<String>,
+}
+
+impl Net {
+    fn new(config: &Config) -> Self {
+        let ids = ["n0", "n1", "n2"];
+        let nodes = ids.iter().map(|id| (id.to_string(),
+            LinKvNode::from_init(&msg::Init {
+                node_id:  id.to_string(),
+                node_ids: ids.iter().map(|id| id.to_string()).collect(),
+            }, config).unwrap())).collect();
+        Self { nodes, queue: VecDeque::new(), isolated: None }
+    }
+
+    fn collect(&mut self, out: Vec<u8>) {
+        for line in String::from_utf8(out).unwrap().lines() {
+            self.queue.push_back(serde_json::from_str(line).unwrap());
+        }
+    }
+
+    fn tick(&mut self) {
+        let mut out = Vec::new();
+        for node in self.nodes.values_mut() {
+            node.tick(&mut out).unwrap();
+        }
+        self.collect(out);
+    }
+
+    /// Deliver everything in flight, returning what was sent to the clients
+    fn deliver(&mut self) -> Vec<Value> {
+        let mut replies = Vec::new();
+        while let Some(msg) = self.queue.pop_front() {
+            let (src, dst) = (msg["src"].as_str().unwrap().to_string(),
+                msg["dest"].as_str().unwrap().to_string());
+            if self.isolated.as_ref().is_some_and(|isolated|
+                    (*isolated == src || *isolated == dst) &&
+                    self.nodes.contains_key(&src)) {
+                continue;
+            }
+            let Some(node) = self.nodes.get_mut(&dst) else {
+                replies.push(msg);
+                continue;
+            };
+            let msg: Message<Payload> = serde_json::from_value(msg).unwrap();
+            let mut out = Vec::new();
+            node.step(msg, &mut out).unwrap();
+            self.collect(out);
+        }
+        replies
+    }
+
+    /// Send the client request `body` to `dst` and deliver it
+    fn request(&mut self, dst: &str, body: Value) -> Vec<Value> {
+        self.queue.push_back(json!({"src": "c1", "dest": dst,
+            "body": body}));
+        self.deliver()
+    }
+}
+
+#[test]
+fn requests_are_committed_by_the_primary() {
+    let mut net = Net::new(&Config::default());
+
+    // The write enters at a backup and is answered by the primary
+    let replies = net.request("n2", json!({"type": "write", "msg_id": 1,
+        "key": 1, "value": 5}));
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["src"], "n0");
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+    assert_eq!(replies[0]["body"]["in_reply_to"], 1);
+
+    let replies = net.request("n1", json!({"type": "cas", "msg_id": 2,
+        "key": 1, "from": 5, "to": 6}));
+    assert_eq!(replies[0]["body"]["type"], "cas_ok");
+    let replies = net.request("n0", json!({"type": "read", "msg_id": 3,
+        "key": 1}));
+    assert_eq!(replies[0]["body"]["value"], 6);
+}
+
+#[test]
+fn backups_take_over_from_a_silent_primary() {
+    let config = Config {
+        retry_timeout: Duration::from_millis(20),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+
+    // Cut the primary off until the backups give up on it
+    net.isolated = Some("n0".into());
+    std::thread::sleep(Duration::from_millis(30));
+    net.tick();
+    net.deliver();
+
+    // The primary of the next view has everything committed before
+    let replies = net.request("n1", json!({"type": "read", "msg_id": 2,
+        "key": 1}));
+    assert_eq!(replies[0]["src"], "n1");
+    assert_eq!(replies[0]["body"]["value"], 5);
+
+    let replies = net.request("n2", json!({"type": "write", "msg_id": 3,
+        "key": 1, "value": 7}));
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+}
+
+#[test]
+fn reads_are_as_consistent_as_asked_for() {
+    let config = Config {
+        retry_timeout: Duration::from_millis(20),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+
+    // The backups haven't heard of the commit yet, their replica is stale
+    let replies = net.request("n2", json!({"type": "read", "msg_id": 2,
+        "key": 1, "consistency": "one"}));
+    assert_eq!(replies[0]["src"], "n2");
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::KEY_DOES_NOT_EXIST);
+
+    // Asking the other replicas finds the most recent value
+    for consistency in ["quorum", "all", "linearizable"] {
+        let replies = net.request("n2", json!({"type": "read", "msg_id": 3,
+            "key": 1, "consistency": consistency}));
+        assert_eq!(replies[0]["body"]["value"], 5, "{consistency}");
+    }
+
+    // Not every replica answers, the read gives up
+    net.isolated = Some("n0".into());
+    assert!(net.request("n2", json!({"type": "read", "msg_id": 4,
+        "key": 1, "consistency": "all"})).is_empty());
+    std::thread::sleep(Duration::from_millis(30));
+    let mut out = Vec::new();
+    net.nodes.get_mut("n2").unwrap().tick(&mut out).unwrap();
+    net.collect(out);
+    let replies = net.deliver();
+    assert!(replies.iter().any(|reply| reply["body"]["in_reply_to"] == 4 &&
+        reply["body"]["code"] == msg::error_code::TEMPORARILY_UNAVAILABLE));
+}
+
+#[test]
+fn writes_of_stale_sessions_are_rejected() {
+    let mut net = Net::new(&Config::default());
+    let open = |net: &mut Net, id| net.request("n1", json!({
+        "type": "open_session", "msg_id": id, "ttl": 60_000}))[0]["body"]
+        ["session"].as_u64().unwrap();
+    let old = open(&mut net, 1);
+    let new = open(&mut net, 2);
+    assert!(new > old);
+
+    let replies = net.request("n1", json!({"type": "write", "msg_id": 3,
+        "key": "lock", "value": "new", "session": new}));
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+
+    // The old session was fenced off the key by the new one
+    let replies = net.request("n1", json!({"type": "cas", "msg_id": 4,
+        "key": "lock", "from": "new", "to": "old", "session": old}));
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::PRECONDITION_FAILED);
+
+    // Closed sessions can't write at all
+    net.request("n1", json!({"type": "close_session", "msg_id": 5,
+        "session": new}));
+    let replies = net.request("n1", json!({"type": "keep_alive", "msg_id": 6,
+        "session": new}));
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::PRECONDITION_FAILED);
+    let replies = net.request("n1", json!({"type": "read", "msg_id": 7,
+        "key": "lock"}));
+    assert_eq!(replies[0]["body"]["value"], "new");
+}
+
+#[test]
+fn restored_clusters_keep_what_was_committed() {
+    let dir = std::env::temp_dir()
+        .join(format!("maelstrom-lin-kv-restore-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = Config {
+        storage_dir: Some(dir.clone()),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+    net.request("n0", json!({"type": "cas", "msg_id": 2, "key": 1,
+        "from": 5, "to": 6}));
+
+    // Every node crashes and comes back with what it wrote ahead
+    config.restore = true;
+    let mut net = Net::new(&config);
+    assert_eq!(net.nodes["n1"].status()["op"], 2);
+    let replies = net.request("n0", json!({"type": "read", "msg_id": 3,
+        "key": 1}));
+    assert_eq!(replies[0]["body"]["value"], 6);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn replicas_follow_the_cluster() {
+    let config = Config::default();
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+
+    // n3 joins and n1 leaves, one step at a time
+    let cluster: Vec<String> = ["n0", "n2", "n3"].iter()
+        .map(|id| id.to_string())
+        .collect();
+    net.nodes.insert("n3".into(), LinKvNode::from_init(&msg::Init {
+        node_id:  "n3".into(),
+        node_ids: cluster.clone(),
+    }, &config).unwrap());
+    for node in net.nodes.values_mut() {
+        assert!(node.membership(&cluster));
+    }
+    for _ in 0..3 {
+        net.tick();
+        net.deliver();
+    }
+    assert_eq!(net.nodes["n0"].status()["replicas"], json!(cluster));
+    assert_eq!(net.nodes["n3"].status()["replicas"], json!(cluster));
+
+    // The joined node caught up, and the primary counts on it
+    let replies = net.request("n3", json!({"type": "read", "msg_id": 2,
+        "key": 1, "consistency": "one"}));
+    assert_eq!(replies[0]["body"]["value"], 5);
+    net.nodes.remove("n1");
+    let replies = net.request("n0", json!({"type": "write", "msg_id": 3,
+        "key": 1, "value": 7}));
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+}
+
+#[test]
+fn reconfiguring_keeps_the_primary_of_the_view() {
+    let config = Config {
+        retry_timeout: Duration::from_millis(20),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+
+    // n1 takes over from n0, which then leaves. Going by the replicas left,
+    // n2 would be the primary of the view
+    net.isolated = Some("n0".into());
+    std::thread::sleep(Duration::from_millis(30));
+    net.tick();
+    net.deliver();
+    net.nodes.remove("n0");
+    let cluster: Vec<String> = ["n1", "n2"].iter()
+        .map(|id| id.to_string())
+        .collect();
+    for node in net.nodes.values_mut() {
+        assert!(node.membership(&cluster));
+    }
+    net.tick();
+    net.deliver();
+    for id in ["n1", "n2"] {
+        assert_eq!(net.nodes[id].status()["replicas"], json!(cluster));
+        assert_eq!(net.nodes[id].status()["view"], 1);
+        assert_eq!(net.nodes[id].status()["primary"], "n1");
+    }
+    let replies = net.request("n2", json!({"type": "write", "msg_id": 2,
+        "key": 1, "value": 7}));
+    assert_eq!(replies[0]["src"], "n1");
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+}
+
+#[test]
+fn primaries_that_leave_hand_over_to_the_rest() {
+    let config = Config {
+        retry_timeout: Duration::from_millis(20),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+
+    // The primary commits its own leave, then steps down
+    let cluster: Vec<String> = ["n1", "n2"].iter()
+        .map(|id| id.to_string())
+        .collect();
+    for node in net.nodes.values_mut() {
+        assert!(node.membership(&cluster));
+    }
+    net.tick();
+    net.deliver();
+    for id in ["n1", "n2"] {
+        assert_eq!(net.nodes[id].status()["replicas"], json!(cluster));
+        assert_eq!(net.nodes[id].status()["primary"], "n0");
+    }
+    net.tick();
+    net.deliver();
+
+    // The replicas left give up on it and move on to a view of their own
+    std::thread::sleep(Duration::from_millis(30));
+    net.tick();
+    net.deliver();
+    net.nodes.remove("n0");
+    let replies = net.request("n1", json!({"type": "write", "msg_id": 2,
+        "key": 1, "value": 7}));
+    assert_eq!(replies[0]["body"]["type"], "write_ok");
+    let replies = net.request("n2", json!({"type": "read", "msg_id": 3,
+        "key": 1}));
+    assert_eq!(replies[0]["body"]["value"], 7);
+}
+
+#[test]
+fn misdirected_forwards_point_at_the_primary() {
+    let mut net = Net::new(&Config::default());
+    net.queue.push_back(json!({"src": "n2", "dest": "n1", "body": {
+        "type": "forward", "msg_id": 1, "client": "c1", "client_id": 7,
+        "command": {"type": "write", "key": 1, "value": 5}}}));
+    let replies = net.deliver();
+    assert_eq!(replies[0]["dest"], "c1");
+    assert_eq!(replies[0]["body"]["in_reply_to"], 7);
+    assert_eq!(replies[0]["body"]["leader"], "n0");
+}
+
+#[test]
+fn backups_serve_reads_that_tolerate_staleness() {
+    let mut net = Net::new(&Config::default());
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 1,
+        "value": 5}));
+    net.tick();
+    net.deliver();
+
+    // n1 heard from the primary just now, so it answers on its own
+    net.queue.push_back(json!({"src": "c1", "dest": "n1", "body": {
+        "type": "read", "msg_id": 2, "key": 1, "max_staleness_ms": 1000}}));
+    let msg = net.queue.pop_front().unwrap();
+    let mut out = Vec::new();
+    net.nodes.get_mut("n1").unwrap()
+        .step(serde_json::from_value(msg).unwrap(), &mut out).unwrap();
+    let reply: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(reply["dest"], "c1");
+    assert_eq!(reply["body"]["value"], 5);
+
+    // Too long ago, and the read goes through the primary
+    std::thread::sleep(Duration::from_millis(20));
+    let replies = net.request("n1", json!({"type": "read", "msg_id": 3,
+        "key": 1, "max_staleness_ms": 10}));
+    assert_eq!(replies[0]["src"], "n0");
+    assert_eq!(replies[0]["body"]["value"], 5);
+}
+
+#[test]
+fn writes_are_proposed_in_batches() {
+    let config = Config {
+        batch_window: Duration::from_millis(10),
+        ..Config::default()
+    };
+    let mut net = Net::new(&config);
+    for id in 1..=3 {
+        let replies = net.request("n0", json!({"type": "write",
+            "msg_id": id, "key": id, "value": id}));
+        assert!(replies.is_empty());
+    }
+    assert_eq!(net.nodes["n0"].status()["batch"], 3);
+    assert_eq!(net.nodes["n0"].status()["op"], 0);
+
+    // The whole batch goes out in a single prepare per backup
+    std::thread::sleep(Duration::from_millis(20));
+    let mut out = Vec::new();
+    net.nodes.get_mut("n0").unwrap().tick(&mut out).unwrap();
+    net.collect(out);
+    let prepares: Vec<&Value> = net.queue.iter()
+        .filter(|msg| msg["body"]["type"] == "prepare" &&
+            msg["body"]["entries"].as_array().is_some_and(|e| !e.is_empty()))
+        .collect();
+    assert_eq!(prepares.len(), 2);
+    assert_eq!(prepares[0]["body"]["entries"].as_array().unwrap().len(), 3);
+
+    let replies = net.deliver();
+    let mut acked: Vec<_> = replies.iter()
+        .map(|reply| reply["body"]["in_reply_to"].as_u64().unwrap())
+        .collect();
+    acked.sort_unstable();
+    assert_eq!(acked, [1, 2, 3]);
+}
+
+#[test]
+fn batches_apply_all_of_their_ops_or_none() {
+    let mut net = Net::new(&Config::default());
+    let replies = net.request("n1", json!({"type": "batch", "msg_id": 1,
+        "ops": [
+            {"type": "write", "key": 1, "value": 5},
+            {"type": "cas", "key": 1, "from": 5, "to": 6},
+            {"type": "read", "key": 1},
+            {"type": "read", "key": 2},
+        ]}));
+    assert_eq!(replies[0]["body"]["type"], "batch_ok");
+    assert_eq!(replies[0]["body"]["values"], json!([null, null, 6, null]));
+
+    // The failed CAS takes the write before it down with it
+    let replies = net.request("n0", json!({"type": "batch", "msg_id": 2,
+        "ops": [
+            {"type": "write", "key": 2, "value": 1},
+            {"type": "cas", "key": 1, "from": 5, "to": 7},
+        ]}));
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::PRECONDITION_FAILED);
+    assert_eq!(replies[0]["body"]["text"], "op 1: expected 5, had 6");
+    let replies = net.request("n0", json!({"type": "read", "msg_id": 3,
+        "key": 2}));
+    assert_eq!(replies[0]["body"]["code"],
+        msg::error_code::KEY_DOES_NOT_EXIST);
+}
+
+#[test]
+fn indexes_follow_the_writes() {
+    let mut net = Net::new(&Config::default());
+    let write = |net: &mut Net, key: u64, color: &str| {
+        net.request("n0", json!({"type": "write", "msg_id": 1, "key": key,
+            "value": {"color": color}}));
+    };
+    let query = |net: &mut Net, color: &str| {
+        let replies = net.request("n2", json!({"type": "query_index",
+            "msg_id": 2, "field": "color", "value": color}));
+        replies[0]["body"].clone()
+    };
+
+    assert_eq!(query(&mut net, "red")["code"],
+        msg::error_code::KEY_DOES_NOT_EXIST);
+
+    // What's written before the index is created is indexed along with it
+    write(&mut net, 1, "red");
+    net.request("n0", json!({"type": "write", "msg_id": 1, "key": 2,
+        "value": 7}));
+    let replies = net.request("n1", json!({"type": "create_index",
+        "msg_id": 3, "field": "color"}));
+    assert_eq!(replies[0]["body"]["type"], "create_index_ok");
+    write(&mut net, 3, "red");
+    assert_eq!(query(&mut net, "red")["keys"], json!([1, 3]));
+
+    // Keys move along with their values, batches included
+    write(&mut net, 1, "blue");
+    net.request("n0", json!({"type": "batch", "msg_id": 4, "ops": [
+        {"type": "write", "key": 2, "value": {"color": "blue"}},
+        {"type": "write", "key": 3, "value": {"shade": "red"}},
+    ]}));
+    assert_eq!(query(&mut net, "red")["keys"], json!([]));
+    assert_eq!(query(&mut net, "blue")["keys"], json!([1, 2]));
+}
@@ -0,0 +1,27 @@
+//! Runs the load generator against real node processes
+
+use std::time::Duration;
+use maelstrom::loadgen::{self, Options, Workload};
+
+fn run(workload: Workload) -> loadgen::Report {
+    let opts = Options {
+        workload,
+        nodes: 3,
+        rate:  1000.,
+        ops:   100,
+        seed:  Some(1),
+        cmd:   vec![env!("CARGO_BIN_EXE_maelstrom").into(),
+            workload.service().into()],
+        ..Default::default()
+    };
+    loadgen::run(&opts).unwrap()
+}
+
+#[test]
+fn all_ops_succeed() {
+    for workload in [Workload::Echo, Workload::UniqueIds, Workload::Broadcast] {
+        let report = run(workload);
+        assert_eq!(report.latencies.len(), 100, "{workload:?}: {report}");
+        assert!(report.percentile(100) < Duration::from_secs(1));
+    }
+}
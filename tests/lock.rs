@@ -0,0 +1,131 @@
+//! Mutual exclusion, leases and fencing tokens of the lock service
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Node};
+use maelstrom::services::lock::{Payload, LockNode};
+
+fn cluster() -> HashMap<String, LockNode> {
+    let ids = ["n0", "n1", "n2"];
+    ids.iter().map(|id| (id.to_string(), LockNode::from_init(&msg::Init {
+        node_id:  id.to_string(),
+        node_ids: ids.iter().map(|id| id.to_string()).collect(),
+    }, &Config::default()).unwrap())).collect()
+}
+
+/// Deliver the messages of `queue` and everything they lead to, returning
+/// those sent to the clients
+fn deliver(nodes: &mut HashMap<String, LockNode>, mut queue: VecDeque<Value>)
+        -> Vec<Value> {
+    let mut replies = Vec::new();
+    while let Some(msg) = queue.pop_front() {
+        let Some(node) = nodes.get_mut(msg["dest"].as_str().unwrap()) else {
+            replies.push(msg);
+            continue;
+        };
+        let msg: Message<Payload> = serde_json::from_value(msg).unwrap();
+        let mut out = Vec::new();
+        node.step(msg, &mut out).unwrap();
+        for line in String::from_utf8(out).unwrap().lines() {
+            queue.push_back(serde_json::from_str(line).unwrap());
+        }
+    }
+    replies
+}
+
+/// Send the request `body` of `client` to `dst`, deliver everything it leads
+/// to and return the reply to the client
+fn request(nodes: &mut HashMap<String, LockNode>, client: &str, dst: &str,
+        body: Value) -> Value {
+    let mut replies = deliver(nodes, VecDeque::from([json!({"src": client,
+        "dest": dst, "body": body})]));
+    assert_eq!(replies.len(), 1);
+    replies.remove(0)["body"].clone()
+}
+
+#[test]
+fn locks_are_held_by_one_client_at_a_time() {
+    let mut nodes = cluster();
+    let acquire = json!({"type": "acquire", "msg_id": 1, "key": "a",
+        "ttl": 60_000});
+
+    let first = request(&mut nodes, "c1", "n1", acquire.clone());
+    assert_eq!(first["type"], "acquire_ok");
+    let reply = request(&mut nodes, "c2", "n2", acquire.clone());
+    assert_eq!(reply["code"], msg::error_code::PRECONDITION_FAILED);
+
+    // The holder acquiring again renews the lock under the same token
+    let renewed = request(&mut nodes, "c1", "n0", acquire.clone());
+    assert_eq!(renewed["token"], first["token"]);
+
+    // Only the token the lock is held with releases it
+    let reply = request(&mut nodes, "c2", "n0", json!({"type": "release",
+        "msg_id": 2, "key": "a", "token": 999}));
+    assert_eq!(reply["code"], msg::error_code::PRECONDITION_FAILED);
+    let reply = request(&mut nodes, "c1", "n0", json!({"type": "release",
+        "msg_id": 2, "key": "a", "token": first["token"]}));
+    assert_eq!(reply["type"], "release_ok");
+
+    let second = request(&mut nodes, "c2", "n2", acquire);
+    assert!(second["token"].as_u64() > first["token"].as_u64());
+}
+
+#[test]
+fn expired_leases_free_the_lock() {
+    let mut nodes = cluster();
+    let first = request(&mut nodes, "c1", "n0", json!({"type": "acquire",
+        "msg_id": 1, "key": "a", "ttl": 10}));
+    std::thread::sleep(Duration::from_millis(20));
+
+    let second = request(&mut nodes, "c2", "n0", json!({"type": "acquire",
+        "msg_id": 1, "key": "a", "ttl": 60_000}));
+    assert_eq!(second["type"], "acquire_ok");
+    assert!(second["token"].as_u64() > first["token"].as_u64());
+
+    // The holder whose lease ran out can't release it anymore
+    let reply = request(&mut nodes, "c1", "n0", json!({"type": "release",
+        "msg_id": 2, "key": "a", "token": first["token"]}));
+    assert_eq!(reply["code"], msg::error_code::PRECONDITION_FAILED);
+}
+
+#[test]
+fn acquiring_again_renews_the_lease_for_the_new_ttl() {
+    let mut nodes = cluster();
+    let first = request(&mut nodes, "c1", "n0", json!({"type": "acquire",
+        "msg_id": 1, "key": "a", "ttl": 10}));
+    let renewed = request(&mut nodes, "c1", "n0", json!({"type": "acquire",
+        "msg_id": 2, "key": "a", "ttl": 60_000}));
+    assert_eq!(renewed["token"], first["token"]);
+    std::thread::sleep(Duration::from_millis(20));
+
+    let reply = request(&mut nodes, "c2", "n0", json!({"type": "acquire",
+        "msg_id": 1, "key": "a", "ttl": 60_000}));
+    assert_eq!(reply["code"], msg::error_code::PRECONDITION_FAILED);
+}
+
+#[test]
+fn replicas_follow_the_membership() {
+    let mut nodes = cluster();
+    let cluster: Vec<String> = ["n0", "n1"].iter()
+        .map(|id| id.to_string())
+        .collect();
+    let mut queue = VecDeque::new();
+    for node in nodes.values_mut() {
+        assert!(node.membership(&cluster));
+        let mut out = Vec::new();
+        node.tick(&mut out).unwrap();
+        for line in String::from_utf8(out).unwrap().lines() {
+            queue.push_back(serde_json::from_str(line).unwrap());
+        }
+    }
+    assert!(deliver(&mut nodes, queue).is_empty());
+    for id in ["n0", "n1"] {
+        assert_eq!(nodes[id].status()["replicas"], json!(cluster));
+    }
+
+    let reply = request(&mut nodes, "c1", "n1", json!({"type": "acquire",
+        "msg_id": 1, "key": "a", "ttl": 60_000}));
+    assert_eq!(reply["type"], "acquire_ok");
+}
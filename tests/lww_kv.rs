@@ -0,0 +1,335 @@
+//! Replication and conflict resolution of the LWW KV
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::lww_kv::LwwKvNode;
+use common::step;
+
+fn node(id: &str) -> LwwKvNode {
+    LwwKvNode::from_init(&msg::Init {
+        node_id:  id.into(),
+        node_ids: vec!["n0".into(), "n1".into()],
+    }, &Config::default()).unwrap()
+}
+
+/// Collect the messages written to `out`
+fn messages(out: Vec<u8>) -> Vec<Value> {
+    String::from_utf8(out).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Run a gossip round on `node` and collect what it sends
+fn tick(node: &mut LwwKvNode) -> Vec<Value> {
+    let mut out = Vec::new();
+    node.tick(&mut out).unwrap();
+    messages(out)
+}
+
+fn write(node: &mut LwwKvNode, dst: &str, key: Value, value: Value) {
+    let out = step(node, json!({"src": "c1", "dest": dst,
+        "body": {"type": "write", "msg_id": 1, "key": key, "value": value}}));
+    assert_eq!(out[0]["body"]["type"], "write_ok");
+}
+
+fn read(node: &mut LwwKvNode, dst: &str, key: Value) -> Value {
+    let out = step(node, json!({"src": "c1", "dest": dst,
+        "body": {"type": "read", "msg_id": 1, "key": key}}));
+    out[0]["body"].clone()
+}
+
+#[test]
+fn writes_replicate() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+
+    write(&mut n0, "n0", json!(3), json!("x"));
+    assert_eq!(read(&mut n1, "n1", json!(3))["code"], 20);
+
+    let gossip = tick(&mut n0);
+    assert_eq!(gossip.len(), 1);
+    assert_eq!(gossip[0]["dest"], "n1");
+    assert!(step(&mut n1, gossip[0].clone()).is_empty());
+    assert_eq!(read(&mut n1, "n1", json!(3))["value"], "x");
+
+    // Nothing changed, nothing is gossiped
+    assert!(tick(&mut n0).is_empty());
+}
+
+#[test]
+fn last_write_wins() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+
+    write(&mut n0, "n0", json!("k"), json!(1));
+    write(&mut n1, "n1", json!("k"), json!(2));
+
+    // Exchange the conflicting writes both ways
+    let from_n0 = tick(&mut n0);
+    let from_n1 = tick(&mut n1);
+    step(&mut n1, from_n0[0].clone());
+    step(&mut n0, from_n1[0].clone());
+
+    let v0 = read(&mut n0, "n0", json!("k"))["value"].clone();
+    let v1 = read(&mut n1, "n1", json!("k"))["value"].clone();
+    assert_eq!(v0, v1);
+}
+
+#[test]
+fn cas_against_local_replica() {
+    let mut n0 = node("n0");
+
+    let cas = |from: i64, to: i64, create: bool| json!({"src": "c1",
+        "dest": "n0", "body": {"type": "cas", "msg_id": 1, "key": 1,
+            "from": from, "to": to, "create_if_not_exists": create}});
+
+    assert_eq!(step(&mut n0, cas(0, 1, false))[0]["body"]["code"], 20);
+    assert_eq!(step(&mut n0, cas(0, 1, true))[0]["body"]["type"], "cas_ok");
+    assert_eq!(step(&mut n0, cas(0, 2, false))[0]["body"]["code"], 22);
+    assert_eq!(step(&mut n0, cas(1, 2, false))[0]["body"]["type"], "cas_ok");
+    assert_eq!(read(&mut n0, "n0", json!(1))["value"], 2);
+}
+
+#[test]
+fn expired_keys_read_as_missing() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+
+    write(&mut n0, "n0", json!(1), json!("old"));
+    step(&mut n1, tick(&mut n0)[0].clone());
+
+    let out = step(&mut n0, json!({"src": "c1", "dest": "n0", "body": {
+        "type": "write", "msg_id": 2, "key": 1, "value": "new",
+        "expires_ms": 0}}));
+    assert_eq!(out[0]["body"]["type"], "write_ok");
+    assert_eq!(read(&mut n0, "n0", json!(1))["code"], 20);
+
+    // The expiry replicates and shadows the older value
+    let gossip = tick(&mut n0);
+    assert!(gossip[0]["body"]["entries"][0]["expires_at"].is_u64());
+    step(&mut n1, gossip[0].clone());
+    assert_eq!(read(&mut n1, "n1", json!(1))["code"], 20);
+
+    // Keys without a TTL never expire
+    write(&mut n0, "n0", json!(2), json!("forever"));
+    assert_eq!(read(&mut n0, "n0", json!(2))["value"], "forever");
+}
+
+#[test]
+fn watchers_are_notified() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+
+    let out = step(&mut n1, json!({"src": "c2", "dest": "n1",
+        "body": {"type": "watch", "msg_id": 1, "key": "w"}}));
+    assert_eq!(out[0]["body"]["type"], "watch_ok");
+
+    // Local writes notify right away
+    let out = step(&mut n1, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "write", "msg_id": 2, "key": "w", "value": 1}}));
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0]["dest"], "c2");
+    assert_eq!(out[0]["body"]["type"], "changed");
+    assert_eq!(out[0]["body"]["value"], 1);
+    let first = out[0]["body"]["version"].as_u64().unwrap();
+
+    // So do replicated ones, but only when they win
+    step(&mut n0, tick(&mut n1)[0].clone());
+    write(&mut n0, "n0", json!("w"), json!(2));
+    let gossip = tick(&mut n0);
+    let out = step(&mut n1, gossip[0].clone());
+    assert_eq!(out[0]["body"]["value"], 2);
+    assert!(out[0]["body"]["version"].as_u64().unwrap() > first);
+    assert!(step(&mut n1, gossip[0].clone()).is_empty());
+
+    // Other keys are not watched
+    assert_eq!(step(&mut n1, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "write", "msg_id": 3, "key": "x", "value": 1}}))
+        .len(), 1);
+}
+
+#[test]
+fn scans_pages_through_keys_in_order() {
+    let mut n0 = node("n0");
+    for key in [json!(5), json!("b"), json!(1), json!("a"), json!(3)] {
+        write(&mut n0, "n0", key.clone(), json!([key]));
+    }
+
+    let scan = |n0: &mut LwwKvNode, body: Value| {
+        let mut body = body;
+        body["type"] = json!("scan");
+        body["msg_id"] = json!(1);
+        step(n0, json!({"src": "c1", "dest": "n0", "body": body}))[0]["body"]
+            .clone()
+    };
+
+    let page = scan(&mut n0, json!({"limit": 2}));
+    assert_eq!(page["type"], "scan_ok");
+    assert_eq!(page["pairs"], json!([[1, [1]], [3, [3]]]));
+    assert_eq!(page["next"], 5);
+
+    let page = scan(&mut n0, json!({"from": page["next"], "limit": 2}));
+    assert_eq!(page["pairs"], json!([[5, [5]], ["a", ["a"]]]));
+    let page = scan(&mut n0, json!({"from": page["next"], "limit": 2}));
+    assert_eq!(page["pairs"], json!([["b", ["b"]]]));
+    assert!(page.get("next").is_none());
+
+    // Bounded on both sides, and empty when inverted
+    let page = scan(&mut n0, json!({"from": 2, "to": "a"}));
+    assert_eq!(page["pairs"], json!([[3, [3]], [5, [5]]]));
+    assert_eq!(scan(&mut n0, json!({"from": 4, "to": 2}))["pairs"], json!([]));
+}
+
+#[test]
+fn sessions_wait_for_the_replica_to_catch_up() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+
+    let out = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 1, "key": 1, "value": "x"}}));
+    let session = out[0]["body"]["session"].clone();
+    assert!(session["n0"].is_u64());
+
+    // n1 has not seen the write yet, so the read waits for it
+    let request = json!({"src": "c1", "dest": "n1", "body": {
+        "type": "read", "msg_id": 2, "key": 1, "session": session}});
+    assert!(step(&mut n1, request.clone()).is_empty());
+
+    let out = step(&mut n1, tick(&mut n0)[0].clone());
+    assert_eq!(out[0]["body"]["in_reply_to"], 2);
+    assert_eq!(out[0]["body"]["value"], "x");
+    let session = out[0]["body"]["session"].clone();
+    assert!(session["n1"].is_u64());
+
+    // Without a session, reads are served by whatever the replica has
+    write(&mut n1, "n1", json!(1), json!("y"));
+    assert_eq!(read(&mut n0, "n0", json!(1))["value"], "x");
+
+    // A replica that missed some gossip waits for anti-entropy
+    let mut n2 = node("n0");
+    let request = json!({"src": "c1", "dest": "n0", "body": {
+        "type": "read", "msg_id": 3, "key": 1, "session": session}});
+    assert!(step(&mut n2, request).is_empty());
+
+    tick(&mut n1);
+    write(&mut n1, "n1", json!(2), json!("z"));
+    assert!(step(&mut n2, tick(&mut n1)[0].clone()).is_empty());
+
+    // Anti-entropy finds the differing bucket and repairs it
+    let digest = (0..10).flat_map(|_| tick(&mut n1)).last().unwrap();
+    assert_eq!(digest["body"]["type"], "digest");
+    let leaves = step(&mut n2, digest);
+    assert_eq!(leaves[0]["body"]["type"], "digest_leaves");
+    let repair = step(&mut n1, leaves[0].clone());
+    assert_eq!(repair[0]["body"]["entries"].as_array().unwrap().len(), 1);
+    let out = step(&mut n2, repair[0].clone());
+    assert_eq!(out[0]["body"]["value"], "y");
+}
+
+#[test]
+fn retried_writes_are_not_applied_again_after_a_restart() {
+    let dir = std::env::temp_dir()
+        .join(format!("maelstrom-lww-kv-replies-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into()],
+    };
+    let mut config = Config::default();
+    config.apply_args(&["--storage-dir".into(),
+        dir.display().to_string()]).unwrap();
+    let cas = json!({"src": "c1", "dest": "n0", "body": {"type": "cas",
+        "msg_id": 7, "key": 1, "from": "a", "to": "b",
+        "create_if_not_exists": true}});
+
+    let mut n0: LwwKvNode = LwwKvNode::from_init(&init, &config).unwrap();
+    assert_eq!(step(&mut n0, cas.clone())[0]["body"]["type"], "cas_ok");
+    drop(n0);
+
+    // The reply got lost and the client retries once the node is back, by
+    // when someone else wrote the key
+    config.restore = true;
+    let mut n0: LwwKvNode = LwwKvNode::from_init(&init, &config).unwrap();
+    write(&mut n0, "n0", json!(1), json!("a"));
+    let retry = step(&mut n0, cas);
+    assert_eq!(retry[0]["body"]["type"], "cas_ok");
+    assert_eq!(retry[0]["body"]["in_reply_to"], 7);
+    assert_eq!(read(&mut n0, "n0", json!(1))["value"], "a");
+
+    // Another request reusing the ID is a request of its own
+    let other = step(&mut n0, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "write", "msg_id": 7, "key": 1, "value": "c"}}));
+    assert_eq!(other[0]["body"]["type"], "write_ok");
+    assert_eq!(read(&mut n0, "n0", json!(1))["value"], "c");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn read_only_replicas_forward_writes_to_the_primaries() {
+    let mut config = Config::default();
+    config.apply_args(&["--primaries".into(), "n0".into()]).unwrap();
+    let init = |id: &str| msg::Init {
+        node_id:  id.into(),
+        node_ids: vec!["n0".into(), "n1".into()],
+    };
+    let mut n0: LwwKvNode = LwwKvNode::from_init(&init("n0"), &config).unwrap();
+    let mut n1: LwwKvNode = LwwKvNode::from_init(&init("n1"), &config)
+        .unwrap();
+    assert_eq!(n1.status()["primaries"], json!(["n0"]));
+    assert_eq!(n0.status()["primaries"], json!([]));
+
+    // n1 hands the write to n0, which answers the client itself
+    let out = step(&mut n1, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "cas", "msg_id": 7, "key": 3, "from": null,
+            "to": "x", "create_if_not_exists": true}}));
+    assert_eq!(out[0]["dest"], "n0");
+    assert_eq!(out[0]["body"]["type"], "forward");
+    assert_eq!(read(&mut n1, "n1", json!(3))["code"], 20);
+
+    let out = step(&mut n0, out[0].clone());
+    assert_eq!(out[0]["dest"], "c1");
+    assert_eq!(out[0]["body"]["type"], "cas_ok");
+    assert_eq!(out[0]["body"]["in_reply_to"], 7);
+
+    // Reads are served by the replica once the write got to it
+    for gossip in tick(&mut n0) {
+        step(&mut n1, gossip);
+    }
+    assert_eq!(read(&mut n1, "n1", json!(3))["value"], "x");
+}
+
+#[test]
+fn writes_may_be_conditional_on_the_version() {
+    let mut n0 = node("n0");
+    let write = |n0: &mut LwwKvNode, id: u64, value: u64, version: &Value| {
+        let out = step(n0, json!({"src": "c1", "dest": "n0",
+            "body": {"type": "write", "msg_id": id, "key": 1,
+                "value": value, "if_version": version}}));
+        out[0]["body"].clone()
+    };
+
+    // Version zero stands for a key that doesn't exist
+    let first = write(&mut n0, 1, 5, &json!(0));
+    assert_eq!(first["type"], "write_ok");
+    assert_eq!(write(&mut n0, 2, 6, &json!(0))["code"], 22);
+
+    let second = write(&mut n0, 3, 6, &first["version"]);
+    assert_eq!(second["type"], "write_ok");
+    assert!(second["version"].as_u64() > first["version"].as_u64());
+    let stale = write(&mut n0, 4, 7, &first["version"]);
+    assert_eq!(stale["code"], 22);
+
+    let read = read(&mut n0, "n0", json!(1));
+    assert_eq!(read["value"], 6);
+    assert_eq!(read["version"], second["version"]);
+}
+
+#[test]
+fn exported_replicas_are_imported_as_if_gossiped() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    write(&mut n0, "n0", json!(1), json!("x"));
+    write(&mut n1, "n1", json!(2), json!("y"));
+
+    let state = n0.export().unwrap().unwrap();
+    assert!(n1.import("n0", state).unwrap());
+    assert_eq!(read(&mut n1, "n1", json!(1))["value"], "x");
+    assert_eq!(read(&mut n1, "n1", json!(2))["value"], "y");
+}
@@ -0,0 +1,46 @@
+//! Nodes joining and leaving the cluster at runtime
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::message as msg;
+use maelstrom::services::{broadcast, echo};
+use common::run;
+
+fn request(msg_id: usize, body: Value) -> Value {
+    let mut body = body;
+    body["msg_id"] = msg_id.into();
+    json!({"src": "c1", "dest": "n1", "body": body})
+}
+
+#[test]
+fn neighbors_follow_the_cluster() {
+    let output = run::<broadcast::Payload, broadcast::BroadcastNode>(&[
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+        request(2, json!({"type": "topology",
+            "topology": {"n1": ["n2"]}})),
+        request(3, json!({"type": "node_join", "node": "n3"})),
+        request(4, json!({"type": "node_leave", "node": "n2"})),
+        request(5, json!({"type": "debug_status"})),
+    ]);
+
+    let types: Vec<_> = output.iter().map(|msg| &msg["body"]["type"])
+        .collect();
+    assert_eq!(types, ["init_ok", "topology_ok", "node_join_ok",
+        "node_leave_ok", "debug_status_ok"]);
+    let status = &output[4]["body"]["service"];
+    assert_eq!(status["neighbors"], json!(["n3"]));
+}
+
+#[test]
+fn fixed_clusters_refuse_changes() {
+    let output = run::<echo::Payload, echo::EchoNode>(&[
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}}),
+        request(2, json!({"type": "node_join", "node": "n2"})),
+    ]);
+    assert_eq!(output[1]["body"]["type"], "error");
+    assert_eq!(output[1]["body"]["code"], msg::error_code::NOT_SUPPORTED);
+    assert_eq!(output[1]["body"]["in_reply_to"], 2);
+}
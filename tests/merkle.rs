@@ -0,0 +1,28 @@
+//! Locating differences between Merkle trees
+
+use maelstrom::merkle::{self, Merkle, FANOUT};
+
+#[test]
+fn differing_buckets_are_found_top_down() {
+    let (mut ours, mut theirs) = (Merkle::new(), Merkle::new());
+    for item in 0..100u64 {
+        let bucket = merkle::bucket(&item);
+        ours.toggle(bucket, merkle::hash(&item));
+        theirs.toggle(bucket, merkle::hash(&item));
+    }
+    assert_eq!(ours, theirs);
+
+    // One item only we have
+    let bucket = merkle::bucket(&"extra");
+    ours.toggle(bucket, merkle::hash(&"extra"));
+
+    let subtrees: Vec<_> = merkle::diff(&ours.top(), &theirs.top()).collect();
+    assert_eq!(subtrees, [bucket / FANOUT]);
+    let leaves: Vec<_> = merkle::diff(ours.leaves(subtrees[0]),
+        theirs.leaves(subtrees[0])).collect();
+    assert_eq!(leaves, [bucket % FANOUT]);
+
+    // Removing it again makes the trees equal
+    ours.toggle(bucket, merkle::hash(&"extra"));
+    assert_eq!(ours.top(), theirs.top());
+}
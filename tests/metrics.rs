@@ -0,0 +1,110 @@
+//! Counting and exposing the metrics of a node
+
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use maelstrom::metrics::{Metrics, Counted, Link};
+
+#[test]
+fn sent_messages_are_counted() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut out = Vec::new();
+    let mut counted = Counted::new(&mut out, metrics.clone());
+    counted.write_all(b"{}\n{}\n{").unwrap();
+    counted.write_all(b"}\n").unwrap();
+    assert_eq!(metrics.sent.load(Ordering::Relaxed), 3);
+
+    Metrics::inc(&metrics.received);
+    let text = metrics.render();
+    assert!(text.contains("# TYPE maelstrom_messages_sent_total counter\n"));
+    assert!(text.contains("maelstrom_messages_sent_total{node=\"n1\"} 3\n"));
+    assert!(text.contains(
+        "maelstrom_messages_received_total{node=\"n1\"} 1\n"));
+}
+
+#[test]
+fn errors_are_counted_and_summed_up() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut out = Vec::new();
+    let mut counted = Counted::new(&mut out, metrics.clone());
+    counted.write_all(b"{\"body\":{\"type\":\"error\",\"code\":11}}\n")
+        .unwrap();
+    counted.write_all(b"{\"body\":{\"type\":\"error_ok\"}}\n").unwrap();
+    assert_eq!(metrics.errors.load(Ordering::Relaxed), 1);
+
+    let summary = metrics.summary();
+    assert_eq!(summary["sent"], 2);
+    assert_eq!(summary["errors"], 1);
+}
+
+#[test]
+fn links_are_rendered_per_neighbor() {
+    let metrics = Metrics::new("n1");
+    assert!(!metrics.render().contains("maelstrom_link"));
+    metrics.set_links([
+        ("n2".into(), Link { sent: 3, acked: 2, retries: 1,
+            synced_ms_ago: Some(1500) }),
+        ("n3".into(), Link { sent: 3, ..Link::default() }),
+    ].into());
+
+    let text = metrics.render();
+    assert!(text.contains(
+        "maelstrom_link_sent_total{node=\"n1\",peer=\"n3\"} 3\n"));
+    assert!(text.contains(
+        "maelstrom_link_retries_total{node=\"n1\",peer=\"n2\"} 1\n"));
+    assert!(text.contains(
+        "maelstrom_link_synced_seconds_ago{node=\"n1\",peer=\"n2\"} 1.5\n"));
+
+    // Neighbors never heard from have no age to tell
+    assert!(!text.contains(
+        "maelstrom_link_synced_seconds_ago{node=\"n1\",peer=\"n3\"}"));
+    assert_eq!(metrics.summary()["links"]["n2"]["acked"], 2);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_are_served_over_http() {
+    use std::io::Read;
+
+    let metrics = Arc::new(Metrics::new("n1"));
+    Metrics::inc(&metrics.ticks);
+    let addr = maelstrom::metrics::serve(metrics, 0).unwrap();
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("maelstrom_ticks_total{node=\"n1\"} 1\n"));
+}
+
+#[test]
+fn debug_status_is_answered_by_the_runtime() {
+    use maelstrom::config::Config;
+    use maelstrom::message as msg;
+    use maelstrom::services::broadcast;
+
+    let input = concat!(
+        r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"#,
+        r#""node_id":"n1","node_ids":["n1"]}}"#, "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":2,"#,
+        r#""message":7}}"#, "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"debug_status","#,
+        r#""msg_id":3}}"#, "\n");
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<broadcast::Payload, broadcast::BroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &Config::default())
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let status: serde_json::Value =
+        serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(status["dest"], "c1");
+    let body = &status["body"];
+    assert_eq!(body["type"], "debug_status_ok");
+    assert_eq!(body["in_reply_to"], 3);
+    assert_eq!(body["node_id"], "n1");
+    assert_eq!(body["received"], 2);
+    assert_eq!(body["sent"], 1);
+    assert_eq!(body["service"]["messages"], 1);
+}
@@ -0,0 +1,86 @@
+//! Ordering of the messages held in the outbox
+
+use std::io::Write;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Node};
+use maelstrom::outbox::{Outbox, Priority};
+use maelstrom::services::echo::Payload;
+
+fn line(body: Value) -> Vec<u8> {
+    let mut line = serde_json::to_vec(&json!({"src": "n0", "dest": "n1",
+        "body": body})).unwrap();
+    line.push(b'\n');
+    line
+}
+
+#[test]
+fn replies_go_out_before_gossip() {
+    let mut out = Vec::new();
+    let mut outbox = Outbox::new(&mut out);
+    let gossip = line(json!({"type": "read", "msg_id": 1}));
+    let reply = line(json!({"type": "write_ok", "in_reply_to": 7}));
+    let ack = line(json!({"type": "ack", "msg_id": 2, "in_reply_to": 3}));
+
+    // Lines may arrive in pieces
+    outbox.write_all(&gossip[..5]).unwrap();
+    outbox.write_all(&gossip[5..]).unwrap();
+    outbox.write_all(&reply).unwrap();
+    outbox.write_all(&ack).unwrap();
+    assert_eq!(outbox.len(), 3);
+    outbox.flush().unwrap();
+    assert!(outbox.is_empty());
+
+    assert_eq!(out, [reply, ack, gossip].concat());
+}
+
+#[test]
+fn priorities_come_from_the_body() {
+    assert_eq!(Priority::of(&line(json!({"in_reply_to": 1}))), Priority::Reply);
+    assert_eq!(Priority::of(&line(json!({"msg_id": 1}))), Priority::Bulk);
+    assert_eq!(Priority::of(b"not json\n"), Priority::Bulk);
+}
+
+/// Node passing every echo on to `n2` before echoing it back
+struct Relay;
+
+impl Node<Payload> for Relay {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let mut input = input;
+        let id = input.body.id;
+        let Payload::Echo { echo } = input.body.payload else { return Ok(()) };
+
+        Message::new("n1", "n2", 100, Payload::Echo { echo: echo.clone() })
+            .send(output)?;
+        input.body.payload = Payload::EchoOk { echo };
+        input.into_reply(id).send(output)
+    }
+}
+
+#[test]
+fn bursts_are_flushed_at_once() {
+    // Whatever arrives ahead of init is surely there right away
+    let echo = |src: &str| json!({"src": src, "dest": "n1",
+        "body": {"type": "echo", "msg_id": 2, "echo": 1}});
+    let input: String = [echo("c1"), echo("c2"),
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+    ].iter().map(|msg| format!("{msg}\n")).collect();
+
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<Payload, Relay>(std::io::Cursor::new(input),
+        &mut output, &Config::default()).unwrap();
+    let dests: Vec<String> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .map(|msg| msg["dest"].as_str().unwrap().to_string())
+        .collect();
+
+    // Both replies go out ahead of what either echo led to
+    assert_eq!(dests, ["c0", "c1", "c2", "n2", "n2"]);
+}
@@ -0,0 +1,58 @@
+use maelstrom::services::{broadcast, distinct, echo};
+use maelstrom::vr;
+
+maelstrom::payload! {
+    /// Payloads of a service declared outside of the crate
+    pub enum Payload {
+        Ping,
+        PingOk {
+            #[serde(default)]
+            count: u64,
+        },
+        Error { code: usize, text: String },
+    }
+}
+
+#[test]
+fn payloads_are_tagged_by_type() {
+    let wire = serde_json::to_string(&Payload::PingOk { count: 2 }).unwrap();
+    assert_eq!(wire, r#"{"type":"ping_ok","count":2}"#);
+
+    let payload: Payload = serde_json::from_str(r#"{"type":"ping_ok"}"#)
+        .unwrap();
+    assert_eq!(payload, Payload::PingOk { count: 0 });
+    assert_eq!(payload.variant(), "PingOk");
+}
+
+#[test]
+fn variants_have_structs_of_their_own() {
+    let ok: Payload = PingOk { count: 2 }.into();
+    assert_eq!(ok, Payload::PingOk { count: 2 });
+    assert_eq!(Payload::from(Ping), Payload::Ping);
+
+    let reply: echo::Payload = echo::EchoOk { echo: "hi".into() }.into();
+    assert!(reply.is_reply());
+}
+
+#[test]
+fn replies_are_told_from_requests() {
+    assert!(!Payload::Ping.is_reply());
+    assert!(Payload::PingOk { count: 0 }.is_reply());
+    assert!(Payload::Error { code: 0, text: String::new() }.is_reply());
+
+    assert!(!broadcast::Payload::Broadcast { message: 1 }.is_reply());
+    assert!(broadcast::Payload::ReadOk {
+        messages: vec![1],
+        upto:     None,
+        epoch:    None,
+    }.is_reply());
+    assert!(distinct::Payload::CountOk { count: 1 }.is_reply());
+    assert!(!distinct::Payload::Gossip {
+        sketch: maelstrom::hll::Hll::new(4),
+    }.is_reply());
+
+    // Generic payloads get the same
+    let ok = vr::Payload::<u64>::PrepareOk { view: 1, op: 2 };
+    assert!(ok.is_reply());
+    assert_eq!(ok.variant(), "PrepareOk");
+}
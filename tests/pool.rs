@@ -0,0 +1,90 @@
+//! Handling messages on the worker pool
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message};
+use maelstrom::pool::{self, Sequencer, SharedNode};
+use maelstrom::services::echo::Payload;
+
+/// Node taking as many milliseconds to echo as it's asked to echo
+struct Sleepy {
+    echoed: AtomicU64,
+}
+
+impl SharedNode<Payload> for Sleepy {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self { echoed: AtomicU64::new(0) })
+    }
+
+    fn step(&self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let mut input = input;
+        let id = input.body.id;
+        let Payload::Echo { echo } = input.body.payload else { return Ok(()) };
+
+        std::thread::sleep(Duration::from_millis(echo.as_u64().unwrap()));
+        self.echoed.fetch_add(1, Ordering::Relaxed);
+        input.body.payload = Payload::EchoOk { echo };
+        input.into_reply(id).send(output)
+    }
+
+    fn shutdown(&self, output: &mut dyn Write) -> anyhow::Result<()> {
+        writeln!(output, "{}", self.echoed.load(Ordering::Relaxed))?;
+        Ok(())
+    }
+}
+
+fn echo(src: &str, id: u64, millis: u64) -> Value {
+    json!({"src": src, "dest": "n1", "body": {"type": "echo", "msg_id": id,
+        "echo": millis}})
+}
+
+#[test]
+fn replies_keep_the_order_of_each_client() {
+    let input: String = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}}),
+        echo("c1", 1, 200),
+        echo("c1", 2, 0),
+        echo("c2", 1, 0),
+    ].iter().map(|msg| format!("{msg}\n")).collect();
+
+    let mut output = Vec::new();
+    pool::main_loop_with_io::<Payload, Sleepy>(std::io::Cursor::new(input),
+        &mut output, &Config::default()).unwrap();
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    // c2 isn't held up by the slow echo of c1, but c1's second one is
+    let replies: Vec<(&str, u64)> = output[1..4].iter()
+        .map(|reply| (reply["dest"].as_str().unwrap(),
+            reply["body"]["in_reply_to"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(replies, [("c2", 1), ("c1", 1), ("c1", 2)]);
+
+    // Everything was handled before the shutdown
+    assert_eq!(output[4], 3);
+}
+
+#[test]
+fn sequencers_release_in_order_per_sender() {
+    let mut sequencer = Sequencer::default();
+    let a1 = sequencer.start("a");
+    let b1 = sequencer.start("b");
+    let a2 = sequencer.start("a");
+
+    let mut out = Vec::new();
+    sequencer.finish(a2, "a", b"a2\n".to_vec(), &mut out).unwrap();
+    sequencer.finish(b1, "b", b"b1\n".to_vec(), &mut out).unwrap();
+    assert_eq!(out, b"b1\n");
+    assert_eq!(sequencer.len(), 2);
+
+    sequencer.finish(a1, "a", b"a1\n".to_vec(), &mut out).unwrap();
+    assert_eq!(out, b"b1\na1\na2\n");
+    assert!(sequencer.is_empty());
+}
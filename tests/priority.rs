@@ -0,0 +1,58 @@
+//! Handling the messages setting the node up ahead of the rest
+
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message as msg;
+use maelstrom::services::broadcast;
+
+#[test]
+fn topology_is_handled_ahead_of_queued_broadcasts() {
+    // Whatever arrives ahead of init is queued up all at once
+    let mut msgs: Vec<Value> = (1..=3).map(|id| json!({"src": "c1",
+        "dest": "n1", "body": {"type": "broadcast", "msg_id": id,
+        "message": id}})).collect();
+    msgs.push(json!({"src": "c1", "dest": "n1", "body": {"type": "topology",
+        "msg_id": 4, "topology": {"n1": ["n2"], "n2": ["n1"]}}}));
+    msgs.push(json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+        "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}));
+    let input: String = msgs.iter().map(|msg| format!("{msg}\n")).collect();
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<broadcast::Payload, broadcast::BroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &Config::default())
+        .unwrap();
+
+    let replies: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|msg| msg["dest"] == "c1")
+        .map(|msg| msg["body"]["in_reply_to"].clone())
+        .collect();
+    assert_eq!(replies, [json!(4), json!(1), json!(2), json!(3)]);
+}
+
+#[test]
+fn topology_in_a_batch_is_handled_ahead_of_queued_broadcasts() {
+    // Another node coalesced the topology into a batch of its stream
+    let mut msgs: Vec<Value> = (1..=100).map(|id| json!({"src": "c1",
+        "dest": "n1", "body": {"type": "broadcast", "msg_id": id,
+        "message": id}})).collect();
+    msgs.push(json!({"src": "n2", "dest": "n1", "body": {
+        "type": "stream_batch", "stream": 7, "seq": 1, "messages": [
+            {"src": "n2", "dest": "n1", "body": {"type": "topology",
+            "msg_id": 101, "topology": {"n1": ["n2"], "n2": ["n1"]}}},
+        ]}}));
+    msgs.push(json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+        "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}));
+    let input: String = msgs.iter().map(|msg| format!("{msg}\n")).collect();
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<broadcast::Payload, broadcast::BroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &Config::default())
+        .unwrap();
+
+    let replies: Vec<u64> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|msg| msg["dest"] != "c0")
+        .filter_map(|msg| msg["body"]["in_reply_to"].as_u64())
+        .collect();
+    assert_eq!(replies.len(), 101);
+    assert_eq!(replies[0], 101);
+}
@@ -0,0 +1,32 @@
+//! Logging the replies to client requests, to answer their retries
+
+use serde_json::{json, Value};
+use maelstrom::replies::Replies;
+
+#[test]
+fn retries_get_the_reply_logged() {
+    let mut replies = Replies::<Value>::open(None, "replies", false).unwrap();
+    let write = json!({"type": "write", "key": 1, "value": 2});
+    assert!(replies.get("c1", 1, &write).is_none());
+
+    replies.insert("c1", 1, &write, json!({"type": "write_ok"})).unwrap();
+    assert_eq!(replies.get("c1", 1, &write),
+        Some(&json!({"type": "write_ok"})));
+
+    // Other clients and other requests under the same ID aren't retries
+    assert!(replies.get("c2", 1, &write).is_none());
+    assert!(replies.get("c1", 1, &json!({"type": "write", "key": 1,
+        "value": 3})).is_none());
+}
+
+#[test]
+fn only_the_latest_replies_of_a_client_are_kept() {
+    let mut replies = Replies::<Value>::open(None, "replies", false).unwrap();
+    for id in 0..100 {
+        replies.insert("c1", id, &id, json!(id)).unwrap();
+    }
+    replies.insert("c2", 0, &0, json!(0)).unwrap();
+    assert_eq!(replies.len(), 65);
+    assert!(replies.get("c1", 0, &0).is_none());
+    assert_eq!(replies.get("c1", 99, &99), Some(&json!(99)));
+}
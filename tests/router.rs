@@ -0,0 +1,61 @@
+//! Routes client requests through a local cluster of real node processes
+
+use std::io::Cursor;
+use std::time::Duration;
+use serde_json::Value;
+use maelstrom::router::{self, Options};
+use maelstrom::nemesis::Scenario;
+
+/// Route `input` through `nodes` nodes of `service` and collect the output
+fn route(service: &str, nodes: usize, scenario: Option<Scenario>,
+        input: &[&str]) -> Vec<Value> {
+    let opts = Options {
+        nodes,
+        timeout: Duration::from_secs(1),
+        linger:  Duration::from_millis(100),
+        scenario,
+        cmd:     vec![env!("CARGO_BIN_EXE_maelstrom").into(), service.into()],
+    };
+
+    let input = Cursor::new(input.join("\n").into_bytes());
+    let mut output = Vec::new();
+    router::run(opts, input, &mut output).unwrap();
+
+    String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn replies_reach_clients() {
+    let mut out = route("echo", 3, None, &[
+        r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":1,"echo":"a"}}"#,
+        r#"{"src":"c2","dest":"n2","body":{"type":"echo","msg_id":1,"echo":"b"}}"#,
+    ]);
+
+    // Replies of different nodes may arrive in any order
+    out.sort_by_key(|msg| msg["dest"].as_str().unwrap().to_string());
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0]["src"], "n0");
+    assert_eq!(out[0]["dest"], "c1");
+    assert_eq!(out[0]["body"]["echo"], "a");
+    assert_eq!(out[1]["src"], "n2");
+    assert_eq!(out[1]["dest"], "c2");
+    assert_eq!(out[1]["body"]["echo"], "b");
+}
+
+#[test]
+fn killed_nodes_do_not_reply() {
+    let scenario: Scenario = serde_json::from_str(r#"{
+        "seed": 1,
+        "events": [{ "at_ms": 0, "type": "kill", "node": "n1" }]
+    }"#).unwrap();
+
+    let out = route("echo", 2, Some(scenario), &[
+        r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"a"}}"#,
+        r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":2,"echo":"b"}}"#,
+    ]);
+
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["src"], "n0");
+}
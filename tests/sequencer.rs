@@ -0,0 +1,191 @@
+//! Drives sequencer nodes through their lin-kv conversations by hand
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::sequencer::{Payload, SequencerNode};
+use common::step;
+
+fn node(id: &str) -> SequencerNode {
+    SequencerNode::from_init(&msg::Init {
+        node_id:  id.into(),
+        node_ids: vec!["n1".into(), "n0".into()],
+    }, &Config::default()).unwrap()
+}
+
+#[test]
+fn leader_checkpoints_before_replying() {
+    let mut leader = node("n0");
+
+    // The leader first finds out where the sequence stands
+    let out = step(&mut leader, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "next", "msg_id": 1}}));
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["dest"], "lin-kv");
+    assert_eq!(out[0]["body"]["type"], "read");
+    let read_id = out[0]["body"]["msg_id"].clone();
+
+    // Another client queues up in the meantime
+    assert!(step(&mut leader, json!({"src": "c2", "dest": "n0",
+        "body": {"type": "next", "msg_id": 5}})).is_empty());
+
+    // Nothing was allocated yet, both numbers are checkpointed at once
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "error", "in_reply_to": read_id, "code": 20,
+            "text": "not found"}}));
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["body"]["type"], "cas");
+    assert_eq!(out[0]["body"]["from"], 0);
+    assert_eq!(out[0]["body"]["to"], 2);
+    let cas_id = out[0]["body"]["msg_id"].clone();
+
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "cas_ok", "in_reply_to": cas_id}}));
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0]["dest"], "c1");
+    assert_eq!(out[0]["body"]["in_reply_to"], 1);
+    assert_eq!(out[0]["body"]["seq"], 1);
+    assert_eq!(out[1]["dest"], "c2");
+    assert_eq!(out[1]["body"]["in_reply_to"], 5);
+    assert_eq!(out[1]["body"]["seq"], 2);
+}
+
+#[test]
+fn conflicting_checkpoint_rereads() {
+    let mut leader = node("n0");
+    let out = step(&mut leader, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "next", "msg_id": 1}}));
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "read_ok", "in_reply_to": out[0]["body"]["msg_id"],
+            "value": 7}}));
+    assert_eq!(out[0]["body"]["from"], 7);
+
+    // Someone else allocated in the meantime
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "error", "in_reply_to": out[0]["body"]["msg_id"],
+            "code": 22, "text": "expected 7"}}));
+    assert_eq!(out[0]["body"]["type"], "read");
+}
+
+#[test]
+fn lost_checkpoints_are_recovered_by_rereading() {
+    let config = Config {
+        retry_timeout: std::time::Duration::from_millis(20),
+        ..Config::default()
+    };
+    let mut leader = SequencerNode::from_init(&msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into()],
+    }, &config).unwrap();
+    let out = step(&mut leader, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "next", "msg_id": 1}}));
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "read_ok", "in_reply_to": out[0]["body"]["msg_id"],
+            "value": 7}}));
+    assert_eq!(out[0]["body"]["to"], 8);
+
+    // The cas_ok is lost, the sequence is read again once the CAS times out
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    let mut out = Vec::new();
+    leader.tick(&mut out).unwrap();
+    let read: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(read["body"]["type"], "read");
+
+    // It's where the CAS would have left it, so the number was ours
+    let out = step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "read_ok", "in_reply_to": read["body"]["msg_id"],
+            "value": 8}}));
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["dest"], "c1");
+    assert_eq!(out[0]["body"]["seq"], 8);
+    assert_eq!(leader.status()["current"], 8);
+}
+
+#[test]
+fn follower_forwards_to_leader() {
+    let mut follower = node("n1");
+
+    let out = step(&mut follower, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "next", "msg_id": 3}}));
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["dest"], "n0");
+    assert_eq!(out[0]["body"]["type"], "next");
+
+    let out = step(&mut follower, json!({"src": "n0", "dest": "n1",
+        "body": {"type": "next_ok", "in_reply_to": out[0]["body"]["msg_id"],
+            "seq": 9}}));
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["dest"], "c1");
+    assert_eq!(out[0]["body"]["in_reply_to"], 3);
+    assert_eq!(out[0]["body"]["seq"], 9);
+}
+
+#[test]
+fn batch_window_holds_allocation_back() {
+    let config = Config {
+        batch_window: std::time::Duration::from_millis(50),
+        ..Config::default()
+    };
+    let mut leader = SequencerNode::from_init(&msg::Init {
+        node_id:  "n0".into(),
+        node_ids: vec!["n0".into()],
+    }, &config).unwrap();
+
+    let out = step(&mut leader, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "next", "msg_id": 1}}));
+    assert!(step(&mut leader, json!({"src": "lin-kv", "dest": "n0",
+        "body": {"type": "read_ok", "in_reply_to": out[0]["body"]["msg_id"],
+            "value": 0}})).is_empty());
+
+    // Once the window closes, the batch is allocated for on the next tick
+    std::thread::sleep(std::time::Duration::from_millis(60));
+    let mut out = Vec::new();
+    leader.tick(&mut out).unwrap();
+    let cas: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(cas["body"]["type"], "cas");
+    assert_eq!(cas["body"]["to"], 1);
+}
+
+#[test]
+fn deadlines_are_forwarded_and_expired_requests_dropped() {
+    let later = msg::now_ms() + 60_000;
+
+    // The follower passes the deadline of the client on to the leader
+    let mut follower = node("n1");
+    let out = step(&mut follower, json!({"src": "c1", "dest": "n1",
+        "body": {"type": "next", "msg_id": 3, "deadline_ms": later}}));
+    assert_eq!(out[0]["dest"], "n0");
+    assert_eq!(out[0]["body"]["deadline_ms"], later);
+
+    // The leader doesn't allocate for requests that already expired
+    let mut leader = node("n0");
+    let out = step(&mut leader, json!({"src": "c1", "dest": "n0",
+        "body": {"type": "next", "msg_id": 1, "deadline_ms": 1}}));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn overloaded_leader_turns_clients_away() {
+    let mut input = String::from(r#"{"src":"c0","dest":"n0","body":{"#);
+    input += r#""type":"init","msg_id":1,"node_id":"n0","node_ids":["n0"]}}"#;
+    input += "\n";
+    for id in 1..=1025 {
+        input += &format!("{}\n", json!({"src": "c1", "dest": "n0",
+            "body": {"type": "next", "msg_id": id}}));
+    }
+
+    // The lin-kv never answers, so every request but the last one queues up
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<Payload, SequencerNode>(
+        std::io::Cursor::new(input), &mut output, &Config::default())
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let last: Value = serde_json::from_str(output.lines().last().unwrap())
+        .unwrap();
+    assert_eq!(last["dest"], "c1");
+    assert_eq!(last["body"]["type"], "error");
+    assert_eq!(last["body"]["code"], msg::error_code::TEMPORARILY_UNAVAILABLE);
+    assert_eq!(last["body"]["in_reply_to"], 1025);
+}
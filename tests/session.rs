@@ -0,0 +1,36 @@
+//! Leases and fencing tokens of the client sessions
+
+use maelstrom::session::Sessions;
+
+#[test]
+fn leases_expire_unless_kept_alive() {
+    let mut sessions = Sessions::<u64>::new();
+    let a = sessions.open(100, 1000);
+    let b = sessions.open(100, 1000);
+    assert!(b > a);
+
+    sessions.keep_alive(a, 1080).unwrap();
+    sessions.expire(1150);
+    assert_eq!(sessions.lease(a).unwrap().expires_ms, 1180);
+    assert!(sessions.lease(b).is_none());
+    assert!(sessions.keep_alive(b, 1150).is_err());
+    assert_eq!(sessions.len(), 1);
+}
+
+#[test]
+fn stale_tokens_are_fenced_off() {
+    let mut sessions = Sessions::new();
+    let old = sessions.open(100, 1000);
+    sessions.fence("lock", old, 1000).unwrap();
+
+    // A later session writes the resource, the old one may no longer
+    let new = sessions.open(100, 1010);
+    sessions.fence("lock", new, 1010).unwrap();
+    assert!(sessions.fence("lock", old, 1020).is_err());
+    sessions.fence("other", old, 1020).unwrap();
+
+    // Neither may an expired one, nor a closed one
+    assert!(sessions.fence("other", old, 1200).is_err());
+    sessions.close(new);
+    assert!(sessions.fence("lock", new, 1020).is_err());
+}
@@ -0,0 +1,99 @@
+//! Broadcast on the worker pool, around a shared seen set
+
+use std::sync::Arc;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message as msg;
+use maelstrom::pool::{self, SharedNode};
+use maelstrom::services::broadcast::{Payload, SeenSet};
+use maelstrom::services::shared_broadcast::SharedBroadcastNode;
+
+#[test]
+fn seen_sets_are_shared_between_threads() {
+    let seen = Arc::new(SeenSet::default());
+    let threads: Vec<_> = (0..4).map(|thread| {
+        let seen = seen.clone();
+        std::thread::spawn(move || {
+            (0..100).filter(|message| seen.insert(thread % 2 * 50 + message))
+                .count()
+        })
+    }).collect();
+    let inserted: usize = threads.into_iter()
+        .map(|thread| thread.join().unwrap())
+        .sum();
+
+    assert_eq!(inserted, 150);
+    assert_eq!(seen.len(), 150);
+    assert!(seen.contains(149) && !seen.contains(150));
+    let mut all = Vec::new();
+    seen.for_each(|message| all.push(message));
+    all.sort_unstable();
+    assert_eq!(all, (0..150).collect::<Vec<_>>());
+}
+
+#[test]
+fn shared_nodes_broadcast_and_read() {
+    let client = |id: u64, body: Value| {
+        let mut body = body;
+        body["msg_id"] = id.into();
+        json!({"src": "c1", "dest": "n1", "body": body})
+    };
+    let input: String = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}}),
+        client(1, json!({"type": "broadcast", "message": 4})),
+        client(2, json!({"type": "broadcast", "message": 4})),
+        client(3, json!({"type": "broadcast", "message": 2})),
+        client(4, json!({"type": "read"})),
+    ].iter().map(|msg| format!("{msg}\n")).collect();
+
+    // Replies are in order either way, but a single worker also handles the
+    // read only after the broadcasts, as a client waiting for them would
+    let config = Config { workers: 1, ..Config::default() };
+    let mut output = Vec::new();
+    pool::main_loop_with_io::<Payload, SharedBroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &config).unwrap();
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let types: Vec<&str> = output.iter()
+        .map(|msg| msg["body"]["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["init_ok", "broadcast_ok", "broadcast_ok",
+        "broadcast_ok", "read_ok"]);
+    assert_eq!(output[4]["body"]["messages"], json!([4, 2]));
+}
+
+#[test]
+fn gossip_runs_on_a_thread_of_its_own() {
+    let config = Config {
+        gossip_interval: std::time::Duration::from_millis(10),
+        ..Config::default()
+    };
+    let node = SharedBroadcastNode::from_init(&msg::Init {
+        node_id:  "n1".into(),
+        node_ids: vec!["n1".into(), "n2".into()],
+    }, &config).unwrap();
+    for (id, body) in [(1, json!({"type": "topology",
+            "topology": {"n1": ["n2"]}})),
+            (2, json!({"type": "broadcast", "message": 4}))] {
+        let mut body = body;
+        body["msg_id"] = id.into();
+        let msg = json!({"src": "c1", "dest": "n1", "body": body});
+        node.step(serde_json::from_value(msg).unwrap(), &mut Vec::new())
+            .unwrap();
+    }
+
+    // The gossip thread read from n2 meanwhile, and the tick sends it out
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let mut out = Vec::new();
+    node.tick(&mut out).unwrap();
+    let reads: Vec<Value> = String::from_utf8(out).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(!reads.is_empty());
+    assert_eq!(reads[0]["dest"], "n2");
+    assert_eq!(reads[0]["body"]["type"], "read");
+    assert_eq!(reads[0]["body"]["messages"], json!([4]));
+}
@@ -0,0 +1,57 @@
+//! Nodes wrapping up once their input runs out
+
+use std::io::Write;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message, Node};
+use maelstrom::services::echo::Payload;
+
+/// Node holding every echo back until it shuts down
+struct Lazy {
+    held: Vec<Message<Payload>>,
+}
+
+impl Node<Payload> for Lazy {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self { held: Vec::new() })
+    }
+
+    fn step(&mut self, input: Message<Payload>, _output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        self.held.push(input);
+        Ok(())
+    }
+
+    fn shutdown(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        for mut input in self.held.drain(..) {
+            let id = input.body.id;
+            let Payload::Echo { echo } = input.body.payload else { continue };
+            input.body.payload = Payload::EchoOk { echo };
+            input.into_reply(id).send(output)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn nodes_shut_down_at_the_end_of_input() {
+    let input: String = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+            "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "echo",
+            "msg_id": 2, "echo": "late"}}),
+    ].iter().map(|msg| format!("{msg}\n")).collect();
+
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<Payload, Lazy>(std::io::Cursor::new(input),
+        &mut output, &Config::default()).unwrap();
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[1]["dest"], "c1");
+    assert_eq!(output[1]["body"]["echo"], "late");
+    assert_eq!(output[1]["body"]["in_reply_to"], 2);
+}
@@ -0,0 +1,74 @@
+//! Signatures of the messages between the nodes
+#![cfg(feature = "hmac")]
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use serde_json::{json, Value};
+use maelstrom::sign::{self, Signer, Verifier};
+
+const SECRET: &[u8] = b"hunter2";
+
+fn nodes() -> sign::Nodes {
+    Rc::new(RefCell::new(["n1".into(), "n2".into()].into()))
+}
+
+/// Write `msg` through a signer and return what was written
+fn signed(msg: &Value) -> String {
+    let mut signer = Signer::new(Vec::new(), Some(SECRET.to_vec()), nodes());
+    writeln!(signer, "{msg}").unwrap();
+    String::from_utf8(signer.inner_mut().clone()).unwrap()
+}
+
+#[test]
+fn messages_between_nodes_are_signed_and_verified() {
+    let msg = json!({"src": "n1", "dest": "n2",
+        "body": {"type": "read", "msg_id": 1}});
+    let line = signed(&msg);
+    let signature: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(signature["body"]["hmac"].as_str().unwrap().len(), 64);
+
+    let verifier = Verifier::new(Some(SECRET.to_vec()), nodes());
+    let verified = verifier.verify(line.trim_end()).unwrap().unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&verified).unwrap(), msg);
+
+    // Some other secret doesn't verify it
+    let other = Verifier::new(Some(b"hunter3".to_vec()), nodes());
+    assert!(other.verify(line.trim_end()).is_err());
+}
+
+#[test]
+fn forged_and_unsigned_messages_are_rejected() {
+    let verifier = Verifier::new(Some(SECRET.to_vec()), nodes());
+    let mut msg = json!({"src": "n1", "dest": "n2",
+        "body": {"type": "broadcast", "message": 1}});
+    assert!(verifier.verify(&msg.to_string()).is_err());
+
+    sign::sign(SECRET, &mut msg);
+    msg["body"]["message"] = 2.into();
+    assert!(verifier.verify(&msg.to_string()).is_err());
+}
+
+#[test]
+fn clients_are_left_alone() {
+    let reply = json!({"src": "n1", "dest": "c1",
+        "body": {"type": "read_ok", "in_reply_to": 1}});
+    assert_eq!(signed(&reply), format!("{reply}\n"));
+
+    let verifier = Verifier::new(Some(SECRET.to_vec()), nodes());
+    let request = json!({"src": "c1", "dest": "n1",
+        "body": {"type": "read", "msg_id": 1}});
+    assert!(verifier.verify(&request.to_string()).unwrap().is_none());
+}
+
+#[test]
+fn nodes_joining_later_are_verified_too() {
+    let nodes = nodes();
+    let verifier = Verifier::new(Some(SECRET.to_vec()), nodes.clone());
+    let msg = json!({"src": "n3", "dest": "n1",
+        "body": {"type": "broadcast", "message": 1}});
+    assert!(verifier.verify(&msg.to_string()).unwrap().is_none());
+
+    nodes.borrow_mut().insert("n3".into());
+    assert!(verifier.verify(&msg.to_string()).is_err());
+}
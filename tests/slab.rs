@@ -0,0 +1,34 @@
+//! Retaining messages in slots that are reused
+
+use maelstrom::slab::Slab;
+
+#[test]
+fn freed_slots_are_reused() {
+    let mut slab = Slab::default();
+    let first = slab.insert(b"first\n");
+    let second = slab.insert(b"second\n");
+    assert_eq!(slab.get(first), b"first\n");
+    assert_eq!((slab.len(), slab.slots()), (2, 2));
+
+    slab.remove(first);
+    let third = slab.insert(b"third\n");
+    assert_eq!(third, first);
+    assert_eq!(slab.get(third), b"third\n");
+    assert_eq!(slab.get(second), b"second\n");
+    assert_eq!((slab.len(), slab.slots()), (2, 2));
+}
+
+#[test]
+fn slots_failing_to_fill_are_freed() {
+    let mut slab = Slab::default();
+    let failed = slab.insert_with(|buf| {
+        buf.extend_from_slice(b"partial");
+        Err("full")
+    });
+    assert_eq!(failed, Err("full"));
+    assert!(slab.is_empty());
+
+    let slot = slab.insert(b"whole\n");
+    assert_eq!(slab.get(slot), b"whole\n");
+    assert_eq!(slab.slots(), 1);
+}
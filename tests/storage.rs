@@ -0,0 +1,123 @@
+//! Both storage backends behave as the same append-only log, and both
+//! storage engines as the same ordered KV
+
+use std::ops::Bound;
+use maelstrom::storage::{Storage, MemStorage, FileStorage};
+use maelstrom::storage::{StorageEngine, MemEngine, LogEngine};
+
+/// Append a few items to `log` and read them back
+fn roundtrip(log: &mut dyn Storage<String>) {
+    assert!(log.is_empty());
+    for item in ["a", "b", "c"] {
+        log.append(item.into()).unwrap();
+    }
+    assert_eq!(log.len(), 3);
+
+    let mut items = Vec::new();
+    log.for_each(&mut |item| items.push(item)).unwrap();
+    assert_eq!(items, ["a", "b", "c"]);
+
+    // Appending after reading keeps the order
+    log.append("d".into()).unwrap();
+    let mut items = Vec::new();
+    log.for_each(&mut |item| items.push(item)).unwrap();
+    assert_eq!(items, ["a", "b", "c", "d"]);
+}
+
+#[test]
+fn memory() {
+    roundtrip(&mut MemStorage::default());
+}
+
+#[test]
+fn file() {
+    let path = std::env::temp_dir()
+        .join(format!("maelstrom-storage-{}.jsonl", std::process::id()));
+    roundtrip(&mut FileStorage::create(&path).unwrap());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(),
+        "\"a\"\n\"b\"\n\"c\"\n\"d\"\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Put, overwrite, remove and scan a few keys of `engine`
+fn kv(engine: &mut impl StorageEngine<u64, String>) {
+    for key in [3, 1, 2] {
+        assert_eq!(engine.put(key, format!("v{key}")).unwrap(), None);
+    }
+    assert_eq!(engine.put(2, "w2".into()).unwrap(), Some("v2".into()));
+    assert_eq!(engine.get(&2).unwrap(), Some("w2".into()));
+    assert_eq!(engine.remove(&1).unwrap(), Some("v1".into()));
+    assert_eq!(engine.get(&1).unwrap(), None);
+
+    assert_eq!(engine.cas(3, Some(&"v2".into()), "x".into()).unwrap(),
+        Err(Some("v3".into())));
+    assert_eq!(engine.cas(3, Some(&"v3".into()), "x".into()).unwrap(), Ok(()));
+    assert_eq!(engine.cas(4, None, "y".into()).unwrap(), Ok(()));
+
+    assert_eq!(engine.snapshot().unwrap(), [
+        (2, "w2".into()), (3, "x".into()), (4, "y".into())]);
+    let mut keys = Vec::new();
+    engine.scan((Bound::Excluded(&2), Bound::Unbounded), &mut |key, _| {
+        keys.push(key);
+        false
+    }).unwrap();
+    assert_eq!(keys, [3]);
+}
+
+#[test]
+fn memory_engine() {
+    kv(&mut MemEngine::open(None, "mem").unwrap());
+}
+
+#[test]
+fn log_engine() {
+    let path = std::env::temp_dir()
+        .join(format!("maelstrom-engine-{}.jsonl", std::process::id()));
+    kv(&mut LogEngine::create(&path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn file_is_restored() {
+    let path = std::env::temp_dir()
+        .join(format!("maelstrom-restore-{}.jsonl", std::process::id()));
+    let mut log = FileStorage::create(&path).unwrap();
+    for item in ["a", "b"] {
+        log.append(item.to_string()).unwrap();
+    }
+    drop(log);
+
+    // A crash cut the last item short
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path)
+        .unwrap();
+    std::io::Write::write_all(&mut file, b"\"c").unwrap();
+
+    let mut log = FileStorage::<String>::reopen(&path).unwrap();
+    assert_eq!(log.len(), 2);
+    log.append("d".into()).unwrap();
+    let mut items = Vec::new();
+    log.for_each(&mut |item| items.push(item)).unwrap();
+    assert_eq!(items, ["a", "b", "d"]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn log_engine_is_restored() {
+    let dir = std::env::temp_dir();
+    let name = format!("maelstrom-engine-restore-{}", std::process::id());
+    let mut engine: LogEngine<u64, String> =
+        StorageEngine::open(Some(&dir), &name).unwrap();
+    engine.put(1, "a".into()).unwrap();
+    engine.put(2, "b".into()).unwrap();
+    engine.put(1, "c".into()).unwrap();
+    engine.remove(&2).unwrap();
+    drop(engine);
+
+    let mut engine: LogEngine<u64, String> =
+        StorageEngine::restore(Some(&dir), &name).unwrap();
+    assert_eq!(engine.snapshot().unwrap(), [(1, "c".into())]);
+    engine.put(3, "d".into()).unwrap();
+    assert_eq!(engine.get(&1).unwrap(), Some("c".into()));
+    assert_eq!(engine.get(&3).unwrap(), Some("d".into()));
+    std::fs::remove_file(dir.join(format!("{name}.jsonl"))).unwrap();
+}
@@ -0,0 +1,265 @@
+//! Numbered streams of messages between the nodes, delivered exactly once
+
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::metrics::Metrics;
+use maelstrom::stream::{Streamer, Streams};
+
+fn retrying(retry: Option<Duration>) -> Streamer<Vec<u8>> {
+    Streamer::new(Vec::new(), retry, None, &["n1".into(), "n2".into()],
+        Arc::new(Metrics::new("n1")))
+}
+
+fn lines(streamer: &Streamer<Vec<u8>>) -> Vec<Value> {
+    String::from_utf8(streamer.inner().clone()).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn gossip(dest: &str, message: usize) -> String {
+    format!("{{\"src\":\"n1\",\"dest\":\"{dest}\",\"body\":{{\
+        \"type\":\"broadcast\",\"message\":{message}}}}}\n")
+}
+
+#[test]
+fn messages_to_nodes_are_numbered_until_acked() {
+    let mut streamer = retrying(Some(Duration::from_secs(60)));
+    for message in 1..=3 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    streamer.write_all(gossip("c1", 4).as_bytes()).unwrap();
+
+    let sent = lines(&streamer);
+    assert_eq!(sent[2]["body"]["seq"], 3);
+    assert_eq!(sent[0]["body"]["stream"], sent[2]["body"]["stream"]);
+    assert!(sent[3]["body"].get("seq").is_none(), "clients aren't numbered");
+    assert_eq!(streamer.unacked(), 3);
+
+    // Acks of other streams are of our earlier runs
+    let stream = sent[0]["body"]["stream"].as_u64().unwrap();
+    streamer.ack("n2", stream ^ 1, 3).unwrap();
+    assert_eq!(streamer.unacked(), 3);
+    streamer.ack("n2", stream, 2).unwrap();
+    assert_eq!(streamer.unacked(), 1);
+}
+
+#[test]
+fn acked_messages_free_their_slots() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut streamer = Streamer::new(Vec::new(),
+        Some(Duration::from_secs(60)), None, &["n2".into()],
+        metrics.clone());
+    let occupancy = || (metrics.retained.load(Ordering::Relaxed),
+        metrics.slots.load(Ordering::Relaxed));
+    for message in 1..=3 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    assert_eq!(occupancy(), (3, 3));
+
+    // The slots freed by the ack are taken by what's sent next
+    let stream = lines(&streamer)[0]["body"]["stream"].as_u64().unwrap();
+    streamer.ack("n2", stream, 2).unwrap();
+    assert_eq!(occupancy(), (1, 3));
+    for message in 4..=5 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    assert_eq!(occupancy(), (3, 3));
+}
+
+#[test]
+fn unacked_messages_are_sent_again() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut streamer = Streamer::new(Vec::new(),
+        Some(Duration::from_millis(10)), None, &["n2".into()],
+        metrics.clone());
+    streamer.write_all(gossip("n2", 1).as_bytes()).unwrap();
+    assert!(streamer.next_deadline().is_some());
+
+    std::thread::sleep(Duration::from_millis(20));
+    streamer.release_due().unwrap();
+    let sent = lines(&streamer);
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0], sent[1]);
+    assert_eq!(metrics.retransmitted.load(Ordering::Relaxed), 1);
+
+    // Without a retry, nothing is numbered nor kept
+    let mut plain = retrying(None);
+    plain.write_all(gossip("n2", 1).as_bytes()).unwrap();
+    assert!(lines(&plain)[0]["body"].get("seq").is_none());
+    assert_eq!(plain.unacked(), 0);
+}
+
+#[test]
+fn streams_let_every_message_through_once() {
+    let mut streamer = retrying(Some(Duration::from_secs(60)));
+    for message in 1..=3 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    let sent: Vec<String> = lines(&streamer).iter()
+        .map(Value::to_string)
+        .collect();
+
+    // Out of order, and duplicated on the way
+    let mut streams = Streams::default();
+    let received: Vec<Value> = [&sent[0], &sent[2], &sent[0], &sent[1],
+            &sent[2]].into_iter()
+        .filter_map(|line| streams.receive(line.clone()))
+        .map(|line| serde_json::from_str(&line).unwrap())
+        .collect();
+    let messages: Vec<&Value> = received.iter()
+        .map(|msg| &msg["body"]["message"])
+        .collect();
+    assert_eq!(messages, [&json!(1), &json!(3), &json!(2)]);
+    assert!(received[0]["body"].get("seq").is_none());
+
+    let stream = lines(&streamer)[0]["body"]["stream"].as_u64().unwrap();
+    assert_eq!(streams.acks(), [("n1".to_string(), stream, 3)]);
+    assert!(streams.acks().is_empty(), "nothing received since");
+
+    // A restarted node starts over, and what its old run sent is dropped.
+    // Streams are random, the new one needn't be greater
+    let restarted = sent[0].replace(&format!("\"stream\":{stream}"),
+        &format!("\"stream\":{}", stream ^ 1));
+    assert!(streams.receive(restarted).is_some());
+    assert_eq!(streams.receive(sent[1].clone()), None);
+    assert_eq!(streams.acks(), [("n1".to_string(), stream ^ 1, 1)]);
+}
+
+#[test]
+fn nodes_follow_the_cluster() {
+    let mut streamer = retrying(Some(Duration::from_secs(60)));
+    streamer.write_all(gossip("n2", 1).as_bytes()).unwrap();
+    streamer.write_all(gossip("n3", 2).as_bytes()).unwrap();
+    assert_eq!(streamer.unacked(), 1);
+
+    // What n2 didn't get it never will, once it left
+    streamer.set_nodes(&["n1".into(), "n3".into()]);
+    assert_eq!(streamer.unacked(), 0);
+    streamer.write_all(gossip("n3", 3).as_bytes()).unwrap();
+    assert_eq!(lines(&streamer)[2]["body"]["seq"], 1);
+    assert_eq!(streamer.unacked(), 1);
+}
+
+#[test]
+fn full_windows_coalesce_what_is_held_back() {
+    let mut streamer = Streamer::new(Vec::new(),
+        Some(Duration::from_secs(60)), Some(2), &["n2".into()],
+        Arc::new(Metrics::new("n1")));
+    for message in 1..=5 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    assert_eq!(lines(&streamer).len(), 2);
+    assert_eq!((streamer.unacked(), streamer.pending()), (2, 3));
+
+    // Room for one is made, and the three held back go out as one
+    let stream = lines(&streamer)[0]["body"]["stream"].as_u64().unwrap();
+    streamer.ack("n2", stream, 1).unwrap();
+    let sent = lines(&streamer);
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[2]["body"]["type"], "stream_batch");
+    assert_eq!(sent[2]["body"]["seq"], 3);
+    assert_eq!((streamer.unacked(), streamer.pending()), (2, 0));
+
+    // The receiver lets the messages of the batch through one by one
+    let mut streams = Streams::default();
+    let first = streams.receive(sent[2].to_string()).unwrap();
+    let first: Value = serde_json::from_str(&first).unwrap();
+    assert_eq!(first["body"]["message"], 3);
+    let rest: Vec<Value> = std::iter::from_fn(|| streams.next_unbatched())
+        .map(|line| serde_json::from_str(&line).unwrap())
+        .collect();
+    assert_eq!(rest.len(), 2);
+    assert_eq!(rest[1]["body"]["message"], 5);
+    assert_eq!(streams.receive(sent[2].to_string()), None);
+}
+
+#[test]
+fn restarted_receivers_get_a_fresh_stream() {
+    let mut streamer = Streamer::new(Vec::new(),
+        Some(Duration::from_secs(60)), Some(2), &["n2".into()],
+        Arc::new(Metrics::new("n1")));
+    for message in 1..=3 {
+        streamer.write_all(gossip("n2", message).as_bytes()).unwrap();
+    }
+    let old = lines(&streamer)[0]["body"]["stream"].as_u64().unwrap();
+
+    // The receiver restarted before it got anything, and never acks past
+    // what it has of the stream it sees now
+    let mut streams = Streams::default();
+    let receive = |streams: &mut Streams, sent: &[Value]| {
+        for line in sent {
+            streams.receive(line.to_string());
+            while streams.next_unbatched().is_some() {}
+        }
+        streams.acks()
+    };
+    let acks = receive(&mut streams, &lines(&streamer)[1..]);
+    assert_eq!(acks, [("n1".to_string(), old, 0)]);
+    streamer.ack("n2", old, 0).unwrap();
+    assert_eq!((streamer.unacked(), streamer.pending()), (2, 1));
+
+    // Once it announces it was restored, everything goes out again on a
+    // new stream, coalesced as far as the window goes
+    streamer.restart("n2").unwrap();
+    let sent = lines(&streamer)[2..].to_vec();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["body"]["type"], "stream_batch");
+    let batch = sent[0]["body"]["messages"].as_array().unwrap();
+    assert_eq!(batch.len(), 3);
+    assert!(batch.iter().all(|msg| msg["body"].get("seq").is_none()));
+    let new = sent[0]["body"]["stream"].as_u64().unwrap();
+    assert_ne!(new, old);
+    assert_eq!(receive(&mut streams, &sent), [("n1".to_string(), new, 1)]);
+    streamer.ack("n2", new, 1).unwrap();
+    assert_eq!((streamer.unacked(), streamer.pending()), (0, 0));
+
+    // And what's sent next goes right out
+    streamer.write_all(gossip("n2", 4).as_bytes()).unwrap();
+    assert_eq!(lines(&streamer)[3]["body"]["seq"], 2);
+}
+
+#[test]
+fn the_runtime_handles_repeats_once_and_acks_them() {
+    use maelstrom::message as msg;
+    use maelstrom::services::broadcast;
+
+    let gossip = concat!(
+        r#"{"src":"n2","dest":"n1","body":{"type":"broadcast","msg_id":1,"#,
+        r#""message":7,"stream":5,"seq":1}}"#, "\n");
+    let input = [
+        concat!(r#"{"src":"c0","dest":"n1","body":{"type":"init","#,
+            r#""msg_id":1,"node_id":"n1","node_ids":["n1","n2"]}}"#, "\n"),
+        gossip,
+        gossip,
+        concat!(r#"{"src":"c1","dest":"n1","body":{"type":"debug_status","#,
+            r#""msg_id":3}}"#, "\n"),
+    ].concat();
+    let mut config = Config::default();
+    config.apply_args(&["--stream-retry-ms".into(), "1000".into()]).unwrap();
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<broadcast::Payload, broadcast::BroadcastNode>(
+        std::io::Cursor::new(input), &mut output, &config).unwrap();
+
+    let output: Vec<Value> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let to_n2: Vec<&Value> = output.iter()
+        .filter(|msg| msg["dest"] == "n2")
+        .map(|msg| &msg["body"])
+        .collect();
+    let oks = to_n2.iter().filter(|body| body["type"] == "broadcast_ok");
+    assert_eq!(oks.count(), 1);
+    assert!(to_n2.iter().any(|body| body["type"] == "stream_ack" &&
+        body["stream"] == 5 && body["upto"] == 1));
+
+    let status = output.iter()
+        .map(|msg| &msg["body"])
+        .find(|body| body["type"] == "debug_status_ok")
+        .unwrap();
+    assert_eq!(status["service"]["messages"], 1);
+    assert_eq!(status["unacked"], 1);
+}
@@ -0,0 +1,69 @@
+//! Rate limiting of the requests a node sends
+
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use maelstrom::metrics::Metrics;
+use maelstrom::throttle::{Throttle, MAX_DEFERRED};
+
+const GOSSIP: &[u8] = b"{\"src\":\"n1\",\"dest\":\"n2\",\"body\":{}}\n";
+const REPLY: &[u8] =
+    b"{\"src\":\"n1\",\"dest\":\"n2\",\"body\":{\"in_reply_to\":1}}\n";
+
+fn throttle(rate: Option<u32>, burst: u32) -> Throttle<Vec<u8>> {
+    Throttle::new(Vec::new(), rate, burst, Arc::new(Metrics::new("n1")))
+}
+
+fn sent(throttle: &Throttle<Vec<u8>>) -> usize {
+    throttle.inner().iter().filter(|b| **b == b'\n').count()
+}
+
+#[test]
+fn unlimited_without_a_rate() {
+    let mut throttle = throttle(None, 1);
+    for _ in 0..100 {
+        throttle.write_all(GOSSIP).unwrap();
+    }
+    assert_eq!(sent(&throttle), 100);
+    assert_eq!(throttle.next_deadline(), None);
+}
+
+#[test]
+fn requests_over_the_burst_are_deferred() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut throttle = Throttle::new(Vec::new(), Some(1000), 3,
+        metrics.clone());
+    for _ in 0..5 {
+        throttle.write_all(GOSSIP).unwrap();
+    }
+
+    // Replies are never held back, not even behind deferred requests
+    throttle.write_all(REPLY).unwrap();
+    assert_eq!(sent(&throttle), 4);
+    assert_eq!(throttle.deferred(), 2);
+    assert_eq!(metrics.deferred.load(Ordering::Relaxed), 2);
+
+    // A token comes by every millisecond
+    let deadline = throttle.next_deadline().unwrap();
+    assert!(deadline <= Instant::now() + Duration::from_millis(1));
+    std::thread::sleep(Duration::from_millis(5));
+    throttle.release_due().unwrap();
+    assert_eq!(sent(&throttle), 6);
+    assert_eq!(throttle.next_deadline(), None);
+}
+
+#[test]
+fn the_oldest_deferred_requests_are_dropped() {
+    let metrics = Arc::new(Metrics::new("n1"));
+    let mut throttle = Throttle::new(Vec::new(), Some(1), 1,
+        metrics.clone());
+    for _ in 0..MAX_DEFERRED + 11 {
+        throttle.write_all(GOSSIP).unwrap();
+    }
+    assert_eq!(throttle.deferred(), MAX_DEFERRED);
+    assert_eq!(metrics.dropped.load(Ordering::Relaxed), 10);
+
+    throttle.release_all().unwrap();
+    assert_eq!(sent(&throttle), MAX_DEFERRED + 1);
+}
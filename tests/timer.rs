@@ -0,0 +1,127 @@
+//! Scheduling timers by when they're due
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Message};
+use maelstrom::services::echo;
+use maelstrom::timer::Timers;
+
+#[test]
+fn timers_come_up_in_the_order_they_are_due() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut timers = Timers::default();
+    timers.set("election", at(30));
+    timers.set("gossip", at(10));
+    timers.set("lease", at(20));
+    assert_eq!(timers.next_deadline(), Some(at(10)));
+
+    assert_eq!(timers.pop_due(at(5)), None);
+    let due: Vec<_> = std::iter::from_fn(|| timers.pop_due(at(25)))
+        .collect();
+    assert_eq!(due, ["gossip", "lease"]);
+    assert_eq!(timers.len(), 1);
+}
+
+#[test]
+fn moved_and_cancelled_timers_are_skipped() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut timers = Timers::default();
+    timers.set("election", at(10));
+    timers.set("gossip", at(20));
+
+    // Heard from the primary, the election is put off
+    timers.set("election", at(40));
+    assert_eq!(timers.due(&"election"), Some(at(40)));
+    assert_eq!(timers.next_deadline(), Some(at(20)));
+
+    assert!(timers.cancel(&"gossip"));
+    assert!(!timers.cancel(&"gossip"));
+    assert_eq!(timers.next_deadline(), Some(at(40)));
+    assert_eq!(timers.pop_due(at(50)), Some("election"));
+    assert!(timers.is_empty());
+    assert_eq!(timers.next_deadline(), None);
+}
+
+/// Input handing out its lines only after a pause each, so that the runtime
+/// has timers to fire meanwhile
+struct Paced {
+    lines: std::collections::VecDeque<(Duration, String)>,
+    line: std::io::Cursor<Vec<u8>>,
+}
+
+impl std::io::Read for Paced {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.line.position() as usize == self.line.get_ref().len() {
+            let Some((pause, line)) = self.lines.pop_front() else {
+                return Ok(0);
+            };
+            std::thread::sleep(pause);
+            self.line = std::io::Cursor::new(line.into_bytes());
+        }
+        self.line.read(buf)
+    }
+}
+
+/// Node setting a timer named after whatever it's asked to echo, and
+/// cancelling it when asked to echo its name prefixed by `cancel `
+struct Alarm;
+
+impl msg::Node<echo::Payload> for Alarm {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    fn step(&mut self, input: Message<echo::Payload>, _output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let echo::Payload::Echo { echo } = input.body.payload else {
+            return Ok(());
+        };
+        let name = echo.as_str().unwrap_or_default();
+        match name.strip_prefix("cancel ") {
+            Some(name) => msg::cancel_timer(name),
+            None => msg::set_timer(name, Duration::from_millis(10)),
+        }
+        Ok(())
+    }
+
+    fn timer(&mut self, name: &str, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        Message::new("n1", "c1", 0, echo::Payload::EchoOk {
+            echo: name.into(),
+        }).send(output)
+    }
+}
+
+#[test]
+fn nodes_are_handed_the_timers_they_set() {
+    let line = |echo: &str| format!("{{\"src\":\"c1\",\"dest\":\"n1\",\
+        \"body\":{{\"type\":\"echo\",\"msg_id\":1,\"echo\":\"{echo}\"}}}}\n");
+    let right_away = Duration::ZERO;
+    let input = Paced {
+        lines: [
+            (right_away, "{\"src\":\"c0\",\"dest\":\"n1\",\"body\":{\"type\":\
+                \"init\",\"msg_id\":1,\"node_id\":\"n1\",\
+                \"node_ids\":[\"n1\"]}}\n".into()),
+            (right_away, line("gossip")),
+            (right_away, line("election")),
+            (right_away, line("cancel election")),
+            (Duration::from_millis(50), line("late")),
+        ].into(),
+        line: std::io::Cursor::new(Vec::new()),
+    };
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<echo::Payload, Alarm>(
+        std::io::BufReader::new(input), &mut output, &Config::default())
+        .unwrap();
+
+    let fired: Vec<String> = String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|msg| msg["body"]["type"] == "echo_ok")
+        .map(|msg| msg["body"]["echo"].as_str().unwrap().into())
+        .collect();
+    assert_eq!(fired, ["gossip"]);
+}
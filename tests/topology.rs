@@ -0,0 +1,106 @@
+//! Overlays laid over the cluster in place of the given topology
+
+use std::collections::{HashSet, VecDeque};
+use maelstrom::config::Config;
+use maelstrom::services::broadcast;
+use maelstrom::topology::{self, Strategy, Topology};
+
+fn nodes(count: usize) -> Vec<String> {
+    (0..count).map(|idx| format!("n{idx}")).collect()
+}
+
+/// Most hops between any two nodes of `topology`
+fn diameter(topology: &Topology) -> usize {
+    topology.keys().map(|from| {
+        let mut hops = 0;
+        let mut seen = HashSet::from([from]);
+        let mut frontier = VecDeque::from([(from, 0)]);
+        while let Some((node, dist)) = frontier.pop_front() {
+            hops = dist;
+            for next in &topology[node] {
+                if seen.insert(next) {
+                    frontier.push_back((next, dist + 1));
+                }
+            }
+        }
+        assert_eq!(seen.len(), topology.len(), "{from} can't reach everyone");
+        hops
+    }).max().unwrap_or(0)
+}
+
+#[test]
+fn hubs_connect_to_each_other_and_leaves_to_one_hub() {
+    let topology = topology::hubs(&nodes(25));
+    let hubs: Vec<&String> = topology.keys()
+        .filter(|node| topology[*node].len() > 1)
+        .collect();
+    assert_eq!(hubs.len(), 5);
+    for hub in &hubs {
+        // The four other hubs and four leaves
+        assert_eq!(topology[*hub].len(), 8);
+    }
+    assert_eq!(diameter(&topology), 3);
+
+    // Neighbors are mutual
+    for (node, neighbors) in &topology {
+        for neighbor in neighbors {
+            assert!(topology[neighbor].contains(node));
+        }
+    }
+}
+
+#[test]
+fn small_clusters_are_all_hubs() {
+    assert_eq!(diameter(&topology::hubs(&nodes(1))), 0);
+    let two = topology::hubs(&nodes(2));
+    assert_eq!(two["n0"], ["n1"]);
+    assert_eq!(diameter(&topology::hubs(&nodes(5))), 3);
+}
+
+#[test]
+fn the_configured_overlay_replaces_the_given_topology() {
+    let mut config = Config::default();
+    assert_eq!(config.topology, Strategy::Given);
+    config.apply_args(&["--topology".into(), "hubs".into()]).unwrap();
+    assert_eq!(config.summary()["topology"], "hubs");
+
+    let given = [("n9".to_string(), vec!["n0".to_string()])].into();
+    let neighbors = broadcast::neighbors("n9", &nodes(10), Some(given),
+        &config);
+    assert_eq!(neighbors, ["n1"]);
+    assert!(config.apply_args(&["--topology".into(), "mesh".into()])
+        .is_err());
+}
+
+#[test]
+fn rings_reach_everyone_in_logarithmic_hops() {
+    for count in [1, 2, 7, 25, 64] {
+        let topology = topology::ring(&nodes(count));
+        let log = (count as f64).log2().ceil() as usize;
+        assert!(diameter(&topology) <= log, "{count} nodes");
+        assert!(topology.values().all(|neighbors|
+            neighbors.len() <= 2 * log));
+    }
+    let ring = topology::ring(&nodes(8));
+    assert_eq!(ring["n0"], ["n1", "n2", "n4", "n6", "n7"]);
+}
+
+#[test]
+fn grids_link_the_nodes_next_to_each_other() {
+    let grid = topology::grid(&nodes(25));
+    assert_eq!(diameter(&grid), 8);
+    assert!(grid.values().all(|neighbors| neighbors.len() <= 4));
+    assert_eq!(grid["n0"], ["n1", "n5"]);
+    assert_eq!(grid["n12"].len(), 4);
+
+    // The last row is cut short, and still hangs together
+    let grid = topology::grid(&nodes(7));
+    assert_eq!(grid["n6"], ["n3"]);
+    assert_eq!(diameter(&grid), 4);
+    let config = {
+        let mut config = Config::default();
+        config.apply_args(&["--topology".into(), "grid".into()]).unwrap();
+        config
+    };
+    assert_eq!(config.topology.overlay(&nodes(7)), Some(grid));
+}
@@ -0,0 +1,73 @@
+//! Traces of client requests carried through the messages they lead to
+
+use std::io::Write;
+use serde_json::{json, Value};
+use maelstrom::config::{Config, LogLevel};
+use maelstrom::message::{self as msg, Message, Node};
+use maelstrom::services::echo::Payload;
+
+/// Node passing every echo on to `n2` before echoing it back
+struct Relay;
+
+impl Node<Payload> for Relay {
+    fn from_init(_init: &msg::Init, _config: &Config)
+            -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    fn step(&mut self, input: Message<Payload>, output: &mut dyn Write)
+            -> anyhow::Result<()> {
+        let mut input = input;
+        let id = input.body.id;
+        let Payload::Echo { echo } = input.body.payload else { return Ok(()) };
+
+        Message::new("n1", "n2", 100, Payload::Echo { echo: echo.clone() })
+            .send(output)?;
+        input.body.payload = Payload::EchoOk { echo };
+        input.into_reply(id).send(output)
+    }
+}
+
+fn run(input: &[Value], log_level: LogLevel) -> Vec<Value> {
+    let input: String = input.iter().map(|msg| format!("{msg}\n")).collect();
+    let config = Config { log_level, ..Config::default() };
+    let mut output = Vec::new();
+    msg::main_loop_with_io::<Payload, Relay>(std::io::Cursor::new(input),
+        &mut output, &config).unwrap();
+    String::from_utf8(output).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn init() -> Value {
+    json!({"src": "c0", "dest": "n1", "body": {"type": "init",
+        "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}})
+}
+
+#[test]
+fn client_requests_are_traced_when_logged() {
+    let echo = json!({"src": "c1", "dest": "n1", "body": {"type": "echo",
+        "msg_id": 2, "echo": 1}});
+
+    // Replies go out first
+    let output = run(&[init(), echo.clone()], LogLevel::Debug);
+    assert_eq!(output[2]["dest"], "n2");
+    assert_eq!(output[2]["body"]["trace_id"], "n1-1");
+
+    // Clients get their replies as they would untraced
+    assert_eq!(output[1]["dest"], "c1");
+    assert!(output[1]["body"].get("trace_id").is_none());
+
+    let output = run(&[init(), echo], LogLevel::Warn);
+    assert!(output[2]["body"].get("trace_id").is_none());
+}
+
+#[test]
+fn traces_are_carried_on() {
+    let output = run(&[init(), json!({"src": "n2", "dest": "n1", "body": {
+        "type": "echo", "msg_id": 2, "echo": 1, "trace_id": "n2-7"}})],
+        LogLevel::Warn);
+    assert_eq!(output[1]["body"]["trace_id"], "n2-7");
+    assert_eq!(output[2]["body"]["trace_id"], "n2-7");
+    assert_eq!(output[1]["body"]["in_reply_to"], 2);
+}
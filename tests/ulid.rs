@@ -0,0 +1,50 @@
+//! Ordering and uniqueness of the ULID-style IDs
+
+use std::collections::HashSet;
+use maelstrom::ulid::Ulids;
+
+#[test]
+fn ids_sort_by_time() {
+    let mut ulids = Ulids::new(3, 42);
+    let first = ulids.next_at(1000);
+    let second = ulids.next_at(1001);
+    assert!(second > first);
+    assert_eq!(first >> 80, 1000);
+    assert_eq!(second >> 80, 1001);
+    assert_eq!((first >> 64) as u16, 3);
+}
+
+#[test]
+fn ids_within_a_millisecond_count_up() {
+    let mut ulids = Ulids::new(0, 42);
+    let first = ulids.next_at(1000);
+    for step in 1..100 {
+        assert_eq!(ulids.next_at(1000), first + step);
+    }
+}
+
+#[test]
+fn nodes_never_collide() {
+    // Same seed and clock; only the index of the node tells them apart
+    let mut seen = HashSet::new();
+    for node in 0..4 {
+        let mut ulids = Ulids::new(node, 42);
+        for ms in 0..100 {
+            assert!(seen.insert(ulids.next_at(1000 + ms / 10)));
+        }
+    }
+}
+
+#[test]
+fn ids_outlive_clock_regressions() {
+    let mut ulids = Ulids::new(0, 42);
+    let first = ulids.next_at(1000);
+
+    // The clock is stepped back; IDs count on from the last one
+    let second = ulids.next_at(400);
+    assert_eq!(second, first + 1);
+    assert_eq!(ulids.last_ms(), 1000);
+
+    // Until the clock catches up
+    assert_eq!(ulids.next_at(1001) >> 80, 1001);
+}
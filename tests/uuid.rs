@@ -0,0 +1,52 @@
+//! Collision audit of the unique ID service
+
+use serde::Deserialize;
+use serde_json::Value;
+use maelstrom::config::Config;
+use maelstrom::message::{Message, Init, Node};
+use maelstrom::services::uuid::{Payload, UUIDNode};
+
+/// A `generate_ok`, read without going through the flattened payload, which
+/// can't hold IDs past `u64`
+#[derive(Deserialize)]
+struct Reply {
+    body: ReplyBody,
+}
+
+#[derive(Deserialize)]
+struct ReplyBody {
+    id: u128,
+}
+
+#[test]
+fn audited_nodes_catch_collisions() {
+    let mut config = Config::default();
+    config.apply_args(&["--id-audit".into(), "1".into()]).unwrap();
+    let init = Init {
+        node_id:  "n1".into(),
+        node_ids: vec!["n1".into(), "n2".into()],
+    };
+    let mut node = UUIDNode::from_init(&init, &config).unwrap();
+    assert!(node.tick_interval().is_some());
+
+    let mut out = Vec::new();
+    node.step(Message::new("c1", "n1", 1, Payload::Generate), &mut out)
+        .unwrap();
+    let reply: Reply = serde_json::from_slice(&out).unwrap();
+    let id = reply.body.id;
+
+    // The generated ID is gossiped to the other node
+    out.clear();
+    node.tick(&mut out).unwrap();
+    let gossip: Message<Payload> = serde_json::from_slice(&out).unwrap();
+    assert_eq!(gossip.dst, "n2");
+    assert_eq!(gossip.body.payload, Payload::Issued {
+        ids: vec![id.to_string()],
+    });
+
+    // Which generates it as well
+    let issued = Payload::Issued { ids: vec![id.to_string(), "7".into()] };
+    node.step(Message::new("n2", "n1", 1, issued), &mut out).unwrap();
+    assert_eq!(node.status()["collisions"], Value::from(1));
+    assert_eq!(node.status()["known"], Value::from(2));
+}
@@ -0,0 +1,90 @@
+//! Vector clocks and the siblings of the vector clock KV
+
+mod common;
+
+use serde_json::{json, Value};
+use maelstrom::config::Config;
+use maelstrom::message::{self as msg, Node};
+use maelstrom::services::vclock_kv::{self, VClockKvNode};
+use maelstrom::vclock::VClock;
+use common::step;
+
+fn node(id: &str) -> VClockKvNode {
+    VClockKvNode::from_init(&msg::Init {
+        node_id:  id.into(),
+        node_ids: vec!["n0".into(), "n1".into()],
+    }, &Config::default()).unwrap()
+}
+
+/// Gossip what changed on `from` to `to`
+fn sync(from: &mut VClockKvNode, to: &mut VClockKvNode) {
+    let mut out = Vec::new();
+    from.tick(&mut out).unwrap();
+    for line in String::from_utf8(out).unwrap().lines() {
+        step(to, serde_json::from_str(line).unwrap());
+    }
+}
+
+fn write(node: &mut VClockKvNode, dst: &str, value: Value, context: Value)
+        -> Value {
+    let out = step(node, json!({"src": "c1", "dest": dst, "body": {
+        "type": "write", "msg_id": 1, "key": 1, "value": value,
+        "context": context}}));
+    assert_eq!(out[0]["body"]["type"], "write_ok");
+    out[0]["body"]["context"].clone()
+}
+
+fn read(node: &mut VClockKvNode, dst: &str) -> Value {
+    step(node, json!({"src": "c1", "dest": dst,
+        "body": {"type": "read", "msg_id": 1, "key": 1}})).remove(0)
+}
+
+#[test]
+fn clocks_are_partially_ordered() {
+    let mut a = VClock::new();
+    a.increment("n0");
+    let mut b = a.clone();
+    b.increment("n1");
+    assert!(b.descends(&a) && !a.descends(&b));
+
+    a.increment("n0");
+    assert!(a.concurrent(&b));
+    a.merge(&b);
+    assert!(a.descends(&b));
+    assert_eq!((a.get("n0"), a.get("n1")), (2, 1));
+}
+
+#[test]
+fn concurrent_writes_become_siblings_until_resolved() {
+    let (mut n0, mut n1) = (node("n0"), node("n1"));
+    write(&mut n0, "n0", json!("a"), json!({}));
+    write(&mut n1, "n1", json!("b"), json!({}));
+    sync(&mut n0, &mut n1);
+    sync(&mut n1, &mut n0);
+
+    // Both replicas hold both values
+    let reply = read(&mut n0, "n0");
+    assert_eq!(reply["body"]["values"], json!(["a", "b"]));
+    assert_eq!(read(&mut n1, "n1")["body"]["values"], json!(["a", "b"]));
+
+    // Writing with the context of the read resolves the siblings everywhere
+    write(&mut n0, "n0", json!("c"), reply["body"]["context"].clone());
+    sync(&mut n0, &mut n1);
+    assert_eq!(read(&mut n1, "n1")["body"]["values"], json!(["c"]));
+}
+
+#[test]
+fn blind_writes_keep_the_previous_value() {
+    let mut n0 = node("n0");
+    write(&mut n0, "n0", json!("a"), json!({}));
+    let context = write(&mut n0, "n0", json!("b"), json!({}));
+    assert_eq!(read(&mut n0, "n0")["body"]["values"], json!(["a", "b"]));
+    assert_eq!(context, json!({"n0": 2}));
+}
+
+#[test]
+fn union_merges_the_elements_of_siblings() {
+    let merged = vclock_kv::union(vec![json!([1, 2]), json!([2, 3]),
+        json!(4)]);
+    assert_eq!(merged, json!([1, 2, 3, 4]));
+}